@@ -10,7 +10,7 @@ use clap::{Parser, arg, command};
 use custom_benchmark::{
     sorts::{Element, HasName, ParSort, SeqSort, Sort},
     threshold_calc::get_threshold,
-    utils::{self, get_input_array},
+    utils::{self, InputKind},
 };
 use rand::seq::SliceRandom;
 
@@ -66,6 +66,13 @@ const BENCHED_PAR_SORTS: &[ParSort] = &[
     // ParSort::NakedVerusArc,
     // ParSort::NakedVerusArcNoScope,
     // ParSort::NakedVerusRayon,
+    // ParSort::VerusBranchless,
+    // ParSort::NakedVerusBranchless,
+    // ParSort::NakedVerusSimd,
+    // ParSort::VerusNatural,
+    // ParSort::VerusQuicksort,
+    // ParSort::VerusSampleSort,
+    // ParSort::VerusRadix,
 ];
 
 fn bench_sort_seq(sort: &SeqSort, input: &Vec<Element>) -> Duration {
@@ -101,12 +108,12 @@ fn bench_sorts_once<S: HasName>(
         .collect()
 }
 
-fn estimate_time(parallel: bool, sequential: bool) -> Duration {
+fn estimate_time(parallel: bool, sequential: bool, kind: InputKind) -> Duration {
     let mut time = Duration::ZERO;
 
     if parallel {
         for &size in PAR_ARRAY_SIZES {
-            let input = get_input_array(size);
+            let input = utils::get_structured_input_array(size, kind);
             let start = Instant::now();
             bench_sorts_once(BENCHED_PAR_SORTS, bench_sort_par, &input);
             let time_spent = start.elapsed();
@@ -115,7 +122,7 @@ fn estimate_time(parallel: bool, sequential: bool) -> Duration {
     };
     if sequential {
         for &size in SEQ_ARRAY_SIZES {
-            let input = get_input_array(size);
+            let input = utils::get_structured_input_array(size, kind);
             let start = Instant::now();
             bench_sorts_once(BENCHED_SEQ_SORTS, bench_sort_seq, &input);
             let time_spent = start.elapsed();
@@ -134,6 +141,7 @@ fn _bench_sorts<S: HasName>(
     sorts: &[S],
     bench: impl Fn(&S, &Vec<Element>) -> Duration,
     sizes: &[usize],
+    kind: InputKind,
 ) -> SortStats {
     let mut res = HashMap::new();
     for &size in sizes {
@@ -148,7 +156,7 @@ fn _bench_sorts<S: HasName>(
         let fraction = 10. / samples_per_size(size) as f32;
         print!("{progress:.0} ");
         for _ in 0..samples_per_size(size) {
-            let input = utils::get_input_array(size);
+            let input = utils::get_structured_input_array(size, kind);
             let micros = bench_sorts_once(sorts, &bench, &input)
                 .into_iter()
                 .map(|(s, d)| (s, d.as_micros()));
@@ -167,16 +175,16 @@ fn _bench_sorts<S: HasName>(
     res
 }
 
-fn bench_sorts_seq() -> SortStats {
+fn bench_sorts_seq(kind: InputKind) -> SortStats {
     println!("Starting sequential benchmark");
-    let res = _bench_sorts(BENCHED_SEQ_SORTS, bench_sort_seq, SEQ_ARRAY_SIZES);
+    let res = _bench_sorts(BENCHED_SEQ_SORTS, bench_sort_seq, SEQ_ARRAY_SIZES, kind);
     println!("Finishing sequential benchmark");
     res
 }
 
-fn bench_sorts_parallel() -> SortStats {
+fn bench_sorts_parallel(kind: InputKind) -> SortStats {
     println!("Starting parallel benchmark");
-    let res = _bench_sorts(BENCHED_PAR_SORTS, bench_sort_par, PAR_ARRAY_SIZES);
+    let res = _bench_sorts(BENCHED_PAR_SORTS, bench_sort_par, PAR_ARRAY_SIZES, kind);
     println!("Finishing parallel benchmark");
     res
 }
@@ -195,6 +203,10 @@ struct Args {
 
     #[arg(long = "out", value_name = "FILE")]
     out: Option<PathBuf>,
+
+    /// Shape of the input arrays
+    #[arg(long, value_enum, default_value_t = InputKind::Random)]
+    input: InputKind,
 }
 
 fn main() {
@@ -205,7 +217,7 @@ fn main() {
     }
 
     // println!("Estimating time");
-    // let time = estimate_time(args.parallel, args.sequential);
+    // let time = estimate_time(args.parallel, args.sequential, args.input);
     // println!(
     //     "Benchmarking will take approximately {} minutes",
     //     time.as_millis() / 1000 / 60
@@ -214,11 +226,11 @@ fn main() {
     let mut res: HashMap<SortName, SortStats> = HashMap::new();
 
     if args.sequential {
-        res.insert("sequential", bench_sorts_seq());
+        res.insert("sequential", bench_sorts_seq(args.input));
     }
 
     if args.parallel {
-        res.insert("parallel", bench_sorts_parallel());
+        res.insert("parallel", bench_sorts_parallel(args.input));
     }
 
     let output = serde_json::to_string_pretty(&res).unwrap();
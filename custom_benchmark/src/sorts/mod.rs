@@ -6,6 +6,8 @@ use disjoint_mut_test::mergesort::ArrayForSorting;
 use enum_iterator::{Sequence, all};
 use rayon::slice::ParallelSliceMut;
 
+pub use mergesort_benchmarks::merge_sorts::merge_kernels;
+
 use crate::sorts;
 
 pub mod naked_verus;
@@ -13,6 +15,7 @@ pub mod naked_verus_arc;
 pub mod naked_verus_arc_clone;
 pub mod naked_verus_arc_clone_no_scope;
 pub mod naked_verus_arc_no_scope;
+pub mod naked_verus_kernel;
 pub mod naked_verus_no_scope;
 pub mod naked_verus_rayon;
 pub mod slices;
@@ -101,6 +104,7 @@ pub enum SeqSort {
     SlicesUnchecked,
     Verus,
     NakedVerus,
+    VerusNatural,
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Copy)]
@@ -125,6 +129,13 @@ pub enum ParSort {
     NakedVerusRayon,
     SlicesUncheckedVspawn,
     Rayon,
+    VerusBranchless,
+    NakedVerusBranchless,
+    NakedVerusSimd,
+    VerusNatural,
+    VerusQuicksort,
+    VerusSampleSort,
+    VerusRadix,
 }
 
 pub trait HasName {
@@ -138,6 +149,7 @@ impl HasName for SeqSort {
             Self::SlicesUnchecked => "slices unchecked",
             Self::Verus => "verus",
             Self::NakedVerus => "naked verus",
+            Self::VerusNatural => "verus natural",
         }
     }
 }
@@ -164,6 +176,13 @@ impl HasName for ParSort {
             Self::SlicesUncheckedVspawn => "vspawn slices unchecked",
             Self::Rayon => "rayon",
             Self::NakedVerusRayon => "naked verus rayon",
+            Self::VerusBranchless => "verus branchless",
+            Self::NakedVerusBranchless => "naked verus branchless",
+            Self::NakedVerusSimd => "naked verus simd",
+            Self::VerusNatural => "verus natural",
+            Self::VerusQuicksort => "verus quicksort",
+            Self::VerusSampleSort => "verus sample sort",
+            Self::VerusRadix => "verus radix",
         }
     }
 }
@@ -206,6 +225,10 @@ impl SeqSort {
                 let buf_a = naked_verus::Array(buf.as_ptr() as *mut i32);
                 naked_verus::merge_sort(input_a, 0, input.len(), buf_a)
             }
+            SeqSort::VerusNatural => disjoint_mut_test::natural_mergesort::natural_merge_sort(
+                input.unwrap_as_verus(),
+                buf.unwrap_as_verus(),
+            ),
         }
     }
 }
@@ -335,6 +358,61 @@ impl ParSort {
             ParSort::Rayon => {
                 normal_sort_par(|input, _, _| Ok(input.par_sort()), input, buf, threshold)
             }
+            ParSort::VerusBranchless => {
+                disjoint_mut_test::mergesort::merge_sort_parallel_with(
+                    input.unwrap_as_verus(),
+                    buf.unwrap_as_verus(),
+                    threshold,
+                    disjoint_mut_test::mergesort::MergeKernel::Branchless,
+                )
+            }
+            ParSort::NakedVerusBranchless => {
+                let (input, buf) = (input.unwrap_as_vec(), buf.unwrap_as_vec());
+                let input_a = naked_verus::Array(input.as_ptr() as *mut i32);
+                let buf_a = naked_verus::Array(buf.as_ptr() as *mut i32);
+                naked_verus_kernel::_merge_sort_parallel(
+                    input_a,
+                    0,
+                    input.len(),
+                    buf_a,
+                    threshold,
+                    naked_verus_kernel::BRANCHLESS,
+                )
+            }
+            ParSort::NakedVerusSimd => {
+                let (input, buf) = (input.unwrap_as_vec(), buf.unwrap_as_vec());
+                let input_a = naked_verus::Array(input.as_ptr() as *mut i32);
+                let buf_a = naked_verus::Array(buf.as_ptr() as *mut i32);
+                naked_verus_kernel::_merge_sort_parallel(
+                    input_a,
+                    0,
+                    input.len(),
+                    buf_a,
+                    threshold,
+                    naked_verus_kernel::SIMD,
+                )
+            }
+            ParSort::VerusNatural => {
+                disjoint_mut_test::natural_mergesort::natural_merge_sort_parallel(
+                    input.unwrap_as_verus(),
+                    buf.unwrap_as_verus(),
+                    threshold,
+                )
+            }
+            ParSort::VerusQuicksort => disjoint_mut_test::quicksort::quick_sort_parallel(
+                input.unwrap_as_verus(),
+                threshold,
+            ),
+            ParSort::VerusSampleSort => disjoint_mut_test::sample_sort::sample_sort_parallel(
+                input.unwrap_as_verus(),
+                buf.unwrap_as_verus(),
+                threshold,
+            ),
+            ParSort::VerusRadix => disjoint_mut_test::radix_sort::radix_sort_parallel(
+                input.unwrap_as_verus(),
+                buf.unwrap_as_verus(),
+                threshold,
+            ),
         }
         .unwrap();
     }
@@ -346,7 +424,13 @@ impl Sort {
         let mapper = |input| match self {
             Sort::Seq(SeqSort::Verus)
             | Sort::Par(ParSort::Verus)
-            | Sort::Par(ParSort::VerusLessArcs) => InputArray::Verus(ArrayForSorting::new(input)),
+            | Sort::Par(ParSort::VerusLessArcs)
+            | Sort::Par(ParSort::VerusBranchless)
+            | Sort::Seq(SeqSort::VerusNatural)
+            | Sort::Par(ParSort::VerusNatural)
+            | Sort::Par(ParSort::VerusQuicksort)
+            | Sort::Par(ParSort::VerusSampleSort)
+            | Sort::Par(ParSort::VerusRadix) => InputArray::Verus(ArrayForSorting::new(input)),
             Sort::Par(ParSort::VerusNoGhostNoArc) => {
                 InputArray::VerusNoGhostNoArc(verus_no_g_no_arc::ArrayForSorting::new(input))
             }
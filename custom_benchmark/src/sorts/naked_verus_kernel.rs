@@ -0,0 +1,79 @@
+use crate::sorts::{
+    merge_kernels,
+    naked_verus::{Array, copy},
+};
+
+/// Merges sorted `left` and `right` into `out`.
+pub type MergeKernel = fn(&[i32], &[i32], &mut [i32]);
+
+pub const BRANCHLESS: MergeKernel = merge_kernels::merge_branchless;
+pub const SIMD: MergeKernel = merge_kernels::merge_simd;
+
+#[inline(always)]
+fn merge(
+    kernel: MergeKernel,
+    array: Array,
+    left_lo: usize,
+    right_lo: usize,
+    right_hi: usize,
+    helper_buf: Array,
+    helper_buf_lo: usize,
+) {
+    let (left, right, out) = unsafe {
+        (
+            std::slice::from_raw_parts(array.0.add(left_lo), right_lo - left_lo),
+            std::slice::from_raw_parts(array.0.add(right_lo), right_hi - right_lo),
+            std::slice::from_raw_parts_mut(helper_buf.0.add(helper_buf_lo), right_hi - left_lo),
+        )
+    };
+    kernel(left, right, out);
+}
+
+pub fn merge_sort(arr: Array, lo: usize, hi: usize, helper_buf: Array, kernel: MergeKernel) {
+    let mid = lo + (hi - lo) / 2;
+    if mid == lo {
+        return;
+    }
+    merge_sort(arr, lo, mid, helper_buf, kernel);
+    merge_sort(arr, mid, hi, helper_buf, kernel);
+    merge(kernel, arr, lo, mid, hi, helper_buf, lo);
+    copy(helper_buf, lo, hi, arr, lo);
+}
+
+pub fn _merge_sort_parallel(
+    arr: Array,
+    lo: usize,
+    hi: usize,
+    helper_buf: Array,
+    threshold: usize,
+    kernel: MergeKernel,
+) -> Result<(), ()> {
+    let mid = lo + (hi - lo) / 2;
+    if mid == lo {
+        return Ok(());
+    }
+    if hi - lo <= threshold {
+        merge_sort(arr, lo, hi, helper_buf, kernel);
+        return Ok(());
+    }
+    std::thread::scope(|scope| {
+        let left_perms = scope.spawn(move || {
+            let t = _merge_sort_parallel(arr, lo, mid, helper_buf, threshold, kernel);
+            if t.is_err() { Err(()) } else { Ok(()) }
+        });
+        match _merge_sort_parallel(arr, mid, hi, helper_buf, threshold, kernel) {
+            Ok(_) => {}
+            Err(_) => {
+                return Err(());
+            }
+        };
+        match left_perms.join() {
+            Ok(Ok(())) => {}
+            _ => return Err(()),
+        };
+        Ok(())
+    })?;
+    merge(kernel, arr, lo, mid, hi, helper_buf, lo);
+    copy(helper_buf, lo, hi, arr, lo);
+    Ok(())
+}
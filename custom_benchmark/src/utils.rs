@@ -4,11 +4,42 @@ use rand::Rng;
 
 use crate::sorts::Element;
 
+/// Shape of the generated input, to compare sorts that adapt to existing order.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum InputKind {
+    #[default]
+    Random,
+    Sorted,
+    Reversed,
+    /// Sorted, with 1% of the elements swapped at random positions
+    NearlySorted,
+    /// Sorted runs of 10_000 elements
+    Sawtooth,
+}
+
 pub fn get_input_array(size: usize) -> Vec<Element> {
     let mut rng = rand::rng();
     (0..size).map(|_| rng.random()).collect()
 }
 
+pub fn get_structured_input_array(size: usize, kind: InputKind) -> Vec<Element> {
+    let mut input = get_input_array(size);
+    match kind {
+        InputKind::Random => {}
+        InputKind::Sorted => input.sort(),
+        InputKind::Reversed => input.sort_by(|a, b| b.cmp(a)),
+        InputKind::NearlySorted => {
+            input.sort();
+            let mut rng = rand::rng();
+            for _ in 0..size / 100 {
+                input.swap(rng.random_range(0..size), rng.random_range(0..size));
+            }
+        }
+        InputKind::Sawtooth => input.chunks_mut(10_000).for_each(|run| run.sort()),
+    }
+    input
+}
+
 pub fn map_values<K, V1, V2>(map: &HashMap<K, V1>, f: impl Fn(&V1) -> V2) -> HashMap<K, V2>
 where
     K: Eq + std::hash::Hash + Clone,
@@ -0,0 +1,376 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use vstd::seq_lib::lemma_multiset_commutative;
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::{self, ArrayForSorting},
+    merge_sorted::{self, MergeOrder},
+};
+
+/// `idx` points into `keys` and the keys it points to are in order.
+pub open spec fn sorted_by(keys: Seq<i32>, idx: Seq<usize>) -> bool {
+    &&& forall |i: int| 0 <= i < idx.len() ==> idx[i] < keys.len()
+    &&& forall |i: int, j: int| 0 <= i <= j < idx.len() ==> keys[idx[i] as int] <= keys[idx[j] as int]
+}
+
+pub open spec fn in_range(idx: Seq<usize>, n: int) -> bool {
+    forall |i: int| 0 <= i < idx.len() ==> idx[i] < n
+}
+
+pub open spec fn indices(n: nat) -> Seq<usize> {
+    Seq::new(n, |i: int| i as usize)
+}
+
+/// `idx` holds every index of `0..n` exactly once.
+pub open spec fn is_permutation(idx: Seq<usize>, n: nat) -> bool {
+    idx.to_multiset() == indices(n).to_multiset()
+}
+
+/// Orders indices into `arr` by the keys they point to.
+struct ByKeys<'a> {
+    arr: &'a Array<i32>,
+    perms: &'a Tracked<Region<i32>>,
+}
+
+impl<'a> MergeOrder<usize> for ByKeys<'a> {
+    open spec fn inv(&self) -> bool {
+        &&& region_array::wf(*self.arr, (*self.perms)@)
+        &&& (*self.perms)@.lo() == 0
+    }
+
+    open spec fn valid(&self, x: usize) -> bool {
+        x < (*self.perms)@.hi()
+    }
+
+    open spec fn key(&self, x: usize) -> int {
+        (*self.perms)@.values()[x as int] as int
+    }
+
+    fn le(&self, x: usize, y: usize) -> (res: bool) {
+        *region_array::read(self.arr, x, Tracked(self.perms.borrow())) <= *region_array::read(self.arr, y, Tracked(self.perms.borrow()))
+    }
+}
+
+/// Ordering by `order` is ordering by the keys it points to.
+proof fn lemma_sorted_by_keys(order: ByKeys, idx: Seq<usize>)
+    requires
+        order.inv(),
+    ensures
+        (merge_sorted::all_valid(order, idx) && merge_sorted::sorted_by(order, idx))
+            == sorted_by((*order.perms)@.values(), idx),
+{
+    let keys = (*order.perms)@.values();
+    region_array::lemma_values_len(order.arr, (*order.perms)@);
+    if merge_sorted::all_valid(order, idx) && merge_sorted::sorted_by(order, idx) {
+        assert forall |i: int| 0 <= i < idx.len() implies idx[i] < keys.len() by {
+            assert(order.valid(idx[i]));
+        }
+        assert forall |i: int, j: int| 0 <= i <= j < idx.len() implies keys[idx[i] as int] <= keys[idx[j] as int] by {
+            assert(order.key(idx[i]) <= order.key(idx[j]));
+        }
+    }
+    if sorted_by(keys, idx) {
+        assert forall |i: int| 0 <= i < idx.len() implies order.valid(#[trigger] idx[i]) by {
+            assert(idx[i] < keys.len());
+        }
+        assert forall |i: int, j: int| 0 <= i <= j < idx.len() implies order.key(idx[i]) <= order.key(idx[j]) by {
+            assert(keys[idx[i] as int] <= keys[idx[j] as int]);
+        }
+    }
+}
+
+/// Indices below `n` are valid for an order over `n` keys.
+proof fn lemma_in_range_valid(order: ByKeys, idx: Seq<usize>, n: int)
+    requires
+        in_range(idx, n),
+        (*order.perms)@.hi() == n,
+    ensures
+        merge_sorted::all_valid(order, idx),
+{
+    assert forall |i: int| 0 <= i < idx.len() implies order.valid(#[trigger] idx[i]) by {
+        assert(idx[i] < n);
+    }
+}
+
+/// Returns the indices `0..n` ordered by `keys`. Equal keys keep the order of their indices.
+pub fn argsort(keys: &ArrayForSorting<i32>) -> (res: ArrayForSorting<usize>)
+    requires
+        keys.perms@.lo() == 0,
+        keys.perms@.hi() == keys.array.len(),
+        region_array::wf(*keys.array, (keys.perms@)),
+    ensures
+        region_array::wf(*res.array, (res.perms@)),
+        res.perms@.lo() == 0,
+        res.perms@.hi() == res.array.len(),
+        res.array.len() == keys.array.len(),
+        sorted_by(keys.perms@.values(), res.perms@.values()),
+        is_permutation(res.perms@.values(), keys.array.len() as nat),
+{
+    let n = (&*keys.array).length();
+    let mut res = identity(n);
+    let mut buf = zeros(n);
+    let order = ByKeys { arr: &*keys.array, perms: &keys.perms };
+    proof {
+        lemma_in_range_valid(order, indices(n as nat), n as int);
+    }
+    merge_sorted::merge_sort_by(
+        &order,
+        &res.array,
+        0,
+        n,
+        Tracked(res.perms.borrow_mut()),
+        &buf.array,
+        Tracked(buf.perms.borrow_mut()),
+    );
+    proof {
+        merge_sorted::lemma_all_valid_multiset(order, indices(n as nat), res.perms@.values());
+        lemma_sorted_by_keys(order, res.perms@.values());
+    }
+    res
+}
+
+/// Like `argsort`, but the halves are sorted in separate threads while they are longer
+/// than `threshold`. The threads only read the keys, so they share their region through an `Arc`.
+pub fn argsort_parallel(
+    keys: &mut ArrayForSorting<i32>,
+    threshold: usize,
+) -> (ret: Result<ArrayForSorting<usize>, ()>)
+    requires
+        old(keys).perms@.lo() == 0,
+        old(keys).perms@.hi() == old(keys).array.len(),
+        region_array::wf(*old(keys).array, (old(keys).perms@)),
+    ensures
+        ret.is_ok() ==> region_array::wf(*keys.array, (keys.perms@)),
+        ret.is_ok() ==> keys.perms@.lo() == old(keys).perms@.lo() && keys.perms@.hi() == old(keys).perms@.hi(),
+        ret.is_ok() ==> keys.perms@.values() == old(keys).perms@.values(),
+        ret.is_ok() ==> region_array::wf(*ret.unwrap().array, (ret.unwrap().perms@)),
+        ret.is_ok() ==> ret.unwrap().perms@.lo() == 0,
+        ret.is_ok() ==> ret.unwrap().perms@.hi() == ret.unwrap().array.len(),
+        ret.is_ok() ==> ret.unwrap().array.len() == old(keys).array.len(),
+        ret.is_ok() ==> sorted_by(keys.perms@.values(), ret.unwrap().perms@.values()),
+        ret.is_ok() ==> is_permutation(ret.unwrap().perms@.values(), old(keys).array.len() as nat),
+{
+    let n = (&*keys.array).length();
+    let mut res = identity(n);
+    let mut buf = zeros(n);
+
+    // leave an empty region in `keys` while the threads share its region
+    let shared = mergesort::share(keys);
+
+    let ret = _argsort_parallel(
+        Arc::clone(&keys.array),
+        Arc::clone(&shared),
+        n,
+        Arc::clone(&res.array),
+        0,
+        n,
+        Tracked(res.perms.borrow_mut()),
+        Arc::clone(&buf.array),
+        Tracked(buf.perms.borrow_mut()),
+        threshold,
+    );
+    // `keys` is given back its region before any error is returned
+    let keys_ret = mergesort::unshare(keys, shared);
+    if ret.is_err() || keys_ret.is_err() {
+        return Err(());
+    }
+    Ok(res)
+}
+
+fn zeros(n: usize) -> (res: ArrayForSorting<usize>)
+    ensures
+        region_array::wf(*res.array, (res.perms@)),
+        res.perms@.lo() == 0,
+        res.perms@.hi() == res.array.len(),
+        res.array.len() == n,
+{
+    let mut data: Vec<usize> = Vec::new();
+    while data.len() < n
+        invariant
+            data.len() <= n,
+    {
+        data.push(0);
+    }
+    ArrayForSorting::new(data)
+}
+
+fn identity(n: usize) -> (res: ArrayForSorting<usize>)
+    ensures
+        region_array::wf(*res.array, (res.perms@)),
+        res.perms@.lo() == 0,
+        res.perms@.hi() == res.array.len(),
+        res.array.len() == n,
+        res.perms@.values() == indices(n as nat),
+{
+    let mut res = zeros(n);
+    proof { region_array::lemma_values_len(&*res.array, res.perms@); }
+    // `new` does not tell the values, so they are written once more
+    let mut i = 0;
+    while i < n
+        invariant
+            region_array::wf(*res.array, (res.perms@)),
+            res.perms@.lo() == 0,
+            res.perms@.hi() == n,
+            res.array.len() == n,
+            res.perms@.values().len() == n,
+            i <= n,
+            forall |k: int| 0 <= k < i ==> res.perms@.values()[k] == k,
+    {
+        region_array::replace(&res.array, i, i, Tracked(res.perms.borrow_mut()));
+        i += 1;
+    }
+    assert(res.perms@.values() =~= indices(n as nat));
+    res
+}
+
+fn _argsort_parallel(
+    keys_arr: Arc<Array<i32>>,
+    shared: Arc<Tracked<Region<i32>>>,
+    n: usize,
+    arr: Arc<Array<usize>>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&mut Region<usize>>,
+    buf_arr: Arc<Array<usize>>,
+    Tracked(buf_perms): Tracked<&mut Region<usize>>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        region_array::wf(*keys_arr, (*shared)@),
+        (*shared)@.lo() == 0,
+        (*shared)@.hi() == n,
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        region_array::wf(*buf_arr, *old(buf_perms)),
+        old(buf_perms).lo() == lo,
+        old(buf_perms).hi() == hi,
+        in_range(old(perms).values(), n as int),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, *perms) && perms.lo() == lo && perms.hi() == hi,
+        ret.is_ok() ==> region_array::wf(*buf_arr, *buf_perms) && buf_perms.lo() == lo && buf_perms.hi() == hi,
+        ret.is_ok() ==> sorted_by((*shared)@.values(), perms.values()),
+        ret.is_ok() ==> perms.values().to_multiset() == old(perms).values().to_multiset(),
+{
+    let ghost keys = (*shared)@.values();
+    let ghost vals = perms.values();
+    proof {
+        region_array::lemma_values_len(&*arr, *perms);
+    }
+    let region: &Tracked<Region<i32>> = &*shared;
+    let order = ByKeys { arr: &*keys_arr, perms: region };
+    proof {
+        lemma_in_range_valid(order, vals, n as int);
+    }
+    if hi - lo <= threshold || hi - lo < 2 {
+        merge_sorted::merge_sort_by(&order, &*arr, lo, hi, Tracked(perms), &*buf_arr, Tracked(buf_perms));
+        proof {
+            merge_sorted::lemma_all_valid_multiset(order, vals, perms.values());
+            lemma_sorted_by_keys(order, perms.values());
+        }
+        return Ok(());
+    }
+
+    let mid = lo + (hi - lo) / 2;
+    let tracked left = region_array::split_front(&*arr, mid, perms);
+    let tracked mut right = region_array::split_front(&*arr, hi, perms);
+    let tracked buf_left = region_array::split_front(&*buf_arr, mid, buf_perms);
+    let tracked mut buf_right = region_array::split_front(&*buf_arr, hi, buf_perms);
+    let ghost left_vals = left.values();
+    let ghost right_vals = right.values();
+    proof {
+        assert(right_vals =~= vals.subrange(mid - lo, hi - lo));
+        assert(left_vals + right_vals =~= vals);
+        assert(in_range(left_vals, n as int));
+        assert(in_range(right_vals, n as int));
+        lemma_multiset_commutative(left_vals, right_vals);
+    }
+
+    let keys_arr_r1 = Arc::clone(&keys_arr);
+    let shared_r1 = Arc::clone(&shared);
+    let arr_r1 = Arc::clone(&arr);
+    let buf_arr_r1 = Arc::clone(&buf_arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<usize>>, Tracked<Region<usize>>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().0@) && ret.unwrap().0@.lo() == lo && ret.unwrap().0@.hi() == mid,
+            ret.is_ok() ==> region_array::wf(*buf_arr, ret.unwrap().1@) && ret.unwrap().1@.lo() == lo && ret.unwrap().1@.hi() == mid,
+            ret.is_ok() ==> sorted_by(keys, ret.unwrap().0@.values()),
+            ret.is_ok() ==> ret.unwrap().0@.values().to_multiset() == left_vals.to_multiset(),
+        {
+            let tracked mut left = left;
+            let tracked mut buf_left = buf_left;
+            let t = _argsort_parallel(keys_arr_r1, shared_r1, n, arr_r1, lo, mid, Tracked(&mut left), buf_arr_r1, Tracked(&mut buf_left), threshold);
+            if t.is_err() {
+                Err(())
+            } else {
+                Ok((Tracked(left), Tracked(buf_left)))
+            }
+        }
+    );
+
+    match _argsort_parallel(
+        Arc::clone(&keys_arr), Arc::clone(&shared), n,
+        Arc::clone(&arr), mid, hi, Tracked(&mut right),
+        Arc::clone(&buf_arr), Tracked(&mut buf_right),
+        threshold,
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut left), Tracked(mut buf_left)) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        lemma_sorted_by_keys(order, left.values());
+        lemma_sorted_by_keys(order, right.values());
+        merge_sorted::lemma_whole_subrange(&*arr, left);
+        merge_sorted::lemma_whole_subrange(&*arr, right);
+        region_array::merge(&*buf_arr, &mut buf_left, buf_right);
+    }
+    merge_sorted::merge_by(&order, &*arr, Tracked(&left), lo, mid, &*arr, Tracked(&right), mid, hi, &*buf_arr, Tracked(&mut buf_left), lo);
+    proof {
+        region_array::merge(&*arr, &mut left, right);
+    }
+    region_array::copy_region(&*buf_arr, Tracked(&buf_left), &*arr, Tracked(&mut left), lo, hi);
+    proof {
+        vstd::modes::tracked_swap(perms, &mut left);
+        vstd::modes::tracked_swap(buf_perms, &mut buf_left);
+        merge_sorted::lemma_all_valid_multiset(order, vals, perms.values());
+        lemma_sorted_by_keys(order, perms.values());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_argsort() {
+    use crate::test_data::{scattered, thresholds};
+
+    let data: Vec<i32> = scattered(1000, 211, -100);
+    let mut expected: Vec<usize> = (0..data.len()).collect();
+    expected.sort_by_key(|i| data[*i]);
+
+    let keys = ArrayForSorting::new(data.clone());
+    assert_eq!(argsort(&keys).clone_to_vec(), expected);
+
+    for threshold in thresholds(data.len()) {
+        let mut keys = ArrayForSorting::new(data.clone());
+        let idx = argsort_parallel(&mut keys, threshold).unwrap();
+        assert_eq!(idx.clone_to_vec(), expected);
+        assert_eq!(keys.clone_to_vec(), data);
+    }
+}
+
+}
@@ -0,0 +1,317 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::{self, ArrayForSorting},
+    merge_sorted::sorted,
+};
+
+/// `i` is the first position of `s` whose value is not less than `x`.
+pub open spec fn is_lower_bound(s: Seq<i32>, x: i32, i: int) -> bool {
+    &&& 0 <= i <= s.len()
+    &&& forall |j: int| 0 <= j < i ==> s[j] < x
+    &&& forall |j: int| i <= j < s.len() ==> x <= s[j]
+}
+
+/// `i` is the first position of `s` whose value is greater than `x`.
+pub open spec fn is_upper_bound(s: Seq<i32>, x: i32, i: int) -> bool {
+    &&& 0 <= i <= s.len()
+    &&& forall |j: int| 0 <= j < i ==> s[j] <= x
+    &&& forall |j: int| i <= j < s.len() ==> x < s[j]
+}
+
+/// Returns the first index of the sorted region `lo..hi` whose value is not less than `x`, or `hi`.
+pub fn lower_bound(
+    arr: &Array<i32>,
+    Tracked(perms): Tracked<&Region<i32>>,
+    lo: usize, hi: usize,
+    x: i32,
+) -> (res: usize)
+    requires
+        region_array::wf(*arr, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        sorted(perms.values()),
+    ensures
+        lo <= res <= hi,
+        is_lower_bound(perms.values(), x, res - lo),
+{
+    let ghost vals = perms.values();
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+    }
+    let (mut l, mut h) = (lo, hi);
+    while l < h
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() == lo,
+            perms.hi() == hi,
+            vals == perms.values(),
+            vals.len() == hi - lo,
+            sorted(vals),
+            lo <= l <= h <= hi,
+            forall |j: int| lo <= j < l ==> vals[j - lo] < x,
+            forall |j: int| h <= j < hi ==> x <= vals[j - lo],
+    {
+        let mid = l + (h - l) / 2;
+        if *region_array::read(arr, mid, Tracked(perms)) < x {
+            assert forall |j: int| lo <= j <= mid implies vals[j - lo] < x by {
+                assert(vals[j - lo] <= vals[mid - lo]);
+            }
+            l = mid + 1;
+        } else {
+            assert forall |j: int| mid <= j < hi implies x <= vals[j - lo] by {
+                assert(vals[mid - lo] <= vals[j - lo]);
+            }
+            h = mid;
+        }
+    }
+    assert forall |j: int| 0 <= j < l - lo implies vals[j] < x by {
+        assert(vals[(j + lo) - lo] < x);
+    }
+    assert forall |j: int| l - lo <= j < vals.len() implies x <= vals[j] by {
+        assert(x <= vals[(j + lo) - lo]);
+    }
+    l
+}
+
+/// Returns the first index of the sorted region `lo..hi` whose value is greater than `x`, or `hi`.
+pub fn upper_bound(
+    arr: &Array<i32>,
+    Tracked(perms): Tracked<&Region<i32>>,
+    lo: usize, hi: usize,
+    x: i32,
+) -> (res: usize)
+    requires
+        region_array::wf(*arr, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        sorted(perms.values()),
+    ensures
+        lo <= res <= hi,
+        is_upper_bound(perms.values(), x, res - lo),
+{
+    let ghost vals = perms.values();
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+    }
+    let (mut l, mut h) = (lo, hi);
+    while l < h
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() == lo,
+            perms.hi() == hi,
+            vals == perms.values(),
+            vals.len() == hi - lo,
+            sorted(vals),
+            lo <= l <= h <= hi,
+            forall |j: int| lo <= j < l ==> vals[j - lo] <= x,
+            forall |j: int| h <= j < hi ==> x < vals[j - lo],
+    {
+        let mid = l + (h - l) / 2;
+        if *region_array::read(arr, mid, Tracked(perms)) <= x {
+            assert forall |j: int| lo <= j <= mid implies vals[j - lo] <= x by {
+                assert(vals[j - lo] <= vals[mid - lo]);
+            }
+            l = mid + 1;
+        } else {
+            assert forall |j: int| mid <= j < hi implies x < vals[j - lo] by {
+                assert(vals[mid - lo] <= vals[j - lo]);
+            }
+            h = mid;
+        }
+    }
+    assert forall |j: int| 0 <= j < l - lo implies vals[j] <= x by {
+        assert(vals[(j + lo) - lo] <= x);
+    }
+    assert forall |j: int| l - lo <= j < vals.len() implies x < vals[j] by {
+        assert(x < vals[(j + lo) - lo]);
+    }
+    l
+}
+
+/// Returns the range of indices of the sorted region `lo..hi` whose values are equal to `x`.
+pub fn equal_range(
+    arr: &Array<i32>,
+    Tracked(perms): Tracked<&Region<i32>>,
+    lo: usize, hi: usize,
+    x: i32,
+) -> (res: (usize, usize))
+    requires
+        region_array::wf(*arr, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        sorted(perms.values()),
+    ensures
+        lo <= res.0 <= res.1 <= hi,
+        is_lower_bound(perms.values(), x, res.0 - lo),
+        is_upper_bound(perms.values(), x, res.1 - lo),
+{
+    let first = lower_bound(arr, Tracked(perms), lo, hi, x);
+    let last = upper_bound(arr, Tracked(perms), lo, hi, x);
+    proof {
+        if last < first {
+            assert(perms.values()[last - lo] < x);
+        }
+    }
+    (first, last)
+}
+
+/// Returns the `lower_bound` of every query. The queries are split in halves, which are
+/// searched in separate threads while there are more than `threshold` of them.
+/// The threads only read the array, so they share its region through an `Arc`.
+pub fn search_many(
+    arr: &mut ArrayForSorting<i32>,
+    queries: Vec<i32>,
+    threshold: usize,
+) -> (ret: Result<Vec<usize>, ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        sorted(old(arr).perms@.values()),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo() && arr.perms@.hi() == old(arr).perms@.hi(),
+        ret.is_ok() ==> arr.perms@.values() == old(arr).perms@.values(),
+        ret.is_ok() ==> ret.unwrap().len() == queries.len(),
+        ret.is_ok() ==> forall |q: int| 0 <= q < queries.len() ==>
+            is_lower_bound(arr.perms@.values(), #[trigger] queries@[q], ret.unwrap()@[q] as int),
+{
+    let n = (&*arr.array).length();
+    let q_len = queries.len();
+
+    // leave an empty region in `arr` while the threads share its region
+    let shared = mergesort::share(arr);
+    let shared_queries = Arc::new(queries);
+
+    let ret = _search_many_parallel(Arc::clone(&arr.array), Arc::clone(&shared), n, shared_queries, 0, q_len, threshold);
+    // `arr` is given back its region before any error is returned
+    let arr_ret = mergesort::unshare(arr, shared);
+    if arr_ret.is_err() {
+        return Err(());
+    }
+    ret
+}
+
+fn _search_many_parallel(
+    arr: Arc<Array<i32>>,
+    shared: Arc<Tracked<Region<i32>>>,
+    n: usize,
+    queries: Arc<Vec<i32>>,
+    q_lo: usize, q_hi: usize,
+    threshold: usize,
+) -> (ret: Result<Vec<usize>, ()>)
+    requires
+        region_array::wf(*arr, (*shared)@),
+        (*shared)@.lo() == 0,
+        (*shared)@.hi() == n,
+        sorted((*shared)@.values()),
+        q_lo <= q_hi <= (*queries).len(),
+    ensures
+        ret.is_ok() ==> ret.unwrap().len() == q_hi - q_lo,
+        ret.is_ok() ==> forall |q: int| 0 <= q < q_hi - q_lo ==>
+            is_lower_bound((*shared)@.values(), (*queries)@[q_lo + q], #[trigger] ret.unwrap()@[q] as int),
+{
+    let ghost vals = (*shared)@.values();
+    if q_hi - q_lo <= threshold || q_hi - q_lo < 2 {
+        let region: &Tracked<Region<i32>> = &*shared;
+        let mut res: Vec<usize> = Vec::with_capacity(q_hi - q_lo);
+        let mut q = q_lo;
+        while q < q_hi
+            invariant
+                region_array::wf(*arr, region@),
+                region@.lo() == 0,
+                region@.hi() == n,
+                region@.values() == vals,
+                sorted(vals),
+                q_lo <= q <= q_hi <= (*queries).len(),
+                res.len() == q - q_lo,
+                forall |p: int| 0 <= p < q - q_lo ==> is_lower_bound(vals, (*queries)@[q_lo + p], #[trigger] res@[p] as int),
+        {
+            let i = lower_bound(&*arr, Tracked(region.borrow()), 0, n, (*queries)[q]);
+            res.push(i);
+            q += 1;
+        }
+        return Ok(res);
+    }
+
+    let q_mid = q_lo + (q_hi - q_lo) / 2;
+
+    let arr_r1 = Arc::clone(&arr);
+    let shared_r1 = Arc::clone(&shared);
+    let queries_r1 = Arc::clone(&queries);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Vec<usize>, ()>)
+        ensures
+            ret.is_ok() ==> ret.unwrap().len() == q_mid - q_lo,
+            ret.is_ok() ==> forall |q: int| 0 <= q < q_mid - q_lo ==>
+                is_lower_bound(vals, (*queries)@[q_lo + q], #[trigger] ret.unwrap()@[q] as int),
+        {
+            _search_many_parallel(arr_r1, shared_r1, n, queries_r1, q_lo, q_mid, threshold)
+        }
+    );
+
+    let mut right = match _search_many_parallel(arr, shared, n, queries, q_mid, q_hi, threshold) {
+        Ok(right) => right,
+        Err(_) => {return Err(());},
+    };
+
+    let mut res = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+    let ghost left_res = res@;
+    let ghost right_res = right@;
+    res.append(&mut right);
+    assert forall |q: int| 0 <= q < q_hi - q_lo implies
+        is_lower_bound(vals, (*queries)@[q_lo + q], #[trigger] res@[q] as int) by {
+        if q >= q_mid - q_lo {
+            assert(res@[q] == right_res[q - (q_mid - q_lo)]);
+            assert(q_lo + q == q_mid + (q - (q_mid - q_lo)));
+        } else {
+            assert(res@[q] == left_res[q]);
+        }
+    }
+    Ok(res)
+}
+
+#[test]
+fn test_binary_search() {
+    use crate::test_data::{scattered, thresholds};
+
+    let data: Vec<i32> = scattered(1000, 331, -160);
+    let mut sorted_data = data.clone();
+    sorted_data.sort();
+    let mut arr = ArrayForSorting::new(data.clone());
+    let mut out = ArrayForSorting::new(vec![0; data.len()]);
+    crate::mergesort::merge_sort_parallel(&mut arr, &mut out, 64).unwrap();
+
+    let queries: Vec<i32> = (-200..200).collect();
+    let n = sorted_data.len();
+    for &x in queries.iter() {
+        let lower = sorted_data.partition_point(|&y| y < x);
+        let upper = sorted_data.partition_point(|&y| y <= x);
+        assert_eq!(lower_bound(&arr.array, Tracked(arr.perms.borrow()), 0, n, x), lower);
+        assert_eq!(upper_bound(&arr.array, Tracked(arr.perms.borrow()), 0, n, x), upper);
+        assert_eq!(equal_range(&arr.array, Tracked(arr.perms.borrow()), 0, n, x), (lower, upper));
+    }
+
+    for threshold in thresholds(queries.len()) {
+        let found = search_many(&mut arr, queries.clone(), threshold).unwrap();
+        let expected: Vec<usize> = queries.iter().map(|&x| sorted_data.partition_point(|&y| y < x)).collect();
+        assert_eq!(found, expected);
+    }
+    assert_eq!(arr.clone_to_vec(), sorted_data);
+}
+
+}
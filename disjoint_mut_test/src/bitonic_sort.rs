@@ -0,0 +1,684 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use vstd::arithmetic::div_mod::{lemma_small_mod, lemma_mod_add_multiples_vanish};
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region, Strided},
+    mergesort::ArrayForSorting,
+};
+
+/// `i` cut off at `end`.
+pub open spec fn clamp(i: int, end: usize) -> int {
+    if i < end { i } else { end as int }
+}
+
+/// A step of the network compares `lo_base + x` with its partner `hi_base + x`,
+/// or `hi_base + h - 1 - x` if the step is `mirrored`.
+/// The partners of `x_lo..x_hi` that are before `hi_end` are `partners_lo..partners_hi`.
+pub open spec fn partners_lo(hi_base: usize, hi_end: usize, h: usize, x_lo: usize, x_hi: usize, mirrored: bool) -> int {
+    if mirrored { clamp(hi_base + h - x_hi, hi_end) } else { clamp(hi_base + x_lo, hi_end) }
+}
+
+pub open spec fn partners_hi(hi_base: usize, hi_end: usize, h: usize, x_lo: usize, x_hi: usize, mirrored: bool) -> int {
+    if mirrored { clamp(hi_base + h - x_lo, hi_end) } else { clamp(hi_base + x_hi, hi_end) }
+}
+
+/// End of blocks `..b`: the last block is cut off at `n`.
+pub open spec fn block_end(n: usize, block: usize, n_blocks: usize, b: usize) -> int {
+    if b == n_blocks { n as int } else { b * block }
+}
+
+proof fn lemma_block(n: usize, block: usize, b: usize)
+    requires
+        0 < block,
+        0 < n,
+        b <= (n - 1) / block,
+    ensures
+        b * block <= n - 1,
+        (b + 1) * block == b * block + block,
+        b == (n - 1) / block ==> n <= (b + 1) * block,
+{
+    let q = (n - 1) / block;
+    vstd::arithmetic::div_mod::lemma_fundamental_div_mod((n - 1) as int, block as int);
+    vstd::arithmetic::div_mod::lemma_mod_pos_bound((n - 1) as int, block as int);
+    assert(b * block <= n - 1) by (nonlinear_arith)
+        requires b <= q, n - 1 == block * q + (n - 1) % block, 0 <= (n - 1) % block;
+    assert((b + 1) * block == b * block + block) by (nonlinear_arith);
+    assert(b == q ==> n <= (b + 1) * block) by (nonlinear_arith)
+        requires n - 1 == block * q + (n - 1) % block, (n - 1) % block < block;
+}
+
+/// Start of block `b`.
+fn block_start(n: usize, block: usize, b: usize) -> (res: usize)
+    requires
+        0 < block,
+        0 < n,
+        b <= (n - 1) / block,
+    ensures
+        res == b * block,
+        res < n,
+{
+    proof {
+        lemma_block(n, block, b);
+    }
+    b * block
+}
+
+/// `base + off` cut off at `end`.
+fn clamped(base: usize, off: usize, end: usize) -> (res: usize)
+    requires
+        base <= end,
+    ensures
+        res == clamp(base + off, end),
+{
+    if off >= end - base { end } else { base + off }
+}
+
+/// Puts the smaller of `arr[i]` and `arr[j]` at `i`.
+pub(crate) fn compare_exchange(arr: &Array<i32>, i: usize, j: usize, Tracked(perms): Tracked<&mut Region<i32>>)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() <= i < old(perms).hi(),
+        old(perms).lo() <= j < old(perms).hi(),
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == old(perms).lo(),
+        perms.hi() == old(perms).hi(),
+{
+    if *region_array::read(arr, j, Tracked(perms)) < *region_array::read(arr, i, Tracked(perms)) {
+        region_array::swap(arr, i, j, Tracked(perms));
+    }
+}
+
+/// `compare_exchange` for `i` and `j` in different regions.
+fn compare_exchange_across(
+    arr: &Array<i32>,
+    i: usize, Tracked(perms_i): Tracked<&mut Region<i32>>,
+    j: usize, Tracked(perms_j): Tracked<&mut Region<i32>>,
+)
+    requires
+        region_array::wf(*arr, *old(perms_i)),
+        old(perms_i).lo() <= i < old(perms_i).hi(),
+        region_array::wf(*arr, *old(perms_j)),
+        old(perms_j).lo() <= j < old(perms_j).hi(),
+    ensures
+        region_array::wf(*arr, *perms_i),
+        perms_i.lo() == old(perms_i).lo(),
+        perms_i.hi() == old(perms_i).hi(),
+        region_array::wf(*arr, *perms_j),
+        perms_j.lo() == old(perms_j).lo(),
+        perms_j.hi() == old(perms_j).hi(),
+{
+    let a = *region_array::read(arr, i, Tracked(perms_i));
+    let b = *region_array::read(arr, j, Tracked(perms_j));
+    if b < a {
+        region_array::replace(arr, i, b, Tracked(perms_i));
+        region_array::replace(arr, j, a, Tracked(perms_j));
+    }
+}
+
+/// `compare_exchange` for two cells of a strided region.
+fn compare_exchange_strided(arr: &Array<i32>, i: usize, j: usize, Tracked(classes): Tracked<&mut Strided<i32>>)
+    requires
+        region_array::strided_wf(*arr, *old(classes)),
+        old(classes).contains(i as int),
+        old(classes).contains(j as int),
+    ensures
+        region_array::strided_wf(*arr, *classes),
+        classes.lo() == old(classes).lo(),
+        classes.hi() == old(classes).hi(),
+        classes.stride() == old(classes).stride(),
+        classes.rem_lo() == old(classes).rem_lo(),
+        classes.rem_hi() == old(classes).rem_hi(),
+{
+    let a = *region_array::read_strided(arr, i, Tracked(classes));
+    let b = *region_array::read_strided(arr, j, Tracked(classes));
+    if b < a {
+        region_array::replace_strided(arr, i, b, Tracked(classes));
+        region_array::replace_strided(arr, j, a, Tracked(classes));
+    }
+}
+
+/// Sorts `arr` with a bitonic sorting network. For a length that is not a power of two,
+/// the network of the next power of two is used, with the missing elements taken to be
+/// larger than all others: every comparison puts the smaller element first,
+/// so the comparisons with missing elements can be skipped.
+pub fn bitonic_sort_parallel(
+    arr: &mut ArrayForSorting<i32>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo(),
+        ret.is_ok() ==> arr.perms@.hi() == old(arr).perms@.hi(),
+{
+    let n = (&*arr.array).length();
+    let mut half: usize = 1;
+    while half < n
+        invariant
+            0 < half,
+            arr.array.len() == n,
+            arr.perms@.lo() == 0,
+            arr.perms@.hi() == n,
+            region_array::wf(*arr.array, (arr.perms@)),
+    {
+        // merge the sorted halves of every block of `2 * half`
+        match step_parallel(Arc::clone(&arr.array), Tracked(arr.perms.borrow_mut()), n, half, true, threshold) {
+            Ok(()) => {},
+            Err(_) => {return Err(());},
+        };
+        let mut h = half / 2;
+        while h > 0
+            invariant
+                h < n,
+                arr.array.len() == n,
+                arr.perms@.lo() == 0,
+                arr.perms@.hi() == n,
+                region_array::wf(*arr.array, (arr.perms@)),
+        {
+            match step_parallel(Arc::clone(&arr.array), Tracked(arr.perms.borrow_mut()), n, h, false, threshold) {
+                Ok(()) => {},
+                Err(_) => {return Err(());},
+            };
+            h = h / 2;
+        }
+        if half > n / 2 {
+            break;
+        }
+        half = half * 2;
+    }
+    Ok(())
+}
+
+/// One step of the network: for every block `b..b + 2 * h`, compares `b + x`
+/// with its partner for every `x < h`.
+fn step_parallel(
+    arr: Arc<Array<i32>>,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    n: usize,
+    h: usize,
+    mirrored: bool,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        0 < h < n,
+        n <= arr.len(),
+        old(perms).lo() == 0,
+        old(perms).hi() == n,
+        region_array::wf(*arr, (*old(perms))),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, (*perms)),
+        ret.is_ok() ==> old(perms).lo() == perms.lo() && old(perms).hi() == perms.hi(),
+{
+    if !mirrored && n / h <= threshold {
+        // few blocks: the step is split by residues instead, so the threads are not limited to one block each
+        return step_strided_parallel(arr, Tracked(perms), n, h, threshold);
+    }
+    if n - h <= h {
+        // a single block, cut off at `n`
+        let tracked mut highs = region_array::split_off(&*arr, h, perms);
+        match _half_parallel(Arc::clone(&arr), Tracked(perms), Tracked(&mut highs), 0, h, n, h, 0, h, mirrored, threshold) {
+            Ok(()) => {},
+            Err(_) => {return Err(());},
+        };
+        proof {
+            region_array::merge(&*arr, perms, highs);
+        }
+        return Ok(());
+    }
+    let block = h * 2;
+    let n_blocks = (n - 1) / block + 1;
+    _blocks_parallel(arr, Tracked(perms), n, h, block, n_blocks, 0, n_blocks, mirrored, threshold)
+}
+
+/// A step that is not mirrored compares `i` only with `i + h`, which has the same residue `i % h`.
+/// The step is split between the threads by residues, so each one owns every `h`-th cell of a
+/// few residues instead of a range, and the ownership is handed out anew for every `h`.
+fn step_strided_parallel(
+    arr: Arc<Array<i32>>,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    n: usize,
+    h: usize,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        0 < h < n,
+        n <= arr.len(),
+        old(perms).lo() == 0,
+        old(perms).hi() == n,
+        region_array::wf(*arr, (*old(perms))),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, (*perms)),
+        ret.is_ok() ==> old(perms).lo() == perms.lo() && old(perms).hi() == perms.hi(),
+{
+    let tracked region = region_array::split_front(&*arr, n, perms);
+    let tracked mut classes = region_array::into_strided(&*arr, region, h);
+    match _residues_parallel(Arc::clone(&arr), Tracked(&mut classes), n, h, 0, h, threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+    let tracked mut whole = region_array::from_strided(&*arr, classes);
+    proof {
+        vstd::modes::tracked_swap(perms, &mut whole);
+        region_array::merge(&*arr, perms, whole);
+    }
+    Ok(())
+}
+
+/// Compares `i` with `i + h` for every `i` with `i % (2 * h) < h` whose residue `i % h`
+/// is in `r_lo..r_hi`, splitting the residues between threads.
+fn _residues_parallel(
+    arr: Arc<Array<i32>>,
+    Tracked(classes): Tracked<&mut Strided<i32>>,
+    n: usize,
+    h: usize,
+    r_lo: usize, r_hi: usize,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        0 < h < n <= arr.len(),
+        r_lo < r_hi <= h,
+        region_array::strided_wf(*arr, *old(classes)),
+        old(classes).lo() == 0,
+        old(classes).hi() == n,
+        old(classes).stride() == h,
+        old(classes).rem_lo() == r_lo,
+        old(classes).rem_hi() == r_hi,
+    ensures
+        ret.is_ok() ==> region_array::strided_wf(*arr, *classes),
+        ret.is_ok() ==> classes.lo() == 0 && classes.hi() == n && classes.stride() == h,
+        ret.is_ok() ==> classes.rem_lo() == r_lo && classes.rem_hi() == r_hi,
+{
+    proof {
+        vstd::arithmetic::div_mod::lemma_fundamental_div_mod(n as int, h as int);
+        vstd::arithmetic::div_mod::lemma_mod_pos_bound(n as int, h as int);
+    }
+    assert(n / h > 0) by (nonlinear_arith)
+        requires 0 < h < n, n == h * (n / h) + n % h, n % h < h;
+    // every residue has about `n / h` cells
+    if r_hi - r_lo == 1 || r_hi - r_lo <= threshold / (n / h) {
+        let mut r = r_lo;
+        while r < r_hi
+            invariant
+                0 < h < n <= arr.len(),
+                r_lo <= r <= r_hi <= h,
+                region_array::strided_wf(*arr, *classes),
+                classes.lo() == 0,
+                classes.hi() == n,
+                classes.stride() == h,
+                classes.rem_lo() == r_lo,
+                classes.rem_hi() == r_hi,
+        {
+            proof {
+                lemma_small_mod(r as nat, h as nat);
+            }
+            let mut i = r;
+            while n - i > h
+                invariant
+                    0 < h < n <= arr.len(),
+                    i < n,
+                    i % h == r,
+                    r_lo <= r < r_hi <= h,
+                    region_array::strided_wf(*arr, *classes),
+                    classes.lo() == 0,
+                    classes.hi() == n,
+                    classes.stride() == h,
+                    classes.rem_lo() == r_lo,
+                    classes.rem_hi() == r_hi,
+            {
+                proof {
+                    lemma_mod_add_multiples_vanish(i as int, h as int);
+                }
+                compare_exchange_strided(&*arr, i, i + h, Tracked(classes));
+                if n - (i + h) <= h {
+                    break;
+                }
+                proof {
+                    lemma_mod_add_multiples_vanish((i + h) as int, h as int);
+                }
+                i = i + h + h;
+            }
+            r += 1;
+        }
+        return Ok(());
+    }
+
+    let r_mid = r_lo + (r_hi - r_lo) / 2;
+    let tracked right_classes = region_array::split_strided(&*arr, r_mid, classes);
+    let tracked left_classes = region_array::split_strided(&*arr, r_lo, classes);
+
+    let arr_r1 = Arc::clone(&arr);
+    let arr_r2 = Arc::clone(&arr);
+
+    let left_classes = vstd::thread::spawn(move || -> (ret: Result<Tracked<Strided<i32>>, ()>)
+        ensures
+            ret.is_ok() ==> region_array::strided_wf(*arr, ret.unwrap()@),
+            ret.is_ok() ==> ret.unwrap()@.lo() == 0 && ret.unwrap()@.hi() == n && ret.unwrap()@.stride() == h,
+            ret.is_ok() ==> ret.unwrap()@.rem_lo() == r_lo && ret.unwrap()@.rem_hi() == r_mid,
+        {
+            let tracked mut left_classes = left_classes;
+            let t = _residues_parallel(arr_r1, Tracked(&mut left_classes), n, h, r_lo, r_mid, threshold);
+            if t.is_err() {
+                Err(())
+            } else {
+                Ok(Tracked(left_classes))
+            }
+        }
+    );
+
+    match _residues_parallel(arr_r2, Tracked(&mut right_classes), n, h, r_mid, r_hi, threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut left_classes) = match left_classes.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::merge_strided(&*arr, &mut left_classes, right_classes);
+        region_array::merge_strided(&*arr, classes, left_classes);
+    }
+    Ok(())
+}
+
+/// Runs a step of the network on blocks `b_lo..b_hi`. Every thread gets whole blocks,
+/// unless there is just one block left, which is then split by `_half_parallel`.
+fn _blocks_parallel(
+    arr: Arc<Array<i32>>,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    n: usize,
+    h: usize,
+    block: usize,
+    n_blocks: usize,
+    b_lo: usize, b_hi: usize,
+    mirrored: bool,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        0 < h,
+        block == 2 * h,
+        block < n <= arr.len(),
+        n_blocks == (n - 1) / block + 1,
+        b_lo < b_hi <= n_blocks,
+        old(perms).lo() == b_lo * block,
+        old(perms).hi() == block_end(n, block, n_blocks, b_hi),
+        region_array::wf(*arr, (*old(perms))),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, (*perms)),
+        ret.is_ok() ==> old(perms).lo() == perms.lo() && old(perms).hi() == perms.hi(),
+{
+    let lo = block_start(n, block, b_lo);
+    let hi = if b_hi == n_blocks { n } else { block_start(n, block, b_hi) };
+    proof {
+        vstd::arithmetic::mul::lemma_mul_strict_inequality(b_lo as int, b_hi as int, block as int);
+    }
+    if b_hi - b_lo == 1 {
+        if n - lo <= h {
+            // the block has no partners
+            return Ok(());
+        }
+        proof {
+            lemma_block(n, block, b_lo);
+        }
+        let tracked mut highs = region_array::split_off(&*arr, lo + h, perms);
+        match _half_parallel(Arc::clone(&arr), Tracked(perms), Tracked(&mut highs), lo, lo + h, hi, h, 0, h, mirrored, threshold) {
+            Ok(()) => {},
+            Err(_) => {return Err(());},
+        };
+        proof {
+            region_array::merge(&*arr, perms, highs);
+        }
+        return Ok(());
+    }
+    if hi - lo <= threshold {
+        step_blocks(&*arr, Tracked(perms), lo, hi, h, mirrored);
+        return Ok(());
+    }
+
+    let b_mid = b_lo + (b_hi - b_lo) / 2;
+    let mid = block_start(n, block, b_mid);
+    proof {
+        vstd::arithmetic::mul::lemma_mul_strict_inequality(b_lo as int, b_mid as int, block as int);
+        vstd::arithmetic::mul::lemma_mul_strict_inequality(b_mid as int, b_hi as int, block as int);
+    }
+
+    let tracked right_perms = region_array::split_off(&*arr, mid, perms);
+    let tracked left_perms = region_array::split_off(&*arr, lo, perms);
+
+    let arr_r1 = Arc::clone(&arr);
+    let arr_r2 = Arc::clone(&arr);
+
+    let left_perms = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<i32>>, ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap()@) && ret.unwrap()@.lo() == lo && ret.unwrap()@.hi() == mid,
+        {
+            let tracked mut left_perms = left_perms;
+            let t = _blocks_parallel(arr_r1, Tracked(&mut left_perms), n, h, block, n_blocks, b_lo, b_mid, mirrored, threshold);
+            if t.is_err() {
+                Err(())
+            } else {
+                Ok(Tracked(left_perms))
+            }
+        }
+    );
+
+    match _blocks_parallel(arr_r2, Tracked(&mut right_perms), n, h, block, n_blocks, b_mid, b_hi, mirrored, threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut left_perms) = match left_perms.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::merge(&*arr, &mut left_perms, right_perms);
+        region_array::merge(&*arr, perms, left_perms);
+    }
+    Ok(())
+}
+
+/// Sequential `_blocks_parallel` on `lo..hi`, which starts at a block boundary
+/// and ends at a block boundary or at the end of the array.
+fn step_blocks(
+    arr: &Array<i32>,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    lo: usize, hi: usize,
+    h: usize,
+    mirrored: bool,
+)
+    requires
+        0 < h,
+        lo <= hi,
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        region_array::wf(*arr, (*old(perms))),
+    ensures
+        region_array::wf(*arr, (*perms)),
+        perms.lo() == old(perms).lo(),
+        perms.hi() == old(perms).hi(),
+{
+    let mut b = lo;
+    while hi - b > h
+        invariant
+            0 < h,
+            lo <= b <= hi,
+            perms.lo() == lo,
+            perms.hi() == hi,
+            region_array::wf(*arr, (*perms)),
+    {
+        let hi_base = b + h;
+        let hi_end = clamped(hi_base, h, hi);
+        let mut x: usize = 0;
+        while x < h
+            invariant
+                lo <= b,
+                hi_base == b + h,
+                hi_base < hi_end <= hi,
+                x <= h,
+                perms.lo() == lo,
+                perms.hi() == hi,
+                region_array::wf(*arr, (*perms)),
+        {
+            let high = if mirrored { clamped(hi_base, h - 1 - x, hi_end) } else { clamped(hi_base, x, hi_end) };
+            if high < hi_end {
+                compare_exchange(arr, b + x, high, Tracked(perms));
+            }
+            x += 1;
+        }
+        b = hi_end;
+    }
+}
+
+/// Compares `lo_base + x` with its partner for every `x` in `x_lo..x_hi` whose partner
+/// is before `hi_end`. A thread owns two regions, its lows `lo_base + x_lo..lo_base + x_hi`
+/// and their partners, which are at the other end of the block if the step is `mirrored`.
+fn _half_parallel(
+    arr: Arc<Array<i32>>,
+    Tracked(lows): Tracked<&mut Region<i32>>,
+    Tracked(highs): Tracked<&mut Region<i32>>,
+    lo_base: usize, hi_base: usize, hi_end: usize,
+    h: usize,
+    x_lo: usize, x_hi: usize,
+    mirrored: bool,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        x_lo < x_hi <= h,
+        lo_base + h <= hi_base <= hi_end <= arr.len(),
+        old(lows).lo() == lo_base + x_lo,
+        old(lows).hi() == lo_base + x_hi,
+        region_array::wf(*arr, (*old(lows))),
+        old(highs).lo() == partners_lo(hi_base, hi_end, h, x_lo, x_hi, mirrored),
+        old(highs).hi() == partners_hi(hi_base, hi_end, h, x_lo, x_hi, mirrored),
+        region_array::wf(*arr, (*old(highs))),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, (*lows)),
+        ret.is_ok() ==> old(lows).lo() == lows.lo() && old(lows).hi() == lows.hi(),
+        ret.is_ok() ==> region_array::wf(*arr, (*highs)),
+        ret.is_ok() ==> old(highs).lo() == highs.lo() && old(highs).hi() == highs.hi(),
+{
+    if x_hi - x_lo <= threshold || x_hi - x_lo == 1 {
+        let mut x = x_lo;
+        while x < x_hi
+            invariant
+                x_lo <= x <= x_hi <= h,
+                lo_base + h <= hi_base <= hi_end,
+                lows.lo() == lo_base + x_lo,
+                lows.hi() == lo_base + x_hi,
+                region_array::wf(*arr, (*lows)),
+                highs.lo() == partners_lo(hi_base, hi_end, h, x_lo, x_hi, mirrored),
+                highs.hi() == partners_hi(hi_base, hi_end, h, x_lo, x_hi, mirrored),
+                region_array::wf(*arr, (*highs)),
+        {
+            let high = if mirrored { clamped(hi_base, h - 1 - x, hi_end) } else { clamped(hi_base, x, hi_end) };
+            if high < hi_end {
+                compare_exchange_across(&*arr, lo_base + x, Tracked(lows), high, Tracked(highs));
+            }
+            x += 1;
+        }
+        return Ok(());
+    }
+
+    let x_mid = x_lo + (x_hi - x_lo) / 2;
+    let tracked right_lows = region_array::split_off(&*arr, lo_base + x_mid, lows);
+    let tracked left_lows = region_array::split_off(&*arr, lo_base + x_lo, lows);
+
+    let m = if mirrored { clamped(hi_base, h - x_mid, hi_end) } else { clamped(hi_base, x_mid, hi_end) };
+    let end = if mirrored { clamped(hi_base, h - x_lo, hi_end) } else { clamped(hi_base, x_hi, hi_end) };
+    let tracked mut left_highs = region_array::split_front(&*arr, m, highs);
+    let tracked mut right_highs = region_array::split_front(&*arr, end, highs);
+    if mirrored {
+        // the partners of `x_lo..x_mid` are the upper part
+        proof {
+            vstd::modes::tracked_swap(&mut left_highs, &mut right_highs);
+        }
+    }
+
+    let arr_r1 = Arc::clone(&arr);
+    let arr_r2 = Arc::clone(&arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<i32>>, Tracked<Region<i32>>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().0@)
+                && ret.unwrap().0@.lo() == lo_base + x_lo && ret.unwrap().0@.hi() == lo_base + x_mid,
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().1@)
+                && ret.unwrap().1@.lo() == partners_lo(hi_base, hi_end, h, x_lo, x_mid, mirrored)
+                && ret.unwrap().1@.hi() == partners_hi(hi_base, hi_end, h, x_lo, x_mid, mirrored),
+        {
+            let tracked mut left_lows = left_lows;
+            let tracked mut left_highs = left_highs;
+            let t = _half_parallel(arr_r1, Tracked(&mut left_lows), Tracked(&mut left_highs), lo_base, hi_base, hi_end, h, x_lo, x_mid, mirrored, threshold);
+            if t.is_err() {
+                Err(())
+            } else {
+                Ok((Tracked(left_lows), Tracked(left_highs)))
+            }
+        }
+    );
+
+    match _half_parallel(arr_r2, Tracked(&mut right_lows), Tracked(&mut right_highs), lo_base, hi_base, hi_end, h, x_mid, x_hi, mirrored, threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut left_lows), Tracked(mut left_highs)) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::merge(&*arr, &mut left_lows, right_lows);
+        region_array::merge(&*arr, lows, left_lows);
+    }
+    if mirrored {
+        proof {
+            vstd::modes::tracked_swap(&mut left_highs, &mut right_highs);
+        }
+    }
+    proof {
+        region_array::merge(&*arr, &mut left_highs, right_highs);
+        vstd::modes::tracked_swap(highs, &mut left_highs);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bitonic_sort_parallel() {
+    use crate::test_data::scattered;
+
+    for n in [0, 1, 2, 3, 7, 64, 1000] {
+        let data: Vec<i32> = scattered(n, 1009, -500);
+        let mut expected = data.clone();
+        expected.sort();
+        let mut arr = ArrayForSorting::new(data);
+        bitonic_sort_parallel(&mut arr, 16).unwrap();
+        assert_eq!(arr.clone_to_vec(), expected);
+    }
+}
+
+}
@@ -0,0 +1,784 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use vstd::arithmetic::div_mod::{
+    lemma_fundamental_div_mod, lemma_fundamental_div_mod_converse, lemma_mod_pos_bound, lemma_div_multiples_vanish,
+};
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::{ArrayForSorting, is_full, share, unshare},
+    filter::decides,
+};
+
+/// The bit `b` of the word `w`.
+pub open spec fn get_bit(w: u64, b: u64) -> bool {
+    (w >> b) & 1u64 == 1u64
+}
+
+/// The bit `i` of the array, which is the bit `i % 64` of the word `i / 64`, read from `region`.
+pub open spec fn bit(region: Region<u64>, i: int) -> bool {
+    get_bit(region.values()[i / 64 - region.lo()], (i % 64) as u64)
+}
+
+/// The bit `i` lies in one of the words of `region`.
+pub open spec fn covers(region: Region<u64>, i: int) -> bool {
+    region.lo() * 64 <= i < region.hi() * 64
+}
+
+/// The number of bits of `lo..hi` that are set.
+pub open spec fn count(region: Region<u64>, lo: int, hi: int) -> int
+    decreases hi - lo,
+{
+    if hi <= lo {
+        0
+    } else {
+        count(region, lo, hi - 1) + if bit(region, hi - 1) { 1int } else { 0int }
+    }
+}
+
+/// The number of the bits `0..e` of `w` that are set.
+pub open spec fn ones(w: u64, e: int) -> int
+    decreases e,
+{
+    if e <= 0 {
+        0
+    } else {
+        ones(w, e - 1) + if get_bit(w, (e - 1) as u64) { 1int } else { 0int }
+    }
+}
+
+proof fn lemma_word(i: int, lo: int, hi: int)
+    requires
+        0 <= lo * 64 <= i < hi * 64,
+    ensures
+        lo <= i / 64 < hi,
+        0 <= i % 64 < 64,
+        i == 64 * (i / 64) + i % 64,
+{
+    lemma_fundamental_div_mod(i, 64);
+    lemma_mod_pos_bound(i, 64);
+}
+
+proof fn lemma_zero_bit(c: u64)
+    requires
+        c < 64,
+    ensures
+        !get_bit(0u64, c),
+{
+    assert((0u64 >> c) & 1u64 != 1u64) by (bit_vector)
+        requires c < 64u64;
+}
+
+proof fn lemma_set_bit(w: u64, b: u64, c: u64)
+    requires
+        b < 64,
+        c < 64,
+    ensures
+        get_bit(w | (1u64 << b), c) == (c == b || get_bit(w, c)),
+{
+    assert(((w | (1u64 << b)) >> c) & 1u64 == 1u64 <==> (c == b || (w >> c) & 1u64 == 1u64)) by (bit_vector)
+        requires b < 64u64, c < 64u64;
+}
+
+proof fn lemma_or_bit(a: u64, b: u64, c: u64)
+    requires
+        c < 64,
+    ensures
+        get_bit(a | b, c) == (get_bit(a, c) || get_bit(b, c)),
+{
+    assert(((a | b) >> c) & 1u64 == 1u64 <==> ((a >> c) & 1u64 == 1u64 || (b >> c) & 1u64 == 1u64)) by (bit_vector)
+        requires c < 64u64;
+}
+
+/// The bit `c` of the bits `b..b + k` of `w`, shifted down and masked.
+proof fn lemma_mask_bit(w: u64, b: u64, k: u64, c: u64)
+    requires
+        b + k <= 64,
+        0 < k <= 64,
+        c < 64,
+    ensures
+        get_bit((w >> b) & (u64::MAX >> (64 - k) as u64), c) == (c < k && get_bit(w, (b + c) as u64)),
+{
+    assert((((w >> b) & (u64::MAX >> (64u64 - k))) >> c) & 1u64 == 1u64 <==> (c < k && (w >> (b + c)) & 1u64 == 1u64)) by (bit_vector)
+        requires b + k <= 64u64, 0u64 < k, k <= 64u64, c < 64u64;
+}
+
+proof fn lemma_count_split(region: Region<u64>, lo: int, m: int, hi: int)
+    requires
+        lo <= m <= hi,
+    ensures
+        count(region, lo, hi) == count(region, lo, m) + count(region, m, hi),
+    decreases hi - m,
+{
+    if hi > m {
+        lemma_count_split(region, lo, m, hi - 1);
+    }
+}
+
+proof fn lemma_ones_bound(w: u64, e: int)
+    requires
+        0 <= e,
+    ensures
+        0 <= ones(w, e) <= e,
+    decreases e,
+{
+    if e > 0 {
+        lemma_ones_bound(w, e - 1);
+    }
+}
+
+/// The bits `e..` of `w` are not set, so counting up to `e` counts them all.
+proof fn lemma_ones_high(w: u64, k: int, e: int)
+    requires
+        0 <= k <= e <= 64,
+        forall |c: u64| k <= c < 64 ==> !#[trigger] get_bit(w, c),
+    ensures
+        ones(w, e) == ones(w, k),
+    decreases e - k,
+{
+    if e > k {
+        lemma_ones_high(w, k, e - 1);
+        assert(!get_bit(w, (e - 1) as u64));
+    }
+}
+
+/// Dropping the lowest bit of `x` shifts the others down.
+proof fn lemma_ones_half(x: u64, e: int)
+    requires
+        1 <= e <= 64,
+    ensures
+        ones(x, e) == (if get_bit(x, 0) { 1int } else { 0int }) + ones(x / 2, e - 1),
+    decreases e,
+{
+    if e == 1 {
+        assert(ones(x, 0) == 0);
+        assert(ones(x / 2, 0) == 0);
+    } else {
+        lemma_ones_half(x, e - 1);
+        let c = (e - 2) as u64;
+        assert(((x / 2) >> c) & 1u64 == 1u64 <==> (x >> (c + 1)) & 1u64 == 1u64) by (bit_vector)
+            requires c < 63u64;
+        assert((c + 1) as u64 == (e - 1) as u64);
+    }
+}
+
+/// `count_ones` counts the bits `0..64`.
+proof fn lemma_count_ones(x: u64)
+    ensures
+        x.count_ones() == ones(x, 64),
+    decreases x,
+{
+    if x == 0 {
+        assert forall |c: u64| 0 <= c < 64 implies !#[trigger] get_bit(0u64, c) by {
+            lemma_zero_bit(c);
+        }
+        lemma_ones_high(0u64, 0, 64);
+    } else {
+        lemma_count_ones(x / 2);
+        lemma_ones_half(x, 64);
+        lemma_ones_bound(x / 2, 64);
+        assert(((x / 2) >> 63u64) & 1u64 != 1u64) by (bit_vector);
+        assert(((x >> 0u64) & 1u64 == 1u64) == (x & 1u64 != 0u64)) by (bit_vector);
+    }
+}
+
+/// The bits `i..i + t` of the array are the bits `0..t` of `part`, the word of `i` shifted
+/// down to `i` and masked to `k` bits.
+proof fn lemma_count_word(region: Region<u64>, i: int, t: int, k: int, w: u64, part: u64)
+    requires
+        region.lo() * 64 <= i,
+        0 <= t <= k,
+        0 < k,
+        i % 64 + k <= 64,
+        w == region.values()[i / 64 - region.lo()],
+        part == (w >> (i % 64) as u64) & (u64::MAX >> (64 - k) as u64),
+    ensures
+        count(region, i, i + t) == ones(part, t),
+    decreases t,
+{
+    if t > 0 {
+        lemma_count_word(region, i, t - 1, k, w, part);
+        let j = i + t - 1;
+        lemma_fundamental_div_mod(i, 64);
+        lemma_mod_pos_bound(i, 64);
+        lemma_fundamental_div_mod_converse(j, 64, i / 64, i % 64 + t - 1);
+        lemma_mask_bit(w, (i % 64) as u64, k as u64, (t - 1) as u64);
+    }
+}
+
+/// Splits `region` at the bit `i`, rounded down to the start of its word, so that every word
+/// has a single owner. Returns the words before the split and keeps the others in `region`.
+pub fn split_bits(aself: &Array<u64>, i: usize, Tracked(region): Tracked<&mut Region<u64>>) -> (res: Tracked<Region<u64>>)
+    requires
+        region_array::wf(*aself, *old(region)),
+        old(region).lo() * 64 <= i <= old(region).hi() * 64,
+    ensures
+        region_array::wf(*aself, *region),
+        region_array::wf(*aself, res@),
+        res@.lo() == old(region).lo(),
+        res@.hi() == region.lo(),
+        region.lo() == i / 64,
+        region.hi() == old(region).hi(),
+        forall |j: int| covers(res@, j) ==> bit(res@, j) == bit(*old(region), j),
+        forall |j: int| covers(*region, j) ==> bit(*region, j) == bit(*old(region), j),
+{
+    let w = i / 64;
+    proof {
+        lemma_fundamental_div_mod(i as int, 64);
+        lemma_mod_pos_bound(i as int, 64);
+        let (lo, hi, q, r) = (region.lo() as int, region.hi() as int, i as int / 64, i as int % 64);
+        assert(lo <= q <= hi) by (nonlinear_arith)
+            requires i == 64 * q + r, 0 <= r < 64, lo * 64 <= i <= hi * 64;
+        region_array::lemma_values_len(aself, *region);
+    }
+    let tracked left = region_array::split_front(aself, w, region);
+    proof {
+        assert forall |j: int| covers(left, j) implies bit(left, j) == bit(*old(region), j) by {
+            lemma_word(j, left.lo() as int, left.hi() as int);
+        }
+        assert forall |j: int| covers(*region, j) implies bit(*region, j) == bit(*old(region), j) by {
+            lemma_word(j, region.lo() as int, region.hi() as int);
+        }
+    }
+    Tracked(left)
+}
+
+/// Sets the bit `i`, which must lie in the words of `region`.
+pub fn set(aself: &Array<u64>, i: usize, Tracked(region): Tracked<&mut Region<u64>>)
+    requires
+        region_array::wf(*aself, *old(region)),
+        covers(*old(region), i as int),
+    ensures
+        region_array::wf(*aself, *region),
+        region.lo() == old(region).lo(),
+        region.hi() == old(region).hi(),
+        forall |j: int| covers(*region, j) ==> bit(*region, j) == (j == i || bit(*old(region), j)),
+{
+    proof {
+        lemma_word(i as int, region.lo() as int, region.hi() as int);
+        region_array::lemma_values_len(aself, *region);
+    }
+    let b = (i % 64) as u64;
+    let w = *region_array::read(aself, i / 64, Tracked(region));
+    region_array::replace(aself, i / 64, w | (1u64 << b), Tracked(region));
+    proof {
+        assert forall |j: int| covers(*region, j) implies bit(*region, j) == (j == i || bit(*old(region), j)) by {
+            lemma_word(j, region.lo() as int, region.hi() as int);
+            if j / 64 == i / 64 {
+                lemma_set_bit(w, b, (j % 64) as u64);
+            }
+        }
+    }
+}
+
+/// Returns the bit `i`, which must lie in the words of `region`.
+pub fn test(aself: &Array<u64>, i: usize, Tracked(region): Tracked<&Region<u64>>) -> (res: bool)
+    requires
+        region_array::wf(*aself, *region),
+        covers(*region, i as int),
+    ensures
+        res == bit(*region, i as int),
+{
+    proof {
+        lemma_word(i as int, region.lo() as int, region.hi() as int);
+    }
+    let w = *region_array::read(aself, i / 64, Tracked(region));
+    (w >> ((i % 64) as u64)) & 1u64 == 1u64
+}
+
+/// Returns the number of bits of `lo..hi` that are set. The bits of every word are counted at
+/// once, the words at either end masked down to the bits of `lo..hi`.
+pub fn count_ones(aself: &Array<u64>, lo: usize, hi: usize, Tracked(region): Tracked<&Region<u64>>) -> (res: usize)
+    requires
+        region_array::wf(*aself, *region),
+        region.lo() * 64 <= lo <= hi <= region.hi() * 64,
+    ensures
+        res == count(*region, lo as int, hi as int),
+{
+    let mut res: usize = 0;
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*aself, *region),
+            region.lo() * 64 <= lo <= i <= hi <= region.hi() * 64,
+            res == count(*region, lo as int, i as int),
+            res <= i - lo,
+    {
+        proof {
+            lemma_word(i as int, region.lo() as int, region.hi() as int);
+        }
+        let b = i % 64;
+        let k = if hi - i < 64 - b { hi - i } else { 64 - b };
+        let w = *region_array::read(aself, i / 64, Tracked(region));
+        let part = (w >> (b as u64)) & (u64::MAX >> ((64 - k) as u64));
+        proof {
+            lemma_count_word(*region, i as int, k as int, k as int, w, part);
+            assert forall |c: u64| k <= c < 64 implies !#[trigger] get_bit(part, c) by {
+                lemma_mask_bit(w, b as u64, k as u64, c);
+            }
+            lemma_ones_high(part, k as int, 64);
+            lemma_ones_bound(part, k as int);
+            lemma_count_ones(part);
+            lemma_count_split(*region, lo as int, i as int, i + k);
+        }
+        res += part.count_ones() as usize;
+        i += k;
+    }
+    res
+}
+
+/// Sets the bits `j` of `lo..hi` for which `pred(j)` holds. The bits are split in halves at the
+/// start of a word, with `split_bits`, which are set in separate threads while there are more
+/// than `threshold` of them.
+fn _set_parallel<F: Fn(usize) -> bool + Copy + Send + 'static>(
+    arr: Arc<Array<u64>>,
+    Tracked(region): Tracked<&mut Region<u64>>,
+    lo: usize, hi: usize,
+    pred: F,
+    Ghost(p): Ghost<spec_fn(usize) -> bool>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        region_array::wf(*arr, *old(region)),
+        old(region).lo() * 64 <= lo <= hi <= old(region).hi() * 64,
+        decides(pred, p),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, *region),
+        ret.is_ok() ==> region.lo() == old(region).lo() && region.hi() == old(region).hi(),
+        ret.is_ok() ==> forall |j: int| covers(*region, j) ==>
+            bit(*region, j) == (bit(*old(region), j) || (lo <= j < hi && p(j as usize))),
+{
+    if hi - lo <= threshold || hi - lo < 128 {
+        let mut i = lo;
+        while i < hi
+            invariant
+                region_array::wf(*arr, *region),
+                region.lo() == old(region).lo() && region.hi() == old(region).hi(),
+                old(region).lo() * 64 <= lo <= i <= hi <= old(region).hi() * 64,
+                decides(pred, p),
+                forall |j: int| covers(*region, j) ==>
+                    bit(*region, j) == (bit(*old(region), j) || (lo <= j < i && p(j as usize))),
+        {
+            if pred(i) {
+                set(&*arr, i, Tracked(region));
+            }
+            i += 1;
+        }
+        return Ok(());
+    }
+
+    // the halves meet at the start of a word, so that every word has a single owner
+    let q = (lo + (hi - lo) / 2) / 64;
+    let m = q * 64;
+    proof {
+        lemma_fundamental_div_mod((lo + (hi - lo) / 2) as int, 64);
+        lemma_mod_pos_bound((lo + (hi - lo) / 2) as int, 64);
+        lemma_div_multiples_vanish(q as int, 64);
+        region_array::lemma_values_len(&*arr, *region);
+    }
+    let Tracked(left_region) = split_bits(&*arr, m, Tracked(region));
+    let ghost left_old = left_region;
+    let ghost right_old = *region;
+
+    let arr_r1 = Arc::clone(&arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<u64>>, ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap()@),
+            ret.is_ok() ==> ret.unwrap()@.lo() == left_old.lo() && ret.unwrap()@.hi() == left_old.hi(),
+            ret.is_ok() ==> forall |j: int| covers(ret.unwrap()@, j) ==>
+                bit(ret.unwrap()@, j) == (bit(left_old, j) || (lo <= j < m && p(j as usize))),
+        {
+            let tracked mut left_region = left_region;
+            match _set_parallel(arr_r1, Tracked(&mut left_region), lo, m, pred, Ghost(p), threshold) {
+                Ok(()) => Ok(Tracked(left_region)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match _set_parallel(Arc::clone(&arr), Tracked(region), m, hi, pred, Ghost(p), threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut left_region) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::lemma_values_len(&*arr, left_region);
+        region_array::lemma_values_len(&*arr, *region);
+        let l = left_region;
+        let r = *region;
+        vstd::modes::tracked_swap(region, &mut left_region);
+        region_array::merge(&*arr, region, left_region);
+        assert forall |j: int| covers(*region, j) implies
+            bit(*region, j) == (bit(*old(region), j) || (lo <= j < hi && p(j as usize))) by {
+            lemma_word(j, region.lo() as int, region.hi() as int);
+            if j < m {
+                lemma_word(j, l.lo() as int, l.hi() as int);
+                assert(region.values()[j / 64 - region.lo()] == l.values()[j / 64 - l.lo()]);
+            } else {
+                lemma_word(j, r.lo() as int, r.hi() as int);
+                assert(region.values()[j / 64 - region.lo()] == r.values()[j / 64 - r.lo()]);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A set of the numbers `0..len`, stored as the bits of an array of words.
+/// The permissions are per word, so threads that own different words can set bits without atomics.
+pub struct Bitset {
+    pub len: usize,
+    pub words: ArrayForSorting<u64>,
+}
+
+impl Bitset {
+    pub open spec fn wf(&self) -> bool {
+        &&& is_full(self.words)
+        &&& self.len <= self.words.array.len() * 64
+    }
+
+    /// The bits of `0..len`.
+    pub open spec fn bits(&self) -> Seq<bool> {
+        Seq::new(self.len as nat, |i: int| bit(self.words.perms@, i))
+    }
+
+    /// Builds a set of the numbers `0..len` with no bit set.
+    pub fn new(len: usize) -> (res: Self)
+        ensures
+            res.wf(),
+            res.len == len,
+            res.bits() == Seq::new(len as nat, |i: int| false),
+    {
+        let n = len / 64 + if len % 64 == 0 { 0 } else { 1 };
+        let mut data: Vec<u64> = Vec::new();
+        while data.len() < n
+            invariant
+                data.len() <= n,
+        {
+            data.push(0);
+        }
+        let mut words = ArrayForSorting::new(data);
+        // `new` does not tell the values, so they are written once more
+        region_array::fill(&*words.array, Tracked(words.perms.borrow_mut()), 0, n, 0u64);
+        let res = Bitset { len, words };
+        proof {
+            lemma_fundamental_div_mod(len as int, 64);
+            lemma_mod_pos_bound(len as int, 64);
+            assert forall |i: int| 0 <= i < len implies !#[trigger] res.bits()[i] by {
+                lemma_word(i, 0, n as int);
+                lemma_zero_bit((i % 64) as u64);
+            }
+            assert(res.bits() =~= Seq::new(len as nat, |i: int| false));
+        }
+        res
+    }
+
+    /// Adds `i` to the set.
+    pub fn set(&mut self, i: usize)
+        requires
+            old(self).wf(),
+            i < old(self).len,
+        ensures
+            self.wf(),
+            self.len == old(self).len,
+            self.bits() == old(self).bits().update(i as int, true),
+    {
+        set(&*self.words.array, i, Tracked(self.words.perms.borrow_mut()));
+        assert(self.bits() =~= old(self).bits().update(i as int, true));
+    }
+
+    /// Returns whether `i` is in the set.
+    pub fn test(&self, i: usize) -> (res: bool)
+        requires
+            self.wf(),
+            i < self.len,
+        ensures
+            res == self.bits()[i as int],
+    {
+        test(&*self.words.array, i, Tracked(self.words.perms.borrow()))
+    }
+
+    /// Adds every number of `0..len` that satisfies `pred`. The numbers are split in halves,
+    /// which are added in separate threads while there are more than `threshold` of them.
+    /// Every thread owns the words of its numbers.
+    pub fn par_set_where<F: Fn(usize) -> bool + Copy + Send + 'static>(
+        &mut self,
+        pred: F,
+        Ghost(p): Ghost<spec_fn(usize) -> bool>,
+        threshold: usize,
+    ) -> (ret: Result<(), ()>)
+        requires
+            old(self).wf(),
+            decides(pred, p),
+        ensures
+            ret.is_ok() ==> self.wf() && self.len == old(self).len,
+            ret.is_ok() ==> forall |i: int| 0 <= i < self.len ==>
+                #[trigger] self.bits()[i] == (old(self).bits()[i] || p(i as usize)),
+    {
+        let ret = _set_parallel(
+            Arc::clone(&self.words.array), Tracked(self.words.perms.borrow_mut()), 0, self.len, pred, Ghost(p), threshold,
+        );
+        if ret.is_err() {
+            return Err(());
+        }
+        proof {
+            assert forall |i: int| 0 <= i < self.len implies
+                #[trigger] self.bits()[i] == (old(self).bits()[i] || p(i as usize)) by {
+                assert(covers(self.words.perms@, i));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the size of the set.
+    pub fn count_ones(&self) -> (res: usize)
+        requires
+            self.wf(),
+        ensures
+            res == count(self.words.perms@, 0, self.len as int),
+    {
+        count_ones(&*self.words.array, 0, self.len, Tracked(self.words.perms.borrow()))
+    }
+}
+
+/// Sets every word of `lo..hi` in `dst` to its union with the word of `src`.
+fn union_words(
+    dst_arr: &Array<u64>,
+    Tracked(dst): Tracked<&mut Region<u64>>,
+    src_arr: &Array<u64>,
+    Tracked(src): Tracked<&Region<u64>>,
+    lo: usize, hi: usize,
+)
+    requires
+        region_array::wf(*dst_arr, *old(dst)),
+        old(dst).lo() == lo,
+        old(dst).hi() == hi,
+        region_array::wf(*src_arr, *src),
+        src.lo() == 0,
+        hi <= src.hi(),
+    ensures
+        region_array::wf(*dst_arr, *dst),
+        dst.lo() == lo,
+        dst.hi() == hi,
+        forall |k: int| 0 <= k < hi - lo ==>
+            #[trigger] dst.values()[k] == old(dst).values()[k] | src.values()[lo + k],
+{
+    let ghost old_v = dst.values();
+    proof {
+        region_array::lemma_values_len(dst_arr, *dst);
+    }
+    let mut w = lo;
+    while w < hi
+        invariant
+            region_array::wf(*dst_arr, *dst),
+            dst.lo() == lo,
+            dst.hi() == hi,
+            region_array::wf(*src_arr, *src),
+            src.lo() == 0,
+            hi <= src.hi(),
+            lo <= w <= hi,
+            old_v.len() == hi - lo,
+            dst.values().len() == hi - lo,
+            forall |k: int| 0 <= k < w - lo ==> #[trigger] dst.values()[k] == old_v[k] | src.values()[lo + k],
+            forall |k: int| w - lo <= k < hi - lo ==> #[trigger] dst.values()[k] == old_v[k],
+    {
+        let a = *region_array::read(dst_arr, w, Tracked(dst));
+        let b = *region_array::read(src_arr, w, Tracked(src));
+        region_array::replace(dst_arr, w, a | b, Tracked(dst));
+        assert(lo + (w - lo) == w);
+        w += 1;
+    }
+}
+
+fn _union_parallel(
+    dst_arr: Arc<Array<u64>>,
+    Tracked(dst): Tracked<&mut Region<u64>>,
+    src_arr: Arc<Array<u64>>,
+    src_shared: Arc<Tracked<Region<u64>>>,
+    lo: usize, hi: usize,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        region_array::wf(*dst_arr, *old(dst)),
+        old(dst).lo() == lo,
+        old(dst).hi() == hi,
+        region_array::wf(*src_arr, (*src_shared)@),
+        (*src_shared)@.lo() == 0,
+        hi <= (*src_shared)@.hi(),
+    ensures
+        ret.is_ok() ==> region_array::wf(*dst_arr, *dst) && dst.lo() == lo && dst.hi() == hi,
+        ret.is_ok() ==> forall |k: int| 0 <= k < hi - lo ==>
+            #[trigger] dst.values()[k] == old(dst).values()[k] | (*src_shared)@.values()[lo + k],
+{
+    let ghost sv = (*src_shared)@.values();
+    let ghost old_v = dst.values();
+    if hi - lo <= threshold || hi - lo < 2 {
+        let src_region: &Tracked<Region<u64>> = &*src_shared;
+        union_words(&*dst_arr, Tracked(dst), &*src_arr, Tracked(src_region.borrow()), lo, hi);
+        return Ok(());
+    }
+
+    let mid = lo + (hi - lo) / 2;
+    let tracked left_dst = region_array::split_front(&*dst_arr, mid, dst);
+    let tracked mut right_dst = region_array::split_front(&*dst_arr, hi, dst);
+    let ghost left_v = left_dst.values();
+    let ghost right_v = right_dst.values();
+
+    let dst_arr_r1 = Arc::clone(&dst_arr);
+    let src_arr_r1 = Arc::clone(&src_arr);
+    let src_shared_r1 = Arc::clone(&src_shared);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<u64>>, ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*dst_arr, ret.unwrap()@) && ret.unwrap()@.lo() == lo && ret.unwrap()@.hi() == mid,
+            ret.is_ok() ==> forall |k: int| 0 <= k < mid - lo ==>
+                #[trigger] ret.unwrap()@.values()[k] == left_v[k] | sv[lo + k],
+        {
+            let tracked mut left_dst = left_dst;
+            match _union_parallel(dst_arr_r1, Tracked(&mut left_dst), src_arr_r1, src_shared_r1, lo, mid, threshold) {
+                Ok(()) => Ok(Tracked(left_dst)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match _union_parallel(
+        Arc::clone(&dst_arr), Tracked(&mut right_dst), Arc::clone(&src_arr), Arc::clone(&src_shared), mid, hi, threshold,
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut left_dst) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::lemma_values_len(&*dst_arr, left_dst);
+        region_array::lemma_values_len(&*dst_arr, right_dst);
+        let l = left_dst.values();
+        let r = right_dst.values();
+        assert forall |k: int| 0 <= k < hi - lo implies #[trigger] (l + r)[k] == old_v[k] | sv[lo + k] by {
+            if k < l.len() {
+                assert(left_v[k] == old_v[k]);
+            } else {
+                assert((l + r)[k] == r[k - l.len()]);
+                assert(right_v[k - l.len()] == old_v[k]);
+                assert(mid + (k - l.len()) == lo + k);
+            }
+        }
+        region_array::merge(&*dst_arr, &mut left_dst, right_dst);
+        vstd::modes::tracked_swap(dst, &mut left_dst);
+    }
+    Ok(())
+}
+
+/// Adds the numbers of `src` to `dst`. The words are split in halves, which are joined in
+/// separate threads while there are more than `threshold` of them. Every thread owns its words
+/// of `dst` and shares `src` through an `Arc`.
+pub fn par_union(dst: &mut Bitset, src: &mut Bitset, threshold: usize) -> (ret: Result<(), ()>)
+    requires
+        old(dst).wf(),
+        old(src).wf(),
+        old(dst).len == old(src).len,
+        old(dst).words.array.len() == old(src).words.array.len(),
+    ensures
+        ret.is_ok() ==> dst.wf() && dst.len == old(dst).len,
+        ret.is_ok() ==> src.wf() && src.len == old(src).len && src.bits() == old(src).bits(),
+        ret.is_ok() ==> forall |i: int| 0 <= i < dst.len ==>
+            #[trigger] dst.bits()[i] == (old(dst).bits()[i] || old(src).bits()[i]),
+{
+    let n = (&*dst.words.array).length();
+    let ghost dv = dst.words.perms@.values();
+    let src_shared = share(&mut src.words);
+    let ghost sv = (*src_shared)@.values();
+
+    let ret = _union_parallel(
+        Arc::clone(&dst.words.array),
+        Tracked(dst.words.perms.borrow_mut()),
+        Arc::clone(&src.words.array),
+        Arc::clone(&src_shared),
+        0,
+        n,
+        threshold,
+    );
+    // `src` is given back its region before any error is returned
+    let src_ret = unshare(&mut src.words, src_shared);
+    if ret.is_err() || src_ret.is_err() {
+        return Err(());
+    }
+    proof {
+        assert(src.bits() =~= old(src).bits());
+        assert forall |i: int| 0 <= i < dst.len implies
+            #[trigger] dst.bits()[i] == (old(dst).bits()[i] || old(src).bits()[i]) by {
+            lemma_word(i, 0, n as int);
+            let w = i / 64;
+            assert(dst.words.perms@.values()[w] == dv[w] | sv[0 + w]);
+            lemma_or_bit(dv[w], sv[w], (i % 64) as u64);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bitset() {
+    use crate::test_data::thresholds;
+
+    let n = 10_000;
+    let a_bits: Vec<bool> = (0..n).map(|i| i % 3 == 0 || i % 64 == 63).collect();
+    let b_bits: Vec<bool> = (0..n).map(|i| i % 7 == 1 || i >= n - 100).collect();
+    let union_count = (0..n).filter(|&i| a_bits[i] || b_bits[i]).count();
+
+    for threshold in thresholds(n) {
+        let mut a = Bitset::new(n);
+        let mut b = Bitset::new(n);
+        assert_eq!(a.count_ones(), 0);
+        for i in 0..n {
+            if a_bits[i] {
+                a.set(i);
+            }
+            if b_bits[i] {
+                b.set(i);
+            }
+        }
+        assert_eq!(a.count_ones(), a_bits.iter().filter(|&&x| x).count());
+        par_union(&mut a, &mut b, threshold).unwrap();
+        for i in 0..n {
+            assert_eq!(a.test(i), a_bits[i] || b_bits[i]);
+            assert_eq!(b.test(i), b_bits[i]);
+        }
+        assert_eq!(a.count_ones(), union_count);
+        // a range that starts and ends inside a word
+        let expected = (100..9_999).filter(|&i| a_bits[i] || b_bits[i]).count();
+        assert_eq!(count_ones(&*a.words.array, 100, 9_999, Tracked(a.words.perms.borrow())), expected);
+
+        let mut c = Bitset::new(n);
+        c.set(1);
+        c.par_set_where(|i: usize| i % 5 == 2 || i % 64 == 0, Ghost(|i: usize| i % 5 == 2 || i % 64 == 0), threshold).unwrap();
+        for i in 0..n {
+            assert_eq!(c.test(i), i == 1 || i % 5 == 2 || i % 64 == 0);
+        }
+        assert_eq!(c.count_ones(), (0..n).filter(|&i| i == 1 || i % 5 == 2 || i % 64 == 0).count());
+    }
+}
+
+}
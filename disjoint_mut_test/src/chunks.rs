@@ -0,0 +1,349 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    scan,
+};
+
+/// Upper bound on the number of chunks, and thus threads, of a pass.
+pub const MAX_CHUNKS: usize = 1024;
+
+/// `offsets` are the boundaries of consecutive, possibly empty, segments covering `0..n`:
+/// segment `s` is `offsets[s]..offsets[s + 1]`.
+pub open spec fn offsets_wf(offsets: Seq<usize>, n: int) -> bool {
+    &&& offsets.len() >= 1
+    &&& offsets[0] == 0
+    &&& offsets.last() == n
+    &&& forall |a: int, b: int| 0 <= a <= b < offsets.len() ==> offsets[a] <= offsets[b]
+}
+
+/// The chunks `bounds[c]..bounds[c + 1]` are non-empty and cover `0..n`.
+pub open spec fn chunks_wf(bounds: Seq<usize>, n: int) -> bool {
+    &&& bounds.len() >= 2
+    &&& offsets_wf(bounds, n)
+    &&& forall |a: int, b: int| 0 <= a < b < bounds.len() ==> bounds[a] < bounds[b]
+}
+
+/// Cuts `0..n` into at most `MAX_CHUNKS` chunks of `max(chunk, min_chunk)` elements,
+/// the last one taking whatever is left.
+pub fn chunk_bounds(n: usize, chunk: usize, min_chunk: usize) -> (res: Vec<usize>)
+    requires
+        n > 0,
+        min_chunk > 0,
+    ensures
+        chunks_wf(res@, n as int),
+        res.len() <= MAX_CHUNKS + 1,
+{
+    let chunk = if chunk < min_chunk { min_chunk } else { chunk };
+    let mut res: Vec<usize> = Vec::new();
+    res.push(0);
+    let mut b: usize = 0;
+    while b < n
+        invariant
+            b <= n,
+            chunk > 0,
+            1 <= res.len() <= MAX_CHUNKS + 1,
+            b < n ==> res.len() <= MAX_CHUNKS,
+            res@[0] == 0,
+            res@.last() == b,
+            b > 0 ==> res.len() >= 2,
+            forall |a: int, c: int| 0 <= a < c < res.len() ==> res@[a] < res@[c],
+    {
+        b = if n - b > chunk && res.len() < MAX_CHUNKS { b + chunk } else { n };
+        res.push(b);
+    }
+    assert forall |a: int, c: int| 0 <= a <= c < res.len() implies res@[a] <= res@[c] by {
+        if a < c {
+            assert(res@[a] < res@[c]);
+        }
+    }
+    res
+}
+
+/// A pass that keeps some of the values of every chunk of its input, in the way of `filter`
+/// and `dedup`. The chunk `a..b` keeps `count(a, b)` values, and `w` is what it writes
+/// when `compacted(a, b, w)` holds.
+pub trait Compaction<T> {
+    spec fn inv(&self) -> bool;
+
+    /// The number of values of the input.
+    spec fn len(&self) -> nat;
+
+    spec fn count(&self, a: int, b: int) -> nat;
+
+    spec fn compacted(&self, a: int, b: int, w: Seq<T>) -> bool;
+
+    /// The outputs of two neighbouring chunks add up to the output of both.
+    proof fn lemma_compacted_append(&self, a: int, m: int, b: int, l: Seq<T>, r: Seq<T>)
+        requires
+            self.inv(),
+            0 <= a < m < b <= self.len(),
+            self.compacted(a, m, l),
+            self.compacted(m, b, r),
+        ensures
+            self.compacted(a, b, l + r);
+
+    fn count_chunk(&self, a: usize, b: usize) -> (res: usize)
+        requires
+            self.inv(),
+            a < b <= self.len(),
+        ensures
+            res <= b - a,
+            res == self.count(a as int, b as int);
+
+    /// Writes the output of the chunk `a..b` to the region `out_lo..out_hi`, which fits it exactly.
+    fn compact_chunk(
+        &self,
+        a: usize, b: usize,
+        out_arr: &Array<T>,
+        out_perms: Tracked<Region<T>>,
+        out_lo: usize, out_hi: usize,
+    ) -> (res: Tracked<Region<T>>)
+        requires
+            self.inv(),
+            a < b <= self.len(),
+            region_array::wf(*out_arr, out_perms@),
+            out_perms@.lo() == out_lo,
+            out_perms@.hi() == out_hi,
+            out_hi - out_lo == self.count(a as int, b as int),
+        ensures
+            region_array::wf(*out_arr, res@),
+            res@.lo() == out_lo,
+            res@.hi() == out_hi,
+            self.compacted(a as int, b as int, res@.values());
+}
+
+/// Writes the output of `pass`, whose input has `n` values, to the front of `out_perms` and
+/// returns its length. The input is cut into at most `MAX_CHUNKS` chunks of `threshold` elements.
+/// The values every chunk keeps are first counted in a thread of its own, then the prefix sums of
+/// the counts give every chunk its own region of the output, which it fills in a thread of its own.
+pub fn compact_parallel<T: Send + Sync + 'static, K: Compaction<T> + Send + Sync + 'static>(
+    pass: Arc<K>,
+    n: usize,
+    out_arr: Arc<Array<T>>,
+    Tracked(out_perms): Tracked<&mut Region<T>>,
+    out_lo: usize,
+    threshold: usize,
+) -> (ret: Result<usize, ()>)
+    requires
+        (*pass).inv(),
+        (*pass).len() == n,
+        n > 0,
+        region_array::wf(*out_arr, *old(out_perms)),
+        old(out_perms).lo() == out_lo,
+        old(out_perms).hi() >= out_lo + n,
+    ensures
+        ret.is_ok() ==> region_array::wf(*out_arr, *out_perms),
+        ret.is_ok() ==> out_perms.lo() == old(out_perms).lo() && out_perms.hi() == old(out_perms).hi(),
+        ret.is_ok() ==> ret.unwrap() <= n,
+        ret.is_ok() ==> (*pass).compacted(0, n as int, out_perms.values().subrange(0, ret.unwrap() as int)),
+        ret.is_ok() ==> out_perms.values().subrange(ret.unwrap() as int, out_perms.hi() - out_lo)
+            == old(out_perms).values().subrange(ret.unwrap() as int, old(out_perms).hi() - out_lo),
+{
+    let ghost w = out_perms.values();
+    proof {
+        region_array::lemma_values_len(&*out_arr, *out_perms);
+    }
+    let bounds = Arc::new(chunk_bounds(n, threshold, 1));
+    let c_hi = (*bounds).len() - 1;
+
+    let counts = match _count_parallel::<T, K>(Arc::clone(&pass), Arc::clone(&bounds), 0, c_hi) {
+        Ok(counts) => counts,
+        Err(_) => {return Err(());},
+    };
+    let offsets = Arc::new(scan::chunk_offsets(&counts, &*bounds));
+    let m = (*offsets)[c_hi];
+    assert forall |c: int| 0 <= c < c_hi implies #[trigger] (*offsets)@[c + 1] - (*offsets)@[c]
+        == (*pass).count((*bounds)@[c] as int, (*bounds)@[c + 1] as int) by {
+        assert(counts@[c] == (*pass).count((*bounds)@[0 + c] as int, (*bounds)@[0 + c + 1] as int));
+    }
+
+    let tracked front = region_array::split_front(&*out_arr, out_lo + m, out_perms);
+    let Tracked(mut front) = match _compact_parallel::<T, K>(
+        pass, bounds, Arc::clone(&out_arr), Tracked(front), out_lo, offsets, 0, c_hi,
+    ) {
+        Ok(front) => front,
+        Err(_) => {return Err(());},
+    };
+    let ghost f = front.values();
+    proof {
+        region_array::lemma_values_len(&*out_arr, front);
+        vstd::modes::tracked_swap(out_perms, &mut front);
+        region_array::merge(&*out_arr, out_perms, front);
+        assert(out_perms.values().subrange(0, m as int) =~= f);
+        assert(out_perms.values().subrange(m as int, out_perms.hi() - out_lo) =~= w.subrange(m as int, w.len() as int));
+    }
+    Ok(m)
+}
+
+/// Counts the values that the chunks `c_lo..c_hi` keep, splitting them in halves
+/// that are counted in separate threads.
+fn _count_parallel<T: Send + Sync + 'static, K: Compaction<T> + Send + Sync + 'static>(
+    pass: Arc<K>,
+    bounds: Arc<Vec<usize>>,
+    c_lo: usize, c_hi: usize,
+) -> (ret: Result<Vec<usize>, ()>)
+    requires
+        (*pass).inv(),
+        chunks_wf((*bounds)@, (*pass).len() as int),
+        c_lo < c_hi < (*bounds).len(),
+    ensures
+        ret.is_ok() ==> ret.unwrap().len() == c_hi - c_lo,
+        ret.is_ok() ==> forall |k: int| 0 <= k < c_hi - c_lo ==>
+            #[trigger] ret.unwrap()@[k] <= (*bounds)@[c_lo + k + 1] - (*bounds)@[c_lo + k],
+        ret.is_ok() ==> forall |k: int| 0 <= k < c_hi - c_lo ==>
+            #[trigger] ret.unwrap()@[k] == (*pass).count((*bounds)@[c_lo + k] as int, (*bounds)@[c_lo + k + 1] as int),
+{
+    if c_hi - c_lo == 1 {
+        proof {
+            if c_hi < (*bounds).len() - 1 {
+                assert((*bounds)@[c_hi as int] < (*bounds)@[(*bounds).len() - 1]);
+            }
+        }
+        let count = (*pass).count_chunk((*bounds)[c_lo], (*bounds)[c_hi]);
+        let mut res: Vec<usize> = Vec::new();
+        res.push(count);
+        return Ok(res);
+    }
+
+    let c_mid = c_lo + (c_hi - c_lo) / 2;
+
+    let pass_r1 = Arc::clone(&pass);
+    let bounds_r1 = Arc::clone(&bounds);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Vec<usize>, ()>)
+        ensures
+            ret.is_ok() ==> ret.unwrap().len() == c_mid - c_lo,
+            ret.is_ok() ==> forall |k: int| 0 <= k < c_mid - c_lo ==>
+                #[trigger] ret.unwrap()@[k] <= (*bounds)@[c_lo + k + 1] - (*bounds)@[c_lo + k],
+            ret.is_ok() ==> forall |k: int| 0 <= k < c_mid - c_lo ==>
+                #[trigger] ret.unwrap()@[k] == (*pass).count((*bounds)@[c_lo + k] as int, (*bounds)@[c_lo + k + 1] as int),
+        {
+            _count_parallel::<T, K>(pass_r1, bounds_r1, c_lo, c_mid)
+        }
+    );
+
+    let mut right = match _count_parallel::<T, K>(pass, Arc::clone(&bounds), c_mid, c_hi) {
+        Ok(right) => right,
+        Err(_) => {return Err(());},
+    };
+
+    let mut res = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+    let ghost left_res = res@;
+    let ghost right_res = right@;
+    res.append(&mut right);
+    assert forall |k: int| 0 <= k < c_hi - c_lo implies
+        #[trigger] res@[k] <= (*bounds)@[c_lo + k + 1] - (*bounds)@[c_lo + k]
+            && res@[k] == (*pass).count((*bounds)@[c_lo + k] as int, (*bounds)@[c_lo + k + 1] as int) by {
+        if k >= c_mid - c_lo {
+            let j = k - (c_mid - c_lo);
+            assert(res@[k] == right_res[j]);
+            assert(c_mid + j == c_lo + k);
+        } else {
+            assert(res@[k] == left_res[k]);
+        }
+    }
+    Ok(res)
+}
+
+/// Compacts the chunks `c_lo..c_hi` into `out_perms`, splitting them in halves
+/// that are compacted in separate threads.
+fn _compact_parallel<T: Send + Sync + 'static, K: Compaction<T> + Send + Sync + 'static>(
+    pass: Arc<K>,
+    bounds: Arc<Vec<usize>>,
+    out_arr: Arc<Array<T>>,
+    out_perms: Tracked<Region<T>>,
+    out_lo: usize,
+    offsets: Arc<Vec<usize>>,
+    c_lo: usize, c_hi: usize,
+) -> (ret: Result<Tracked<Region<T>>, ()>)
+    requires
+        (*pass).inv(),
+        chunks_wf((*bounds)@, (*pass).len() as int),
+        c_lo < c_hi < (*bounds).len(),
+        (*offsets).len() == (*bounds).len(),
+        forall |a: int, b: int| 0 <= a <= b < (*offsets).len() ==> (*offsets)@[a] <= (*offsets)@[b],
+        forall |c: int| c_lo <= c < c_hi ==> #[trigger] (*offsets)@[c + 1] - (*offsets)@[c]
+            == (*pass).count((*bounds)@[c] as int, (*bounds)@[c + 1] as int),
+        region_array::wf(*out_arr, out_perms@),
+        out_perms@.lo() == out_lo + (*offsets)@[c_lo as int],
+        out_perms@.hi() == out_lo + (*offsets)@[c_hi as int],
+    ensures
+        ret.is_ok() ==> region_array::wf(*out_arr, ret.unwrap()@),
+        ret.is_ok() ==> ret.unwrap()@.lo() == out_perms@.lo() && ret.unwrap()@.hi() == out_perms@.hi(),
+        ret.is_ok() ==> (*pass).compacted((*bounds)@[c_lo as int] as int, (*bounds)@[c_hi as int] as int, ret.unwrap()@.values()),
+{
+    proof {
+        if c_hi < (*bounds).len() - 1 {
+            assert((*bounds)@[c_hi as int] < (*bounds)@[(*bounds).len() - 1]);
+        }
+    }
+    if c_hi - c_lo == 1 {
+        assert((*offsets)@[c_lo + 1] - (*offsets)@[c_lo as int]
+            == (*pass).count((*bounds)@[c_lo as int] as int, (*bounds)@[c_lo + 1] as int));
+        let res = (*pass).compact_chunk(
+            (*bounds)[c_lo], (*bounds)[c_hi],
+            &*out_arr, out_perms, out_lo + (*offsets)[c_lo], out_lo + (*offsets)[c_hi],
+        );
+        return Ok(res);
+    }
+
+    let c_mid = c_lo + (c_hi - c_lo) / 2;
+    let mid = out_lo + (*offsets)[c_mid];
+    let Tracked(mut right_perms) = out_perms;
+    let tracked left_perms = region_array::split_front(&*out_arr, mid, &mut right_perms);
+    let ghost lo_bound = (*bounds)@[c_lo as int] as int;
+    let ghost mid_bound = (*bounds)@[c_mid as int] as int;
+
+    let pass_r1 = Arc::clone(&pass);
+    let bounds_r1 = Arc::clone(&bounds);
+    let out_arr_r1 = Arc::clone(&out_arr);
+    let offsets_r1 = Arc::clone(&offsets);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<T>>, ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*out_arr, ret.unwrap()@),
+            ret.is_ok() ==> ret.unwrap()@.lo() == out_lo + (*offsets)@[c_lo as int] && ret.unwrap()@.hi() == mid,
+            ret.is_ok() ==> (*pass).compacted(lo_bound, mid_bound, ret.unwrap()@.values()),
+        {
+            _compact_parallel::<T, K>(pass_r1, bounds_r1, out_arr_r1, Tracked(left_perms), out_lo, offsets_r1, c_lo, c_mid)
+        }
+    );
+
+    let Tracked(right_perms) = match _compact_parallel::<T, K>(
+        Arc::clone(&pass), Arc::clone(&bounds), Arc::clone(&out_arr), Tracked(right_perms), out_lo,
+        Arc::clone(&offsets), c_mid, c_hi,
+    ) {
+        Ok(right_perms) => right_perms,
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut left_perms) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        (*pass).lemma_compacted_append(lo_bound, mid_bound, (*bounds)@[c_hi as int] as int, left_perms.values(), right_perms.values());
+        region_array::merge(&*out_arr, &mut left_perms, right_perms);
+    }
+    Ok(Tracked(left_perms))
+}
+
+}
@@ -0,0 +1,420 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::ArrayForSorting,
+    merge_sorted::{sorted, lemma_sorted_by_value, ByValue},
+    sorted_region::{self, SortedRegion, SortedArray},
+    chunks::{self, Compaction},
+};
+
+pub open spec fn strictly_increasing(s: Seq<i32>) -> bool {
+    forall |i: int, j: int| 0 <= i < j < s.len() ==> s[i] < s[j]
+}
+
+/// `a` and `b` hold the same values, not counting repetitions.
+pub open spec fn same_elements(a: Seq<i32>, b: Seq<i32>) -> bool {
+    &&& forall |i: int| 0 <= i < a.len() ==> b.contains(#[trigger] a[i])
+    &&& forall |j: int| 0 <= j < b.len() ==> a.contains(#[trigger] b[j])
+}
+
+/// `w` holds, in increasing order, the values of the chunk `v[lo..hi]`. The values equal
+/// to `v[lo - 1]` are left out, since the output of the chunk before already holds them.
+pub open spec fn unique_of(v: Seq<i32>, lo: int, hi: int, w: Seq<i32>) -> bool {
+    &&& strictly_increasing(w)
+    &&& forall |k: int| 0 <= k < w.len() ==> v.contains(#[trigger] w[k])
+    &&& forall |k: int| 0 <= k < w.len() && lo > 0 ==> v[lo - 1] < #[trigger] w[k]
+    &&& forall |k: int| 0 <= k < w.len() && hi > lo ==> #[trigger] w[k] <= v[hi - 1]
+    &&& forall |p: int| lo <= p < hi ==> w.contains(#[trigger] v[p]) || (lo > 0 && v[p] == v[lo - 1])
+}
+
+/// `v[p]` differs from the value before it.
+pub open spec fn is_first(v: Seq<i32>, p: int) -> bool {
+    p == 0 || v[p - 1] != v[p]
+}
+
+/// The number of values of `v[lo..hi]` that differ from the value before them.
+pub open spec fn first_count(v: Seq<i32>, lo: int, hi: int) -> nat
+    decreases hi - lo,
+{
+    if hi <= lo {
+        0
+    } else {
+        first_count(v, lo, hi - 1) + if is_first(v, hi - 1) { 1nat } else { 0nat }
+    }
+}
+
+/// The first values of `lo..hi` are those of `lo..m` and those of `m..hi`.
+proof fn lemma_first_count_split(v: Seq<i32>, lo: int, m: int, hi: int)
+    requires
+        lo <= m <= hi,
+    ensures
+        first_count(v, lo, hi) == first_count(v, lo, m) + first_count(v, m, hi),
+    decreases hi - m,
+{
+    if hi > m {
+        lemma_first_count_split(v, lo, m, hi - 1);
+    }
+}
+
+/// The outputs of two neighbouring chunks add up to the output of both.
+proof fn lemma_unique_append(v: Seq<i32>, a: int, m: int, b: int, l: Seq<i32>, r: Seq<i32>)
+    requires
+        sorted(v),
+        0 <= a < m < b <= v.len(),
+        unique_of(v, a, m, l),
+        unique_of(v, m, b, r),
+    ensures
+        unique_of(v, a, b, l + r),
+{
+    let w = l + r;
+    assert forall |i: int, j: int| 0 <= i < j < w.len() implies w[i] < w[j] by {
+        if i < l.len() && j >= l.len() {
+            assert(l[i] <= v[m - 1]);
+            assert(v[m - 1] < r[j - l.len()]);
+        } else if i < l.len() {
+            assert(l[i] < l[j]);
+        } else {
+            assert(r[i - l.len()] < r[j - l.len()]);
+        }
+    }
+    assert forall |k: int| 0 <= k < w.len() implies v.contains(#[trigger] w[k]) by {
+        if k < l.len() {
+            assert(w[k] == l[k]);
+        } else {
+            assert(w[k] == r[k - l.len()]);
+        }
+    }
+    assert forall |k: int| 0 <= k < w.len() && a > 0 implies v[a - 1] < #[trigger] w[k] by {
+        if k < l.len() {
+            assert(w[k] == l[k]);
+        } else {
+            assert(w[k] == r[k - l.len()]);
+            assert(v[a - 1] <= v[m - 1]);
+        }
+    }
+    assert forall |k: int| 0 <= k < w.len() && b > a implies #[trigger] w[k] <= v[b - 1] by {
+        if k < l.len() {
+            assert(w[k] == l[k]);
+            assert(v[m - 1] <= v[b - 1]);
+        } else {
+            assert(w[k] == r[k - l.len()]);
+        }
+    }
+    assert forall |p: int| a <= p < b implies w.contains(#[trigger] v[p]) || (a > 0 && v[p] == v[a - 1]) by {
+        if p < m {
+            if l.contains(v[p]) {
+                let k = choose |k: int| 0 <= k < l.len() && l[k] == v[p];
+                assert(w[k] == v[p]);
+            }
+        } else if r.contains(v[p]) {
+            let k = choose |k: int| 0 <= k < r.len() && r[k] == v[p];
+            assert(w[l.len() + k] == v[p]);
+        } else {
+            // `v[p]` is a repetition of the last value of the left chunk
+            assert(l.contains(v[m - 1]) || (a > 0 && v[m - 1] == v[a - 1]));
+            if l.contains(v[m - 1]) {
+                let k = choose |k: int| 0 <= k < l.len() && l[k] == v[m - 1];
+                assert(w[k] == v[p]);
+            }
+        }
+    }
+}
+
+/// Writes the values of `arr` to the front of `out`, once each, and returns their number.
+/// The input is cut into chunks of `threshold` elements. The unique values of every chunk are
+/// first counted in a thread of its own, then the prefix sums of the counts give every chunk
+/// its own region of `out`, which it fills in a thread of its own.
+pub fn dedup(
+    arr: &mut SortedArray,
+    out: &mut ArrayForSorting<i32>,
+    threshold: usize,
+) -> (ret: Result<usize, ()>)
+    requires
+        old(arr).wf(),
+        old(out).perms@.lo() == 0,
+        old(out).perms@.hi() == old(out).array.len(),
+        region_array::wf(*old(out).array, (old(out).perms@)),
+        old(out).array.len() == old(arr).array.len(),
+    ensures
+        ret.is_ok() ==> arr.wf(),
+        ret.is_ok() ==> arr.perms@.values() == old(arr).perms@.values(),
+        ret.is_ok() ==> region_array::wf(*out.array, (out.perms@)),
+        ret.is_ok() ==> out.perms@.lo() == old(out).perms@.lo() && out.perms@.hi() == old(out).perms@.hi(),
+        ret.is_ok() ==> ret.unwrap() <= old(arr).array.len(),
+        ret.is_ok() ==> strictly_increasing(out.perms@.values().subrange(0, ret.unwrap() as int)),
+        ret.is_ok() ==> same_elements(old(arr).perms@.values(), out.perms@.values().subrange(0, ret.unwrap() as int)),
+{
+    let n = arr.len();
+    proof {
+        sorted_region::lemma_sorted(&*arr.array, arr.perms.borrow());
+        lemma_sorted_by_value(arr.perms@.values());
+        region_array::lemma_values_len(&*out.array, out.perms@);
+    }
+    if n == 0 {
+        assert(old(arr).perms@.values() =~= Seq::<i32>::empty());
+        return Ok(0);
+    }
+
+    // leave an empty region in `arr` while the threads share its region
+    let tracked mut perms = sorted_region::split_front(&*arr.array, 0, arr.perms.borrow_mut());
+    proof {
+        vstd::modes::tracked_swap(arr.perms.borrow_mut(), &mut perms);
+        assert(perms.values() =~= old(arr).perms@.values());
+        sorted_region::lemma_sorted(&*arr.array, &perms);
+        lemma_sorted_by_value(perms.values());
+    }
+    let shared = Arc::new(Tracked(perms));
+    let pass = Arc::new(Dedup { arr: Arc::clone(&arr.array), shared: Arc::clone(&shared) });
+
+    let ret = chunks::compact_parallel(pass, n, Arc::clone(&out.array), Tracked(out.perms.borrow_mut()), 0, threshold);
+
+    // `arr` is given back its region before any error is returned
+    let Tracked(mut perms) = match region_array::take_shared(shared) {
+        Some(perms) => perms,
+        None => {return Err(());},
+    };
+    proof {
+        vstd::modes::tracked_swap(arr.perms.borrow_mut(), &mut perms);
+    }
+    ret
+}
+
+/// `dedup` as a `Compaction` of the shared sorted region.
+struct Dedup {
+    arr: Arc<Array<i32>>,
+    shared: Arc<Tracked<SortedRegion<i32, ByValue>>>,
+}
+
+impl Compaction<i32> for Dedup {
+    open spec fn inv(&self) -> bool {
+        &&& sorted_region::wf(*self.arr, (*self.shared)@)
+        &&& (*self.shared)@.lo() == 0
+        &&& (*self.shared)@.values().len() == (*self.shared)@.hi()
+        &&& sorted((*self.shared)@.values())
+    }
+
+    open spec fn len(&self) -> nat {
+        (*self.shared)@.hi() as nat
+    }
+
+    open spec fn count(&self, a: int, b: int) -> nat {
+        first_count((*self.shared)@.values(), a, b)
+    }
+
+    open spec fn compacted(&self, a: int, b: int, w: Seq<i32>) -> bool {
+        unique_of((*self.shared)@.values(), a, b, w)
+    }
+
+    proof fn lemma_compacted_append(&self, a: int, m: int, b: int, l: Seq<i32>, r: Seq<i32>) {
+        lemma_unique_append((*self.shared)@.values(), a, m, b, l, r);
+    }
+
+    fn count_chunk(&self, a: usize, b: usize) -> (res: usize) {
+        let region: &Tracked<SortedRegion<i32, ByValue>> = &*self.shared;
+        let tracked perms = sorted_region::as_region(&*self.arr, region.borrow());
+        count_chunk(&*self.arr, Tracked(perms), a, b)
+    }
+
+    fn compact_chunk(
+        &self,
+        a: usize, b: usize,
+        out_arr: &Array<i32>,
+        out_perms: Tracked<Region<i32>>,
+        out_lo: usize, out_hi: usize,
+    ) -> (res: Tracked<Region<i32>>) {
+        let Tracked(mut out_perms) = out_perms;
+        let region: &Tracked<SortedRegion<i32, ByValue>> = &*self.shared;
+        let tracked perms = sorted_region::as_region(&*self.arr, region.borrow());
+        compact_chunk(&*self.arr, Tracked(perms), a, b, out_arr, Tracked(&mut out_perms), out_lo, out_hi);
+        Tracked(out_perms)
+    }
+}
+
+/// The number of values of `lo..hi` that differ from the value before them.
+fn count_chunk(
+    arr: &Array<i32>,
+    Tracked(perms): Tracked<&Region<i32>>,
+    lo: usize, hi: usize,
+) -> (res: usize)
+    requires
+        region_array::wf(*arr, *perms),
+        perms.lo() == 0,
+        lo <= hi <= perms.hi(),
+    ensures
+        res <= hi - lo,
+        res == first_count(perms.values(), lo as int, hi as int),
+{
+    let ghost v = perms.values();
+    let mut count = 0;
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() == 0,
+            v == perms.values(),
+            lo <= i <= hi <= perms.hi(),
+            count <= i - lo,
+            count == first_count(v, lo as int, i as int),
+    {
+        if i == 0 || *region_array::read(arr, i - 1, Tracked(perms)) != *region_array::read(arr, i, Tracked(perms)) {
+            count += 1;
+        }
+        i += 1;
+    }
+    count
+}
+
+/// Writes the values of `lo..hi` that differ from the value before them to the region
+/// `out_lo..out_hi`, which fits them exactly.
+fn compact_chunk(
+    arr: &Array<i32>,
+    Tracked(perms): Tracked<&Region<i32>>,
+    lo: usize, hi: usize,
+    out_arr: &Array<i32>,
+    Tracked(out_perms): Tracked<&mut Region<i32>>,
+    out_lo: usize, out_hi: usize,
+)
+    requires
+        region_array::wf(*arr, *perms),
+        perms.lo() == 0,
+        sorted(perms.values()),
+        lo < hi <= perms.hi(),
+        region_array::wf(*out_arr, *old(out_perms)),
+        old(out_perms).lo() == out_lo,
+        old(out_perms).hi() == out_hi,
+        out_hi - out_lo == first_count(perms.values(), lo as int, hi as int),
+    ensures
+        region_array::wf(*out_arr, *out_perms),
+        out_perms.lo() == out_lo && out_perms.hi() == out_hi,
+        unique_of(perms.values(), lo as int, hi as int, out_perms.values()),
+{
+    let ghost v = perms.values();
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+        region_array::lemma_values_len(out_arr, *out_perms);
+    }
+    let mut o = out_lo;
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() == 0,
+            v == perms.values(),
+            v.len() == perms.hi(),
+            sorted(v),
+            lo < hi <= perms.hi(),
+            region_array::wf(*out_arr, *out_perms),
+            out_perms.lo() == out_lo,
+            out_perms.hi() == out_hi,
+            out_perms.values().len() == out_hi - out_lo,
+            lo <= i <= hi,
+            out_lo <= o <= out_hi,
+            out_hi - out_lo == first_count(v, lo as int, hi as int),
+            o - out_lo == first_count(v, lo as int, i as int),
+            unique_of(v, lo as int, i as int, out_perms.values().subrange(0, o - out_lo)),
+            o > out_lo ==> i > lo && out_perms.values()[o - out_lo - 1] == v[i - 1],
+            o == out_lo && i > lo ==> lo > 0 && v[i - 1] == v[lo - 1],
+    {
+        let ghost done = out_perms.values().subrange(0, o - out_lo);
+        let x = *region_array::read(arr, i, Tracked(perms));
+        let first = i == 0 || *region_array::read(arr, i - 1, Tracked(perms)) != x;
+        proof {
+            lemma_first_count_split(v, lo as int, i + 1, hi as int);
+            assert(first_count(v, lo as int, i + 1) == first_count(v, lo as int, i as int) + if first { 1nat } else { 0nat });
+        }
+        if first {
+            region_array::replace(out_arr, o, x, Tracked(out_perms));
+            o += 1;
+        }
+        proof {
+            let new_done = out_perms.values().subrange(0, o - out_lo);
+            if first {
+                assert(new_done =~= done.push(x));
+                if i > 0 {
+                    assert(v[i - 1] <= x);
+                }
+                if i > lo && lo > 0 {
+                    assert(v[lo - 1] <= v[i - 1]);
+                }
+                assert forall |a: int, b: int| 0 <= a < b < new_done.len() implies new_done[a] < new_done[b] by {
+                    if b == done.len() {
+                        assert(done[a] <= v[i - 1]);
+                    } else {
+                        assert(done[a] < done[b]);
+                    }
+                }
+                assert forall |k: int| 0 <= k < new_done.len() implies v.contains(#[trigger] new_done[k]) by {
+                    if k == done.len() {
+                        assert(v[i as int] == new_done[k]);
+                    } else {
+                        assert(new_done[k] == done[k]);
+                    }
+                }
+                assert forall |k: int| 0 <= k < new_done.len() && lo > 0 implies v[lo - 1] < #[trigger] new_done[k] by {
+                    if k < done.len() {
+                        assert(new_done[k] == done[k]);
+                    }
+                }
+                assert forall |k: int| 0 <= k < new_done.len() implies #[trigger] new_done[k] <= v[i as int] by {
+                    if k < done.len() {
+                        assert(new_done[k] == done[k]);
+                        assert(v[i - 1] <= v[i as int]);
+                    }
+                }
+                assert forall |p: int| lo <= p < i + 1 implies new_done.contains(#[trigger] v[p]) || (lo > 0 && v[p] == v[lo - 1]) by {
+                    if p == i {
+                        assert(new_done[done.len() as int] == v[p]);
+                    } else if done.contains(v[p]) {
+                        let k = choose |k: int| 0 <= k < done.len() && done[k] == v[p];
+                        assert(new_done[k] == v[p]);
+                    }
+                }
+            } else {
+                assert(new_done =~= done);
+                assert(v[i - 1] == x);
+                assert forall |k: int| 0 <= k < new_done.len() implies #[trigger] new_done[k] <= v[i as int] by {
+                    assert(new_done[k] <= v[i - 1]);
+                }
+                assert forall |p: int| lo <= p < i + 1 implies new_done.contains(#[trigger] v[p]) || (lo > 0 && v[p] == v[lo - 1]) by {
+                    if p == i && done.len() > 0 {
+                        assert(new_done[done.len() - 1] == v[p]);
+                    }
+                }
+            }
+        }
+        i += 1;
+    }
+    assert(out_perms.values().subrange(0, o - out_lo) =~= out_perms.values());
+}
+
+#[test]
+fn test_dedup() {
+    use crate::test_data::{scattered, thresholds};
+
+    let data: Vec<i32> = scattered(2000, 211, -100);
+    let mut expected = data.clone();
+    expected.sort();
+    expected.dedup();
+
+    for threshold in thresholds(data.len()) {
+        let mut buf = ArrayForSorting::new(vec![0; data.len()]);
+        let mut arr = SortedArray::sort_parallel(ArrayForSorting::new(data.clone()), &mut buf, 64).ok().unwrap();
+        let mut out = ArrayForSorting::new(vec![0; data.len()]);
+        let m = dedup(&mut arr, &mut out, threshold).unwrap();
+        assert_eq!(m, expected.len());
+        assert_eq!(&out.clone_to_vec()[..m], &expected[..]);
+    }
+
+    let mut buf = ArrayForSorting::new(vec![]);
+    let mut arr = SortedArray::sort_parallel(ArrayForSorting::new(vec![]), &mut buf, 64).ok().unwrap();
+    let mut out = ArrayForSorting::new(vec![]);
+    assert_eq!(dedup(&mut arr, &mut out, 16).unwrap(), 0);
+}
+
+}
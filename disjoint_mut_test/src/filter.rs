@@ -0,0 +1,333 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    chunks::{self, Compaction},
+};
+
+/// The values of `s` that satisfy `p`, in their order in `s`.
+pub open spec fn filtered<T>(s: Seq<T>, p: spec_fn(T) -> bool) -> Seq<T>
+    decreases s.len(),
+{
+    if s.len() == 0 {
+        Seq::empty()
+    } else if p(s.last()) {
+        filtered(s.drop_last(), p).push(s.last())
+    } else {
+        filtered(s.drop_last(), p)
+    }
+}
+
+/// `pred` computes `p` and can be called on anything.
+pub open spec fn decides<T, F: Fn(T) -> bool>(pred: F, p: spec_fn(T) -> bool) -> bool {
+    &&& forall |x: T| #[trigger] pred.requires((x,))
+    &&& forall |x: T, r: bool| #[trigger] pred.ensures((x,), r) ==> r == p(x)
+}
+
+proof fn lemma_filtered_push<T>(s: Seq<T>, a: int, k: int, p: spec_fn(T) -> bool)
+    requires
+        0 <= a <= k < s.len(),
+    ensures
+        filtered(s.subrange(a, k + 1), p) == if p(s[k]) {
+            filtered(s.subrange(a, k), p).push(s[k])
+        } else {
+            filtered(s.subrange(a, k), p)
+        },
+{
+    assert(s.subrange(a, k + 1).drop_last() =~= s.subrange(a, k));
+}
+
+/// Filtering two parts and concatenating the results is filtering their concatenation.
+proof fn lemma_filtered_append<T>(l: Seq<T>, r: Seq<T>, p: spec_fn(T) -> bool)
+    ensures
+        filtered(l + r, p) == filtered(l, p) + filtered(r, p),
+    decreases r.len(),
+{
+    if r.len() == 0 {
+        assert(l + r =~= l);
+        assert(filtered(l, p) + filtered(r, p) =~= filtered(l, p));
+    } else {
+        lemma_filtered_append(l, r.drop_last(), p);
+        assert((l + r).drop_last() =~= l + r.drop_last());
+        assert((l + r).last() == r.last());
+        let fl = filtered(l, p);
+        let fr = filtered(r.drop_last(), p);
+        assert(fl + fr.push(r.last()) =~= (fl + fr).push(r.last()));
+    }
+}
+
+/// Writes the values of `lo..hi` that satisfy `pred` to the front of `out_perms`, keeping their
+/// order, and returns their number. The input is cut into chunks of `threshold` elements. The
+/// matches of every chunk are first counted in a thread of its own, then the prefix sums of the
+/// counts give every chunk its own region of the output, which it fills in a thread of its own.
+pub fn par_filter<T: Copy + Send + Sync + 'static, F: Fn(T) -> bool + Copy + Send + Sync + 'static>(
+    arr: Arc<Array<T>>,
+    Tracked(perms): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize,
+    out_arr: Arc<Array<T>>,
+    Tracked(out_perms): Tracked<&mut Region<T>>,
+    out_lo: usize,
+    pred: F,
+    Ghost(p): Ghost<spec_fn(T) -> bool>,
+    threshold: usize,
+) -> (ret: Result<usize, ()>)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        region_array::wf(*out_arr, *old(out_perms)),
+        old(out_perms).lo() == out_lo,
+        old(out_perms).hi() >= out_lo + (hi - lo),
+        decides(pred, p),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, *perms),
+        ret.is_ok() ==> perms.lo() == lo && perms.hi() == hi,
+        ret.is_ok() ==> perms.values() == old(perms).values(),
+        ret.is_ok() ==> region_array::wf(*out_arr, *out_perms),
+        ret.is_ok() ==> out_perms.lo() == old(out_perms).lo() && out_perms.hi() == old(out_perms).hi(),
+        ret.is_ok() ==> ret.unwrap() <= hi - lo,
+        ret.is_ok() ==> out_perms.values() == filtered(old(perms).values(), p)
+            + old(out_perms).values().subrange(ret.unwrap() as int, old(out_perms).hi() - out_lo),
+{
+    let ghost v = perms.values();
+    let ghost w = out_perms.values();
+    proof {
+        region_array::lemma_values_len(&*arr, *perms);
+        region_array::lemma_values_len(&*out_arr, *out_perms);
+    }
+    if hi == lo {
+        assert(v =~= Seq::<T>::empty());
+        assert(filtered(v, p) =~= Seq::<T>::empty());
+        assert(out_perms.values() =~= filtered(v, p) + w.subrange(0, w.len() as int));
+        return Ok(0);
+    }
+    let n = hi - lo;
+
+    // leave an empty region in `perms` while the threads share it
+    let shared = region_array::share_region(&*arr, Tracked(perms), lo);
+    let pass = Arc::new(Filter { arr: Arc::clone(&arr), shared: Arc::clone(&shared), lo, pred, p: Ghost(p) });
+
+    let ret = chunks::compact_parallel(pass, n, Arc::clone(&out_arr), Tracked(out_perms), out_lo, threshold);
+
+    // `perms` is given back its region before any error is returned
+    if region_array::unshare_region(Tracked(perms), shared).is_err() {
+        return Err(());
+    }
+    proof {
+        if ret.is_ok() {
+            let m = ret.unwrap() as int;
+            let u = out_perms.values();
+            region_array::lemma_values_len(&*out_arr, *out_perms);
+            assert(v.subrange(0, n as int) =~= v);
+            assert(u =~= u.subrange(0, m) + u.subrange(m, u.len() as int));
+        }
+    }
+    ret
+}
+
+/// `par_filter` as a `Compaction` of the shared region `lo..`.
+struct Filter<T, F> {
+    arr: Arc<Array<T>>,
+    shared: Arc<Tracked<Region<T>>>,
+    lo: usize,
+    pred: F,
+    p: Ghost<spec_fn(T) -> bool>,
+}
+
+impl<T: Copy, F: Fn(T) -> bool + Copy> Compaction<T> for Filter<T, F> {
+    open spec fn inv(&self) -> bool {
+        &&& region_array::wf(*self.arr, (*self.shared)@)
+        &&& (*self.shared)@.lo() == self.lo
+        &&& decides(self.pred, self.p@)
+    }
+
+    open spec fn len(&self) -> nat {
+        ((*self.shared)@.hi() - self.lo) as nat
+    }
+
+    open spec fn count(&self, a: int, b: int) -> nat {
+        filtered((*self.shared)@.values().subrange(a, b), self.p@).len()
+    }
+
+    open spec fn compacted(&self, a: int, b: int, w: Seq<T>) -> bool {
+        w == filtered((*self.shared)@.values().subrange(a, b), self.p@)
+    }
+
+    proof fn lemma_compacted_append(&self, a: int, m: int, b: int, l: Seq<T>, r: Seq<T>) {
+        let v = (*self.shared)@.values();
+        region_array::lemma_values_len(&*self.arr, (*self.shared)@);
+        assert(v.subrange(a, b) =~= v.subrange(a, m) + v.subrange(m, b));
+        lemma_filtered_append(v.subrange(a, m), v.subrange(m, b), self.p@);
+    }
+
+    fn count_chunk(&self, a: usize, b: usize) -> (res: usize) {
+        let region: &Tracked<Region<T>> = &*self.shared;
+        count_chunk(&*self.arr, Tracked(region.borrow()), self.lo + a, self.lo + b, self.pred, self.p)
+    }
+
+    fn compact_chunk(
+        &self,
+        a: usize, b: usize,
+        out_arr: &Array<T>,
+        out_perms: Tracked<Region<T>>,
+        out_lo: usize, out_hi: usize,
+    ) -> (res: Tracked<Region<T>>) {
+        let Tracked(mut out_perms) = out_perms;
+        let region: &Tracked<Region<T>> = &*self.shared;
+        compact_chunk(
+            &*self.arr, Tracked(region.borrow()), self.lo + a, self.lo + b,
+            out_arr, Tracked(&mut out_perms), out_lo, out_hi,
+            self.pred, self.p,
+        );
+        Tracked(out_perms)
+    }
+}
+
+/// The number of values of `a..b` that satisfy `pred`.
+fn count_chunk<T: Copy, F: Fn(T) -> bool>(
+    arr: &Array<T>,
+    Tracked(perms): Tracked<&Region<T>>,
+    a: usize, b: usize,
+    pred: F,
+    Ghost(p): Ghost<spec_fn(T) -> bool>,
+) -> (res: usize)
+    requires
+        region_array::wf(*arr, *perms),
+        perms.lo() <= a <= b <= perms.hi(),
+        decides(pred, p),
+    ensures
+        res <= b - a,
+        res == filtered(perms.values().subrange(a - perms.lo(), b - perms.lo()), p).len(),
+{
+    let ghost v = perms.values();
+    let ghost base = perms.lo() as int;
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+    }
+    let mut count: usize = 0;
+    let mut i = a;
+    assert(v.subrange(a - base, a - base) =~= Seq::<T>::empty());
+    while i < b
+        invariant
+            region_array::wf(*arr, *perms),
+            v == perms.values(),
+            base == perms.lo(),
+            v.len() == perms.hi() - base,
+            perms.lo() <= a <= i <= b <= perms.hi(),
+            decides(pred, p),
+            count <= i - a,
+            count == filtered(v.subrange(a - base, i - base), p).len(),
+    {
+        let x = *region_array::read(arr, i, Tracked(perms));
+        proof {
+            lemma_filtered_push(v, a - base, i - base, p);
+        }
+        if pred(x) {
+            count += 1;
+        }
+        i += 1;
+    }
+    count
+}
+
+/// Writes the values of `a..b` that satisfy `pred` to the region `out_lo..out_hi`,
+/// which fits them exactly.
+fn compact_chunk<T: Copy, F: Fn(T) -> bool>(
+    arr: &Array<T>,
+    Tracked(perms): Tracked<&Region<T>>,
+    a: usize, b: usize,
+    out_arr: &Array<T>,
+    Tracked(out_perms): Tracked<&mut Region<T>>,
+    out_lo: usize, out_hi: usize,
+    pred: F,
+    Ghost(p): Ghost<spec_fn(T) -> bool>,
+)
+    requires
+        region_array::wf(*arr, *perms),
+        perms.lo() <= a <= b <= perms.hi(),
+        region_array::wf(*out_arr, *old(out_perms)),
+        old(out_perms).lo() == out_lo,
+        old(out_perms).hi() == out_hi,
+        out_hi - out_lo == filtered(perms.values().subrange(a - perms.lo(), b - perms.lo()), p).len(),
+        decides(pred, p),
+    ensures
+        region_array::wf(*out_arr, *out_perms),
+        out_perms.lo() == out_lo,
+        out_perms.hi() == out_hi,
+        out_perms.values() == filtered(perms.values().subrange(a - perms.lo(), b - perms.lo()), p),
+{
+    let ghost v = perms.values();
+    let ghost base = perms.lo() as int;
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+        region_array::lemma_values_len(out_arr, *out_perms);
+    }
+    let mut o = out_lo;
+    let mut i = a;
+    assert(v.subrange(a - base, a - base) =~= Seq::<T>::empty());
+    assert(out_perms.values().subrange(0, 0) =~= Seq::<T>::empty());
+    while i < b
+        invariant
+            region_array::wf(*arr, *perms),
+            v == perms.values(),
+            base == perms.lo(),
+            v.len() == perms.hi() - base,
+            perms.lo() <= a <= i <= b <= perms.hi(),
+            region_array::wf(*out_arr, *out_perms),
+            out_perms.lo() == out_lo,
+            out_perms.hi() == out_hi,
+            out_perms.values().len() == out_hi - out_lo,
+            out_hi - out_lo == filtered(v.subrange(a - base, b - base), p).len(),
+            decides(pred, p),
+            out_lo <= o <= out_hi,
+            out_perms.values().subrange(0, o - out_lo) == filtered(v.subrange(a - base, i - base), p),
+    {
+        let ghost done = out_perms.values().subrange(0, o - out_lo);
+        let x = *region_array::read(arr, i, Tracked(perms));
+        proof {
+            lemma_filtered_push(v, a - base, i - base, p);
+            // the matches up to `i` are a prefix of the matches of the whole chunk
+            assert(v.subrange(a - base, b - base) =~= v.subrange(a - base, i + 1 - base) + v.subrange(i + 1 - base, b - base));
+            lemma_filtered_append(v.subrange(a - base, i + 1 - base), v.subrange(i + 1 - base, b - base), p);
+        }
+        if pred(x) {
+            region_array::replace(out_arr, o, x, Tracked(out_perms));
+            o += 1;
+            assert(out_perms.values().subrange(0, o - out_lo) =~= done.push(x));
+        }
+        i += 1;
+    }
+    assert(out_perms.values().subrange(0, o - out_lo) =~= out_perms.values());
+}
+
+#[test]
+fn test_par_filter() {
+    use crate::test_data::{scattered, thresholds};
+
+    let data: Vec<i32> = scattered(2000, 401, -200);
+    let expected: Vec<i32> = data.iter().copied().filter(|x| x % 3 == 0).collect();
+    let n = data.len();
+
+    for threshold in thresholds(n) {
+        let mut arr = crate::mergesort::ArrayForSorting::new(data.clone());
+        let mut out = crate::mergesort::ArrayForSorting::new(vec![7; n]);
+        let m = par_filter(
+            Arc::clone(&arr.array), Tracked(arr.perms.borrow_mut()), 0, n,
+            Arc::clone(&out.array), Tracked(out.perms.borrow_mut()), 0,
+            |x: i32| x % 3 == 0, Ghost(|x: i32| x % 3 == 0),
+            threshold,
+        ).unwrap();
+        assert_eq!(m, expected.len());
+        let res = out.clone_to_vec();
+        assert_eq!(&res[..m], &expected[..]);
+        assert!(res[m..].iter().all(|x| *x == 7));
+        assert_eq!(arr.clone_to_vec(), data);
+    }
+}
+
+}
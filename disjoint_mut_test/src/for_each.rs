@@ -0,0 +1,172 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+};
+
+/// `f` can be called on every cell of `lo..hi`, whatever its value.
+pub open spec fn callable_on<T, F: Fn(usize, T) -> T>(f: F, lo: int, hi: int) -> bool {
+    forall |i: usize, x: T| lo <= i < hi ==> #[trigger] f.requires((i, x))
+}
+
+/// Every value of `new_vals`, which start at the cell `lo`, is what `f` returned for
+/// the cell and its value in `old_vals`.
+pub open spec fn mapped<T, F: Fn(usize, T) -> T>(f: F, old_vals: Seq<T>, new_vals: Seq<T>, lo: int) -> bool {
+    &&& new_vals.len() == old_vals.len()
+    &&& forall |k: int| 0 <= k < new_vals.len() ==> f.ensures(((lo + k) as usize, old_vals[k]), #[trigger] new_vals[k])
+}
+
+/// Replaces the value of every cell `i` of `lo..hi` by `f(i, value)`.
+pub fn for_each_mut<T: Copy, F: Fn(usize, T) -> T>(
+    arr: &Array<T>,
+    Tracked(perms): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize,
+    f: F,
+)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        callable_on(f, lo as int, hi as int),
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        mapped(f, old(perms).values(), perms.values(), lo as int),
+{
+    let ghost old_vals = perms.values();
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+    }
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() == lo,
+            perms.hi() == hi,
+            perms.values().len() == old_vals.len(),
+            old_vals.len() == hi - lo,
+            lo <= i <= hi,
+            callable_on(f, lo as int, hi as int),
+            forall |k: int| 0 <= k < i - lo ==> f.ensures(((lo + k) as usize, old_vals[k]), #[trigger] perms.values()[k]),
+            forall |k: int| i - lo <= k < hi - lo ==> #[trigger] perms.values()[k] == old_vals[k],
+    {
+        let x = *region_array::read(arr, i, Tracked(perms));
+        let y = f(i, x);
+        region_array::replace(arr, i, y, Tracked(perms));
+        proof {
+            assert(perms.values()[i - lo] == y);
+            assert((lo + (i - lo)) as usize == i);
+        }
+        i += 1;
+    }
+}
+
+/// Like `for_each_mut`, but the region is split in halves that are updated in separate
+/// threads while they are longer than `threshold`.
+pub fn par_for_each_mut<T: Copy + Send + 'static, F: Fn(usize, T) -> T + Copy + Send + 'static>(
+    arr: Arc<Array<T>>,
+    Tracked(perms): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize,
+    threshold: usize,
+    f: F,
+) -> (ret: Result<(), ()>)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        callable_on(f, lo as int, hi as int),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, *perms),
+        ret.is_ok() ==> perms.lo() == lo && perms.hi() == hi,
+        ret.is_ok() ==> mapped(f, old(perms).values(), perms.values(), lo as int),
+{
+    if hi - lo <= threshold || hi - lo < 2 {
+        for_each_mut(&*arr, Tracked(perms), lo, hi, f);
+        return Ok(());
+    }
+
+    let mid = lo + (hi - lo) / 2;
+    let ghost old_vals = perms.values();
+    let tracked left_perms = region_array::split_front(&*arr, mid, perms);
+    let tracked mut right_perms = region_array::split_front(&*arr, hi, perms);
+    let ghost left_vals = left_perms.values();
+    let ghost right_vals = right_perms.values();
+    proof {
+        region_array::lemma_values_len(&*arr, left_perms);
+        region_array::lemma_values_len(&*arr, right_perms);
+    }
+
+    let arr_r1 = Arc::clone(&arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<T>>, ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap()@) && ret.unwrap()@.lo() == lo && ret.unwrap()@.hi() == mid,
+            ret.is_ok() ==> mapped(f, left_vals, ret.unwrap()@.values(), lo as int),
+        {
+            let tracked mut left_perms = left_perms;
+            match par_for_each_mut(arr_r1, Tracked(&mut left_perms), lo, mid, threshold, f) {
+                Ok(()) => Ok(Tracked(left_perms)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match par_for_each_mut(Arc::clone(&arr), Tracked(&mut right_perms), mid, hi, threshold, f) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut left_perms) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        let l = left_perms.values();
+        let r = right_perms.values();
+        assert(old_vals =~= left_vals + right_vals);
+        assert forall |k: int| 0 <= k < (l + r).len() implies
+            f.ensures(((lo + k) as usize, old_vals[k]), #[trigger] (l + r)[k]) by {
+            if k < l.len() {
+                assert((l + r)[k] == l[k]);
+            } else {
+                assert((l + r)[k] == r[k - l.len()]);
+                assert(old_vals[k] == right_vals[k - l.len()]);
+                assert((mid + (k - l.len())) as usize == (lo + k) as usize);
+            }
+        }
+        region_array::merge(&*arr, &mut left_perms, right_perms);
+        vstd::modes::tracked_swap(perms, &mut left_perms);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_par_for_each_mut() {
+    use crate::test_data::{scattered, thresholds};
+
+    let data: Vec<i64> = scattered(2000, 401, -200);
+    let expected: Vec<i64> = data.iter().enumerate().map(|(i, x)| x * 3 + i as i64).collect();
+
+    for threshold in thresholds(data.len()) {
+        let mut arr = crate::mergesort::ArrayForSorting::new(data.clone());
+        let n = data.len();
+        par_for_each_mut(
+            Arc::clone(&arr.array), Tracked(arr.perms.borrow_mut()), 0, n, threshold,
+            |i: usize, x: i64| x * 3 + i as i64,
+        ).unwrap();
+        assert_eq!(arr.clone_to_vec(), expected);
+    }
+}
+
+}
@@ -0,0 +1,710 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::ArrayForSorting,
+    chunks::offsets_wf,
+};
+
+/// `parts` holds the region `lo_cuts[r]..hi_cuts[r]` of every run `r`.
+pub open spec fn owns_parts(
+    arr: Array<i32>,
+    parts: Map<usize, Region<i32>>,
+    lo_cuts: Seq<usize>,
+    hi_cuts: Seq<usize>,
+) -> bool {
+    &&& lo_cuts.len() == hi_cuts.len()
+    &&& forall |r: usize| r < lo_cuts.len() ==>
+            #[trigger] parts.contains_key(r)
+            && region_array::wf(arr, parts[r])
+            && parts[r].lo() == lo_cuts[r as int]
+            && parts[r].hi() == hi_cuts[r as int]
+            && lo_cuts[r as int] <= hi_cuts[r as int]
+}
+
+/// Merges the sorted runs `runs[r]..runs[r + 1]` of `arr` into `out_arr`.
+/// Equal elements keep their order, runs that come first go first.
+pub fn k_way_merge(
+    arr: &mut ArrayForSorting<i32>,
+    runs: &Vec<usize>,
+    out_arr: &mut ArrayForSorting<i32>,
+) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        old(out_arr).perms@.lo() == 0,
+        old(out_arr).perms@.hi() == old(out_arr).array.len(),
+        region_array::wf(*old(out_arr).array, (old(out_arr).perms@)),
+        old(arr).array.len() == old(out_arr).array.len(),
+        offsets_wf(runs@, old(arr).array.len() as int),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo() && arr.perms@.hi() == old(arr).perms@.hi(),
+        ret.is_ok() ==> region_array::wf(*out_arr.array, (out_arr.perms@)),
+        ret.is_ok() ==> out_arr.perms@.lo() == old(out_arr).perms@.lo() && out_arr.perms@.hi() == old(out_arr).perms@.hi(),
+{
+    let n = (&*arr.array).length();
+    if n == 0 {
+        return Ok(());
+    }
+    if runs.len() > usize::MAX / 2 {
+        return Err(());
+    }
+    let (Tracked(parts), lo_cuts, hi_cuts) = take_runs(&arr.array, runs, Tracked(arr.perms.borrow_mut()));
+    if !merge_runs(&arr.array, Tracked(&parts), &lo_cuts, &hi_cuts, &out_arr.array, 0, n, Tracked(out_arr.perms.borrow_mut())) {
+        return Err(());
+    }
+    return_runs(&arr.array, runs, Tracked(arr.perms.borrow_mut()), Tracked(parts), &lo_cuts, &hi_cuts);
+    Ok(())
+}
+
+/// Like `k_way_merge`, but the output is split in halves, which are merged in separate threads
+/// while they are longer than `threshold`. The halves are found by co-ranking:
+/// every run is cut so that the parts before the cuts hold the first half of the output.
+pub fn k_way_merge_parallel(
+    arr: &mut ArrayForSorting<i32>,
+    runs: &Vec<usize>,
+    out_arr: &mut ArrayForSorting<i32>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        old(out_arr).perms@.lo() == 0,
+        old(out_arr).perms@.hi() == old(out_arr).array.len(),
+        region_array::wf(*old(out_arr).array, (old(out_arr).perms@)),
+        old(arr).array.len() == old(out_arr).array.len(),
+        offsets_wf(runs@, old(arr).array.len() as int),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo() && arr.perms@.hi() == old(arr).perms@.hi(),
+        ret.is_ok() ==> region_array::wf(*out_arr.array, (out_arr.perms@)),
+        ret.is_ok() ==> out_arr.perms@.lo() == old(out_arr).perms@.lo() && out_arr.perms@.hi() == old(out_arr).perms@.hi(),
+{
+    let n = (&*arr.array).length();
+    if n == 0 {
+        return Ok(());
+    }
+    if runs.len() > usize::MAX / 2 {
+        return Err(());
+    }
+    let (Tracked(parts), lo_cuts, hi_cuts) = take_runs(&arr.array, runs, Tracked(arr.perms.borrow_mut()));
+    let Tracked(parts) = match _k_way_merge_parallel(
+        Arc::clone(&arr.array),
+        Tracked(parts),
+        copy_cuts(&lo_cuts),
+        copy_cuts(&hi_cuts),
+        Arc::clone(&out_arr.array),
+        0,
+        n,
+        Tracked(out_arr.perms.borrow_mut()),
+        threshold,
+    ) {
+        Ok(parts) => parts,
+        Err(_) => {return Err(());},
+    };
+    return_runs(&arr.array, runs, Tracked(arr.perms.borrow_mut()), Tracked(parts), &lo_cuts, &hi_cuts);
+    Ok(())
+}
+
+fn copy_cuts(cuts: &Vec<usize>) -> (res: Vec<usize>)
+    ensures
+        res@ == cuts@,
+{
+    let mut res: Vec<usize> = Vec::new();
+    while res.len() < cuts.len()
+        invariant
+            res.len() <= cuts.len(),
+            forall |r: int| 0 <= r < res.len() ==> res@[r] == cuts@[r],
+    {
+        res.push(cuts[res.len()]);
+    }
+    assert(res@ =~= cuts@);
+    res
+}
+
+/// Cuts `perms` into one region per run.
+fn take_runs(
+    arr: &Array<i32>,
+    runs: &Vec<usize>,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+) -> (res: (Tracked<Map<usize, Region<i32>>>, Vec<usize>, Vec<usize>))
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == 0,
+        old(perms).hi() > 0,
+        offsets_wf(runs@, old(perms).hi() as int),
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == 0,
+        perms.hi() == 0,
+        owns_parts(*arr, res.0@, res.1@, res.2@),
+        res.1.len() == runs@.len() - 1,
+        forall |r: int| 0 <= r < res.1.len() ==> res.1@[r] == runs@[r] && res.2@[r] == runs@[r + 1],
+{
+    let k = runs.len() - 1;
+    let tracked mut rest = region_array::split_off(arr, 0, perms);
+    let tracked mut parts = Map::<usize, Region<i32>>::tracked_empty();
+    let mut lo_cuts: Vec<usize> = Vec::new();
+    let mut hi_cuts: Vec<usize> = Vec::new();
+    while lo_cuts.len() < k
+        invariant
+            k == runs@.len() - 1,
+            lo_cuts.len() <= k,
+            hi_cuts.len() == lo_cuts.len(),
+            offsets_wf(runs@, runs@.last() as int),
+            region_array::wf(*arr, rest),
+            rest.lo() == runs@[lo_cuts.len() as int],
+            rest.hi() == runs@.last(),
+            forall |r: int| 0 <= r < lo_cuts.len() ==> lo_cuts@[r] == runs@[r] && hi_cuts@[r] == runs@[r + 1],
+            owns_parts(*arr, parts, lo_cuts@, hi_cuts@),
+            region_array::wf(*arr, *perms),
+            perms.lo() == 0,
+            perms.hi() == 0,
+    {
+        let r = lo_cuts.len();
+        let (lo, hi) = (runs[r], runs[r + 1]);
+        let tracked part = region_array::split_front(arr, hi, &mut rest);
+        proof {
+            parts.tracked_insert(r, part);
+        }
+        lo_cuts.push(lo);
+        hi_cuts.push(hi);
+    }
+    (Tracked(parts), lo_cuts, hi_cuts)
+}
+
+/// Puts the regions of the runs back together, the inverse of `take_runs`.
+fn return_runs(
+    arr: &Array<i32>,
+    runs: &Vec<usize>,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    Tracked(parts): Tracked<Map<usize, Region<i32>>>,
+    lo_cuts: &Vec<usize>,
+    hi_cuts: &Vec<usize>,
+)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == 0,
+        old(perms).hi() == 0,
+        owns_parts(*arr, parts, lo_cuts@, hi_cuts@),
+        runs@.len() >= 1,
+        runs@[0] == 0,
+        lo_cuts.len() == runs@.len() - 1,
+        forall |r: int| 0 <= r < lo_cuts.len() ==> lo_cuts@[r] == runs@[r] && hi_cuts@[r] == runs@[r + 1],
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == 0,
+        perms.hi() == runs@.last(),
+{
+    let tracked mut parts = parts;
+    let k = lo_cuts.len();
+    let mut r: usize = 0;
+    while r < k
+        invariant
+            r <= k,
+            k == lo_cuts.len() == runs@.len() - 1,
+            forall |q: usize| r <= q < k ==>
+                #[trigger] parts.contains_key(q)
+                && region_array::wf(*arr, parts[q])
+                && parts[q].lo() == runs@[q as int]
+                && parts[q].hi() == runs@[q + 1],
+            region_array::wf(*arr, *perms),
+            perms.lo() == 0,
+            perms.hi() == runs@[r as int],
+    {
+        proof {
+            let tracked part = parts.tracked_remove(r);
+            region_array::merge(arr, perms, part);
+        }
+        r += 1;
+    }
+}
+
+/// Splits the regions of `lo_cuts..hi_cuts` at `mid_cuts`.
+fn split_parts(
+    arr: &Array<i32>,
+    Tracked(parts): Tracked<Map<usize, Region<i32>>>,
+    lo_cuts: &Vec<usize>,
+    mid_cuts: &Vec<usize>,
+    hi_cuts: &Vec<usize>,
+) -> (res: (Tracked<Map<usize, Region<i32>>>, Tracked<Map<usize, Region<i32>>>))
+    requires
+        owns_parts(*arr, parts, lo_cuts@, hi_cuts@),
+        mid_cuts.len() == lo_cuts.len(),
+        forall |r: int| 0 <= r < lo_cuts.len() ==> lo_cuts@[r] <= mid_cuts@[r] <= hi_cuts@[r],
+    ensures
+        owns_parts(*arr, res.0@, lo_cuts@, mid_cuts@),
+        owns_parts(*arr, res.1@, mid_cuts@, hi_cuts@),
+{
+    let tracked mut right_parts = parts;
+    let tracked mut left_parts = Map::<usize, Region<i32>>::tracked_empty();
+    let k = lo_cuts.len();
+    let mut r: usize = 0;
+    while r < k
+        invariant
+            r <= k,
+            k == lo_cuts.len() == mid_cuts.len() == hi_cuts.len(),
+            forall |q: int| 0 <= q < k ==> lo_cuts@[q] <= mid_cuts@[q] <= hi_cuts@[q],
+            forall |q: usize| q < r ==>
+                #[trigger] left_parts.contains_key(q)
+                && region_array::wf(*arr, left_parts[q])
+                && left_parts[q].lo() == lo_cuts@[q as int]
+                && left_parts[q].hi() == mid_cuts@[q as int],
+            forall |q: usize| q < k ==>
+                #[trigger] right_parts.contains_key(q)
+                && region_array::wf(*arr, right_parts[q])
+                && right_parts[q].lo() == (if q < r { mid_cuts@[q as int] } else { lo_cuts@[q as int] })
+                && right_parts[q].hi() == hi_cuts@[q as int],
+    {
+        let m = mid_cuts[r];
+        let tracked mut part = right_parts.tracked_remove(r);
+        let tracked left = region_array::split_front(arr, m, &mut part);
+        proof {
+            left_parts.tracked_insert(r, left);
+            right_parts.tracked_insert(r, part);
+        }
+        r += 1;
+    }
+    (Tracked(left_parts), Tracked(right_parts))
+}
+
+/// Puts the regions split by `split_parts` back together.
+fn join_parts(
+    arr: &Array<i32>,
+    Tracked(left_parts): Tracked<Map<usize, Region<i32>>>,
+    Tracked(right_parts): Tracked<Map<usize, Region<i32>>>,
+    lo_cuts: &Vec<usize>,
+    mid_cuts: &Vec<usize>,
+    hi_cuts: &Vec<usize>,
+) -> (parts: Tracked<Map<usize, Region<i32>>>)
+    requires
+        owns_parts(*arr, left_parts, lo_cuts@, mid_cuts@),
+        owns_parts(*arr, right_parts, mid_cuts@, hi_cuts@),
+    ensures
+        owns_parts(*arr, parts@, lo_cuts@, hi_cuts@),
+{
+    let tracked mut left_parts = left_parts;
+    let tracked mut right_parts = right_parts;
+    let tracked mut parts = Map::<usize, Region<i32>>::tracked_empty();
+    let k = lo_cuts.len();
+    let mut r: usize = 0;
+    while r < k
+        invariant
+            r <= k,
+            k == lo_cuts.len() == mid_cuts.len() == hi_cuts.len(),
+            forall |q: int| 0 <= q < k ==> lo_cuts@[q] <= mid_cuts@[q] <= hi_cuts@[q],
+            forall |q: usize| q < r ==>
+                #[trigger] parts.contains_key(q)
+                && region_array::wf(*arr, parts[q])
+                && parts[q].lo() == lo_cuts@[q as int]
+                && parts[q].hi() == hi_cuts@[q as int],
+            forall |q: usize| r <= q < k ==>
+                #[trigger] left_parts.contains_key(q)
+                && region_array::wf(*arr, left_parts[q])
+                && left_parts[q].lo() == lo_cuts@[q as int]
+                && left_parts[q].hi() == mid_cuts@[q as int],
+            forall |q: usize| r <= q < k ==>
+                #[trigger] right_parts.contains_key(q)
+                && region_array::wf(*arr, right_parts[q])
+                && right_parts[q].lo() == mid_cuts@[q as int]
+                && right_parts[q].hi() == hi_cuts@[q as int],
+    {
+        proof {
+            let tracked mut left = left_parts.tracked_remove(r);
+            let tracked right = right_parts.tracked_remove(r);
+            region_array::merge(arr, &mut left, right);
+            parts.tracked_insert(r, left);
+        }
+        r += 1;
+    }
+    Tracked(parts)
+}
+
+/// The first index in `lo..hi` whose element is `> v`, or `>= v` unless `inclusive`.
+fn search(
+    arr: &Array<i32>,
+    Tracked(part): Tracked<&Region<i32>>,
+    lo: usize, hi: usize,
+    v: i64,
+    inclusive: bool,
+) -> (res: usize)
+    requires
+        region_array::wf(*arr, *part),
+        part.lo() <= lo <= hi <= part.hi(),
+    ensures
+        lo <= res <= hi,
+{
+    let (mut l, mut h) = (lo, hi);
+    while l < h
+        invariant
+            region_array::wf(*arr, *part),
+            part.lo() <= lo <= l <= h <= hi <= part.hi(),
+    {
+        let mid = l + (h - l) / 2;
+        let x = *region_array::read(arr, mid, Tracked(part)) as i64;
+        if x < v || (inclusive && x == v) {
+            l = mid + 1;
+        } else {
+            h = mid;
+        }
+    }
+    l
+}
+
+/// Whether at most `limit` elements of the parts are `< v`.
+fn count_less_at_most(
+    arr: &Array<i32>,
+    Tracked(parts): Tracked<&Map<usize, Region<i32>>>,
+    lo_cuts: &Vec<usize>,
+    hi_cuts: &Vec<usize>,
+    v: i64,
+    limit: usize,
+) -> bool
+    requires
+        owns_parts(*arr, *parts, lo_cuts@, hi_cuts@),
+{
+    let mut count: usize = 0;
+    let mut r: usize = 0;
+    while r < lo_cuts.len()
+        invariant
+            owns_parts(*arr, *parts, lo_cuts@, hi_cuts@),
+            count <= limit,
+    {
+        let tracked part = parts.tracked_borrow(r);
+        let c = search(arr, Tracked(part), lo_cuts[r], hi_cuts[r], v, false) - lo_cuts[r];
+        if c > limit - count {
+            return false;
+        }
+        count += c;
+        r += 1;
+    }
+    true
+}
+
+/// Cuts every part so that the parts before the cuts hold its `p` smallest elements,
+/// where equal elements of earlier runs count as smaller.
+fn co_rank(
+    arr: &Array<i32>,
+    Tracked(parts): Tracked<&Map<usize, Region<i32>>>,
+    lo_cuts: &Vec<usize>,
+    hi_cuts: &Vec<usize>,
+    p: usize,
+) -> (cuts: Vec<usize>)
+    requires
+        owns_parts(*arr, *parts, lo_cuts@, hi_cuts@),
+    ensures
+        cuts.len() == lo_cuts.len(),
+        forall |r: int| 0 <= r < cuts.len() ==> lo_cuts@[r] <= cuts@[r] <= hi_cuts@[r],
+{
+    // the largest `v` with at most `p` elements `< v`
+    let mut v_lo = i32::MIN as i64;
+    let mut v_hi = i32::MAX as i64 + 1;
+    while v_hi - v_lo > 1
+        invariant
+            owns_parts(*arr, *parts, lo_cuts@, hi_cuts@),
+            i32::MIN <= v_lo < v_hi <= i32::MAX + 1,
+    {
+        let v = v_lo + (v_hi - v_lo) / 2;
+        if count_less_at_most(arr, Tracked(parts), lo_cuts, hi_cuts, v, p) {
+            v_lo = v;
+        } else {
+            v_hi = v;
+        }
+    }
+
+    // take everything `< v_lo`, and then as many elements `== v_lo` as needed, earlier runs first
+    let k = lo_cuts.len();
+    let mut cuts: Vec<usize> = Vec::new();
+    let mut need = p;
+    while cuts.len() < k
+        invariant
+            owns_parts(*arr, *parts, lo_cuts@, hi_cuts@),
+            k == lo_cuts.len(),
+            cuts.len() <= k,
+            forall |r: int| 0 <= r < cuts.len() ==> lo_cuts@[r] <= cuts@[r] <= hi_cuts@[r],
+    {
+        let r = cuts.len();
+        let tracked part = parts.tracked_borrow(r);
+        let c = search(arr, Tracked(part), lo_cuts[r], hi_cuts[r], v_lo, false);
+        need = if c - lo_cuts[r] > need { 0 } else { need - (c - lo_cuts[r]) };
+        cuts.push(c);
+    }
+    let mut r: usize = 0;
+    while r < k && need > 0
+        invariant
+            owns_parts(*arr, *parts, lo_cuts@, hi_cuts@),
+            k == lo_cuts.len() == cuts.len(),
+            forall |q: int| 0 <= q < k ==> lo_cuts@[q] <= cuts@[q] <= hi_cuts@[q],
+    {
+        let c = cuts[r];
+        let tracked part = parts.tracked_borrow(r);
+        let u = search(arr, Tracked(part), c, hi_cuts[r], v_lo, true);
+        let t = if u - c < need { u - c } else { need };
+        cuts.set(r, c + t);
+        need -= t;
+        r += 1;
+    }
+    cuts
+}
+
+/// Whether the next element of run `a` goes before the one of run `b`.
+/// Finished runs go last and ties go to the earlier run, which keeps the merge stable.
+fn goes_first(heads: &Vec<i32>, pos: &Vec<usize>, hi_cuts: &Vec<usize>, a: usize, b: usize) -> bool
+    requires
+        a < heads.len(),
+        b < heads.len(),
+        heads.len() == pos.len(),
+        pos.len() == hi_cuts.len(),
+{
+    if pos[b] >= hi_cuts[b] {
+        return true;
+    }
+    if pos[a] >= hi_cuts[a] {
+        return false;
+    }
+    heads[a] < heads[b] || (heads[a] == heads[b] && a < b)
+}
+
+/// Merges the parts into `out_arr[out_lo..out_hi]` with a tournament tree over the runs.
+/// Returns `false` if the parts do not add up to `out_hi - out_lo` elements.
+fn merge_runs(
+    arr: &Array<i32>,
+    Tracked(parts): Tracked<&Map<usize, Region<i32>>>,
+    lo_cuts: &Vec<usize>,
+    hi_cuts: &Vec<usize>,
+    out_arr: &Array<i32>,
+    out_lo: usize, out_hi: usize,
+    Tracked(out_perms): Tracked<&mut Region<i32>>,
+) -> (ok: bool)
+    requires
+        owns_parts(*arr, *parts, lo_cuts@, hi_cuts@),
+        lo_cuts.len() <= usize::MAX / 2,
+        region_array::wf(*out_arr, *old(out_perms)),
+        old(out_perms).lo() <= out_lo <= out_hi <= old(out_perms).hi(),
+    ensures
+        region_array::wf(*out_arr, *out_perms),
+        out_perms.lo() == old(out_perms).lo(),
+        out_perms.hi() == old(out_perms).hi(),
+{
+    let k = lo_cuts.len();
+    if k == 0 {
+        return out_lo == out_hi;
+    }
+
+    let mut pos: Vec<usize> = Vec::new();
+    let mut heads: Vec<i32> = Vec::new();
+    while pos.len() < k
+        invariant
+            owns_parts(*arr, *parts, lo_cuts@, hi_cuts@),
+            k == lo_cuts.len(),
+            pos.len() <= k,
+            heads.len() == pos.len(),
+            forall |r: int| 0 <= r < pos.len() ==> lo_cuts@[r] <= pos@[r] <= hi_cuts@[r],
+    {
+        let r = pos.len();
+        let lo = lo_cuts[r];
+        if lo < hi_cuts[r] {
+            let tracked part = parts.tracked_borrow(r);
+            heads.push(*region_array::read(arr, lo, Tracked(part)));
+        } else {
+            heads.push(0);
+        }
+        pos.push(lo);
+    }
+
+    // node `i` holds the run that goes first among its leaves, the leaves are `k..2 * k`
+    let mut tree: Vec<usize> = Vec::new();
+    while tree.len() < 2 * k
+        invariant
+            k <= usize::MAX / 2,
+            tree.len() <= 2 * k,
+            forall |i: int| 0 <= i < tree.len() ==> tree@[i] < k,
+    {
+        let i = tree.len();
+        tree.push(if i < k { 0 } else { i - k });
+    }
+    let mut i = k - 1;
+    while i > 0
+        invariant
+            0 < k <= usize::MAX / 2,
+            i < k,
+            tree.len() == 2 * k,
+            forall |j: int| 0 <= j < tree.len() ==> tree@[j] < k,
+            heads.len() == k,
+            pos.len() == k,
+            hi_cuts.len() == k,
+    {
+        let (a, b) = (tree[2 * i], tree[2 * i + 1]);
+        tree.set(i, if goes_first(&heads, &pos, hi_cuts, a, b) { a } else { b });
+        i -= 1;
+    }
+
+    let mut out = out_lo;
+    loop
+        invariant
+            owns_parts(*arr, *parts, lo_cuts@, hi_cuts@),
+            0 < k <= usize::MAX / 2,
+            k == lo_cuts.len() == hi_cuts.len(),
+            tree.len() == 2 * k,
+            forall |j: int| 0 <= j < tree.len() ==> tree@[j] < k,
+            heads.len() == k,
+            pos.len() == k,
+            forall |r: int| 0 <= r < k ==> lo_cuts@[r] <= pos@[r] <= hi_cuts@[r],
+            out_lo <= out <= out_hi,
+            region_array::wf(*out_arr, *out_perms),
+            out_perms.lo() == old(out_perms).lo(),
+            out_perms.hi() == old(out_perms).hi(),
+            old(out_perms).lo() <= out_lo,
+            out_hi <= old(out_perms).hi(),
+    {
+        let r = if k == 1 { 0 } else { tree[1] };
+        if pos[r] >= hi_cuts[r] {
+            // every run is finished
+            break;
+        }
+        if out == out_hi {
+            return false;
+        }
+        region_array::replace(out_arr, out, heads[r], Tracked(out_perms));
+        out += 1;
+
+        let p = pos[r] + 1;
+        pos.set(r, p);
+        if p < hi_cuts[r] {
+            let tracked part = parts.tracked_borrow(r);
+            heads.set(r, *region_array::read(arr, p, Tracked(part)));
+        }
+        let mut i = (r + k) / 2;
+        while i > 0
+            invariant
+                0 < k <= usize::MAX / 2,
+                i < k,
+                tree.len() == 2 * k,
+                forall |j: int| 0 <= j < tree.len() ==> tree@[j] < k,
+                heads.len() == k,
+                pos.len() == k,
+                hi_cuts.len() == k,
+        {
+            let (a, b) = (tree[2 * i], tree[2 * i + 1]);
+            tree.set(i, if goes_first(&heads, &pos, hi_cuts, a, b) { a } else { b });
+            i /= 2;
+        }
+    }
+    out == out_hi
+}
+
+/// Merges the parts into `out_arr[out_lo..out_hi]`: the first half of the output
+/// and the part of every run that goes there are handed to a new thread.
+fn _k_way_merge_parallel(
+    arr: Arc<Array<i32>>,
+    Tracked(parts): Tracked<Map<usize, Region<i32>>>,
+    lo_cuts: Vec<usize>,
+    hi_cuts: Vec<usize>,
+    out_arr: Arc<Array<i32>>,
+    out_lo: usize, out_hi: usize,
+    Tracked(out_perms): Tracked<&mut Region<i32>>,
+    threshold: usize,
+) -> (ret: Result<Tracked<Map<usize, Region<i32>>>, ()>)
+    requires
+        owns_parts(*arr, parts, lo_cuts@, hi_cuts@),
+        lo_cuts.len() <= usize::MAX / 2,
+        region_array::wf(*out_arr, *old(out_perms)),
+        old(out_perms).lo() <= out_lo <= out_hi <= old(out_perms).hi(),
+    ensures
+        ret.is_ok() ==> owns_parts(*arr, ret.unwrap()@, lo_cuts@, hi_cuts@),
+        ret.is_ok() ==> region_array::wf(*out_arr, *out_perms),
+        ret.is_ok() ==> old(out_perms).lo() == out_perms.lo() && old(out_perms).hi() == out_perms.hi(),
+{
+    let tracked parts = parts;
+    if out_hi - out_lo <= threshold || out_hi - out_lo < 2 {
+        if !merge_runs(&*arr, Tracked(&parts), &lo_cuts, &hi_cuts, &*out_arr, out_lo, out_hi, Tracked(out_perms)) {
+            return Err(());
+        }
+        return Ok(Tracked(parts));
+    }
+
+    let mid = out_lo + (out_hi - out_lo) / 2;
+    let mid_cuts = co_rank(&*arr, Tracked(&parts), &lo_cuts, &hi_cuts, mid - out_lo);
+    let (Tracked(left_parts), Tracked(right_parts)) = split_parts(&*arr, Tracked(parts), &lo_cuts, &mid_cuts, &hi_cuts);
+
+    let tracked out_right_perms = region_array::split_off(&*out_arr, mid, out_perms);
+    let tracked out_left_perms = region_array::split_off(&*out_arr, out_lo, out_perms);
+
+    let arr_r1 = Arc::clone(&arr);
+    let arr_r2 = Arc::clone(&arr);
+
+    let out_arr_r1 = Arc::clone(&out_arr);
+    let out_arr_r2 = Arc::clone(&out_arr);
+
+    let ghost lo_view = lo_cuts@;
+    let ghost mid_view = mid_cuts@;
+    let left_lo_cuts = copy_cuts(&lo_cuts);
+    let left_mid_cuts = copy_cuts(&mid_cuts);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<i32>>, Tracked<Map<usize, Region<i32>>>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*out_arr, ret.unwrap().0@) && ret.unwrap().0@.lo() == out_lo && ret.unwrap().0@.hi() == mid,
+            ret.is_ok() ==> owns_parts(*arr, ret.unwrap().1@, lo_view, mid_view),
+        {
+            let tracked mut out_left_perms = out_left_perms;
+            match _k_way_merge_parallel(arr_r1, Tracked(left_parts), left_lo_cuts, left_mid_cuts, out_arr_r1, out_lo, mid, Tracked(&mut out_left_perms), threshold) {
+                Ok(left_parts) => Ok((Tracked(out_left_perms), left_parts)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    let right_parts = match _k_way_merge_parallel(arr_r2, Tracked(right_parts), copy_cuts(&mid_cuts), copy_cuts(&hi_cuts), out_arr_r2, mid, out_hi, Tracked(&mut out_right_perms), threshold) {
+        Ok(right_parts) => right_parts,
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut out_left_perms), left_parts) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::merge(&*out_arr, &mut out_left_perms, out_right_perms);
+        region_array::merge(&*out_arr, out_perms, out_left_perms);
+    }
+    Ok(join_parts(&*arr, left_parts, right_parts, &lo_cuts, &mid_cuts, &hi_cuts))
+}
+
+#[test]
+fn test_k_way_merge_parallel() {
+    let mut data: Vec<i32> = Vec::new();
+    let mut runs = vec![0];
+    for r in 0..7 {
+        let mut run: Vec<i32> = (0..(r * 37) % 101).map(|i| (i * 7919 + r) % 50 - 25).collect();
+        run.sort();
+        data.extend(run);
+        runs.push(data.len());
+    }
+    let mut expected = data.clone();
+    expected.sort();
+
+    let mut arr = ArrayForSorting::new(data.clone());
+    let mut out_arr = ArrayForSorting::new(vec![0; data.len()]);
+    k_way_merge(&mut arr, &runs, &mut out_arr).unwrap();
+    assert_eq!(out_arr.clone_to_vec(), expected);
+
+    let mut out_arr = ArrayForSorting::new(vec![0; data.len()]);
+    k_way_merge_parallel(&mut arr, &runs, &mut out_arr, 16).unwrap();
+    assert_eq!(out_arr.clone_to_vec(), expected);
+    assert_eq!(arr.clone_to_vec(), data);
+}
+
+}
@@ -3,5 +3,32 @@ pub mod permissions_array;
 pub mod region_array;
 pub mod mergesort;
 pub mod mergesort_less_arcs;
+pub mod natural_mergesort;
+pub mod quicksort;
+pub mod sample_sort;
+pub mod radix_sort;
+pub mod bitonic_sort;
+pub mod odd_even_sort;
+pub mod k_way_merge;
+pub mod merge_sorted;
+pub mod binary_search;
+pub mod sorted_region;
+pub mod selection;
+pub mod argsort;
+pub mod soa_sort;
+pub mod segmented_sort;
+pub mod chunks;
+pub mod dedup;
+pub mod scan;
+pub mod reduce;
+pub mod for_each;
+pub mod filter;
+pub mod scatter;
+pub mod matrix;
+pub mod stencil;
+pub mod spmv;
+pub mod bitset;
 mod sandbox;
 mod shell;
+#[cfg(test)]
+mod test_data;
@@ -0,0 +1,1065 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+use crate::permissions_array::SpecPerms;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::take_shared,
+};
+
+/// The cells of the rows `r0..r1` and the columns `c0..c1` of a matrix with `cols` columns,
+/// which is stored row by row in an `Array`. Row bands span all the columns,
+/// column bands span all the rows.
+pub tracked struct Tile<T> {
+    tracked r0: usize,
+    tracked r1: usize,
+    tracked c0: usize,
+    tracked c1: usize,
+    tracked cols: usize,
+    perms: SpecPerms<T>,
+}
+
+impl<T> Tile<T> {
+    pub closed spec fn r0(&self) -> usize {
+        self.r0
+    }
+
+    pub closed spec fn r1(&self) -> usize {
+        self.r1
+    }
+
+    pub closed spec fn c0(&self) -> usize {
+        self.c0
+    }
+
+    pub closed spec fn c1(&self) -> usize {
+        self.c1
+    }
+
+    /// The number of columns of the whole matrix.
+    pub closed spec fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// The value of the cell in row `r` and column `c` of the whole matrix.
+    pub closed spec fn get(&self, r: int, c: int) -> T {
+        self.perms[index(self.cols, r, c)]@.value.unwrap()
+    }
+
+    pub open spec fn contains(&self, r: int, c: int) -> bool {
+        self.r0() <= r < self.r1() && self.c0() <= c < self.c1()
+    }
+
+    pub open spec fn same_bounds(&self, other: Tile<T>) -> bool {
+        &&& self.r0() == other.r0()
+        &&& self.r1() == other.r1()
+        &&& self.c0() == other.c0()
+        &&& self.c1() == other.c1()
+        &&& self.cols() == other.cols()
+    }
+
+    /// The tile covers the whole `rows` by `cols` matrix.
+    pub open spec fn is_full(&self, rows: usize, cols: usize) -> bool {
+        &&& self.r0() == 0
+        &&& self.r1() == rows
+        &&& self.c0() == 0
+        &&& self.c1() == cols
+        &&& self.cols() == cols
+    }
+
+    /// The rows of the tile, starting from `r0`, each starting from `c0`.
+    pub open spec fn values(&self) -> Seq<Seq<T>> {
+        Seq::new((self.r1() - self.r0()) as nat, |i: int|
+            Seq::new((self.c1() - self.c0()) as nat, |j: int| self.get(self.r0() + i, self.c0() + j)))
+    }
+}
+
+pub open spec fn index(cols: usize, r: int, c: int) -> usize {
+    (r * cols + c) as usize
+}
+
+spec fn cells(cols: usize, r0: int, r1: int, c0: int, c1: int) -> Set<usize> {
+    Set::new(|k: usize| r0 <= k / cols < r1 && c0 <= k % cols < c1)
+}
+
+pub closed spec fn wf<T>(aself: Array<T>, tile: Tile<T>) -> bool {
+    &&& 0 < tile.cols
+    &&& tile.r0 <= tile.r1
+    &&& tile.c0 <= tile.c1 <= tile.cols
+    &&& tile.r1 * tile.cols <= aself.len()
+    &&& aself.wf(tile.perms)
+    &&& tile.perms.dom() == cells(tile.cols, tile.r0 as int, tile.r1 as int, tile.c0 as int, tile.c1 as int)
+}
+
+pub proof fn lemma_bounds<T>(aself: &Array<T>, tile: Tile<T>)
+    requires
+        wf(*aself, tile),
+    ensures
+        0 < tile.cols(),
+        tile.r0() <= tile.r1(),
+        tile.c0() <= tile.c1() <= tile.cols(),
+        tile.r1() * tile.cols() <= aself.len(),
+{
+}
+
+/// The cell of row `r` and column `c` is stored before the row `r1`, and the row and the column
+/// can be recovered from its position.
+proof fn lemma_index(cols: usize, r: int, c: int, r1: int)
+    requires
+        0 < cols,
+        0 <= r < r1,
+        0 <= c < cols,
+    ensures
+        0 <= r * cols + c < r1 * cols,
+        (r * cols + c) / (cols as int) == r,
+        (r * cols + c) % (cols as int) == c,
+{
+    assert(0 <= r * cols + c < r1 * cols) by (nonlinear_arith)
+        requires 0 <= r < r1, 0 <= c < cols;
+    vstd::arithmetic::div_mod::lemma_fundamental_div_mod_converse(r * cols + c, cols as int, r, c);
+}
+
+/// The cells before the row `rows` are those whose row is before `rows`.
+proof fn lemma_cell(cols: usize, k: usize, rows: usize)
+    requires
+        0 < cols,
+    ensures
+        k < rows * cols <==> k / cols < rows,
+        k % cols < cols,
+{
+    let q = k as int / cols as int;
+    let m = k as int % cols as int;
+    vstd::arithmetic::div_mod::lemma_fundamental_div_mod(k as int, cols as int);
+    vstd::arithmetic::div_mod::lemma_mod_pos_bound(k as int, cols as int);
+    vstd::arithmetic::div_mod::lemma_div_pos_is_pos(k as int, cols as int);
+    assert(q < rows ==> k < rows * cols) by (nonlinear_arith)
+        requires k == cols * q + m, 0 <= m < cols, 0 <= q;
+    assert(q >= rows ==> k >= rows * cols) by (nonlinear_arith)
+        requires k == cols * q + m, 0 <= m < cols, 0 <= q;
+}
+
+/// Like `region_array::split_front`: returns the rows `r0..m` and keeps the rows `m..r1` in `tile`.
+pub proof fn split_rows<T>(aself: &Array<T>, tracked m: usize, tracked tile: &mut Tile<T>) -> (tracked res: Tile<T>)
+    requires
+        old(tile).r0() <= m <= old(tile).r1(),
+        wf(*aself, *old(tile)),
+    ensures
+        wf(*aself, *tile),
+        wf(*aself, res),
+        tile.r0() == m,
+        tile.r1() == old(tile).r1(),
+        tile.c0() == old(tile).c0() && tile.c1() == old(tile).c1() && tile.cols() == old(tile).cols(),
+        res.r0() == old(tile).r0(),
+        res.r1() == m,
+        res.c0() == old(tile).c0() && res.c1() == old(tile).c1() && res.cols() == old(tile).cols(),
+        forall |r: int, c: int| tile.contains(r, c) ==> #[trigger] tile.get(r, c) == old(tile).get(r, c),
+        forall |r: int, c: int| res.contains(r, c) ==> #[trigger] res.get(r, c) == old(tile).get(r, c),
+{
+    let ghost old_tile = *tile;
+    let ghost top_keys = cells(tile.cols, tile.r0 as int, m as int, tile.c0 as int, tile.c1 as int);
+    let tracked top_perms = tile.perms.tracked_remove_keys(top_keys);
+    let tracked top = Tile {
+        r0: tile.r0,
+        r1: m,
+        c0: tile.c0,
+        c1: tile.c1,
+        cols: tile.cols,
+        perms: top_perms,
+    };
+    tile.r0 = m;
+    assert(m * old_tile.cols <= old_tile.r1 * old_tile.cols) by (nonlinear_arith)
+        requires m <= old_tile.r1;
+    assert(aself.wf(tile.perms)) by {
+        aself.submap_wf(old_tile.perms, tile.perms);
+    }
+    assert(aself.wf(top_perms)) by {
+        aself.submap_wf(old_tile.perms, top_perms);
+    }
+    assert(top_perms.dom() =~= top_keys);
+    assert(tile.perms.dom() =~= cells(tile.cols, m as int, tile.r1 as int, tile.c0 as int, tile.c1 as int));
+    assert forall |r: int, c: int| top.contains(r, c) implies #[trigger] top.get(r, c) == old_tile.get(r, c) by {
+        lemma_index(old_tile.cols, r, c, old_tile.r1 as int);
+    }
+    assert forall |r: int, c: int| tile.contains(r, c) implies #[trigger] tile.get(r, c) == old_tile.get(r, c) by {
+        lemma_index(old_tile.cols, r, c, old_tile.r1 as int);
+    }
+    top
+}
+
+/// Returns the columns `c0..m` and keeps the columns `m..c1` in `tile`.
+pub proof fn split_cols<T>(aself: &Array<T>, tracked m: usize, tracked tile: &mut Tile<T>) -> (tracked res: Tile<T>)
+    requires
+        old(tile).c0() <= m <= old(tile).c1(),
+        wf(*aself, *old(tile)),
+    ensures
+        wf(*aself, *tile),
+        wf(*aself, res),
+        tile.c0() == m,
+        tile.c1() == old(tile).c1(),
+        tile.r0() == old(tile).r0() && tile.r1() == old(tile).r1() && tile.cols() == old(tile).cols(),
+        res.c0() == old(tile).c0(),
+        res.c1() == m,
+        res.r0() == old(tile).r0() && res.r1() == old(tile).r1() && res.cols() == old(tile).cols(),
+        forall |r: int, c: int| tile.contains(r, c) ==> #[trigger] tile.get(r, c) == old(tile).get(r, c),
+        forall |r: int, c: int| res.contains(r, c) ==> #[trigger] res.get(r, c) == old(tile).get(r, c),
+{
+    let ghost old_tile = *tile;
+    let ghost left_keys = cells(tile.cols, tile.r0 as int, tile.r1 as int, tile.c0 as int, m as int);
+    let tracked left_perms = tile.perms.tracked_remove_keys(left_keys);
+    let tracked left = Tile {
+        r0: tile.r0,
+        r1: tile.r1,
+        c0: tile.c0,
+        c1: m,
+        cols: tile.cols,
+        perms: left_perms,
+    };
+    tile.c0 = m;
+    assert(aself.wf(tile.perms)) by {
+        aself.submap_wf(old_tile.perms, tile.perms);
+    }
+    assert(aself.wf(left_perms)) by {
+        aself.submap_wf(old_tile.perms, left_perms);
+    }
+    assert(left_perms.dom() =~= left_keys);
+    assert(tile.perms.dom() =~= cells(tile.cols, tile.r0 as int, tile.r1 as int, m as int, tile.c1 as int));
+    assert forall |r: int, c: int| left.contains(r, c) implies #[trigger] left.get(r, c) == old_tile.get(r, c) by {
+        lemma_index(old_tile.cols, r, c, old_tile.r1 as int);
+    }
+    assert forall |r: int, c: int| tile.contains(r, c) implies #[trigger] tile.get(r, c) == old_tile.get(r, c) by {
+        lemma_index(old_tile.cols, r, c, old_tile.r1 as int);
+    }
+    left
+}
+
+/// Puts the rows of `bottom` below the rows of `top`.
+pub proof fn merge_rows<T>(aself: &Array<T>, tracked top: &mut Tile<T>, tracked bottom: Tile<T>)
+    requires
+        wf(*aself, *old(top)),
+        wf(*aself, bottom),
+        old(top).r1() == bottom.r0(),
+        old(top).c0() == bottom.c0() && old(top).c1() == bottom.c1() && old(top).cols() == bottom.cols(),
+    ensures
+        wf(*aself, *top),
+        top.r0() == old(top).r0(),
+        top.r1() == bottom.r1(),
+        top.c0() == old(top).c0() && top.c1() == old(top).c1() && top.cols() == old(top).cols(),
+        forall |r: int, c: int| old(top).contains(r, c) ==> #[trigger] top.get(r, c) == old(top).get(r, c),
+        forall |r: int, c: int| bottom.contains(r, c) ==> #[trigger] top.get(r, c) == bottom.get(r, c),
+{
+    let ghost old_top = *top;
+    top.perms.tracked_union_prefer_right(bottom.perms);
+    top.r1 = bottom.r1;
+    assert(aself.wf(top.perms)) by {
+        aself.union_wf(old_top.perms, bottom.perms);
+    }
+    assert(top.perms.dom() =~= cells(top.cols, top.r0 as int, top.r1 as int, top.c0 as int, top.c1 as int));
+    assert forall |r: int, c: int| old_top.contains(r, c) implies #[trigger] top.get(r, c) == old_top.get(r, c) by {
+        lemma_index(old_top.cols, r, c, old_top.r1 as int);
+        assert(!bottom.perms.dom().contains(index(old_top.cols, r, c)));
+    }
+    assert forall |r: int, c: int| bottom.contains(r, c) implies #[trigger] top.get(r, c) == bottom.get(r, c) by {
+        lemma_index(bottom.cols, r, c, bottom.r1 as int);
+    }
+}
+
+/// Puts the columns of `right` next to the columns of `left`.
+pub proof fn merge_cols<T>(aself: &Array<T>, tracked left: &mut Tile<T>, tracked right: Tile<T>)
+    requires
+        wf(*aself, *old(left)),
+        wf(*aself, right),
+        old(left).c1() == right.c0(),
+        old(left).r0() == right.r0() && old(left).r1() == right.r1() && old(left).cols() == right.cols(),
+    ensures
+        wf(*aself, *left),
+        left.c0() == old(left).c0(),
+        left.c1() == right.c1(),
+        left.r0() == old(left).r0() && left.r1() == old(left).r1() && left.cols() == old(left).cols(),
+        forall |r: int, c: int| old(left).contains(r, c) ==> #[trigger] left.get(r, c) == old(left).get(r, c),
+        forall |r: int, c: int| right.contains(r, c) ==> #[trigger] left.get(r, c) == right.get(r, c),
+{
+    let ghost old_left = *left;
+    left.perms.tracked_union_prefer_right(right.perms);
+    left.c1 = right.c1;
+    assert(aself.wf(left.perms)) by {
+        aself.union_wf(old_left.perms, right.perms);
+    }
+    assert(left.perms.dom() =~= cells(left.cols, left.r0 as int, left.r1 as int, left.c0 as int, left.c1 as int));
+    assert forall |r: int, c: int| old_left.contains(r, c) implies #[trigger] left.get(r, c) == old_left.get(r, c) by {
+        lemma_index(old_left.cols, r, c, old_left.r1 as int);
+        assert(!right.perms.dom().contains(index(old_left.cols, r, c)));
+    }
+    assert forall |r: int, c: int| right.contains(r, c) implies #[trigger] left.get(r, c) == right.get(r, c) by {
+        lemma_index(right.cols, r, c, right.r1 as int);
+    }
+}
+
+#[inline]
+pub fn read<'a, T>(aself: &'a Array<T>, cols: usize, r: usize, c: usize, Tracked(tile): Tracked<&'a Tile<T>>) -> (res: &'a T)
+    requires
+        wf(*aself, *tile),
+        tile.cols() == cols,
+        tile.contains(r as int, c as int),
+    ensures
+        *res == tile.get(r as int, c as int),
+{
+    proof {
+        lemma_index(cols, r as int, c as int, tile.r1 as int);
+    }
+    aself.read(r * cols + c, Tracked(&tile.perms))
+}
+
+#[inline]
+pub fn replace<T>(aself: &Array<T>, cols: usize, r: usize, c: usize, x: T, Tracked(tile): Tracked<&mut Tile<T>>) -> (res: T)
+    requires
+        wf(*aself, *old(tile)),
+        old(tile).cols() == cols,
+        old(tile).contains(r as int, c as int),
+    ensures
+        wf(*aself, *tile),
+        tile.same_bounds(*old(tile)),
+        tile.get(r as int, c as int) == x,
+        forall |r2: int, c2: int| tile.contains(r2, c2) && (r2 != r || c2 != c) ==>
+            #[trigger] tile.get(r2, c2) == old(tile).get(r2, c2),
+        res == old(tile).get(r as int, c as int),
+{
+    proof {
+        lemma_index(cols, r as int, c as int, tile.r1 as int);
+    }
+    let res = aself.replace(r * cols + c, x, Tracked(&mut tile.perms));
+    proof {
+        assert(tile.perms.dom() =~= old(tile).perms.dom());
+        assert forall |r2: int, c2: int| tile.contains(r2, c2) && (r2 != r || c2 != c) implies
+            #[trigger] tile.get(r2, c2) == old(tile).get(r2, c2) by {
+            lemma_index(cols, r2, c2, tile.r1 as int);
+        }
+    }
+    res
+}
+
+pub struct Matrix<T> {
+    pub array: Arc<Array<T>>,
+    pub rows: usize,
+    pub cols: usize,
+    pub perms: Tracked<Tile<T>>,
+}
+
+impl<T> Matrix<T> {
+    pub open spec fn full(&self) -> bool {
+        &&& wf(*self.array, self.perms@)
+        &&& self.perms@.is_full(self.rows, self.cols)
+    }
+
+    /// Takes the matrix as `data`, row by row. Matrices without columns are not supported.
+    pub fn new(data: Vec<T>, rows: usize, cols: usize) -> (res: Self)
+        where Self: std::marker::Sized,
+        requires
+            0 < cols,
+            data.len() == rows * cols,
+        ensures
+            res.full(),
+            res.rows == rows,
+            res.cols == cols,
+    {
+        let (array, Tracked(region)) = crate::region_array::new(data);
+        let tracked perms = crate::region_array::into_perms(&array, region);
+        proof {
+            assert forall |k: usize| #[trigger] cells(cols, 0, rows as int, 0, cols as int).contains(k)
+                <==> perms.dom().contains(k) by {
+                lemma_cell(cols, k, rows);
+            }
+            assert(perms.dom() =~= cells(cols, 0, rows as int, 0, cols as int));
+        }
+        let tracked tile = Tile {
+            r0: 0usize,
+            r1: rows,
+            c0: 0usize,
+            c1: cols,
+            cols: cols,
+            perms: perms,
+        };
+        Self {
+            array: Arc::new(array),
+            rows,
+            cols,
+            perms: Tracked(tile),
+        }
+    }
+
+    /// Returns the matrix row by row.
+    pub fn clone_to_vec(&self) -> Vec<T>
+    where
+        Self: Sized,
+        T: Clone,
+    requires
+        self.full(),
+    {
+        let mut res: Vec<T> = Vec::with_capacity((&*self.array).length());
+        let mut r: usize = 0;
+        while r < self.rows
+            invariant
+                self.full(),
+                r <= self.rows,
+        {
+            let mut c: usize = 0;
+            while c < self.cols
+                invariant
+                    self.full(),
+                    r < self.rows,
+                    c <= self.cols,
+            {
+                res.push(read(&*self.array, self.cols, r, c, Tracked(self.perms.borrow())).clone());
+                c += 1;
+            }
+            r += 1;
+        }
+        res
+    }
+}
+
+/// The entry in row `i` and column `j` of the product of `a` and `b`, summed over the first `len` terms.
+pub open spec fn dot(a: Seq<Seq<u64>>, b: Seq<Seq<u64>>, i: int, j: int, len: int) -> int
+    decreases len,
+{
+    if len <= 0 {
+        0
+    } else {
+        dot(a, b, i, j, len - 1) + a[i][len - 1] * b[len - 1][j]
+    }
+}
+
+/// Every entry of the product of `a`, with `k` columns, and `b`, with `p` columns, fits in `u64`.
+pub open spec fn fits(a: Seq<Seq<u64>>, b: Seq<Seq<u64>>, k: int, p: int) -> bool {
+    forall |i: int, j: int| 0 <= i < a.len() && 0 <= j < p ==> #[trigger] dot(a, b, i, j, k) <= u64::MAX
+}
+
+proof fn lemma_dot_monotonic(a: Seq<Seq<u64>>, b: Seq<Seq<u64>>, i: int, j: int, l1: int, l2: int)
+    requires
+        0 <= l1 <= l2,
+    ensures
+        0 <= dot(a, b, i, j, l1) <= dot(a, b, i, j, l2),
+    decreases l2,
+{
+    if l2 > 0 {
+        if l1 < l2 {
+            lemma_dot_monotonic(a, b, i, j, l1, l2 - 1);
+        } else {
+            lemma_dot_monotonic(a, b, i, j, l1 - 1, l2 - 1);
+        }
+        let x = a[i][l2 - 1] as int;
+        let y = b[l2 - 1][j] as int;
+        assert(x * y >= 0) by (nonlinear_arith)
+            requires x >= 0, y >= 0;
+    }
+}
+
+/// Computes the entry in row `i` and column `j` of the product.
+fn dot_entry(
+    a_arr: &Array<u64>,
+    Tracked(a): Tracked<&Tile<u64>>,
+    b_arr: &Array<u64>,
+    Tracked(b): Tracked<&Tile<u64>>,
+    n: usize, k: usize, p: usize,
+    i: usize, j: usize,
+) -> (res: u64)
+    requires
+        wf(*a_arr, *a),
+        a.is_full(n, k),
+        wf(*b_arr, *b),
+        b.is_full(k, p),
+        i < n,
+        j < p,
+        dot(a.values(), b.values(), i as int, j as int, k as int) <= u64::MAX,
+    ensures
+        res == dot(a.values(), b.values(), i as int, j as int, k as int),
+{
+    let ghost av = a.values();
+    let ghost bv = b.values();
+    let mut acc: u64 = 0;
+    let mut t: usize = 0;
+    while t < k
+        invariant
+            wf(*a_arr, *a),
+            a.is_full(n, k),
+            wf(*b_arr, *b),
+            b.is_full(k, p),
+            av == a.values(),
+            bv == b.values(),
+            i < n,
+            j < p,
+            t <= k,
+            dot(av, bv, i as int, j as int, k as int) <= u64::MAX,
+            acc == dot(av, bv, i as int, j as int, t as int),
+    {
+        let x = *read(a_arr, k, i, t, Tracked(a));
+        let y = *read(b_arr, p, t, j, Tracked(b));
+        proof {
+            assert(av[i as int][t as int] == x);
+            assert(bv[t as int][j as int] == y);
+            assert(dot(av, bv, i as int, j as int, t + 1) == acc + x * y);
+            lemma_dot_monotonic(av, bv, i as int, j as int, t + 1, k as int);
+        }
+        acc = acc + x * y;
+        t += 1;
+    }
+    acc
+}
+
+/// Computes the cells of the tile `c` of the product of `a` and `b`.
+fn matmul_tile(
+    a_arr: &Array<u64>,
+    Tracked(a): Tracked<&Tile<u64>>,
+    b_arr: &Array<u64>,
+    Tracked(b): Tracked<&Tile<u64>>,
+    c_arr: &Array<u64>,
+    Tracked(c): Tracked<&mut Tile<u64>>,
+    n: usize, k: usize, p: usize,
+    r0: usize, r1: usize, c0: usize, c1: usize,
+)
+    requires
+        wf(*a_arr, *a),
+        a.is_full(n, k),
+        wf(*b_arr, *b),
+        b.is_full(k, p),
+        fits(a.values(), b.values(), k as int, p as int),
+        wf(*c_arr, *old(c)),
+        old(c).cols() == p,
+        old(c).r0() == r0 && old(c).r1() == r1 && old(c).c0() == c0 && old(c).c1() == c1,
+        r1 <= n,
+    ensures
+        wf(*c_arr, *c),
+        c.same_bounds(*old(c)),
+        forall |r: int, q: int| c.contains(r, q) ==>
+            #[trigger] c.get(r, q) == dot(a.values(), b.values(), r, q, k as int),
+{
+    let ghost av = a.values();
+    let ghost bv = b.values();
+    proof {
+        lemma_bounds(c_arr, *c);
+    }
+    let mut i = r0;
+    while i < r1
+        invariant
+            wf(*a_arr, *a),
+            a.is_full(n, k),
+            wf(*b_arr, *b),
+            b.is_full(k, p),
+            av == a.values(),
+            bv == b.values(),
+            fits(av, bv, k as int, p as int),
+            wf(*c_arr, *c),
+            c.same_bounds(*old(c)),
+            old(c).cols() == p,
+            old(c).r0() == r0 && old(c).r1() == r1 && old(c).c0() == c0 && old(c).c1() == c1,
+            c1 <= p,
+            r1 <= n,
+            r0 <= i <= r1,
+            forall |r: int, q: int| c.contains(r, q) && r < i ==>
+                #[trigger] c.get(r, q) == dot(av, bv, r, q, k as int),
+    {
+        let mut j = c0;
+        while j < c1
+            invariant
+                wf(*a_arr, *a),
+                a.is_full(n, k),
+                wf(*b_arr, *b),
+                b.is_full(k, p),
+                av == a.values(),
+                bv == b.values(),
+                fits(av, bv, k as int, p as int),
+                wf(*c_arr, *c),
+                c.same_bounds(*old(c)),
+                old(c).cols() == p,
+                old(c).r0() == r0 && old(c).r1() == r1 && old(c).c0() == c0 && old(c).c1() == c1,
+                c1 <= p,
+                r1 <= n,
+                r0 <= i < r1,
+                c0 <= j <= c1,
+                forall |r: int, q: int| c.contains(r, q) && (r < i || (r == i && q < j)) ==>
+                    #[trigger] c.get(r, q) == dot(av, bv, r, q, k as int),
+        {
+            proof {
+                assert(dot(av, bv, i as int, j as int, k as int) <= u64::MAX);
+            }
+            let x = dot_entry(a_arr, Tracked(a), b_arr, Tracked(b), n, k, p, i, j);
+            replace(c_arr, p, i, j, x, Tracked(c));
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+fn _matmul_parallel(
+    a_arr: Arc<Array<u64>>,
+    a_shared: Arc<Tracked<Tile<u64>>>,
+    b_arr: Arc<Array<u64>>,
+    b_shared: Arc<Tracked<Tile<u64>>>,
+    c_arr: Arc<Array<u64>>,
+    Tracked(c): Tracked<&mut Tile<u64>>,
+    n: usize, k: usize, p: usize,
+    r0: usize, r1: usize, c0: usize, c1: usize,
+    tile_rows: usize, tile_cols: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        wf(*a_arr, (*a_shared)@),
+        (*a_shared)@.is_full(n, k),
+        wf(*b_arr, (*b_shared)@),
+        (*b_shared)@.is_full(k, p),
+        fits((*a_shared)@.values(), (*b_shared)@.values(), k as int, p as int),
+        wf(*c_arr, *old(c)),
+        old(c).cols() == p,
+        old(c).r0() == r0 && old(c).r1() == r1 && old(c).c0() == c0 && old(c).c1() == c1,
+        r1 <= n,
+    ensures
+        ret.is_ok() ==> wf(*c_arr, *c) && c.same_bounds(*old(c)),
+        ret.is_ok() ==> forall |r: int, q: int| c.contains(r, q) ==>
+            #[trigger] c.get(r, q) == dot((*a_shared)@.values(), (*b_shared)@.values(), r, q, k as int),
+{
+    let ghost av = (*a_shared)@.values();
+    let ghost bv = (*b_shared)@.values();
+    proof {
+        lemma_bounds(&*c_arr, *c);
+    }
+    let split_rows_first = r1 - r0 > tile_rows && r1 - r0 >= 2;
+    if !split_rows_first && (c1 - c0 <= tile_cols || c1 - c0 < 2) {
+        let a_tile: &Tracked<Tile<u64>> = &*a_shared;
+        let b_tile: &Tracked<Tile<u64>> = &*b_shared;
+        matmul_tile(
+            &*a_arr, Tracked(a_tile.borrow()), &*b_arr, Tracked(b_tile.borrow()),
+            &*c_arr, Tracked(c), n, k, p, r0, r1, c0, c1,
+        );
+        return Ok(());
+    }
+
+    // the first half gets the top rows, or the left columns once the tile is short enough
+    let mid = if split_rows_first { r0 + (r1 - r0) / 2 } else { c0 + (c1 - c0) / 2 };
+    let tracked first_tile = if split_rows_first {
+        split_rows(&*c_arr, mid, c)
+    } else {
+        split_cols(&*c_arr, mid, c)
+    };
+    let tracked mut second_tile = if split_rows_first {
+        split_rows(&*c_arr, r1, c)
+    } else {
+        split_cols(&*c_arr, c1, c)
+    };
+    let ghost first_bounds = first_tile;
+    let (r0_1, r1_1, c0_1, c1_1) = if split_rows_first { (r0, mid, c0, c1) } else { (r0, r1, c0, mid) };
+    let (r0_2, r1_2, c0_2, c1_2) = if split_rows_first { (mid, r1, c0, c1) } else { (r0, r1, mid, c1) };
+
+    let a_arr_r1 = Arc::clone(&a_arr);
+    let a_shared_r1 = Arc::clone(&a_shared);
+    let b_arr_r1 = Arc::clone(&b_arr);
+    let b_shared_r1 = Arc::clone(&b_shared);
+    let c_arr_r1 = Arc::clone(&c_arr);
+
+    let first = vstd::thread::spawn(move || -> (ret: Result<Tracked<Tile<u64>>, ()>)
+        ensures
+            ret.is_ok() ==> wf(*c_arr, ret.unwrap()@) && ret.unwrap()@.same_bounds(first_bounds),
+            ret.is_ok() ==> forall |r: int, q: int| ret.unwrap()@.contains(r, q) ==>
+                #[trigger] ret.unwrap()@.get(r, q) == dot(av, bv, r, q, k as int),
+        {
+            let tracked mut first_tile = first_tile;
+            match _matmul_parallel(
+                a_arr_r1, a_shared_r1, b_arr_r1, b_shared_r1, c_arr_r1, Tracked(&mut first_tile),
+                n, k, p, r0_1, r1_1, c0_1, c1_1, tile_rows, tile_cols,
+            ) {
+                Ok(()) => Ok(Tracked(first_tile)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match _matmul_parallel(
+        Arc::clone(&a_arr), Arc::clone(&a_shared), Arc::clone(&b_arr), Arc::clone(&b_shared),
+        Arc::clone(&c_arr), Tracked(&mut second_tile), n, k, p, r0_2, r1_2, c0_2, c1_2, tile_rows, tile_cols,
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut first_tile) = match first.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        let ghost first_done = first_tile;
+        let ghost second_done = second_tile;
+        if split_rows_first {
+            merge_rows(&*c_arr, &mut first_tile, second_tile);
+        } else {
+            merge_cols(&*c_arr, &mut first_tile, second_tile);
+        }
+        vstd::modes::tracked_swap(c, &mut first_tile);
+        assert forall |r: int, q: int| c.contains(r, q) implies #[trigger] c.get(r, q) == dot(av, bv, r, q, k as int) by {
+            if first_done.contains(r, q) {
+                assert(first_done.get(r, q) == dot(av, bv, r, q, k as int));
+            } else {
+                assert(second_done.contains(r, q));
+                assert(second_done.get(r, q) == dot(av, bv, r, q, k as int));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Moves the tile of `m` into an `Arc` that threads can share, leaving an empty tile in `m`.
+pub fn share<T>(m: &mut Matrix<T>) -> (res: Arc<Tracked<Tile<T>>>)
+    requires
+        old(m).full(),
+    ensures
+        m.array == old(m).array,
+        m.rows == old(m).rows,
+        m.cols == old(m).cols,
+        wf(*m.array, (*res)@),
+        (*res)@.is_full(m.rows, m.cols),
+        (*res)@.values() == old(m).perms@.values(),
+{
+    let tracked mut tile = split_rows(&*m.array, 0, m.perms.borrow_mut());
+    proof {
+        vstd::modes::tracked_swap(m.perms.borrow_mut(), &mut tile);
+        assert(tile.values() =~~= old(m).perms@.values());
+    }
+    Arc::new(Tracked(tile))
+}
+
+/// Puts back the tile that `share` moved out of `m`. Like `mergesort::unshare`, it is
+/// called on every path, including after errors.
+pub fn unshare<T>(m: &mut Matrix<T>, shared: Arc<Tracked<Tile<T>>>) -> (ret: Result<(), ()>)
+    ensures
+        m.array == old(m).array,
+        m.rows == old(m).rows,
+        m.cols == old(m).cols,
+        ret.is_ok() ==> m.perms@ == (*shared)@,
+{
+    let Tracked(mut tile) = match take_shared(shared) {
+        Some(tile) => tile,
+        None => {return Err(());},
+    };
+    proof {
+        vstd::modes::tracked_swap(m.perms.borrow_mut(), &mut tile);
+    }
+    Ok(())
+}
+
+/// Writes the product of `a` and `b` to `c`. The tile of `c` is split in halves, first by rows
+/// and then by columns, until it has at most `tile_rows` rows and `tile_cols` columns,
+/// and every thread owns its tile of `c` while sharing `a` and `b` through `Arc`s.
+/// The entries of the product must fit in `u64`.
+pub fn par_matmul(
+    a: &mut Matrix<u64>,
+    b: &mut Matrix<u64>,
+    c: &mut Matrix<u64>,
+    tile_rows: usize,
+    tile_cols: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(a).full(),
+        old(b).full(),
+        old(c).full(),
+        old(a).cols == old(b).rows,
+        old(c).rows == old(a).rows,
+        old(c).cols == old(b).cols,
+        fits(old(a).perms@.values(), old(b).perms@.values(), old(a).cols as int, old(b).cols as int),
+    ensures
+        ret.is_ok() ==> a.full() && a.rows == old(a).rows && a.cols == old(a).cols,
+        ret.is_ok() ==> a.perms@.values() == old(a).perms@.values(),
+        ret.is_ok() ==> b.full() && b.rows == old(b).rows && b.cols == old(b).cols,
+        ret.is_ok() ==> b.perms@.values() == old(b).perms@.values(),
+        ret.is_ok() ==> c.full() && c.rows == old(c).rows && c.cols == old(c).cols,
+        ret.is_ok() ==> forall |i: int, j: int| 0 <= i < c.rows && 0 <= j < c.cols ==>
+            #[trigger] c.perms@.values()[i][j] == dot(old(a).perms@.values(), old(b).perms@.values(), i, j, old(a).cols as int),
+{
+    let n = a.rows;
+    let k = a.cols;
+    let p = b.cols;
+
+    // leave empty tiles in `a` and `b` while the threads share them
+    let a_shared = share(a);
+    let b_shared = share(b);
+    let ghost av = (*a_shared)@.values();
+    let ghost bv = (*b_shared)@.values();
+
+    let ret = _matmul_parallel(
+        Arc::clone(&a.array),
+        Arc::clone(&a_shared),
+        Arc::clone(&b.array),
+        Arc::clone(&b_shared),
+        Arc::clone(&c.array),
+        Tracked(c.perms.borrow_mut()),
+        n, k, p,
+        0, n, 0, p,
+        tile_rows, tile_cols,
+    );
+    // `a` and `b` are given back their tiles before any error is returned
+    let a_ret = unshare(a, a_shared);
+    let b_ret = unshare(b, b_shared);
+    if ret.is_err() || a_ret.is_err() || b_ret.is_err() {
+        return Err(());
+    }
+    proof {
+        assert forall |i: int, j: int| 0 <= i < c.rows && 0 <= j < c.cols implies
+            #[trigger] c.perms@.values()[i][j] == dot(av, bv, i, j, k as int) by {
+            assert(c.perms@.contains(i, j));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the transpose of the cells of the tile `b`.
+fn transpose_tile<T: Copy>(
+    a_arr: &Array<T>,
+    Tracked(a): Tracked<&Tile<T>>,
+    b_arr: &Array<T>,
+    Tracked(b): Tracked<&mut Tile<T>>,
+    n: usize, m: usize,
+    r0: usize, r1: usize, c0: usize, c1: usize,
+)
+    requires
+        wf(*a_arr, *a),
+        a.is_full(n, m),
+        wf(*b_arr, *old(b)),
+        old(b).cols() == n,
+        old(b).r0() == r0 && old(b).r1() == r1 && old(b).c0() == c0 && old(b).c1() == c1,
+        r1 <= m,
+    ensures
+        wf(*b_arr, *b),
+        b.same_bounds(*old(b)),
+        forall |r: int, q: int| b.contains(r, q) ==> #[trigger] b.get(r, q) == a.get(q, r),
+{
+    proof {
+        lemma_bounds(b_arr, *b);
+    }
+    let mut i = r0;
+    while i < r1
+        invariant
+            wf(*a_arr, *a),
+            a.is_full(n, m),
+            wf(*b_arr, *b),
+            b.same_bounds(*old(b)),
+            old(b).cols() == n,
+            old(b).r0() == r0 && old(b).r1() == r1 && old(b).c0() == c0 && old(b).c1() == c1,
+            c1 <= n,
+            r1 <= m,
+            r0 <= i <= r1,
+            forall |r: int, q: int| b.contains(r, q) && r < i ==> #[trigger] b.get(r, q) == a.get(q, r),
+    {
+        let mut j = c0;
+        while j < c1
+            invariant
+                wf(*a_arr, *a),
+                a.is_full(n, m),
+                wf(*b_arr, *b),
+                b.same_bounds(*old(b)),
+                old(b).cols() == n,
+                old(b).r0() == r0 && old(b).r1() == r1 && old(b).c0() == c0 && old(b).c1() == c1,
+                c1 <= n,
+                r1 <= m,
+                r0 <= i < r1,
+                c0 <= j <= c1,
+                forall |r: int, q: int| b.contains(r, q) && (r < i || (r == i && q < j)) ==>
+                    #[trigger] b.get(r, q) == a.get(q, r),
+        {
+            let x = *read(a_arr, m, j, i, Tracked(a));
+            replace(b_arr, n, i, j, x, Tracked(b));
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+fn _transpose_parallel<T: Copy + Send + Sync + 'static>(
+    a_arr: Arc<Array<T>>,
+    a_shared: Arc<Tracked<Tile<T>>>,
+    b_arr: Arc<Array<T>>,
+    Tracked(b): Tracked<&mut Tile<T>>,
+    n: usize, m: usize,
+    r0: usize, r1: usize, c0: usize, c1: usize,
+    tile_rows: usize, tile_cols: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        wf(*a_arr, (*a_shared)@),
+        (*a_shared)@.is_full(n, m),
+        wf(*b_arr, *old(b)),
+        old(b).cols() == n,
+        old(b).r0() == r0 && old(b).r1() == r1 && old(b).c0() == c0 && old(b).c1() == c1,
+        r1 <= m,
+    ensures
+        ret.is_ok() ==> wf(*b_arr, *b) && b.same_bounds(*old(b)),
+        ret.is_ok() ==> forall |r: int, q: int| b.contains(r, q) ==> #[trigger] b.get(r, q) == (*a_shared)@.get(q, r),
+{
+    let ghost at = (*a_shared)@;
+    proof {
+        lemma_bounds(&*b_arr, *b);
+    }
+    let split_rows_first = r1 - r0 > tile_rows && r1 - r0 >= 2;
+    if !split_rows_first && (c1 - c0 <= tile_cols || c1 - c0 < 2) {
+        let a_tile: &Tracked<Tile<T>> = &*a_shared;
+        transpose_tile(&*a_arr, Tracked(a_tile.borrow()), &*b_arr, Tracked(b), n, m, r0, r1, c0, c1);
+        return Ok(());
+    }
+
+    let mid = if split_rows_first { r0 + (r1 - r0) / 2 } else { c0 + (c1 - c0) / 2 };
+    let tracked first_tile = if split_rows_first {
+        split_rows(&*b_arr, mid, b)
+    } else {
+        split_cols(&*b_arr, mid, b)
+    };
+    let tracked mut second_tile = if split_rows_first {
+        split_rows(&*b_arr, r1, b)
+    } else {
+        split_cols(&*b_arr, c1, b)
+    };
+    let ghost first_bounds = first_tile;
+    let (r0_1, r1_1, c0_1, c1_1) = if split_rows_first { (r0, mid, c0, c1) } else { (r0, r1, c0, mid) };
+    let (r0_2, r1_2, c0_2, c1_2) = if split_rows_first { (mid, r1, c0, c1) } else { (r0, r1, mid, c1) };
+
+    let a_arr_r1 = Arc::clone(&a_arr);
+    let a_shared_r1 = Arc::clone(&a_shared);
+    let b_arr_r1 = Arc::clone(&b_arr);
+
+    let first = vstd::thread::spawn(move || -> (ret: Result<Tracked<Tile<T>>, ()>)
+        ensures
+            ret.is_ok() ==> wf(*b_arr, ret.unwrap()@) && ret.unwrap()@.same_bounds(first_bounds),
+            ret.is_ok() ==> forall |r: int, q: int| ret.unwrap()@.contains(r, q) ==>
+                #[trigger] ret.unwrap()@.get(r, q) == at.get(q, r),
+        {
+            let tracked mut first_tile = first_tile;
+            match _transpose_parallel(
+                a_arr_r1, a_shared_r1, b_arr_r1, Tracked(&mut first_tile),
+                n, m, r0_1, r1_1, c0_1, c1_1, tile_rows, tile_cols,
+            ) {
+                Ok(()) => Ok(Tracked(first_tile)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match _transpose_parallel(
+        Arc::clone(&a_arr), Arc::clone(&a_shared), Arc::clone(&b_arr), Tracked(&mut second_tile),
+        n, m, r0_2, r1_2, c0_2, c1_2, tile_rows, tile_cols,
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut first_tile) = match first.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        let ghost first_done = first_tile;
+        let ghost second_done = second_tile;
+        if split_rows_first {
+            merge_rows(&*b_arr, &mut first_tile, second_tile);
+        } else {
+            merge_cols(&*b_arr, &mut first_tile, second_tile);
+        }
+        vstd::modes::tracked_swap(b, &mut first_tile);
+        assert forall |r: int, q: int| b.contains(r, q) implies #[trigger] b.get(r, q) == at.get(q, r) by {
+            if first_done.contains(r, q) {
+                assert(first_done.get(r, q) == at.get(q, r));
+            } else {
+                assert(second_done.contains(r, q));
+                assert(second_done.get(r, q) == at.get(q, r));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the transpose of `a` to `b`, splitting the tile of `b` like `par_matmul`.
+pub fn par_transpose<T: Copy + Send + Sync + 'static>(
+    a: &mut Matrix<T>,
+    b: &mut Matrix<T>,
+    tile_rows: usize,
+    tile_cols: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(a).full(),
+        old(b).full(),
+        old(b).rows == old(a).cols,
+        old(b).cols == old(a).rows,
+    ensures
+        ret.is_ok() ==> a.full() && a.rows == old(a).rows && a.cols == old(a).cols,
+        ret.is_ok() ==> a.perms@.values() == old(a).perms@.values(),
+        ret.is_ok() ==> b.full() && b.rows == old(b).rows && b.cols == old(b).cols,
+        ret.is_ok() ==> forall |i: int, j: int| 0 <= i < b.rows && 0 <= j < b.cols ==>
+            #[trigger] b.perms@.values()[i][j] == old(a).perms@.values()[j][i],
+{
+    let n = a.rows;
+    let m = a.cols;
+
+    // leave an empty tile in `a` while the threads share it
+    let a_shared = share(a);
+    let ghost at = (*a_shared)@;
+
+    let ret = _transpose_parallel(
+        Arc::clone(&a.array),
+        Arc::clone(&a_shared),
+        Arc::clone(&b.array),
+        Tracked(b.perms.borrow_mut()),
+        n, m,
+        0, m, 0, n,
+        tile_rows, tile_cols,
+    );
+    // `a` is given back its tile before any error is returned
+    if unshare(a, a_shared).is_err() || ret.is_err() {
+        return Err(());
+    }
+    proof {
+        assert forall |i: int, j: int| 0 <= i < b.rows && 0 <= j < b.cols implies
+            #[trigger] b.perms@.values()[i][j] == at.values()[j][i] by {
+            assert(b.perms@.contains(i, j));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_par_matmul_transpose() {
+    let (n, k, p) = (37, 23, 29);
+    let a_data: Vec<u64> = (0..n * k).map(|i| ((i * 31 + 7) % 10) as u64).collect();
+    let b_data: Vec<u64> = (0..k * p).map(|i| ((i * 17 + 3) % 9) as u64).collect();
+    let mut expected = vec![0u64; n * p];
+    for i in 0..n {
+        for j in 0..p {
+            expected[i * p + j] = (0..k).map(|t| a_data[i * k + t] * b_data[t * p + j]).sum();
+        }
+    }
+    let mut transposed = vec![0u64; n * k];
+    for i in 0..n {
+        for j in 0..k {
+            transposed[j * n + i] = a_data[i * k + j];
+        }
+    }
+
+    for (tile_rows, tile_cols) in [(1, 1), (4, 7), (16, 3), (100, 100)] {
+        let mut a = Matrix::new(a_data.clone(), n, k);
+        let mut b = Matrix::new(b_data.clone(), k, p);
+        let mut c = Matrix::new(vec![0; n * p], n, p);
+        par_matmul(&mut a, &mut b, &mut c, tile_rows, tile_cols).unwrap();
+        assert_eq!(c.clone_to_vec(), expected);
+        assert_eq!(a.clone_to_vec(), a_data);
+
+        let mut t = Matrix::new(vec![0; k * n], k, n);
+        par_transpose(&mut a, &mut t, tile_rows, tile_cols).unwrap();
+        assert_eq!(t.clone_to_vec(), transposed);
+    }
+}
+
+}
@@ -0,0 +1,851 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use vstd::seq_lib::lemma_multiset_commutative;
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::{ArrayForSorting, is_full, share, unshare},
+};
+
+pub open spec fn sorted(s: Seq<i32>) -> bool {
+    forall |i: int, j: int| 0 <= i <= j < s.len() ==> s[i] <= s[j]
+}
+
+/// `out` is sorted and holds exactly the elements of `a` and `b`.
+/// A sorted sequence is determined by its elements, so `out` is the sorted interleaving of `a` and `b`.
+pub open spec fn merged(a: Seq<i32>, b: Seq<i32>, out: Seq<i32>) -> bool {
+    &&& sorted(out)
+    &&& out.to_multiset() == a.to_multiset().add(b.to_multiset())
+}
+
+pub proof fn lemma_sorted_subrange(s: Seq<i32>, lo: int, hi: int)
+    requires
+        sorted(s),
+        0 <= lo <= hi <= s.len(),
+    ensures
+        sorted(s.subrange(lo, hi)),
+{
+    assert forall |i: int, j: int| 0 <= i <= j < hi - lo implies s.subrange(lo, hi)[i] <= s.subrange(lo, hi)[j] by {
+        assert(s[lo + i] <= s[lo + j]);
+    }
+}
+
+pub(crate) proof fn lemma_merged_empty()
+    ensures
+        merged(Seq::empty(), Seq::empty(), Seq::empty()),
+{
+    lemma_merged_by_empty(ByValue);
+    lemma_sorted_by_value(Seq::empty());
+}
+
+/// Appending `x` to `out` and to one of the inputs keeps them merged, if `x` is not smaller than `out`.
+pub(crate) proof fn lemma_merged_push(a: Seq<i32>, b: Seq<i32>, out: Seq<i32>, x: i32, from_a: bool)
+    requires
+        merged(a, b, out),
+        out.len() > 0 ==> out.last() <= x,
+    ensures
+        from_a ==> merged(a.push(x), b, out.push(x)),
+        !from_a ==> merged(a, b.push(x), out.push(x)),
+{
+    lemma_sorted_by_value(out);
+    lemma_sorted_by_value(out.push(x));
+    lemma_merged_by_push(ByValue, a, b, out, x, from_a);
+}
+
+/// Every element of `out` comes from `a` or `b`.
+proof fn lemma_merged_origin(a: Seq<i32>, b: Seq<i32>, out: Seq<i32>, k: int)
+    requires
+        merged(a, b, out),
+        0 <= k < out.len(),
+    ensures
+        a.contains(out[k]) || b.contains(out[k]),
+{
+    a.to_multiset_ensures();
+    b.to_multiset_ensures();
+    out.to_multiset_ensures();
+    assert(out.contains(out[k]));
+}
+
+/// The merges of the left and the right parts of `a` and `b` add up to the merge of `a` and `b`,
+/// if no element of the left parts goes after an element of the right parts.
+proof fn lemma_merged_append(
+    a_l: Seq<i32>, a_r: Seq<i32>,
+    b_l: Seq<i32>, b_r: Seq<i32>,
+    out_l: Seq<i32>, out_r: Seq<i32>,
+)
+    requires
+        sorted(a_l + a_r),
+        sorted(b_l + b_r),
+        merged(a_l, b_l, out_l),
+        merged(a_r, b_r, out_r),
+        a_l.len() > 0 && b_r.len() > 0 ==> a_l.last() <= b_r[0],
+        b_l.len() > 0 && a_r.len() > 0 ==> b_l.last() <= a_r[0],
+    ensures
+        merged(a_l + a_r, b_l + b_r, out_l + out_r),
+{
+    lemma_multiset_commutative(a_l, a_r);
+    lemma_multiset_commutative(b_l, b_r);
+    lemma_multiset_commutative(out_l, out_r);
+    assert((out_l + out_r).to_multiset() =~= (a_l + a_r).to_multiset().add((b_l + b_r).to_multiset()));
+
+    let a = a_l + a_r;
+    let b = b_l + b_r;
+    let out = out_l + out_r;
+    assert forall |i: int, j: int| 0 <= i <= j < out.len() implies out[i] <= out[j] by {
+        if i < out_l.len() && j >= out_l.len() {
+            let x = out_l.last();
+            let y = out_r[0];
+            lemma_merged_origin(a_l, b_l, out_l, out_l.len() - 1);
+            lemma_merged_origin(a_r, b_r, out_r, 0);
+            assert(x <= y) by {
+                if a_l.contains(x) {
+                    let p = choose |p: int| 0 <= p < a_l.len() && a_l[p] == x;
+                    assert(a[p] == x);
+                    if a_r.contains(y) {
+                        let q = choose |q: int| 0 <= q < a_r.len() && a_r[q] == y;
+                        assert(a[a_l.len() + q] == y);
+                    } else {
+                        let q = choose |q: int| 0 <= q < b_r.len() && b_r[q] == y;
+                        assert(a[a_l.len() - 1] == a_l.last());
+                        assert(b[b_l.len()] == b_r[0]);
+                        assert(b[b_l.len() + q] == y);
+                    }
+                } else {
+                    let p = choose |p: int| 0 <= p < b_l.len() && b_l[p] == x;
+                    assert(b[p] == x);
+                    if b_r.contains(y) {
+                        let q = choose |q: int| 0 <= q < b_r.len() && b_r[q] == y;
+                        assert(b[b_l.len() + q] == y);
+                    } else {
+                        let q = choose |q: int| 0 <= q < a_r.len() && a_r[q] == y;
+                        assert(b[b_l.len() - 1] == b_l.last());
+                        assert(a[a_l.len()] == a_r[0]);
+                        assert(a[a_l.len() + q] == y);
+                    }
+                }
+            }
+            assert(out_l[i] <= out_l[out_l.len() - 1]);
+            assert(out_r[0] <= out_r[j - out_l.len()]);
+        }
+    }
+}
+
+/// Merges the sorted arrays `a` and `b` into `out`. Equal elements of `a` go before those of `b`.
+pub fn merge_sorted(
+    a: &ArrayForSorting<i32>,
+    b: &ArrayForSorting<i32>,
+    out: &mut ArrayForSorting<i32>,
+)
+    requires
+        a.perms@.lo() == 0,
+        a.perms@.hi() == a.array.len(),
+        region_array::wf(*a.array, (a.perms@)),
+        b.perms@.lo() == 0,
+        b.perms@.hi() == b.array.len(),
+        region_array::wf(*b.array, (b.perms@)),
+        old(out).perms@.lo() == 0,
+        old(out).perms@.hi() == old(out).array.len(),
+        region_array::wf(*old(out).array, (old(out).perms@)),
+        a.array.len() + b.array.len() == old(out).array.len(),
+        sorted(a.perms@.values()),
+        sorted(b.perms@.values()),
+    ensures
+        region_array::wf(*out.array, (out.perms@)),
+        out.perms@.lo() == old(out).perms@.lo(),
+        out.perms@.hi() == old(out).perms@.hi(),
+        merged(a.perms@.values(), b.perms@.values(), out.perms@.values()),
+{
+    proof {
+        lemma_whole_subrange(&*a.array, a.perms@);
+        lemma_whole_subrange(&*b.array, b.perms@);
+    }
+    merge_into(
+        &a.array,
+        Tracked(a.perms.borrow()),
+        0,
+        (&*a.array).length(),
+        &b.array,
+        Tracked(b.perms.borrow()),
+        0,
+        (&*b.array).length(),
+        &out.array,
+        Tracked(out.perms.borrow_mut()),
+        0,
+    );
+}
+
+/// Like `merge_sorted`, but the output is split in halves, which are merged in separate threads
+/// while they are longer than `threshold`. The halves are found by co-ranking. The regions of `a`
+/// and `b` are only read, and are shared with the threads while merging, hence the `&mut`.
+pub fn merge_sorted_parallel(
+    a: &mut ArrayForSorting<i32>,
+    b: &mut ArrayForSorting<i32>,
+    out: &mut ArrayForSorting<i32>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        is_full(*old(a)),
+        is_full(*old(b)),
+        is_full(*old(out)),
+        old(a).array.len() + old(b).array.len() == old(out).array.len(),
+        sorted(old(a).perms@.values()),
+        sorted(old(b).perms@.values()),
+    ensures
+        ret.is_ok() ==> is_full(*a) && a.perms@.values() == old(a).perms@.values(),
+        ret.is_ok() ==> is_full(*b) && b.perms@.values() == old(b).perms@.values(),
+        ret.is_ok() ==> is_full(*out),
+        ret.is_ok() ==> merged(old(a).perms@.values(), old(b).perms@.values(), out.perms@.values()),
+{
+    proof {
+        lemma_whole_subrange(&*a.array, a.perms@);
+        lemma_whole_subrange(&*b.array, b.perms@);
+    }
+    let a_len = (&*a.array).length();
+    let b_len = (&*b.array).length();
+    let a_shared = share(a);
+    let b_shared = share(b);
+    assert(range_values((*a_shared)@, 0, a_len) == old(a).perms@.values());
+    assert(range_values((*b_shared)@, 0, b_len) == old(b).perms@.values());
+    let ret = _merge_sorted_parallel(
+        Arc::clone(&a.array),
+        Arc::clone(&a_shared),
+        0,
+        a_len,
+        Arc::clone(&b.array),
+        Arc::clone(&b_shared),
+        0,
+        b_len,
+        Arc::clone(&out.array),
+        0,
+        (&*out.array).length(),
+        Tracked(out.perms.borrow_mut()),
+        threshold,
+    );
+    // `a` and `b` are given back their regions before any error is returned
+    let a_ret = unshare(a, a_shared);
+    let b_ret = unshare(b, b_shared);
+    if ret.is_err() || a_ret.is_err() || b_ret.is_err() {
+        return Err(());
+    }
+    Ok(())
+}
+
+/// The part of a region from its start to its end is all of it.
+pub(crate) proof fn lemma_whole_subrange<T>(aself: &Array<T>, region: Region<T>)
+    requires
+        region_array::wf(*aself, region),
+    ensures
+        region.values().subrange(0, region.hi() - region.lo()) == region.values(),
+{
+    region_array::lemma_values_len(aself, region);
+    assert(region.values().subrange(0, region.hi() - region.lo()) =~= region.values());
+}
+
+/// The values of the cells `lo..hi` of `region`.
+pub open spec fn range_values<T>(region: Region<T>, lo: usize, hi: usize) -> Seq<T> {
+    region.values().subrange(lo - region.lo(), hi - region.lo())
+}
+
+/// How `merge_by` orders its elements: by their `key`, which `le` compares.
+/// Only the elements for which `valid` holds can be compared.
+pub trait MergeOrder<T> {
+    spec fn inv(&self) -> bool;
+
+    spec fn valid(&self, x: T) -> bool;
+
+    spec fn key(&self, x: T) -> int;
+
+    fn le(&self, x: T, y: T) -> (res: bool)
+        requires
+            self.inv(),
+            self.valid(x),
+            self.valid(y),
+        ensures
+            res == (self.key(x) <= self.key(y));
+}
+
+/// Orders `i32`s by their value.
+pub struct ByValue;
+
+impl MergeOrder<i32> for ByValue {
+    open spec fn inv(&self) -> bool {
+        true
+    }
+
+    open spec fn valid(&self, x: i32) -> bool {
+        true
+    }
+
+    open spec fn key(&self, x: i32) -> int {
+        x as int
+    }
+
+    fn le(&self, x: i32, y: i32) -> (res: bool) {
+        x <= y
+    }
+}
+
+pub open spec fn all_valid<T, O: MergeOrder<T>>(order: O, s: Seq<T>) -> bool {
+    forall |i: int| 0 <= i < s.len() ==> order.valid(#[trigger] s[i])
+}
+
+pub open spec fn sorted_by<T, O: MergeOrder<T>>(order: O, s: Seq<T>) -> bool {
+    forall |i: int, j: int| 0 <= i <= j < s.len() ==> order.key(s[i]) <= order.key(s[j])
+}
+
+/// `out` is sorted by `order` and holds exactly the elements of `a` and `b`.
+pub open spec fn merged_by<T, O: MergeOrder<T>>(order: O, a: Seq<T>, b: Seq<T>, out: Seq<T>) -> bool {
+    &&& sorted_by(order, out)
+    &&& out.to_multiset() == a.to_multiset().add(b.to_multiset())
+}
+
+/// Ordering by value is the plain order.
+pub proof fn lemma_sorted_by_value(s: Seq<i32>)
+    ensures
+        sorted_by(ByValue, s) == sorted(s),
+{
+    if sorted_by(ByValue, s) {
+        assert forall |i: int, j: int| 0 <= i <= j < s.len() implies s[i] <= s[j] by {
+            assert(ByValue.key(s[i]) <= ByValue.key(s[j]));
+        }
+    }
+}
+
+pub proof fn lemma_sorted_by_subrange<T, O: MergeOrder<T>>(order: O, s: Seq<T>, lo: int, hi: int)
+    requires
+        sorted_by(order, s),
+        0 <= lo <= hi <= s.len(),
+    ensures
+        sorted_by(order, s.subrange(lo, hi)),
+{
+    assert forall |i: int, j: int| 0 <= i <= j < hi - lo implies order.key(s.subrange(lo, hi)[i]) <= order.key(s.subrange(lo, hi)[j]) by {
+        assert(order.key(s[lo + i]) <= order.key(s[lo + j]));
+    }
+}
+
+pub(crate) proof fn lemma_merged_by_empty<T, O: MergeOrder<T>>(order: O)
+    ensures
+        merged_by(order, Seq::<T>::empty(), Seq::empty(), Seq::empty()),
+{
+    assert(Seq::<T>::empty().to_multiset() =~= Multiset::empty());
+    assert(Multiset::<T>::empty().add(Multiset::empty()) =~= Multiset::empty());
+}
+
+/// Appending `x` to `out` and to one of the inputs keeps them merged, if `x` does not go before `out`.
+pub(crate) proof fn lemma_merged_by_push<T, O: MergeOrder<T>>(order: O, a: Seq<T>, b: Seq<T>, out: Seq<T>, x: T, from_a: bool)
+    requires
+        merged_by(order, a, b, out),
+        out.len() > 0 ==> order.key(out.last()) <= order.key(x),
+    ensures
+        from_a ==> merged_by(order, a.push(x), b, out.push(x)),
+        !from_a ==> merged_by(order, a, b.push(x), out.push(x)),
+{
+    a.to_multiset_ensures();
+    b.to_multiset_ensures();
+    out.to_multiset_ensures();
+    assert(out.to_multiset().insert(x) =~= a.to_multiset().insert(x).add(b.to_multiset()));
+    assert(out.to_multiset().insert(x) =~= a.to_multiset().add(b.to_multiset().insert(x)));
+    assert forall |i: int, j: int| 0 <= i <= j < out.push(x).len() implies order.key(out.push(x)[i]) <= order.key(out.push(x)[j]) by {
+        if i < j && j == out.len() {
+            assert(order.key(out[i]) <= order.key(out[out.len() - 1]));
+        }
+    }
+}
+
+/// A sequence with the same elements as one of valid elements has valid elements only.
+pub(crate) proof fn lemma_all_valid_multiset<T, O: MergeOrder<T>>(order: O, s: Seq<T>, t: Seq<T>)
+    requires
+        all_valid(order, s),
+        t.to_multiset() == s.to_multiset(),
+    ensures
+        all_valid(order, t),
+{
+    s.to_multiset_ensures();
+    t.to_multiset_ensures();
+    assert forall |i: int| 0 <= i < t.len() implies order.valid(#[trigger] t[i]) by {
+        assert(t.contains(t[i]));
+        assert(s.contains(t[i]));
+        let j = choose |j: int| 0 <= j < s.len() && s[j] == t[i];
+        assert(order.valid(s[j]));
+    }
+}
+
+/// Merges the cells `a_lo..a_hi` of `a_perms` and `b_lo..b_hi` of `b_perms` into the whole of `out_perms`.
+pub(crate) fn merge_into(
+    a_arr: &Array<i32>,
+    Tracked(a_perms): Tracked<&Region<i32>>,
+    a_lo: usize, a_hi: usize,
+    b_arr: &Array<i32>,
+    Tracked(b_perms): Tracked<&Region<i32>>,
+    b_lo: usize, b_hi: usize,
+    out_arr: &Array<i32>,
+    Tracked(out_perms): Tracked<&mut Region<i32>>,
+    out_lo: usize,
+)
+    requires
+        region_array::wf(*a_arr, *a_perms),
+        a_perms.lo() <= a_lo <= a_hi <= a_perms.hi(),
+        region_array::wf(*b_arr, *b_perms),
+        b_perms.lo() <= b_lo <= b_hi <= b_perms.hi(),
+        region_array::wf(*out_arr, *old(out_perms)),
+        old(out_perms).lo() == out_lo,
+        old(out_perms).hi() - out_lo == (a_hi - a_lo) + (b_hi - b_lo),
+        sorted(range_values(*a_perms, a_lo, a_hi)),
+        sorted(range_values(*b_perms, b_lo, b_hi)),
+    ensures
+        region_array::wf(*out_arr, *out_perms),
+        out_perms.lo() == old(out_perms).lo(),
+        out_perms.hi() == old(out_perms).hi(),
+        merged(range_values(*a_perms, a_lo, a_hi), range_values(*b_perms, b_lo, b_hi), out_perms.values()),
+{
+    proof {
+        lemma_sorted_by_value(range_values(*a_perms, a_lo, a_hi));
+        lemma_sorted_by_value(range_values(*b_perms, b_lo, b_hi));
+    }
+    merge_by(&ByValue, a_arr, Tracked(a_perms), a_lo, a_hi, b_arr, Tracked(b_perms), b_lo, b_hi, out_arr, Tracked(out_perms), out_lo);
+    proof {
+        lemma_sorted_by_value(out_perms.values());
+    }
+}
+
+/// Like `merge_into`, with the elements ordered by `order`.
+pub(crate) fn merge_by<T: Copy, O: MergeOrder<T>>(
+    order: &O,
+    a_arr: &Array<T>,
+    Tracked(a_perms): Tracked<&Region<T>>,
+    a_lo: usize, a_hi: usize,
+    b_arr: &Array<T>,
+    Tracked(b_perms): Tracked<&Region<T>>,
+    b_lo: usize, b_hi: usize,
+    out_arr: &Array<T>,
+    Tracked(out_perms): Tracked<&mut Region<T>>,
+    out_lo: usize,
+)
+    requires
+        order.inv(),
+        region_array::wf(*a_arr, *a_perms),
+        a_perms.lo() <= a_lo <= a_hi <= a_perms.hi(),
+        region_array::wf(*b_arr, *b_perms),
+        b_perms.lo() <= b_lo <= b_hi <= b_perms.hi(),
+        region_array::wf(*out_arr, *old(out_perms)),
+        old(out_perms).lo() == out_lo,
+        old(out_perms).hi() - out_lo == (a_hi - a_lo) + (b_hi - b_lo),
+        all_valid(*order, range_values(*a_perms, a_lo, a_hi)),
+        all_valid(*order, range_values(*b_perms, b_lo, b_hi)),
+        sorted_by(*order, range_values(*a_perms, a_lo, a_hi)),
+        sorted_by(*order, range_values(*b_perms, b_lo, b_hi)),
+    ensures
+        region_array::wf(*out_arr, *out_perms),
+        out_perms.lo() == old(out_perms).lo(),
+        out_perms.hi() == old(out_perms).hi(),
+        merged_by(*order, range_values(*a_perms, a_lo, a_hi), range_values(*b_perms, b_lo, b_hi), out_perms.values()),
+{
+    let ghost a_vals = range_values(*a_perms, a_lo, a_hi);
+    let ghost b_vals = range_values(*b_perms, b_lo, b_hi);
+    proof {
+        region_array::lemma_values_len(a_arr, *a_perms);
+        region_array::lemma_values_len(b_arr, *b_perms);
+        region_array::lemma_values_len(out_arr, *out_perms);
+        lemma_merged_by_empty(*order);
+        assert(a_vals.subrange(0, 0) =~= Seq::empty());
+        assert(b_vals.subrange(0, 0) =~= Seq::empty());
+        assert(out_perms.values().subrange(0, 0) =~= Seq::empty());
+    }
+
+    let mut i = a_lo;
+    let mut j = b_lo;
+    let mut o = out_lo;
+    while i < a_hi || j < b_hi
+        invariant
+            order.inv(),
+            region_array::wf(*a_arr, *a_perms),
+            a_perms.lo() <= a_lo <= a_hi <= a_perms.hi(),
+            a_perms.values().len() == a_perms.hi() - a_perms.lo(),
+            a_vals == range_values(*a_perms, a_lo, a_hi),
+            a_vals.len() == a_hi - a_lo,
+            all_valid(*order, a_vals),
+            sorted_by(*order, a_vals),
+            region_array::wf(*b_arr, *b_perms),
+            b_perms.lo() <= b_lo <= b_hi <= b_perms.hi(),
+            b_perms.values().len() == b_perms.hi() - b_perms.lo(),
+            b_vals == range_values(*b_perms, b_lo, b_hi),
+            b_vals.len() == b_hi - b_lo,
+            all_valid(*order, b_vals),
+            sorted_by(*order, b_vals),
+            region_array::wf(*out_arr, *out_perms),
+            out_perms.lo() == out_lo,
+            out_perms.hi() == old(out_perms).hi(),
+            out_perms.values().len() == out_perms.hi() - out_lo,
+            out_perms.hi() - out_lo == (a_hi - a_lo) + (b_hi - b_lo),
+            a_lo <= i <= a_hi,
+            b_lo <= j <= b_hi,
+            o - out_lo == (i - a_lo) + (j - b_lo),
+            merged_by(*order, a_vals.subrange(0, i - a_lo), b_vals.subrange(0, j - b_lo), out_perms.values().subrange(0, o - out_lo)),
+            o > out_lo && i < a_hi ==> order.key(out_perms.values()[o - out_lo - 1]) <= order.key(a_vals[i - a_lo]),
+            o > out_lo && j < b_hi ==> order.key(out_perms.values()[o - out_lo - 1]) <= order.key(b_vals[j - b_lo]),
+    {
+        let ghost done = out_perms.values().subrange(0, o - out_lo);
+        let ghost a_done = a_vals.subrange(0, i - a_lo);
+        let ghost b_done = b_vals.subrange(0, j - b_lo);
+
+        // on ties `a` goes first
+        let take_a = if i == a_hi {
+            false
+        } else if j == b_hi {
+            true
+        } else {
+            assert(order.valid(a_vals[i - a_lo]));
+            assert(order.valid(b_vals[j - b_lo]));
+            order.le(*region_array::read(a_arr, i, Tracked(a_perms)), *region_array::read(b_arr, j, Tracked(b_perms)))
+        };
+        let x;
+        if take_a {
+            x = *region_array::read(a_arr, i, Tracked(a_perms));
+            i += 1;
+        } else {
+            x = *region_array::read(b_arr, j, Tracked(b_perms));
+            j += 1;
+        }
+        region_array::replace(out_arr, o, x, Tracked(out_perms));
+        o += 1;
+
+        proof {
+            lemma_merged_by_push(*order, a_done, b_done, done, x, take_a);
+            assert(out_perms.values().subrange(0, o - out_lo) =~= done.push(x));
+            if take_a {
+                assert(a_vals.subrange(0, i - a_lo) =~= a_done.push(x));
+            } else {
+                assert(b_vals.subrange(0, j - b_lo) =~= b_done.push(x));
+            }
+        }
+    }
+
+    proof {
+        assert(a_vals.subrange(0, i - a_lo) =~= a_vals);
+        assert(b_vals.subrange(0, j - b_lo) =~= b_vals);
+        assert(out_perms.values().subrange(0, o - out_lo) =~= out_perms.values());
+    }
+}
+
+/// Sorts the whole region `perms` of `arr`, over `lo..hi`, by `order`, using `buf_perms` over the
+/// same range of `buf_arr` as scratch space. Equal elements keep their order.
+pub(crate) fn merge_sort_by<T: Copy, O: MergeOrder<T>>(
+    order: &O,
+    arr: &Array<T>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&mut Region<T>>,
+    buf_arr: &Array<T>,
+    Tracked(buf_perms): Tracked<&mut Region<T>>,
+)
+    requires
+        order.inv(),
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        region_array::wf(*buf_arr, *old(buf_perms)),
+        old(buf_perms).lo() == lo,
+        old(buf_perms).hi() == hi,
+        all_valid(*order, old(perms).values()),
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        region_array::wf(*buf_arr, *buf_perms),
+        buf_perms.lo() == lo,
+        buf_perms.hi() == hi,
+        sorted_by(*order, perms.values()),
+        perms.values().to_multiset() == old(perms).values().to_multiset(),
+{
+    let ghost vals = perms.values();
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+    }
+    if hi - lo < 2 {
+        return;
+    }
+
+    let mid = lo + (hi - lo) / 2;
+    let tracked mut left = region_array::split_front(arr, mid, perms);
+    let tracked mut right = region_array::split_front(arr, hi, perms);
+    let tracked mut buf_left = region_array::split_front(buf_arr, mid, buf_perms);
+    let tracked mut buf_right = region_array::split_front(buf_arr, hi, buf_perms);
+    let ghost left_vals = left.values();
+    let ghost right_vals = right.values();
+    proof {
+        assert(right_vals =~= vals.subrange(mid - lo, hi - lo));
+        assert(left_vals + right_vals =~= vals);
+        assert forall |i: int| 0 <= i < left_vals.len() implies order.valid(#[trigger] left_vals[i]) by {
+            assert(left_vals[i] == vals[i]);
+        }
+        assert forall |i: int| 0 <= i < right_vals.len() implies order.valid(#[trigger] right_vals[i]) by {
+            assert(right_vals[i] == vals[left_vals.len() + i]);
+        }
+        lemma_multiset_commutative(left_vals, right_vals);
+    }
+
+    merge_sort_by(order, arr, lo, mid, Tracked(&mut left), buf_arr, Tracked(&mut buf_left));
+    merge_sort_by(order, arr, mid, hi, Tracked(&mut right), buf_arr, Tracked(&mut buf_right));
+
+    proof {
+        lemma_all_valid_multiset(*order, left_vals, left.values());
+        lemma_all_valid_multiset(*order, right_vals, right.values());
+        lemma_whole_subrange(arr, left);
+        lemma_whole_subrange(arr, right);
+        region_array::merge(buf_arr, &mut buf_left, buf_right);
+    }
+    merge_by(order, arr, Tracked(&left), lo, mid, arr, Tracked(&right), mid, hi, buf_arr, Tracked(&mut buf_left), lo);
+    proof {
+        region_array::merge(arr, &mut left, right);
+    }
+    region_array::copy_region(buf_arr, Tracked(&buf_left), arr, Tracked(&mut left), lo, hi);
+    proof {
+        vstd::modes::tracked_swap(perms, &mut left);
+        vstd::modes::tracked_swap(buf_perms, &mut buf_left);
+    }
+}
+
+/// Returns the cuts `a_mid`, `b_mid` such that the first `k` elements of the merge of
+/// `a_lo..a_hi` and `b_lo..b_hi` are the elements of `a_lo..a_mid` and `b_lo..b_mid`:
+/// nothing before a cut is greater than what is after the other cut.
+fn co_rank(
+    a_arr: &Array<i32>,
+    Tracked(a_perms): Tracked<&Region<i32>>,
+    a_lo: usize, a_hi: usize,
+    b_arr: &Array<i32>,
+    Tracked(b_perms): Tracked<&Region<i32>>,
+    b_lo: usize, b_hi: usize,
+    k: usize,
+) -> (res: (usize, usize))
+    requires
+        region_array::wf(*a_arr, *a_perms),
+        a_perms.lo() <= a_lo <= a_hi <= a_perms.hi(),
+        region_array::wf(*b_arr, *b_perms),
+        b_perms.lo() <= b_lo <= b_hi <= b_perms.hi(),
+        k <= (a_hi - a_lo) + (b_hi - b_lo),
+    ensures
+        a_lo <= res.0 <= a_hi,
+        b_lo <= res.1 <= b_hi,
+        (res.0 - a_lo) + (res.1 - b_lo) == k,
+        res.0 > a_lo && res.1 < b_hi ==>
+            a_perms.values()[res.0 - 1 - a_perms.lo()] <= b_perms.values()[res.1 - b_perms.lo()],
+        res.1 > b_lo && res.0 < a_hi ==>
+            b_perms.values()[res.1 - 1 - b_perms.lo()] <= a_perms.values()[res.0 - a_perms.lo()],
+{
+    let ghost a_vals = range_values(*a_perms, a_lo, a_hi);
+    let ghost b_vals = range_values(*b_perms, b_lo, b_hi);
+    proof {
+        region_array::lemma_values_len(a_arr, *a_perms);
+        region_array::lemma_values_len(b_arr, *b_perms);
+    }
+    let na = a_hi - a_lo;
+    let nb = b_hi - b_lo;
+    // `l..=h` are the numbers of elements of `a` that can be among the first `k`
+    let mut l = if k > nb { k - nb } else { 0 };
+    let mut h = if k < na { k } else { na };
+    while l < h
+        invariant
+            region_array::wf(*a_arr, *a_perms),
+            a_perms.lo() <= a_lo <= a_hi <= a_perms.hi(),
+            a_perms.values().len() == a_perms.hi() - a_perms.lo(),
+            a_vals == range_values(*a_perms, a_lo, a_hi),
+            region_array::wf(*b_arr, *b_perms),
+            b_perms.lo() <= b_lo <= b_hi <= b_perms.hi(),
+            b_perms.values().len() == b_perms.hi() - b_perms.lo(),
+            b_vals == range_values(*b_perms, b_lo, b_hi),
+            na == a_hi - a_lo,
+            nb == b_hi - b_lo,
+            k - nb <= l <= h <= na,
+            h <= k,
+            // taking `l` elements of `a` does not leave out one that goes before the next of `b`,
+            // taking `h` of them does not take one that goes after the last taken of `b`
+            l > 0 && k - l < nb ==> a_vals[l - 1] <= b_vals[k - l],
+            h < na && k - h > 0 ==> b_vals[k - h - 1] < a_vals[h as int],
+    {
+        let i = l + (h - l) / 2;
+        let j = k - i;
+        // `a[i]` goes before `b[j - 1]`, so it is among the first `k` as well
+        if *region_array::read(a_arr, a_lo + i, Tracked(a_perms)) <= *region_array::read(b_arr, b_lo + j - 1, Tracked(b_perms)) {
+            l = i + 1;
+        } else {
+            h = i;
+        }
+    }
+    (a_lo + l, b_lo + (k - l))
+}
+
+/// Merges `a_lo..a_hi` and `b_lo..b_hi` of the shared regions `a_shared` and `b_shared`
+/// into `out_lo..out_hi`.
+fn _merge_sorted_parallel(
+    a_arr: Arc<Array<i32>>,
+    a_shared: Arc<Tracked<Region<i32>>>,
+    a_lo: usize, a_hi: usize,
+    b_arr: Arc<Array<i32>>,
+    b_shared: Arc<Tracked<Region<i32>>>,
+    b_lo: usize, b_hi: usize,
+    out_arr: Arc<Array<i32>>,
+    out_lo: usize, out_hi: usize,
+    Tracked(out_perms): Tracked<&mut Region<i32>>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        region_array::wf(*a_arr, (*a_shared)@),
+        (*a_shared)@.lo() <= a_lo <= a_hi <= (*a_shared)@.hi(),
+        region_array::wf(*b_arr, (*b_shared)@),
+        (*b_shared)@.lo() <= b_lo <= b_hi <= (*b_shared)@.hi(),
+        region_array::wf(*out_arr, *old(out_perms)),
+        old(out_perms).lo() == out_lo,
+        old(out_perms).hi() == out_hi,
+        out_hi - out_lo == (a_hi - a_lo) + (b_hi - b_lo),
+        sorted(range_values((*a_shared)@, a_lo, a_hi)),
+        sorted(range_values((*b_shared)@, b_lo, b_hi)),
+    ensures
+        ret.is_ok() ==> region_array::wf(*out_arr, *out_perms) && out_perms.lo() == out_lo && out_perms.hi() == out_hi,
+        ret.is_ok() ==> merged(range_values((*a_shared)@, a_lo, a_hi), range_values((*b_shared)@, b_lo, b_hi), out_perms.values()),
+{
+    let a_region: &Tracked<Region<i32>> = &*a_shared;
+    let b_region: &Tracked<Region<i32>> = &*b_shared;
+    let ghost a_all = (*a_shared)@;
+    let ghost b_all = (*b_shared)@;
+    let ghost a_vals = range_values(a_all, a_lo, a_hi);
+    let ghost b_vals = range_values(b_all, b_lo, b_hi);
+    proof {
+        region_array::lemma_values_len(&*a_arr, a_all);
+        region_array::lemma_values_len(&*b_arr, b_all);
+    }
+
+    let n = out_hi - out_lo;
+    if n <= threshold || n < 2 {
+        merge_into(
+            &*a_arr, Tracked(a_region.borrow()), a_lo, a_hi,
+            &*b_arr, Tracked(b_region.borrow()), b_lo, b_hi,
+            &*out_arr, Tracked(out_perms), out_lo,
+        );
+        return Ok(());
+    }
+
+    let half = n / 2;
+    let (a_mid, b_mid) = co_rank(
+        &*a_arr, Tracked(a_region.borrow()), a_lo, a_hi,
+        &*b_arr, Tracked(b_region.borrow()), b_lo, b_hi,
+        half,
+    );
+    let out_mid = out_lo + half;
+
+    let tracked out_left = region_array::split_front(&*out_arr, out_mid, out_perms);
+    let tracked mut out_right = region_array::split_front(&*out_arr, out_hi, out_perms);
+
+    let ghost a_left_vals = range_values(a_all, a_lo, a_mid);
+    let ghost a_right_vals = range_values(a_all, a_mid, a_hi);
+    let ghost b_left_vals = range_values(b_all, b_lo, b_mid);
+    let ghost b_right_vals = range_values(b_all, b_mid, b_hi);
+    proof {
+        assert(a_left_vals =~= a_vals.subrange(0, a_mid - a_lo));
+        assert(a_right_vals =~= a_vals.subrange(a_mid - a_lo, a_hi - a_lo));
+        assert(b_left_vals =~= b_vals.subrange(0, b_mid - b_lo));
+        assert(b_right_vals =~= b_vals.subrange(b_mid - b_lo, b_hi - b_lo));
+        assert(a_left_vals + a_right_vals =~= a_vals);
+        assert(b_left_vals + b_right_vals =~= b_vals);
+        lemma_sorted_subrange(a_vals, 0, a_mid - a_lo);
+        lemma_sorted_subrange(a_vals, a_mid - a_lo, a_hi - a_lo);
+        lemma_sorted_subrange(b_vals, 0, b_mid - b_lo);
+        lemma_sorted_subrange(b_vals, b_mid - b_lo, b_hi - b_lo);
+        // nothing on the left goes after something on the right
+        if a_mid > a_lo && b_mid < b_hi {
+            assert(a_left_vals.last() == a_all.values()[a_mid - 1 - a_all.lo()]);
+            assert(b_right_vals[0] == b_all.values()[b_mid - b_all.lo()]);
+        }
+        if b_mid > b_lo && a_mid < a_hi {
+            assert(b_left_vals.last() == b_all.values()[b_mid - 1 - b_all.lo()]);
+            assert(a_right_vals[0] == a_all.values()[a_mid - a_all.lo()]);
+        }
+    }
+
+    let a_arr_r1 = Arc::clone(&a_arr);
+    let a_shared_r1 = Arc::clone(&a_shared);
+    let b_arr_r1 = Arc::clone(&b_arr);
+    let b_shared_r1 = Arc::clone(&b_shared);
+    let out_arr_r1 = Arc::clone(&out_arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<i32>>, ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*out_arr, ret.unwrap()@) && ret.unwrap()@.lo() == out_lo && ret.unwrap()@.hi() == out_mid,
+            ret.is_ok() ==> merged(a_left_vals, b_left_vals, ret.unwrap()@.values()),
+        {
+            let tracked mut out_left = out_left;
+            let t = _merge_sorted_parallel(
+                a_arr_r1, a_shared_r1, a_lo, a_mid,
+                b_arr_r1, b_shared_r1, b_lo, b_mid,
+                out_arr_r1, out_lo, out_mid, Tracked(&mut out_left),
+                threshold,
+            );
+            if t.is_err() {
+                Err(())
+            } else {
+                Ok(Tracked(out_left))
+            }
+        }
+    );
+
+    let right = _merge_sorted_parallel(
+        Arc::clone(&a_arr), Arc::clone(&a_shared), a_mid, a_hi,
+        Arc::clone(&b_arr), Arc::clone(&b_shared), b_mid, b_hi,
+        Arc::clone(&out_arr), out_mid, out_hi, Tracked(&mut out_right),
+        threshold,
+    );
+
+    // the left half is joined before returning, so that no thread outlives the merge
+    let Tracked(mut out_left) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+    if right.is_err() {
+        return Err(());
+    }
+
+    proof {
+        lemma_merged_append(a_left_vals, a_right_vals, b_left_vals, b_right_vals, out_left.values(), out_right.values());
+        region_array::merge(&*out_arr, &mut out_left, out_right);
+        vstd::modes::tracked_swap(out_perms, &mut out_left);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_merge_sorted() {
+    use crate::test_data::{scattered, thresholds};
+
+    let mut left: Vec<i32> = scattered(500, 211, -100);
+    let mut right: Vec<i32> = (0..333).map(|i| (i * 104729) % 97 - 40).collect();
+    left.sort();
+    right.sort();
+    let mut expected = left.clone();
+    expected.extend_from_slice(&right);
+    expected.sort();
+
+    let a = ArrayForSorting::new(left.clone());
+    let b = ArrayForSorting::new(right.clone());
+    let mut out = ArrayForSorting::new(vec![0; expected.len()]);
+    merge_sorted(&a, &b, &mut out);
+    assert_eq!(out.clone_to_vec(), expected);
+
+    for threshold in thresholds(expected.len()) {
+        let mut a = ArrayForSorting::new(left.clone());
+        let mut b = ArrayForSorting::new(right.clone());
+        let mut out = ArrayForSorting::new(vec![0; expected.len()]);
+        merge_sorted_parallel(&mut a, &mut b, &mut out, threshold).unwrap();
+        assert_eq!(out.clone_to_vec(), expected);
+        assert_eq!(a.clone_to_vec(), left);
+        assert_eq!(b.clone_to_vec(), right);
+    }
+}
+
+}
@@ -4,9 +4,12 @@ use std::sync::Arc;
 
 verus! {
 
+use vstd::seq_lib::lemma_multiset_commutative;
+
 use crate::{
     permissions_array::Array,
     region_array::{self, Region},
+    merge_sorted::{self, sorted, merged},
 };
 
 pub struct ArrayForSorting<T> {
@@ -20,6 +23,8 @@ impl<T> ArrayForSorting<T> {
         ensures
             data.len() == res.array.len(),
             region_array::wf(*res.array, res.perms@),
+            res.perms@.lo() == 0,
+            res.perms@.hi() == res.array.len(),
     {
         let (array, perms) = region_array::new(data);
         Self {
@@ -41,6 +46,46 @@ impl<T> ArrayForSorting<T> {
     }
 }
 
+/// The region of `arr` covers the whole array.
+pub open spec fn is_full<T>(arr: ArrayForSorting<T>) -> bool {
+    &&& region_array::wf(*arr.array, arr.perms@)
+    &&& arr.perms@.lo() == 0
+    &&& arr.perms@.hi() == arr.array.len()
+}
+
+/// `region_array::share_region` of the whole region of `arr`.
+pub fn share<T>(arr: &mut ArrayForSorting<T>) -> (res: Arc<Tracked<Region<T>>>)
+    requires
+        is_full(*old(arr)),
+    ensures
+        arr.array == old(arr).array,
+        region_array::wf(*arr.array, (*res)@),
+        (*res)@.lo() == 0,
+        (*res)@.hi() == arr.array.len(),
+        (*res)@.values() == old(arr).perms@.values(),
+{
+    region_array::share_region(&*arr.array, Tracked(arr.perms.borrow_mut()), 0)
+}
+
+/// `region_array::unshare_region` of the whole region of `arr`.
+pub fn unshare<T>(arr: &mut ArrayForSorting<T>, shared: Arc<Tracked<Region<T>>>) -> (ret: Result<(), ()>)
+    ensures
+        arr.array == old(arr).array,
+        ret.is_ok() ==> arr.perms@ == (*shared)@,
+{
+    region_array::unshare_region(Tracked(arr.perms.borrow_mut()), shared)
+}
+
+/// The loop that merges two sorted runs.
+#[derive(Clone, Copy)]
+pub enum MergeKernel {
+    /// Branches on the comparison of the two candidates.
+    Branching,
+    /// Reads both candidates and selects one with conditional moves,
+    /// so there is no data-dependent branch to mispredict.
+    Branchless,
+}
+
 pub fn merge_sort(
     arr: &mut ArrayForSorting<i32>,
     out_arr: &mut ArrayForSorting<i32>,
@@ -57,7 +102,12 @@ pub fn merge_sort(
         region_array::wf(*arr.array, (arr.perms@)),
         arr.perms@.lo() == old(arr).perms@.lo(),
         arr.perms@.hi() == old(arr).perms@.hi(),
+        sorted(arr.perms@.values()),
+        arr.perms@.values().to_multiset() == old(arr).perms@.values().to_multiset(),
 {
+    proof {
+        region_array::lemma_values_len(&*arr.array, arr.perms@);
+    }
     _merge_sort(
         &arr.array,
         0,
@@ -66,7 +116,11 @@ pub fn merge_sort(
         &out_arr.array,
         0,
         Tracked(out_arr.perms.borrow_mut()),
-    )
+        MergeKernel::Branching,
+    );
+    proof {
+        lemma_sorts_range_whole(old(arr).perms@.values(), arr.perms@.values());
+    }
 }
 
 pub fn merge_sort_parallel(
@@ -86,8 +140,38 @@ pub fn merge_sort_parallel(
         ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
         ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo(),
         ret.is_ok() ==> arr.perms@.hi() == old(arr).perms@.hi(),
+        ret.is_ok() ==> sorted(arr.perms@.values()),
+        ret.is_ok() ==> arr.perms@.values().to_multiset() == old(arr).perms@.values().to_multiset(),
+{
+    merge_sort_parallel_with(arr, out_arr, threshold, MergeKernel::Branching)
+}
+
+/// Like `merge_sort_parallel`, but merges with `kernel`.
+pub fn merge_sort_parallel_with(
+    arr: &mut ArrayForSorting<i32>,
+    out_arr: &mut ArrayForSorting<i32>,
+    threshold: usize,
+    kernel: MergeKernel,
+) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        old(out_arr).perms@.lo() == 0,
+        old(out_arr).perms@.hi() == old(out_arr).array.len(),
+        region_array::wf(*old(out_arr).array, (old(out_arr).perms@)),
+        old(arr).array.len() == old(out_arr).array.len(),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo(),
+        ret.is_ok() ==> arr.perms@.hi() == old(arr).perms@.hi(),
+        ret.is_ok() ==> sorted(arr.perms@.values()),
+        ret.is_ok() ==> arr.perms@.values().to_multiset() == old(arr).perms@.values().to_multiset(),
 {
-    _merge_sort_parallel(
+    proof {
+        region_array::lemma_values_len(&*arr.array, arr.perms@);
+    }
+    let ret = _merge_sort_parallel(
         Arc::clone(&arr.array),
         0,
         (&*arr.array).length(),
@@ -96,18 +180,115 @@ pub fn merge_sort_parallel(
         0,
         Tracked(out_arr.perms.borrow_mut()),
         threshold,
-    )
+        kernel,
+    );
+    proof {
+        if ret.is_ok() {
+            lemma_sorts_range_whole(old(arr).perms@.values(), arr.perms@.values());
+        }
+    }
+    ret
+}
+
+
+/// `after` is `before` with the elements of `lo..hi` sorted, and everything else left in place.
+pub open spec fn sorts_range(before: Seq<i32>, after: Seq<i32>, lo: int, hi: int) -> bool {
+    &&& 0 <= lo <= hi <= before.len()
+    &&& after.len() == before.len()
+    &&& after.subrange(0, lo) == before.subrange(0, lo)
+    &&& after.subrange(hi, after.len() as int) == before.subrange(hi, before.len() as int)
+    &&& sorted(after.subrange(lo, hi))
+    &&& after.subrange(lo, hi).to_multiset() == before.subrange(lo, hi).to_multiset()
+}
+
+/// Sorting the whole of `before` gives a sorted permutation of it.
+pub proof fn lemma_sorts_range_whole(before: Seq<i32>, after: Seq<i32>)
+    requires
+        sorts_range(before, after, 0, before.len() as int),
+    ensures
+        sorted(after),
+        after.to_multiset() == before.to_multiset(),
+{
+    assert(after.subrange(0, after.len() as int) =~= after);
+    assert(before.subrange(0, before.len() as int) =~= before);
+}
+
+proof fn lemma_sorts_range_short(s: Seq<i32>, lo: int, hi: int)
+    requires
+        0 <= lo <= hi <= s.len(),
+        hi - lo <= 1,
+    ensures
+        sorts_range(s, s, lo, hi),
+{
+    assert forall |i: int, j: int| 0 <= i <= j < s.subrange(lo, hi).len() implies s.subrange(lo, hi)[i] <= s.subrange(lo, hi)[j] by {
+        assert(i == j);
+    }
+}
+
+/// Sorting a range of a sequence sorts the same range, shifted, of any sequence around it.
+proof fn lemma_sorts_range_frame(p: Seq<i32>, before: Seq<i32>, after: Seq<i32>, q: Seq<i32>, lo: int, hi: int)
+    requires
+        sorts_range(before, after, lo, hi),
+    ensures
+        sorts_range(p + before + q, p + after + q, p.len() + lo, p.len() + hi),
+{
+    let o = p + before + q;
+    let n = p + after + q;
+    let a = p.len() + lo;
+    let b = p.len() + hi;
+    assert(o.subrange(0, a) =~= p + before.subrange(0, lo));
+    assert(n.subrange(0, a) =~= p + after.subrange(0, lo));
+    assert(o.subrange(b, o.len() as int) =~= before.subrange(hi, before.len() as int) + q);
+    assert(n.subrange(b, n.len() as int) =~= after.subrange(hi, after.len() as int) + q);
+    assert(o.subrange(a, b) =~= before.subrange(lo, hi));
+    assert(n.subrange(a, b) =~= after.subrange(lo, hi));
 }
 
+/// Sorting `lo..mid`, then `mid..hi`, then putting the merge of both in their place sorts `lo..hi`.
+proof fn lemma_sorts_range_halves(v0: Seq<i32>, v1: Seq<i32>, v2: Seq<i32>, out: Seq<i32>, v3: Seq<i32>, lo: int, mid: int, hi: int)
+    requires
+        lo <= mid <= hi,
+        sorts_range(v0, v1, lo, mid),
+        sorts_range(v1, v2, mid, hi),
+        out.len() == hi - lo,
+        sorted(v2.subrange(lo, mid)) && sorted(v2.subrange(mid, hi)) ==> merged(v2.subrange(lo, mid), v2.subrange(mid, hi), out),
+        v3 == v2.subrange(0, lo) + out + v2.subrange(hi, v2.len() as int),
+    ensures
+        sorted(v2.subrange(lo, mid)),
+        sorted(v2.subrange(mid, hi)),
+        sorts_range(v0, v3, lo, hi),
+{
+    let len = v0.len() as int;
+    // `lo..mid` is left alone by the second sort, `mid..hi` by the first one
+    assert(v2.subrange(lo, mid) =~= v2.subrange(0, mid).subrange(lo, mid));
+    assert(v1.subrange(lo, mid) =~= v1.subrange(0, mid).subrange(lo, mid));
+    assert(v1.subrange(mid, hi) =~= v1.subrange(mid, len).subrange(0, hi - mid));
+    assert(v0.subrange(mid, hi) =~= v0.subrange(mid, len).subrange(0, hi - mid));
+
+    assert(v3.subrange(0, lo) =~= v2.subrange(0, lo));
+    assert(v2.subrange(0, lo) =~= v2.subrange(0, mid).subrange(0, lo));
+    assert(v1.subrange(0, lo) =~= v1.subrange(0, mid).subrange(0, lo));
+    assert(v3.subrange(hi, len) =~= v2.subrange(hi, len));
+    assert(v1.subrange(hi, len) =~= v1.subrange(mid, len).subrange(hi - mid, len - mid));
+    assert(v0.subrange(hi, len) =~= v0.subrange(mid, len).subrange(hi - mid, len - mid));
+    assert(v0.subrange(0, lo) =~= v0.subrange(0, mid).subrange(0, lo));
 
-fn merge(
+    assert(v3.subrange(lo, hi) =~= out);
+    assert(v0.subrange(lo, hi) =~= v0.subrange(lo, mid) + v0.subrange(mid, hi));
+    lemma_multiset_commutative(v0.subrange(lo, mid), v0.subrange(mid, hi));
+}
+
+/// Merges `left_lo..left_hi` and `right_lo..right_hi` of `perms` into `out_lo..` of `out_perms`.
+/// If both runs are sorted, the output is their merge.
+pub(crate) fn merge(
     array: &Array<i32>,
     Tracked(perms): Tracked<&Region<i32>>,
-    mut left_lo: usize, left_hi: usize,
-    mut right_lo: usize, right_hi: usize,
+    left_lo: usize, left_hi: usize,
+    right_lo: usize, right_hi: usize,
     out_array: &Array<i32>,
     Tracked(out_perms): Tracked<&mut Region<i32>>,
-    mut out_lo: usize,
+    out_lo: usize,
+    kernel: MergeKernel,
 )
     requires
         perms.lo() <= left_lo <= left_hi <= perms.hi() <= array.len(),
@@ -121,79 +302,246 @@ fn merge(
         region_array::wf(*out_array, *out_perms),
         old(out_perms).lo() == out_perms.lo(),
         old(out_perms).hi() == out_perms.hi(),
+        ({
+    // the kernel is picked once here, so the loop of each one is compiled without the other
+    match kernel {
+        MergeKernel::Branching => merge_with::<Branching>(array, Tracked(perms), left_lo, left_hi, right_lo, right_hi, out_array, Tracked(out_perms), out_lo),
+        MergeKernel::Branchless => merge_with::<Branchless>(array, Tracked(perms), left_lo, left_hi, right_lo, right_hi, out_array, Tracked(out_perms), out_lo),
+    }
+}
+
+/// One step of a merge kernel.
+trait MergeStep {
+    /// Reads the candidates at `l` and `r` and picks the smaller one, the left one on ties
+    /// so that the merge is stable.
+    fn step(array: &Array<i32>, perms: Tracked<&Region<i32>>, l: usize, r: usize) -> (res: (i32, bool))
+        requires
+            region_array::wf(*array, *perms@),
+            perms@.lo() <= l < perms@.hi(),
+            perms@.lo() <= r < perms@.hi(),
+        ensures
+            res.1 == (perms@.values()[l - perms@.lo()] <= perms@.values()[r - perms@.lo()]),
+            res.0 == perms@.values()[(if res.1 { l } else { r }) - perms@.lo()];
+}
+
+/// [`MergeKernel::Branching`].
+struct Branching;
+
+impl MergeStep for Branching {
+    fn step(array: &Array<i32>, perms: Tracked<&Region<i32>>, l: usize, r: usize) -> (res: (i32, bool)) {
+        if region_array::read(array, l, perms) <= region_array::read(array, r, perms) {
+            (*region_array::read(array, l, perms), true)
+        } else {
+            (*region_array::read(array, r, perms), false)
+        }
+    }
+}
+
+/// [`MergeKernel::Branchless`].
+struct Branchless;
+
+impl MergeStep for Branchless {
+    fn step(array: &Array<i32>, perms: Tracked<&Region<i32>>, l: usize, r: usize) -> (res: (i32, bool)) {
+        // both candidates are read unconditionally, so the selection below
+        // compiles to conditional moves instead of a data-dependent branch
+        let x = *region_array::read(array, l, perms);
+        let y = *region_array::read(array, r, perms);
+        let take_left = x <= y;
+        (if take_left { x } else { y }, take_left)
+    }
+}
+
+/// [`merge`] with the kernel `K`.
+fn merge_with<K: MergeStep>(
+    array: &Array<i32>,
+    Tracked(perms): Tracked<&Region<i32>>,
+    left_lo: usize, left_hi: usize,
+    right_lo: usize, right_hi: usize,
+    out_array: &Array<i32>,
+    Tracked(out_perms): Tracked<&mut Region<i32>>,
+    out_lo: usize,
+)
+    requires
+        perms.lo() <= left_lo <= left_hi <= perms.hi() <= array.len(),
+        perms.lo() <= right_lo <= right_hi <= perms.hi() <= array.len(),
+        left_hi - left_lo + right_hi - right_lo <= usize::MAX,
+        region_array::wf(*array, (*perms)),
+        region_array::wf(*out_array, *old(out_perms)),
+        old(out_perms).lo() <= out_lo <= out_lo + (left_hi - left_lo + right_hi - right_lo) <= old(out_perms).hi() <= out_array.len(),
+        out_lo + right_hi - right_lo + left_hi - left_lo <= old(out_perms).hi(),
+    ensures
+        region_array::wf(*out_array, *out_perms),
+        old(out_perms).lo() == out_perms.lo(),
+        old(out_perms).hi() == out_perms.hi(),
+        ({
+            let left = perms.values().subrange(left_lo - perms.lo(), left_hi - perms.lo());
+            let right = perms.values().subrange(right_lo - perms.lo(), right_hi - perms.lo());
+            let out_start = out_lo - out_perms.lo();
+            let out = out_perms.values().subrange(out_start, out_start + left.len() + right.len());
+            sorted(left) && sorted(right) ==> merged(left, right, out)
+        }),
 {
-    let ghost old_out_lo = out_lo;
-    let ghost old_left_lo = left_lo;
-    let ghost old_right_lo = right_lo;
-    while left_lo < left_hi && right_lo < right_hi
+    let ghost vals = perms.values();
+    let ghost left = vals.subrange(left_lo - perms.lo(), left_hi - perms.lo());
+    let ghost right = vals.subrange(right_lo - perms.lo(), right_hi - perms.lo());
+    let ghost inputs_sorted = sorted(left) && sorted(right);
+    let ghost out_start = out_lo - out_perms.lo();
+    proof {
+        region_array::lemma_values_len(array, *perms);
+        region_array::lemma_values_len(out_array, *out_perms);
+        merge_sorted::lemma_merged_empty();
+        assert(left.subrange(0, 0) =~= Seq::empty());
+        assert(right.subrange(0, 0) =~= Seq::empty());
+        assert(out_perms.values().subrange(out_start, out_start) =~= Seq::empty());
+    }
+
+    let mut l = left_lo;
+    let mut r = right_lo;
+    let mut o = out_lo;
+    while l < left_hi && r < right_hi
         invariant
             region_array::wf(*array, (*perms)),
-            perms.lo() <= left_lo <= left_hi <= perms.hi() <= array.len(),
-            perms.lo() <= right_lo <= right_hi <= perms.hi() <= array.len(),
+            perms.values() == vals,
+            vals.len() == perms.hi() - perms.lo(),
+            left == vals.subrange(left_lo - perms.lo(), left_hi - perms.lo()),
+            right == vals.subrange(right_lo - perms.lo(), right_hi - perms.lo()),
+            inputs_sorted == (sorted(left) && sorted(right)),
+            perms.lo() <= left_lo <= l <= left_hi <= perms.hi() <= array.len(),
+            perms.lo() <= right_lo <= r <= right_hi <= perms.hi() <= array.len(),
             region_array::wf(*out_array, *out_perms),
-            out_perms.lo() <= out_lo <= old_out_lo + (left_hi - old_left_lo + right_hi - old_right_lo) <= out_perms.hi() <= out_array.len(),
-            out_lo == old_out_lo + (left_lo - old_left_lo) + (right_lo - old_right_lo),
+            out_perms.values().len() == out_perms.hi() - out_perms.lo(),
+            out_perms.lo() <= out_lo <= out_lo + (left_hi - left_lo + right_hi - right_lo) <= out_perms.hi() <= out_array.len(),
+            out_start == out_lo - out_perms.lo(),
+            o == out_lo + (l - left_lo) + (r - right_lo),
             old(out_perms).lo() == out_perms.lo(),
             old(out_perms).hi() == out_perms.hi(),
+            inputs_sorted ==> merged(left.subrange(0, l - left_lo), right.subrange(0, r - right_lo), out_perms.values().subrange(out_start, o - out_perms.lo())),
+            inputs_sorted && o > out_lo && l < left_hi ==> out_perms.values()[o - out_perms.lo() - 1] <= left[l - left_lo],
+            inputs_sorted && o > out_lo && r < right_hi ==> out_perms.values()[o - out_perms.lo() - 1] <= right[r - right_lo],
     {
-        let element: i32;
-        if region_array::read(array, left_lo, Tracked(perms)) < region_array::read(array, right_lo, Tracked(perms)) {
-            element = *region_array::read(array, left_lo, Tracked(perms));
-            left_lo += 1;
+        let ghost done = out_perms.values().subrange(out_start, o - out_perms.lo());
+        let ghost left_done = left.subrange(0, l - left_lo);
+        let ghost right_done = right.subrange(0, r - right_lo);
+
+        let (element, take_left) = K::step(array, Tracked(perms), l, r);
+        l = if take_left { l + 1 } else { l };
+        r = if take_left { r } else { r + 1 };
+        region_array::replace(out_array, o, element, Tracked(out_perms));
+        o += 1;
+
+        proof {
+            lemma_merge_step(left, right, done, left_done, right_done, element, take_left, inputs_sorted);
+            assert(out_perms.values().subrange(out_start, o - out_perms.lo()) =~= done.push(element));
+            if take_left {
+                assert(left.subrange(0, l - left_lo) =~= left_done.push(element));
+            } else {
+                assert(right.subrange(0, r - right_lo) =~= right_done.push(element));
+            }
+        }
+    }
+
+    // one of the runs is used up, the rest of the other one is copied as it is
+    while l < left_hi || r < right_hi
+        invariant
+            region_array::wf(*array, (*perms)),
+            perms.values() == vals,
+            vals.len() == perms.hi() - perms.lo(),
+            left == vals.subrange(left_lo - perms.lo(), left_hi - perms.lo()),
+            right == vals.subrange(right_lo - perms.lo(), right_hi - perms.lo()),
+            inputs_sorted == (sorted(left) && sorted(right)),
+            perms.lo() <= left_lo <= l <= left_hi <= perms.hi() <= array.len(),
+            perms.lo() <= right_lo <= r <= right_hi <= perms.hi() <= array.len(),
+            l == left_hi || r == right_hi,
+            region_array::wf(*out_array, *out_perms),
+            out_perms.values().len() == out_perms.hi() - out_perms.lo(),
+            out_perms.lo() <= out_lo <= out_lo + (left_hi - left_lo + right_hi - right_lo) <= out_perms.hi() <= out_array.len(),
+            out_start == out_lo - out_perms.lo(),
+            o == out_lo + (l - left_lo) + (r - right_lo),
+            old(out_perms).lo() == out_perms.lo(),
+            old(out_perms).hi() == out_perms.hi(),
+            inputs_sorted ==> merged(left.subrange(0, l - left_lo), right.subrange(0, r - right_lo), out_perms.values().subrange(out_start, o - out_perms.lo())),
+            inputs_sorted && o > out_lo && l < left_hi ==> out_perms.values()[o - out_perms.lo() - 1] <= left[l - left_lo],
+            inputs_sorted && o > out_lo && r < right_hi ==> out_perms.values()[o - out_perms.lo() - 1] <= right[r - right_lo],
+    {
+        let ghost done = out_perms.values().subrange(out_start, o - out_perms.lo());
+        let ghost left_done = left.subrange(0, l - left_lo);
+        let ghost right_done = right.subrange(0, r - right_lo);
+
+        let take_left = l < left_hi;
+        let element;
+        if take_left {
+            element = *region_array::read(array, l, Tracked(perms));
+            l += 1;
         } else {
-            element = *region_array::read(array, right_lo, Tracked(perms));
-            right_lo += 1;
+            element = *region_array::read(array, r, Tracked(perms));
+            r += 1;
+        }
+        region_array::replace(out_array, o, element, Tracked(out_perms));
+        o += 1;
+
+        proof {
+            lemma_merge_step(left, right, done, left_done, right_done, element, take_left, inputs_sorted);
+            assert(out_perms.values().subrange(out_start, o - out_perms.lo()) =~= done.push(element));
+            if take_left {
+                assert(left.subrange(0, l - left_lo) =~= left_done.push(element));
+            } else {
+                assert(right.subrange(0, r - right_lo) =~= right_done.push(element));
+            }
         }
-        region_array::replace(out_array, out_lo, element, Tracked(out_perms));
-        out_lo += 1;
     }
 
-    if left_lo < left_hi {
-        while left_lo < left_hi
-            invariant
-                region_array::wf(*array, (*perms)),
-                left_hi - left_lo + right_hi - right_lo + out_lo - old_out_lo == left_hi - old_left_lo + right_hi - old_right_lo,
-                perms.lo() <= left_lo <= left_hi <= perms.hi() <= array.len(),
-                perms.lo() <= right_lo <= right_hi,
-                region_array::wf(*out_array, *out_perms),
-                out_perms.lo() <= out_lo <= out_perms.hi() <= out_array.len(),
-                old_out_lo + right_hi - old_right_lo + left_hi - old_left_lo <= old(out_perms).hi(),
-                old(out_perms).lo() == out_perms.lo(),
-                old(out_perms).hi() == out_perms.hi(),
-        {
-            let e = *region_array::read(array, left_lo, Tracked(perms));
-            region_array::replace(out_array, out_lo, e, Tracked(out_perms));
-            left_lo += 1;
-            out_lo += 1;
+    proof {
+        assert(left.subrange(0, l - left_lo) =~= left);
+        assert(right.subrange(0, r - right_lo) =~= right);
+    }
+}
+
+/// Taking the next element of `left` or `right` keeps the output merged, as long as
+/// the element is not greater than the next element of the other run.
+proof fn lemma_merge_step(
+    left: Seq<i32>, right: Seq<i32>,
+    done: Seq<i32>, left_done: Seq<i32>, right_done: Seq<i32>,
+    x: i32, take_left: bool, inputs_sorted: bool,
+)
+    requires
+        inputs_sorted ==> sorted(left) && sorted(right),
+        left_done == left.subrange(0, left_done.len() as int),
+        right_done == right.subrange(0, right_done.len() as int),
+        left_done.len() <= left.len(),
+        right_done.len() <= right.len(),
+        take_left ==> left_done.len() < left.len() && x == left[left_done.len() as int],
+        !take_left ==> right_done.len() < right.len() && x == right[right_done.len() as int],
+        take_left && right_done.len() < right.len() ==> x <= right[right_done.len() as int],
+        !take_left && left_done.len() < left.len() ==> x <= left[left_done.len() as int],
+        inputs_sorted ==> merged(left_done, right_done, done),
+        inputs_sorted && done.len() > 0 && left_done.len() < left.len() ==> done.last() <= left[left_done.len() as int],
+        inputs_sorted && done.len() > 0 && right_done.len() < right.len() ==> done.last() <= right[right_done.len() as int],
+    ensures
+        inputs_sorted && take_left ==> merged(left_done.push(x), right_done, done.push(x)),
+        inputs_sorted && !take_left ==> merged(left_done, right_done.push(x), done.push(x)),
+        inputs_sorted && take_left && left_done.len() + 1 < left.len() ==> x <= left[left_done.len() + 1],
+        inputs_sorted && !take_left && right_done.len() + 1 < right.len() ==> x <= right[right_done.len() + 1],
+{
+    if inputs_sorted {
+        merge_sorted::lemma_merged_push(left_done, right_done, done, x, take_left);
+        if take_left && left_done.len() + 1 < left.len() {
+            assert(left[left_done.len() as int] <= left[left_done.len() + 1]);
         }
-    } else if right_lo < right_hi {
-        while right_lo < right_hi
-            invariant
-                region_array::wf(*array, (*perms)),
-                left_hi - left_lo + right_hi - right_lo + out_lo - old_out_lo == left_hi - old_left_lo + right_hi - old_right_lo,
-                perms.lo() <= right_lo <= right_hi <= perms.hi() <= array.len(),
-                perms.lo() <= left_lo <= left_hi,
-                region_array::wf(*out_array, *out_perms),
-                out_perms.lo() <= out_lo <= out_perms.hi() <= out_array.len(),
-                old_out_lo + right_hi - old_right_lo + left_hi - old_left_lo <= old(out_perms).hi(),
-                old(out_perms).lo() == out_perms.lo(),
-                old(out_perms).hi() == out_perms.hi(),
-        {
-            let e = *region_array::read(array, right_lo, Tracked(perms));
-            region_array::replace(out_array, out_lo, e, Tracked(out_perms));
-            right_lo += 1;
-            out_lo += 1;
+        if !take_left && right_done.len() + 1 < right.len() {
+            assert(right[right_done.len() as int] <= right[right_done.len() + 1]);
         }
     }
 }
 
-fn _merge_sort(
+/// Sorts `lo..hi` of `perms`, using `out_lo..` of `out_perms` as the buffer.
+pub(crate) fn _merge_sort(
     arr: &Array<i32>,
-    mut lo: usize, hi: usize,
+    lo: usize, hi: usize,
     Tracked(perms): Tracked<&mut Region<i32>>,
     out_arr: &Array<i32>,
-    mut out_lo: usize,
+    out_lo: usize,
     Tracked(out_perms): Tracked<&mut Region<i32>>,
+    kernel: MergeKernel,
 )
     requires
         old(perms).lo() <= lo <= hi <= old(perms).hi() <= arr.len(),
@@ -204,52 +552,45 @@ fn _merge_sort(
         region_array::wf(*arr, (*perms)),
         perms.lo() == old(perms).lo(),
         perms.hi() == old(perms).hi(),
+        sorts_range(old(perms).values(), perms.values(), lo - perms.lo(), hi - perms.lo()),
         region_array::wf(*out_arr, *out_perms),
         out_perms.lo() == old(out_perms).lo(),
         out_perms.hi() == old(out_perms).hi(),
 {
-    let ghost old_out_lo = out_lo;
-    let ghost old_lo = lo;
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+    }
+    let ghost v0 = perms.values();
     let mid = lo + (hi - lo) / 2;
     if mid == lo {
+        proof {
+            lemma_sorts_range_short(v0, lo - perms.lo(), hi - perms.lo());
+        }
         return;
     }
 
-    let ghost old_perms = *perms;
-    _merge_sort(arr, lo, mid, Tracked(perms), out_arr, out_lo, Tracked(out_perms));
-    let ghost perms1 = *perms;
-    _merge_sort(arr, mid, hi, Tracked(perms), out_arr, out_lo, Tracked(out_perms));
+    _merge_sort(arr, lo, mid, Tracked(perms), out_arr, out_lo, Tracked(out_perms), kernel);
+    let ghost v1 = perms.values();
+    _merge_sort(arr, mid, hi, Tracked(perms), out_arr, out_lo, Tracked(out_perms), kernel);
+    let ghost v2 = perms.values();
 
-    merge(arr, Tracked(perms), lo, mid, mid, hi, out_arr, Tracked(out_perms), out_lo);
-    while lo < hi
-        invariant
-            perms.lo() == old(perms).lo(),
-            perms.hi() == old(perms).hi(),
-            perms.lo() <= lo <= hi <= perms.hi() <= arr.len(),
-            region_array::wf(*arr, (*perms)),
-            region_array::wf(*out_arr, *out_perms),
-            out_perms.lo() == old(out_perms).lo(),
-            out_perms.hi() == old(out_perms).hi(),
-            out_perms.lo() <= out_lo <= out_perms.hi() <= out_arr.len(),
-            out_lo <= old_out_lo + hi - old_lo <= old(out_perms).hi(),
-            out_lo - old_out_lo == lo - old_lo,
-    {
-        let ghost perms_prev = *perms;
-        let e = *region_array::read(out_arr, out_lo, Tracked(out_perms));
-        region_array::replace(arr, lo, e, Tracked(perms));
-        out_lo += 1;
-        lo += 1;
+    merge(arr, Tracked(perms), lo, mid, mid, hi, out_arr, Tracked(out_perms), out_lo, kernel);
+    let ghost merged_vals = out_perms.values().subrange(out_lo - out_perms.lo(), out_lo - out_perms.lo() + (hi - lo));
+    region_array::copy_range(out_arr, Tracked(out_perms), out_lo, arr, Tracked(perms), lo, hi - lo);
+    proof {
+        lemma_sorts_range_halves(v0, v1, v2, merged_vals, perms.values(), lo - perms.lo(), mid - perms.lo(), hi - perms.lo());
     }
 }
 
-fn _merge_sort_parallel(
+pub(crate) fn _merge_sort_parallel(
     arr: Arc<Array<i32>>,
-    mut lo: usize, hi: usize,
+    lo: usize, hi: usize,
     Tracked(perms): Tracked<&mut Region<i32>>,
     out_arr: Arc<Array<i32>>,
-    mut out_lo: usize,
+    out_lo: usize,
     Tracked(out_perms): Tracked<&mut Region<i32>>,
-    threshold: usize
+    threshold: usize,
+    kernel: MergeKernel,
 ) -> (ret: Result<(), ()>)
     requires
         old(perms).lo() <= lo <= hi <= old(perms).hi() <= arr.len(),
@@ -259,21 +600,26 @@ fn _merge_sort_parallel(
     ensures
         ret.is_ok() ==> region_array::wf(*arr, (*perms)),
         ret.is_ok() ==> old(perms).lo() == perms.lo() && old(perms).hi() == perms.hi(),
+        ret.is_ok() ==> sorts_range(old(perms).values(), perms.values(), lo - perms.lo(), hi - perms.lo()),
         ret.is_ok() ==> region_array::wf(*out_arr, *out_perms),
         ret.is_ok() ==> old(out_perms).lo() == out_perms.lo() && old(out_perms).hi() == out_perms.hi(),
 {
-    let ghost old_perms = *old(perms);
-    let ghost old_out_perms = *old(out_perms);
-    let ghost old_out_lo = out_lo;
-    let ghost old_lo = lo;
+    proof {
+        region_array::lemma_values_len(&*arr, *perms);
+    }
+    let ghost v0 = perms.values();
+    let ghost plo = perms.lo();
     let mid = lo + (hi - lo) / 2;
     let out_mid = out_lo + (hi - lo) / 2;
     if mid == lo {
+        proof {
+            lemma_sorts_range_short(v0, lo - plo, hi - plo);
+        }
         return Ok(());
     }
 
     if hi - lo <= threshold {
-        _merge_sort(&*arr, lo, hi, Tracked(perms), &*out_arr, out_lo, Tracked(out_perms));
+        _merge_sort(&*arr, lo, hi, Tracked(perms), &*out_arr, out_lo, Tracked(out_perms), kernel);
         return Ok(());
     }
 
@@ -283,6 +629,15 @@ fn _merge_sort_parallel(
     let tracked out_right_perms = region_array::split_off(&*out_arr, out_mid, out_perms);
     let tracked out_left_perms = region_array::split_off(&*out_arr, out_lo, out_perms);
 
+    let ghost front_vals = perms.values();
+    let ghost left_vals = left_perms.values();
+    let ghost right_vals = right_perms.values();
+    proof {
+        region_array::lemma_values_len(&*arr, left_perms);
+        region_array::lemma_values_len(&*arr, right_perms);
+        assert(v0 =~= front_vals + left_vals + right_vals);
+    }
+
     let arr_r1 = Arc::clone(&arr);
     let arr_r2 = Arc::clone(&arr);
 
@@ -292,13 +647,12 @@ fn _merge_sort_parallel(
     let left_perms = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<i32>>, Tracked<Region<i32>>), ()>)
         ensures
             ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().0@) && ret.unwrap().0@.lo() == lo && ret.unwrap().0@.hi() == mid,
+            ret.is_ok() ==> sorts_range(left_vals, ret.unwrap().0@.values(), 0, mid - lo),
             ret.is_ok() ==> region_array::wf(*out_arr,ret.unwrap().1@) && ret.unwrap().1@.lo() == out_lo && ret.unwrap().1@.hi() == out_mid,
         {
             let tracked mut left_perms = left_perms;
             let tracked mut out_left_perms = out_left_perms;
-            let ghost old_left_perms = left_perms;
-            let ghost old_out_left_perms = out_left_perms;
-            let t = _merge_sort_parallel(arr_r1, lo, mid, Tracked(&mut left_perms), out_arr_r1, out_lo, Tracked(&mut out_left_perms), threshold);
+            let t = _merge_sort_parallel(arr_r1, lo, mid, Tracked(&mut left_perms), out_arr_r1, out_lo, Tracked(&mut out_left_perms), threshold, kernel);
             if t.is_err() {
                 Err(())
             } else {
@@ -307,7 +661,7 @@ fn _merge_sort_parallel(
         }
     );
 
-    match _merge_sort_parallel(arr_r2, mid, hi, Tracked(&mut right_perms), out_arr_r2, out_mid, Tracked(&mut out_right_perms), threshold) {
+    match _merge_sort_parallel(arr_r2, mid, hi, Tracked(&mut right_perms), out_arr_r2, out_mid, Tracked(&mut out_right_perms), threshold, kernel) {
         Ok(()) => {},
         Err(_) => {return Err(());},
     };
@@ -323,31 +677,28 @@ fn _merge_sort_parallel(
         }
     };
 
+    let ghost left_sorted = left_perms.values();
+    let ghost right_sorted = right_perms.values();
+    let ghost v1 = front_vals + left_sorted + right_vals;
     proof {
+        let empty = Seq::<i32>::empty();
+        lemma_sorts_range_frame(front_vals, left_vals, left_sorted, right_vals, 0, mid - lo);
+        lemma_sorts_range_frame(front_vals + left_sorted, right_vals, right_sorted, empty, 0, hi - mid);
+        assert(front_vals + left_sorted + right_vals + empty =~= v1);
+
         region_array::merge(&*arr, &mut left_perms, right_perms);
         region_array::merge(&*arr, perms, left_perms);
         region_array::merge(&*out_arr, &mut out_left_perms, out_right_perms);
         region_array::merge(&*out_arr, out_perms, out_left_perms);
+        assert(perms.values() =~= front_vals + left_sorted + right_sorted + empty);
     }
+    let ghost v2 = perms.values();
 
-    merge(&arr, Tracked(perms), lo, mid, mid, hi, &out_arr, Tracked(out_perms), out_lo);
-    while lo < hi
-        invariant
-            perms.lo() == old_perms.lo(),
-            perms.hi() == old_perms.hi(),
-            perms.lo() <= lo <= hi <= perms.hi() <= arr.len(),
-            region_array::wf(*arr, *perms),
-            region_array::wf(*out_arr, *out_perms),
-            out_perms.lo() == old(out_perms).lo(),
-            out_perms.hi() == old(out_perms).hi(),
-            out_perms.lo() <= out_lo <= out_perms.hi() <= out_arr.len(),
-            out_lo <= old_out_lo + hi - old_lo <= old(out_perms).hi(),
-            out_lo - old_out_lo == lo - old_lo,
-    {
-        let e = *region_array::read(&*out_arr, out_lo, Tracked(out_perms));
-        region_array::replace(&*arr, lo, e, Tracked(perms));
-        out_lo += 1;
-        lo += 1;
+    merge(&arr, Tracked(perms), lo, mid, mid, hi, &out_arr, Tracked(out_perms), out_lo, kernel);
+    let ghost merged_vals = out_perms.values().subrange(out_lo - out_perms.lo(), out_lo - out_perms.lo() + (hi - lo));
+    region_array::copy_range(&*out_arr, Tracked(out_perms), out_lo, &*arr, Tracked(perms), lo, hi - lo);
+    proof {
+        lemma_sorts_range_halves(v0, v1, v2, merged_vals, perms.values(), lo - plo, mid - plo, hi - plo);
     }
     Ok(())
 }
@@ -359,9 +710,14 @@ fn test_par_array() {
     let arr = Arc::new(arr);
     let (out_arr, Tracked(mut out_perms)) = region_array::new(vec![0, 0, 0, 0, 0]);
     let out_arr = Arc::new(out_arr);
-    _merge_sort_parallel(Arc::clone(&arr), 0, len, Tracked(&mut perms), Arc::clone(&out_arr), 0, Tracked(&mut out_perms), 2).unwrap();
+    _merge_sort_parallel(Arc::clone(&arr), 0, len, Tracked(&mut perms), Arc::clone(&out_arr), 0, Tracked(&mut out_perms), 2, MergeKernel::Branching).unwrap();
     let arr = region_array::clone_to_vec(&arr, Tracked(&perms));
     assert_eq!(arr, vec![1, 2, 3, 4, 5]);
+
+    let mut arr = ArrayForSorting::new(vec![3, 1, 4, 1, 5, 9, 2, 6, 5, 3]);
+    let mut out_arr = ArrayForSorting::new(vec![0; 10]);
+    merge_sort_parallel_with(&mut arr, &mut out_arr, 2, MergeKernel::Branchless).unwrap();
+    assert_eq!(arr.clone_to_vec(), vec![1, 1, 2, 3, 3, 4, 5, 5, 6, 9]);
 }
 
 }
@@ -0,0 +1,540 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::{self, ArrayForSorting, MergeKernel},
+    merge_sorted::sorted,
+};
+
+/// Runs shorter than this are extended with insertion sort,
+/// so random input does not degrade into merging runs of length 2.
+pub const MIN_RUN: usize = 32;
+
+/// `runs` are the boundaries of consecutive non-empty runs covering `lo..hi`:
+/// run `k` is `runs[k]..runs[k + 1]`.
+pub open spec fn runs_wf(runs: Seq<usize>, lo: usize, hi: usize) -> bool {
+    &&& runs.len() >= 1
+    &&& runs[0] == lo
+    &&& runs.last() == hi
+    &&& forall |a: int, b: int| 0 <= a < b < runs.len() ==> runs[a] < runs[b]
+}
+
+pub fn natural_merge_sort(
+    arr: &mut ArrayForSorting<i32>,
+    out_arr: &mut ArrayForSorting<i32>,
+)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        old(out_arr).perms@.lo() == 0,
+        old(out_arr).perms@.hi() == old(out_arr).array.len(),
+        region_array::wf(*old(out_arr).array, (old(out_arr).perms@)),
+        old(arr).array.len() == old(out_arr).array.len(),
+    ensures
+        region_array::wf(*arr.array, (arr.perms@)),
+        arr.perms@.lo() == old(arr).perms@.lo(),
+        arr.perms@.hi() == old(arr).perms@.hi(),
+{
+    let len = (&*arr.array).length();
+    let runs = find_runs(&arr.array, 0, len, Tracked(arr.perms.borrow_mut()));
+    if runs.len() == 1 {
+        return;
+    }
+    _natural_merge_sort(
+        &arr.array,
+        &runs,
+        0,
+        runs.len() - 1,
+        Tracked(arr.perms.borrow_mut()),
+        &out_arr.array,
+        Tracked(out_arr.perms.borrow_mut()),
+    )
+}
+
+pub fn natural_merge_sort_parallel(
+    arr: &mut ArrayForSorting<i32>,
+    out_arr: &mut ArrayForSorting<i32>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        old(out_arr).perms@.lo() == 0,
+        old(out_arr).perms@.hi() == old(out_arr).array.len(),
+        region_array::wf(*old(out_arr).array, (old(out_arr).perms@)),
+        old(arr).array.len() == old(out_arr).array.len(),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo(),
+        ret.is_ok() ==> arr.perms@.hi() == old(arr).perms@.hi(),
+{
+    let len = (&*arr.array).length();
+    let runs = match _find_runs_parallel(Arc::clone(&arr.array), 0, len, Tracked(arr.perms.borrow_mut()), threshold) {
+        Ok(runs) => runs,
+        Err(_) => { return Err(()); },
+    };
+    if runs.len() == 1 {
+        return Ok(());
+    }
+    let n_runs = runs.len() - 1;
+    _natural_merge_sort_parallel(
+        Arc::clone(&arr.array),
+        Arc::new(runs),
+        0,
+        n_runs,
+        Tracked(arr.perms.borrow_mut()),
+        Arc::clone(&out_arr.array),
+        Tracked(out_arr.perms.borrow_mut()),
+        threshold,
+    )
+}
+
+/// Splits `lo..hi` into non-decreasing runs of at least `MIN_RUN` elements
+/// (except for the last one). Strictly decreasing runs are reversed in place,
+/// which keeps equal elements in their original order.
+fn find_runs(
+    arr: &Array<i32>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+) -> (runs: Vec<usize>)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() <= lo <= hi <= old(perms).hi(),
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == old(perms).lo(),
+        perms.hi() == old(perms).hi(),
+        runs_wf(runs@, lo, hi),
+{
+    let mut runs: Vec<usize> = Vec::new();
+    runs.push(lo);
+    let mut start = lo;
+    while start < hi
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() == old(perms).lo(),
+            perms.hi() == old(perms).hi(),
+            perms.lo() <= lo <= start <= hi <= perms.hi(),
+            runs_wf(runs@, lo, start),
+    {
+        let mut end = start + 1;
+        let mut descending = false;
+        if end < hi && *region_array::read(arr, end, Tracked(perms)) < *region_array::read(arr, start, Tracked(perms)) {
+            while end < hi && *region_array::read(arr, end, Tracked(perms)) < *region_array::read(arr, end - 1, Tracked(perms))
+                invariant
+                    region_array::wf(*arr, *perms),
+                    perms.lo() <= start < end <= hi <= perms.hi(),
+            {
+                end += 1;
+            }
+            descending = true;
+        } else {
+            while end < hi && *region_array::read(arr, end - 1, Tracked(perms)) <= *region_array::read(arr, end, Tracked(perms))
+                invariant
+                    region_array::wf(*arr, *perms),
+                    perms.lo() <= start < end <= hi <= perms.hi(),
+            {
+                end += 1;
+            }
+        }
+        let extend = end - start < MIN_RUN && end < hi;
+        let run_end = if !extend { end } else if hi - start < MIN_RUN { hi } else { start + MIN_RUN };
+
+        let tracked mut before = region_array::split_front(arr, start, perms);
+        let tracked mut run = region_array::split_front(arr, run_end, perms);
+        if extend {
+            // a short descending run is sorted along with the elements that extend it
+            insertion_sort(arr, Tracked(&mut run), start, run_end);
+        } else if descending {
+            region_array::reverse(arr, Tracked(&mut run), start, run_end);
+        }
+        proof {
+            vstd::modes::tracked_swap(perms, &mut run);
+            region_array::merge(arr, perms, run);
+            vstd::modes::tracked_swap(perms, &mut before);
+            region_array::merge(arr, perms, before);
+        }
+        end = run_end;
+        runs.push(end);
+        start = end;
+    }
+    runs
+}
+
+/// Sorts the cells of `lo..hi` by insertion, which is only worth it for short ranges
+/// or ranges that are already mostly sorted.
+pub fn insertion_sort(aself: &Array<i32>, Tracked(perms): Tracked<&mut Region<i32>>, lo: usize, hi: usize)
+    requires
+        region_array::wf(*aself, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+    ensures
+        region_array::wf(*aself, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        sorted(perms.values()),
+        perms.values().to_multiset() == old(perms).values().to_multiset(),
+{
+    proof {
+        region_array::lemma_values_len(aself, *perms);
+    }
+    if hi - lo < 2 {
+        return;
+    }
+    let mut i = lo + 1;
+    while i < hi
+        invariant
+            region_array::wf(*aself, *perms),
+            perms.lo() == lo,
+            perms.hi() == hi,
+            perms.values().len() == hi - lo,
+            perms.values().to_multiset() == old(perms).values().to_multiset(),
+            lo < i <= hi,
+            forall |a: int, b: int| 0 <= a <= b < i - lo ==> perms.values()[a] <= perms.values()[b],
+    {
+        // `lo..j` and `j..=i` are sorted, and nothing in `lo..j` is greater than `j + 1..=i`
+        let mut j = i;
+        while j > lo && *region_array::read(aself, j - 1, Tracked(perms)) > *region_array::read(aself, j, Tracked(perms))
+            invariant
+                region_array::wf(*aself, *perms),
+                perms.lo() == lo,
+                perms.hi() == hi,
+                perms.values().len() == hi - lo,
+                perms.values().to_multiset() == old(perms).values().to_multiset(),
+                lo < i < hi,
+                lo <= j <= i,
+                forall |a: int, b: int| 0 <= a <= b < j - lo ==> perms.values()[a] <= perms.values()[b],
+                forall |a: int, b: int| j - lo <= a <= b <= i - lo ==> perms.values()[a] <= perms.values()[b],
+                forall |a: int, b: int| 0 <= a < j - lo < b <= i - lo ==> perms.values()[a] <= perms.values()[b],
+        {
+            let ghost v = perms.values();
+            region_array::swap(aself, j - 1, j, Tracked(perms));
+            proof {
+                let w = perms.values();
+                let k = j - lo;
+                region_array::lemma_swap_multiset(v, k - 1, k);
+                assert(w[k - 1] == v[k] && w[k] == v[k - 1]);
+                assert forall |a: int, b: int| k - 1 <= a <= b <= i - lo implies w[a] <= w[b] by {
+                    if a == k - 1 && b > k {
+                        assert(v[k] <= v[b]);
+                    } else if a == k && b > k {
+                        assert(v[k - 1] <= v[b]);
+                    }
+                }
+                assert forall |a: int, b: int| 0 <= a < k - 1 < b <= i - lo implies w[a] <= w[b] by {
+                    if b == k {
+                        assert(v[a] <= v[k - 1]);
+                    } else {
+                        assert(v[a] <= v[b]);
+                    }
+                }
+            }
+            j -= 1;
+        }
+        proof {
+            let v = perms.values();
+            let k = j - lo;
+            assert forall |a: int, b: int| 0 <= a <= b < i + 1 - lo implies v[a] <= v[b] by {
+                if a < k && b == k {
+                    assert(v[a] <= v[k - 1]);
+                }
+            }
+        }
+        i += 1;
+    }
+    assert(sorted(perms.values()));
+}
+
+/// Runs are searched for in chunks of at most `threshold` elements in parallel.
+/// The runs on either side of a chunk boundary are joined into one when they are in order,
+/// or rotated into one when every element before the boundary is greater than every one
+/// after it, as in a descending run that the boundary cut in two.
+fn _find_runs_parallel(
+    arr: Arc<Array<i32>>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    threshold: usize,
+) -> (ret: Result<Vec<usize>, ()>)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() <= lo <= hi <= old(perms).hi(),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, *perms),
+        ret.is_ok() ==> perms.lo() == old(perms).lo() && perms.hi() == old(perms).hi(),
+        ret.is_ok() ==> runs_wf(ret.unwrap()@, lo, hi),
+{
+    let mid = lo + (hi - lo) / 2;
+    if hi - lo <= threshold || mid == lo {
+        return Ok(find_runs(&*arr, lo, hi, Tracked(perms)));
+    }
+
+    let tracked right_perms = region_array::split_off(&*arr, mid, perms);
+    let tracked left_perms = region_array::split_off(&*arr, lo, perms);
+
+    let arr_r1 = Arc::clone(&arr);
+    let arr_r2 = Arc::clone(&arr);
+
+    let left_runs = vstd::thread::spawn(move || -> (ret: Result<(Vec<usize>, Tracked<Region<i32>>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().1@) && ret.unwrap().1@.lo() == lo && ret.unwrap().1@.hi() == mid,
+            ret.is_ok() ==> runs_wf(ret.unwrap().0@, lo, mid),
+        {
+            let tracked mut left_perms = left_perms;
+            match _find_runs_parallel(arr_r1, lo, mid, Tracked(&mut left_perms), threshold) {
+                Ok(runs) => Ok((runs, Tracked(left_perms))),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    let right_runs = match _find_runs_parallel(arr_r2, mid, hi, Tracked(&mut right_perms), threshold) {
+        Ok(runs) => runs,
+        Err(_) => { return Err(()); },
+    };
+
+    let (mut runs, Tracked(mut left_perms)) = match left_runs.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::merge(&*arr, &mut left_perms, right_perms);
+        region_array::merge(&*arr, perms, left_perms);
+    }
+
+    proof {
+        assert(runs@.len() >= 2 && runs@[runs@.len() - 2] < mid);
+        assert(right_runs@.len() >= 2 && mid < right_runs@[1]);
+    }
+    // both chunks are sorted run by run now, so the last run of the left one
+    // and the first run of the right one are compared at their ends
+    let run_lo = runs[runs.len() - 2];
+    let run_hi = right_runs[1];
+    let in_order = *region_array::read(&*arr, mid - 1, Tracked(perms)) <= *region_array::read(&*arr, mid, Tracked(perms));
+    let rotated = !in_order && *region_array::read(&*arr, run_hi - 1, Tracked(perms)) < *region_array::read(&*arr, run_lo, Tracked(perms));
+    if rotated {
+        let tracked mut before = region_array::split_front(&*arr, run_lo, perms);
+        let tracked mut run = region_array::split_front(&*arr, run_hi, perms);
+        match region_array::rotate_left_parallel(Arc::clone(&arr), Tracked(&mut run), run_lo, run_hi, mid, threshold) {
+            Ok(()) => {},
+            Err(_) => { return Err(()); },
+        };
+        proof {
+            vstd::modes::tracked_swap(perms, &mut run);
+            region_array::merge(&*arr, perms, run);
+            vstd::modes::tracked_swap(perms, &mut before);
+            region_array::merge(&*arr, perms, before);
+        }
+    }
+
+    // `right_runs[0] == mid` is already the last boundary of `runs`,
+    // unless the runs around it were joined
+    let mut k: usize = 1;
+    if in_order || rotated {
+        runs.pop();
+        proof {
+            assert(runs_wf(runs@, lo, run_lo));
+        }
+        runs.push(run_hi);
+        k = 2;
+    }
+    while k < right_runs.len()
+        invariant
+            runs_wf(right_runs@, mid, hi),
+            1 <= k <= right_runs.len(),
+            runs_wf(runs@, lo, right_runs@[k - 1]),
+    {
+        runs.push(right_runs[k]);
+        k += 1;
+    }
+    Ok(runs)
+}
+
+/// Merges runs `r_lo..r_hi` of `runs`, pairing them up as a balanced tree.
+fn _natural_merge_sort(
+    arr: &Array<i32>,
+    runs: &Vec<usize>,
+    r_lo: usize, r_hi: usize,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    out_arr: &Array<i32>,
+    Tracked(out_perms): Tracked<&mut Region<i32>>,
+)
+    requires
+        r_lo < r_hi < runs@.len(),
+        forall |a: int, b: int| 0 <= a < b < runs@.len() ==> runs@[a] < runs@[b],
+        old(perms).lo() <= runs@[r_lo as int] <= runs@[r_hi as int] <= old(perms).hi() <= arr.len(),
+        region_array::wf(*arr, (*old(perms))),
+        old(out_perms).lo() <= runs@[r_lo as int] <= runs@[r_hi as int] <= old(out_perms).hi() <= out_arr.len(),
+        region_array::wf(*out_arr, (*old(out_perms))),
+    ensures
+        region_array::wf(*arr, (*perms)),
+        perms.lo() == old(perms).lo(),
+        perms.hi() == old(perms).hi(),
+        region_array::wf(*out_arr, *out_perms),
+        out_perms.lo() == old(out_perms).lo(),
+        out_perms.hi() == old(out_perms).hi(),
+{
+    if r_hi - r_lo == 1 {
+        return;
+    }
+    let r_mid = r_lo + (r_hi - r_lo) / 2;
+    _natural_merge_sort(arr, runs, r_lo, r_mid, Tracked(perms), out_arr, Tracked(out_perms));
+    _natural_merge_sort(arr, runs, r_mid, r_hi, Tracked(perms), out_arr, Tracked(out_perms));
+
+    let (lo, mid, hi) = (runs[r_lo], runs[r_mid], runs[r_hi]);
+    mergesort::merge(arr, Tracked(perms), lo, mid, mid, hi, out_arr, Tracked(out_perms), lo, MergeKernel::Branching);
+    region_array::copy_range(out_arr, Tracked(out_perms), lo, arr, Tracked(perms), lo, hi - lo);
+}
+
+/// Like `_natural_merge_sort`, but the two halves of the runs are merged
+/// in separate threads while they span more than `threshold` elements.
+fn _natural_merge_sort_parallel(
+    arr: Arc<Array<i32>>,
+    runs: Arc<Vec<usize>>,
+    r_lo: usize, r_hi: usize,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    out_arr: Arc<Array<i32>>,
+    Tracked(out_perms): Tracked<&mut Region<i32>>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        r_lo < r_hi < runs@.len(),
+        forall |a: int, b: int| 0 <= a < b < runs@.len() ==> runs@[a] < runs@[b],
+        old(perms).lo() <= runs@[r_lo as int] <= runs@[r_hi as int] <= old(perms).hi() <= arr.len(),
+        region_array::wf(*arr, (*old(perms))),
+        old(out_perms).lo() <= runs@[r_lo as int] <= runs@[r_hi as int] <= old(out_perms).hi() <= out_arr.len(),
+        region_array::wf(*out_arr, *old(out_perms)),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, (*perms)),
+        ret.is_ok() ==> old(perms).lo() == perms.lo() && old(perms).hi() == perms.hi(),
+        ret.is_ok() ==> region_array::wf(*out_arr, *out_perms),
+        ret.is_ok() ==> old(out_perms).lo() == out_perms.lo() && old(out_perms).hi() == out_perms.hi(),
+{
+    let (lo, hi) = (runs[r_lo], runs[r_hi]);
+    if r_hi - r_lo == 1 {
+        return Ok(());
+    }
+    if hi - lo <= threshold {
+        _natural_merge_sort(&*arr, &*runs, r_lo, r_hi, Tracked(perms), &*out_arr, Tracked(out_perms));
+        return Ok(());
+    }
+    let r_mid = r_lo + (r_hi - r_lo) / 2;
+    let mid = runs[r_mid];
+
+    let tracked right_perms = region_array::split_off(&*arr, mid, perms);
+    let tracked left_perms = region_array::split_off(&*arr, lo, perms);
+
+    let tracked out_right_perms = region_array::split_off(&*out_arr, mid, out_perms);
+    let tracked out_left_perms = region_array::split_off(&*out_arr, lo, out_perms);
+
+    let arr_r1 = Arc::clone(&arr);
+    let arr_r2 = Arc::clone(&arr);
+
+    let out_arr_r1 = Arc::clone(&out_arr);
+    let out_arr_r2 = Arc::clone(&out_arr);
+
+    let runs_r1 = Arc::clone(&runs);
+    let runs_r2 = Arc::clone(&runs);
+
+    let left_perms = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<i32>>, Tracked<Region<i32>>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().0@) && ret.unwrap().0@.lo() == lo && ret.unwrap().0@.hi() == mid,
+            ret.is_ok() ==> region_array::wf(*out_arr, ret.unwrap().1@) && ret.unwrap().1@.lo() == lo && ret.unwrap().1@.hi() == mid,
+        {
+            let tracked mut left_perms = left_perms;
+            let tracked mut out_left_perms = out_left_perms;
+            let t = _natural_merge_sort_parallel(arr_r1, runs_r1, r_lo, r_mid, Tracked(&mut left_perms), out_arr_r1, Tracked(&mut out_left_perms), threshold);
+            if t.is_err() {
+                Err(())
+            } else {
+                Ok((Tracked(left_perms), Tracked(out_left_perms)))
+            }
+        }
+    );
+
+    match _natural_merge_sort_parallel(arr_r2, runs_r2, r_mid, r_hi, Tracked(&mut right_perms), out_arr_r2, Tracked(&mut out_right_perms), threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut left_perms), Tracked(mut out_left_perms)) = match left_perms.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::merge(&*arr, &mut left_perms, right_perms);
+        region_array::merge(&*arr, perms, left_perms);
+        region_array::merge(&*out_arr, &mut out_left_perms, out_right_perms);
+        region_array::merge(&*out_arr, out_perms, out_left_perms);
+    }
+
+    mergesort::merge(&arr, Tracked(perms), lo, mid, mid, hi, &out_arr, Tracked(out_perms), lo, MergeKernel::Branching);
+    region_array::copy_range(&*out_arr, Tracked(out_perms), lo, &*arr, Tracked(perms), lo, hi - lo);
+    Ok(())
+}
+
+#[test]
+fn test_natural_merge_sort_parallel() {
+    use crate::test_data::{scattered, thresholds};
+
+    let mut data: Vec<i32> = (0..200).collect();
+    data[10..150].reverse();
+    data.swap(3, 170);
+    let mut expected = data.clone();
+    expected.sort();
+    let mut arr = ArrayForSorting::new(data);
+    let mut out_arr = ArrayForSorting::new(vec![0; 200]);
+    natural_merge_sort_parallel(&mut arr, &mut out_arr, 40).unwrap();
+    assert_eq!(arr.clone_to_vec(), expected);
+
+    let sorted_data: Vec<i32> = (0..500).collect();
+    let reversed_data: Vec<i32> = (0..500).rev().collect();
+    // runs of 3 that descend from run to run, all shorter than `MIN_RUN`
+    let short_runs: Vec<i32> = (0..500).map(|i| (500 - i / 3 * 3) + i % 3).collect();
+    for data in [sorted_data, reversed_data, short_runs] {
+        let mut expected = data.clone();
+        expected.sort();
+        for threshold in thresholds(data.len()) {
+            let mut arr = ArrayForSorting::new(data.clone());
+            let mut out_arr = ArrayForSorting::new(vec![0; data.len()]);
+            natural_merge_sort_parallel(&mut arr, &mut out_arr, threshold).unwrap();
+            assert_eq!(arr.clone_to_vec(), expected);
+        }
+        let mut arr = ArrayForSorting::new(data.clone());
+        let mut out_arr = ArrayForSorting::new(vec![0; data.len()]);
+        natural_merge_sort(&mut arr, &mut out_arr);
+        assert_eq!(arr.clone_to_vec(), expected);
+    }
+
+    // a sorted or descending input stays a single run across the chunk boundaries
+    for data in [(0..500).collect::<Vec<i32>>(), (0..500).rev().collect()] {
+        let mut arr = ArrayForSorting::new(data);
+        let runs = _find_runs_parallel(Arc::clone(&arr.array), 0, 500, Tracked(arr.perms.borrow_mut()), 7).unwrap();
+        assert_eq!(runs, vec![0, 500]);
+    }
+
+    let data: Vec<i32> = scattered(1000, 401, 0);
+    let mut arr = ArrayForSorting::new(data.clone());
+    insertion_sort(&arr.array, Tracked(arr.perms.borrow_mut()), 0, 1000);
+    let mut expected = data;
+    expected.sort();
+    assert_eq!(arr.clone_to_vec(), expected);
+}
+
+}
@@ -0,0 +1,139 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::ArrayForSorting,
+    bitonic_sort::compare_exchange,
+};
+
+/// Sorts `arr` with `len` rounds of odd-even transposition: round `r` compares
+/// the pairs `(i, i + 1)` with `i % 2 == r % 2`. The regions handed to the threads
+/// start at indices of the round's parity, so their boundaries move by one every round.
+pub fn odd_even_sort_parallel(
+    arr: &mut ArrayForSorting<i32>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo(),
+        ret.is_ok() ==> arr.perms@.hi() == old(arr).perms@.hi(),
+{
+    let n = (&*arr.array).length();
+    let mut round: usize = 0;
+    while round < n
+        invariant
+            arr.array.len() == n,
+            arr.perms@.lo() == 0,
+            arr.perms@.hi() == n,
+            region_array::wf(*arr.array, (arr.perms@)),
+    {
+        // `arr[0]` sits out the odd rounds
+        let lo = round % 2;
+        match _round_parallel(Arc::clone(&arr.array), lo, n, Tracked(arr.perms.borrow_mut()), threshold) {
+            Ok(()) => {},
+            Err(_) => {return Err(());},
+        };
+        round += 1;
+    }
+    Ok(())
+}
+
+/// Compares the pairs `(lo, lo + 1), (lo + 2, lo + 3), ...` within `lo..hi`.
+fn _round_parallel(
+    arr: Arc<Array<i32>>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(perms).lo() <= lo <= hi <= old(perms).hi() <= arr.len(),
+        region_array::wf(*arr, (*old(perms))),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, (*perms)),
+        ret.is_ok() ==> old(perms).lo() == perms.lo() && old(perms).hi() == perms.hi(),
+{
+    if hi - lo <= threshold || hi - lo < 4 {
+        let mut i = lo;
+        while hi - i >= 2
+            invariant
+                lo <= i <= hi,
+                perms.lo() == old(perms).lo(),
+                perms.hi() == old(perms).hi(),
+                perms.lo() <= lo,
+                hi <= perms.hi(),
+                region_array::wf(*arr, (*perms)),
+        {
+            compare_exchange(&*arr, i, i + 1, Tracked(perms));
+            i += 2;
+        }
+        return Ok(());
+    }
+
+    // `mid - lo` is even, so that no pair is cut in two
+    let mid = lo + (hi - lo) / 4 * 2;
+    assert(lo < mid < hi) by (nonlinear_arith)
+        requires hi - lo >= 4, mid == lo + (hi - lo) / 4 * 2;
+
+    let tracked right_perms = region_array::split_off(&*arr, mid, perms);
+    let tracked left_perms = region_array::split_off(&*arr, lo, perms);
+
+    let arr_r1 = Arc::clone(&arr);
+    let arr_r2 = Arc::clone(&arr);
+
+    let left_perms = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<i32>>, ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap()@) && ret.unwrap()@.lo() == lo && ret.unwrap()@.hi() == mid,
+        {
+            let tracked mut left_perms = left_perms;
+            let t = _round_parallel(arr_r1, lo, mid, Tracked(&mut left_perms), threshold);
+            if t.is_err() {
+                Err(())
+            } else {
+                Ok(Tracked(left_perms))
+            }
+        }
+    );
+
+    match _round_parallel(arr_r2, mid, hi, Tracked(&mut right_perms), threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut left_perms) = match left_perms.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::merge(&*arr, &mut left_perms, right_perms);
+        region_array::merge(&*arr, perms, left_perms);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_odd_even_sort_parallel() {
+    use crate::test_data::scattered;
+
+    let data: Vec<i32> = scattered(301, 307, -150);
+    let mut expected = data.clone();
+    expected.sort();
+    let mut arr = ArrayForSorting::new(data);
+    odd_even_sort_parallel(&mut arr, 16).unwrap();
+    assert_eq!(arr.clone_to_vec(), expected);
+}
+
+}
@@ -166,7 +166,10 @@ impl<T> Array<T> {
             self.available(i, *old(perms)),
         ensures
             self.availability_unchanged(*old(perms), *perms),
-            self.wf(*perms)
+            self.wf(*perms),
+            *perms == old(perms).insert(i, perms[i]),
+            perms[i]@.value.unwrap() == x,
+            res == old(perms)[i]@.value.unwrap(),
     {
         let tracked mut perm = perms.tracked_remove(i);
         let ghost old_perm = perm;
@@ -174,6 +177,7 @@ impl<T> Array<T> {
         // let res = self.ptrs[i].replace(Tracked(&mut perm), x);
         proof {
             perms.tracked_insert(i, perm);
+            assert(*perms =~= old(perms).insert(i, perms[i]));
         }
         res
     }
@@ -184,12 +188,48 @@ impl<T> Array<T> {
             i < self.len(),
             self.wf(*perms),
             self.available(i, *perms),
+        ensures
+            *res == perms[i]@.value.unwrap(),
     {
         let tracked perm = perms.tracked_borrow(i);
         Self::vec_borrow(&self.ptrs, i, Tracked(perm))
         // self.ptrs[i].borrow(Tracked(perm))
     }
 
+    /// Copies the cells `src_lo..src_lo + len` of `src` to `lo..lo + len` with a single `memmove`.
+    #[verifier::external_body]
+    pub fn copy_from(
+        &self,
+        lo: usize,
+        src: &Array<T>,
+        src_lo: usize,
+        len: usize,
+        Tracked(src_perms): Tracked<&SpecPerms<T>>,
+        Tracked(perms): Tracked<&mut SpecPerms<T>>,
+    )
+        where T: Copy,
+        requires
+            lo + len <= self.len(),
+            src_lo + len <= src.len(),
+            self.wf(*old(perms)),
+            src.wf(*src_perms),
+            forall |i: usize| lo <= i < lo + len ==> self.available(i, *old(perms)),
+            forall |i: usize| src_lo <= i < src_lo + len ==> src.available(i, *src_perms),
+        ensures
+            self.availability_unchanged(*old(perms), *perms),
+            self.wf(*perms),
+            forall |i: usize| !(lo <= i < lo + len) ==> #[trigger] perms[i] == old(perms)[i],
+            forall |i: usize| lo <= i < lo + len ==>
+                #[trigger] perms[i]@.value.unwrap() == src_perms[(src_lo + (i - lo)) as usize]@.value.unwrap(),
+    {
+        // the values live in `UnsafeCell`s, so they may be written through a shared reference
+        unsafe {
+            let src_ptr = src.ptrs.as_ptr().add(src_lo);
+            let dst_ptr = self.ptrs.as_ptr().add(lo) as *mut PCell<T>;
+            std::ptr::copy(src_ptr, dst_ptr, len);
+        }
+    }
+
     /// probably it should be implemented not as clone,
     /// but as consuming into Vec<T>,
     /// but to make it faster I have to cast?? TODO
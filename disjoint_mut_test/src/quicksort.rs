@@ -0,0 +1,339 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use vstd::thread::JoinHandle;
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::ArrayForSorting,
+    selection,
+};
+
+pub fn quick_sort(arr: &mut ArrayForSorting<i32>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+    ensures
+        region_array::wf(*arr.array, (arr.perms@)),
+        arr.perms@.lo() == old(arr).perms@.lo(),
+        arr.perms@.hi() == old(arr).perms@.hi(),
+{
+    _quick_sort(&arr.array, 0, (&*arr.array).length(), Tracked(arr.perms.borrow_mut()))
+}
+
+pub fn quick_sort_parallel(
+    arr: &mut ArrayForSorting<i32>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo(),
+        ret.is_ok() ==> arr.perms@.hi() == old(arr).perms@.hi(),
+{
+    _quick_sort_parallel(
+        Arc::clone(&arr.array),
+        0,
+        (&*arr.array).length(),
+        Tracked(arr.perms.borrow_mut()),
+        threshold,
+    )
+}
+
+/// Partitions `lo..hi` three ways around its middle element and returns `(p, q)`:
+/// `lo..p` holds the elements `< pivot`, `p..q` the elements `== pivot` and `q..hi`
+/// the elements `> pivot`. Only `lo..p` and `q..hi` are left to sort, so runs of equal
+/// elements do not make the recursion deeper.
+pub(crate) fn partition(
+    arr: &Array<i32>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+) -> (res: (usize, usize))
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() <= lo < hi <= old(perms).hi(),
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == old(perms).lo(),
+        perms.hi() == old(perms).hi(),
+        lo <= res.0 <= res.1 <= hi,
+{
+    let pivot = *region_array::read(arr, lo + (hi - lo) / 2, Tracked(perms));
+    let ghost (low, high) = (i32::MIN as int, i32::MAX as int);
+
+    let tracked mut before = region_array::split_front(arr, lo, perms);
+    let tracked mut part = region_array::split_front(arr, hi, perms);
+    let p = selection::partition(arr, lo, hi, Tracked(&mut part), pivot, false, Ghost(low), Ghost(high));
+    let tracked mut smaller = region_array::split_front(arr, p, &mut part);
+    let q = selection::partition(arr, p, hi, Tracked(&mut part), pivot, true, Ghost(low), Ghost(high));
+    proof {
+        region_array::merge(arr, &mut smaller, part);
+        vstd::modes::tracked_swap(perms, &mut smaller);
+        region_array::merge(arr, perms, smaller);
+        vstd::modes::tracked_swap(perms, &mut before);
+        region_array::merge(arr, perms, before);
+    }
+    (p, q)
+}
+
+/// Sorts the smaller side of each partition recursively and loops on the larger one,
+/// so the recursion is at most `log2(hi - lo)` deep.
+pub(crate) fn _quick_sort(
+    arr: &Array<i32>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+)
+    requires
+        old(perms).lo() <= lo <= hi <= old(perms).hi() <= arr.len(),
+        region_array::wf(*arr, (*old(perms))),
+    ensures
+        region_array::wf(*arr, (*perms)),
+        perms.lo() == old(perms).lo(),
+        perms.hi() == old(perms).hi(),
+{
+    let mut lo = lo;
+    let mut hi = hi;
+    while hi - lo > 1
+        invariant
+            region_array::wf(*arr, (*perms)),
+            perms.lo() == old(perms).lo(),
+            perms.hi() == old(perms).hi(),
+            perms.lo() <= lo <= hi <= perms.hi(),
+    {
+        let (p, q) = partition(arr, lo, hi, Tracked(perms));
+        if p - lo <= hi - q {
+            _quick_sort(arr, lo, p, Tracked(perms));
+            lo = q;
+        } else {
+            _quick_sort(arr, q, hi, Tracked(perms));
+            hi = p;
+        }
+    }
+}
+
+/// The smaller side of a partition that `_quick_sort_parallel` sent to another thread,
+/// together with everything of the partitioned region on its side of the larger one.
+/// `lo..hi` is at one end of `outer_lo..outer_hi`, the region that was left before it was taken.
+struct Layer {
+    lo: usize,
+    hi: usize,
+    outer_lo: usize,
+    outer_hi: usize,
+    handle: JoinHandle<Result<Tracked<Region<i32>>, ()>>,
+}
+
+impl Layer {
+    /// The start of what is left of `outer_lo..outer_hi` without the layer.
+    spec fn inner_lo(&self) -> usize {
+        if self.lo == self.outer_lo { self.hi } else { self.outer_lo }
+    }
+
+    /// The end of what is left of `outer_lo..outer_hi` without the layer.
+    spec fn inner_hi(&self) -> usize {
+        if self.lo == self.outer_lo { self.outer_hi } else { self.lo }
+    }
+
+    spec fn wf(&self, arr: Array<i32>) -> bool {
+        &&& self.outer_lo <= self.lo < self.hi <= self.outer_hi
+        &&& self.lo == self.outer_lo || self.hi == self.outer_hi
+        &&& forall |ret: Result<Tracked<Region<i32>>, ()>| #[trigger] self.handle.predicate(ret) ==>
+            (ret.is_ok() ==> region_array::wf(arr, ret.unwrap()@) && ret.unwrap()@.lo() == self.lo && ret.unwrap()@.hi() == self.hi)
+    }
+}
+
+/// `layers` were taken one after another from the ends of `lo..hi`, which leaves `w_lo..w_hi`.
+spec fn layers_wf(layers: Seq<Layer>, arr: Array<i32>, lo: usize, hi: usize, w_lo: usize, w_hi: usize) -> bool
+    decreases layers.len()
+{
+    if layers.len() == 0 {
+        w_lo == lo && w_hi == hi
+    } else {
+        let l = layers.last();
+        &&& l.wf(arr)
+        &&& w_lo == l.inner_lo()
+        &&& w_hi == l.inner_hi()
+        &&& layers_wf(layers.drop_last(), arr, lo, hi, l.outer_lo, l.outer_hi)
+    }
+}
+
+/// After partitioning, the smaller side is sorted in a new thread if it is longer than
+/// `threshold`, and in this one otherwise. Unlike mergesort, the split point depends on the data.
+/// Like `_quick_sort`, this loops on the larger side, so the recursion is at most
+/// `log2(hi - lo)` deep; the threads are joined once the larger side is sorted.
+fn _quick_sort_parallel(
+    arr: Arc<Array<i32>>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(perms).lo() <= lo <= hi <= old(perms).hi() <= arr.len(),
+        region_array::wf(*arr, (*old(perms))),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, (*perms)),
+        ret.is_ok() ==> old(perms).lo() == perms.lo() && old(perms).hi() == perms.hi(),
+{
+    let ghost (lo0, hi0) = (lo, hi);
+    // `work` is the region that the loop works on, `before` and `perms` are the rest
+    let tracked mut before = region_array::split_front(&*arr, lo, perms);
+    let tracked mut work = region_array::split_front(&*arr, hi, perms);
+
+    let mut layers: Vec<Layer> = Vec::new();
+    let (mut w_lo, mut w_hi) = (lo, hi);
+    let (mut lo, mut hi) = (lo, hi);
+    while hi - lo > 1 && hi - lo > threshold
+        invariant
+            region_array::wf(*arr, work),
+            work.lo() == w_lo,
+            work.hi() == w_hi,
+            w_lo <= lo <= hi <= w_hi,
+            layers_wf(layers@, *arr, lo0, hi0, w_lo, w_hi),
+            region_array::wf(*arr, before),
+            before.lo() == old(perms).lo(),
+            before.hi() == lo0,
+            region_array::wf(*arr, *perms),
+            perms.lo() == hi0,
+            perms.hi() == old(perms).hi(),
+    {
+        let (p, q) = partition(&*arr, lo, hi, Tracked(&mut work));
+        let (s_lo, s_hi, l_lo, l_hi) = if p - lo <= hi - q { (lo, p, q, hi) } else { (q, hi, lo, p) };
+        if s_hi - s_lo <= threshold {
+            // too short to be worth a thread
+            _quick_sort(&*arr, s_lo, s_hi, Tracked(&mut work));
+        } else {
+            // the new thread takes everything of `work` on the side of the larger one that the smaller one is on
+            let left = s_lo == lo;
+            let (layer_lo, layer_hi) = if left { (w_lo, l_lo) } else { (l_hi, w_hi) };
+            let tracked mut layer_perms = region_array::split_front(&*arr, if left { l_lo } else { l_hi }, &mut work);
+            if !left {
+                proof {
+                    vstd::modes::tracked_swap(&mut work, &mut layer_perms);
+                }
+            }
+
+            let arr_r = Arc::clone(&arr);
+            let handle = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<i32>>, ()>)
+                ensures
+                    ret.is_ok() ==> region_array::wf(*arr, ret.unwrap()@) && ret.unwrap()@.lo() == layer_lo && ret.unwrap()@.hi() == layer_hi,
+                {
+                    let tracked mut layer_perms = layer_perms;
+                    let t = _quick_sort_parallel(arr_r, s_lo, s_hi, Tracked(&mut layer_perms), threshold);
+                    if t.is_err() {
+                        Err(())
+                    } else {
+                        Ok(Tracked(layer_perms))
+                    }
+                }
+            );
+            let layer = Layer { lo: layer_lo, hi: layer_hi, outer_lo: w_lo, outer_hi: w_hi, handle };
+            let ghost old_layers = layers@;
+            layers.push(layer);
+            proof {
+                assert(layers@.drop_last() =~= old_layers);
+            }
+            if left {
+                w_lo = l_lo;
+            } else {
+                w_hi = l_hi;
+            }
+        }
+        lo = l_lo;
+        hi = l_hi;
+    }
+    _quick_sort(&*arr, lo, hi, Tracked(&mut work));
+
+    // the layers are put back around `work` in the reverse order they were taken
+    while layers.len() > 0
+        invariant
+            region_array::wf(*arr, work),
+            layers_wf(layers@, *arr, lo0, hi0, work.lo(), work.hi()),
+            region_array::wf(*arr, before),
+            before.lo() == old(perms).lo(),
+            before.hi() == lo0,
+            region_array::wf(*arr, *perms),
+            perms.lo() == hi0,
+            perms.hi() == old(perms).hi(),
+    {
+        let ghost old_layers = layers@;
+        let Layer { lo: layer_lo, hi: _, outer_lo, outer_hi: _, handle } = layers.pop().unwrap();
+        proof {
+            assert(layers@ =~= old_layers.drop_last());
+        }
+        let Tracked(mut layer_perms) = match handle.join() {
+            Result::Ok(Ok(l)) => {
+                l
+            },
+            _ => {
+                return Result::Err(());
+            }
+        };
+        if layer_lo == outer_lo {
+            proof {
+                vstd::modes::tracked_swap(&mut work, &mut layer_perms);
+            }
+        }
+        proof {
+            region_array::merge(&*arr, &mut work, layer_perms);
+        }
+    }
+
+    proof {
+        vstd::modes::tracked_swap(perms, &mut work);
+        region_array::merge(&*arr, perms, work);
+        vstd::modes::tracked_swap(perms, &mut before);
+        region_array::merge(&*arr, perms, before);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_quick_sort_parallel() {
+    use crate::test_data::thresholds;
+
+    let (arr, Tracked(mut perms)) = region_array::new(vec![5, 1, 4, 1, 3, 9, 2, 6, 5, 3]);
+    let len = arr.length();
+    let arr = Arc::new(arr);
+    _quick_sort_parallel(Arc::clone(&arr), 0, len, Tracked(&mut perms), 2).unwrap();
+    let arr = region_array::clone_to_vec(&arr, Tracked(&perms));
+    assert_eq!(arr, vec![1, 1, 2, 3, 3, 4, 5, 5, 6, 9]);
+
+    // with a two-way partition every element equal to the pivot is left to sort,
+    // which made the recursion as deep as the input was long
+    for threshold in thresholds(1_000_000) {
+        let mut arr = ArrayForSorting::new(vec![7; 1_000_000]);
+        quick_sort_parallel(&mut arr, threshold).unwrap();
+        assert_eq!(arr.clone_to_vec(), vec![7; 1_000_000]);
+    }
+
+    // a killer for the middle pivot: the middle of every range left to sort is its largest
+    // element, so each partition only takes off the pivot. It is built by partitioning the
+    // positions the way the sort will, with the largest value put in the middle each time.
+    let n = 20_000;
+    let (positions, Tracked(mut perms)) = region_array::new((0..n as i32).collect::<Vec<i32>>());
+    let mut killer = vec![0; n];
+    let mut hi = n;
+    while hi > 0 {
+        let position = region_array::replace(&positions, hi / 2, i32::MAX, Tracked(&mut perms));
+        partition(&positions, 0, hi, Tracked(&mut perms));
+        region_array::replace(&positions, hi - 1, position, Tracked(&mut perms));
+        killer[position as usize] = hi as i32;
+        hi -= 1;
+    }
+    let mut expected = killer.clone();
+    expected.sort();
+    for threshold in [1, 4] {
+        let mut arr = ArrayForSorting::new(killer.clone());
+        quick_sort_parallel(&mut arr, threshold).unwrap();
+        assert_eq!(arr.clone_to_vec(), expected);
+    }
+}
+
+}
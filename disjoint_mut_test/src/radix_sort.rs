@@ -0,0 +1,550 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::ArrayForSorting,
+    chunks::{chunks_wf, chunk_bounds, MAX_CHUNKS},
+};
+
+/// Number of distinct digits: keys are sorted 8 bits per pass.
+pub const RADIX: usize = 256;
+
+/// Segment `j` of the output is `offsets[j]..offsets[j + 1]` and receives the elements
+/// of chunk `j % n_chunks` with digit `j / n_chunks`.
+/// `segs` holds the regions of exactly the segments of chunks `t_lo..t_hi`.
+pub open spec fn owns_segments(
+    dst: Array<i32>,
+    segs: Map<usize, Region<i32>>,
+    offsets: Seq<usize>,
+    n_chunks: usize,
+    t_lo: usize, t_hi: usize,
+) -> bool {
+    &&& forall |j: usize| #[trigger] segs.contains_key(j) ==> j < n_chunks * RADIX && t_lo <= j % n_chunks < t_hi
+    &&& forall |j: usize| j < n_chunks * RADIX && t_lo <= j % n_chunks < t_hi ==>
+            #[trigger] segs.contains_key(j)
+            && region_array::wf(dst, segs[j])
+            && segs[j].lo() == offsets[j as int]
+            && segs[j].hi() == offsets[j + 1]
+}
+
+proof fn lemma_segment(d: usize, t: usize, n_chunks: usize)
+    requires
+        d < RADIX,
+        t < n_chunks,
+    ensures
+        d * n_chunks + t < n_chunks * RADIX,
+        (d * n_chunks + t) % (n_chunks as int) == t,
+{
+    assert(d * n_chunks + t < n_chunks * RADIX) by (nonlinear_arith)
+        requires d < RADIX, t < n_chunks;
+    vstd::arithmetic::div_mod::lemma_fundamental_div_mod_converse(
+        (d * n_chunks + t) as int, n_chunks as int, d as int, t as int);
+}
+
+/// Sorts `arr` with four passes of a least significant digit radix sort,
+/// which move the elements to `out_arr` and back twice.
+/// Every pass splits the input into chunks of at least `threshold` elements:
+/// each chunk is counted and then scattered by its own thread.
+pub fn radix_sort_parallel(
+    arr: &mut ArrayForSorting<i32>,
+    out_arr: &mut ArrayForSorting<i32>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        old(out_arr).perms@.lo() == 0,
+        old(out_arr).perms@.hi() == old(out_arr).array.len(),
+        region_array::wf(*old(out_arr).array, (old(out_arr).perms@)),
+        old(arr).array.len() == old(out_arr).array.len(),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo(),
+        ret.is_ok() ==> arr.perms@.hi() == old(arr).perms@.hi(),
+{
+    let n = (&*arr.array).length();
+    if n == 0 {
+        return Ok(());
+    }
+    let chunks = Arc::new(chunk_bounds(n, threshold, RADIX));
+
+    let mut shift: u32 = 0;
+    while shift < 32
+        invariant
+            shift <= 32,
+            shift % 16 == 0,
+            arr.array.len() == n,
+            arr.perms@.lo() == 0,
+            arr.perms@.hi() == n,
+            region_array::wf(*arr.array, (arr.perms@)),
+            out_arr.array.len() == n,
+            out_arr.perms@.lo() == 0,
+            out_arr.perms@.hi() == n,
+            region_array::wf(*out_arr.array, (out_arr.perms@)),
+            chunks_wf(chunks@, n as int),
+            chunks@.len() <= MAX_CHUNKS + 1,
+    {
+        match radix_pass(
+            Arc::clone(&arr.array),
+            Tracked(arr.perms.borrow_mut()),
+            Arc::clone(&out_arr.array),
+            Tracked(out_arr.perms.borrow_mut()),
+            Arc::clone(&chunks),
+            shift,
+        ) {
+            Ok(()) => {},
+            Err(_) => {return Err(());},
+        };
+        match radix_pass(
+            Arc::clone(&out_arr.array),
+            Tracked(out_arr.perms.borrow_mut()),
+            Arc::clone(&arr.array),
+            Tracked(arr.perms.borrow_mut()),
+            Arc::clone(&chunks),
+            shift + 8,
+        ) {
+            Ok(()) => {},
+            Err(_) => {return Err(());},
+        };
+        shift += 16;
+    }
+    Ok(())
+}
+
+/// The byte of `x` at `shift`, with the sign bit flipped so that negative numbers come first.
+fn digit(x: i32, shift: u32) -> (d: usize)
+    requires
+        shift < 32,
+    ensures
+        d < RADIX,
+{
+    let key = (x as u32) ^ 0x8000_0000u32;
+    let d = (key >> shift) & 0xFFu32;
+    assert(d <= 0xFFu32) by (bit_vector)
+        requires d == (key >> shift) & 0xFFu32;
+    d as usize
+}
+
+/// Moves `src` into `dst`, stably sorted by the digit at `shift`.
+fn radix_pass(
+    src: Arc<Array<i32>>,
+    Tracked(src_perms): Tracked<&mut Region<i32>>,
+    dst: Arc<Array<i32>>,
+    Tracked(dst_perms): Tracked<&mut Region<i32>>,
+    chunks: Arc<Vec<usize>>,
+    shift: u32,
+) -> (ret: Result<(), ()>)
+    requires
+        old(src_perms).lo() == 0,
+        old(src_perms).hi() == src.len(),
+        region_array::wf(*src, *old(src_perms)),
+        old(dst_perms).lo() == 0,
+        old(dst_perms).hi() == dst.len(),
+        region_array::wf(*dst, *old(dst_perms)),
+        src.len() == dst.len(),
+        chunks_wf(chunks@, src.len() as int),
+        chunks@.len() <= MAX_CHUNKS + 1,
+        shift < 32,
+    ensures
+        ret.is_ok() ==> region_array::wf(*src, *src_perms),
+        ret.is_ok() ==> old(src_perms).lo() == src_perms.lo() && old(src_perms).hi() == src_perms.hi(),
+        ret.is_ok() ==> region_array::wf(*dst, *dst_perms),
+        ret.is_ok() ==> old(dst_perms).lo() == dst_perms.lo() && old(dst_perms).hi() == dst_perms.hi(),
+{
+    let n = (&*src).length();
+    let n_chunks = chunks.len() - 1;
+    let hists = match _histograms_parallel(Arc::clone(&src), Tracked(src_perms), Arc::clone(&chunks), 0, n_chunks, shift) {
+        Ok(hists) => hists,
+        Err(_) => {return Err(());},
+    };
+
+    // Cut `dst` into the segments, which are laid out by digit and then by chunk.
+    let n_segs = n_chunks * RADIX;
+    let mut offsets: Vec<usize> = Vec::new();
+    offsets.push(0);
+    let tracked mut rest = region_array::split_off(&*dst, 0, dst_perms);
+    let tracked mut segs = Map::<usize, Region<i32>>::tracked_empty();
+    let mut j: usize = 0;
+    while j < n_segs
+        invariant
+            0 < n_chunks <= MAX_CHUNKS,
+            n_segs == n_chunks * RADIX,
+            j <= n_segs,
+            hists.len() == n_chunks,
+            forall |t: int| 0 <= t < n_chunks ==> #[trigger] hists@[t].len() == RADIX,
+            offsets.len() == j + 1,
+            offsets@[0] == 0,
+            region_array::wf(*dst, rest),
+            rest.lo() == offsets@[j as int],
+            rest.hi() == n,
+            n == dst.len(),
+            forall |i: usize| #[trigger] segs.contains_key(i) ==> i < j,
+            forall |i: usize| i < j ==>
+                #[trigger] segs.contains_key(i)
+                && region_array::wf(*dst, segs[i])
+                && segs[i].lo() == offsets@[i as int]
+                && segs[i].hi() == offsets@[i + 1],
+            region_array::wf(*dst, *dst_perms),
+            dst_perms.lo() == 0,
+            dst_perms.hi() == 0,
+    {
+        let (d, t) = (j / n_chunks, j % n_chunks);
+        assert(d < RADIX) by (nonlinear_arith)
+            requires j < n_chunks * RADIX, d == j / n_chunks, n_chunks > 0;
+        let lo = offsets[j];
+        let c = hists[t][d];
+        if c > n - lo {
+            return Err(());
+        }
+        let tracked seg = region_array::split_front(&*dst, lo + c, &mut rest);
+        proof {
+            segs.tracked_insert(j, seg);
+        }
+        offsets.push(lo + c);
+        j += 1;
+    }
+    if offsets[n_segs] != n {
+        return Err(());
+    }
+
+    let offsets = Arc::new(offsets);
+    let Tracked(mut segs) = match _scatter_parallel(
+        Arc::clone(&src),
+        Tracked(src_perms),
+        Arc::clone(&dst),
+        Tracked(segs),
+        Arc::clone(&chunks),
+        Arc::clone(&offsets),
+        0,
+        n_chunks,
+        shift,
+    ) {
+        Ok(segs) => segs,
+        Err(_) => {return Err(());},
+    };
+
+    proof {
+        assert forall |i: usize| i < n_segs implies #[trigger] segs.contains_key(i) by {
+            assert(i % n_chunks < n_chunks);
+        }
+    }
+    let mut j: usize = 0;
+    while j < n_segs
+        invariant
+            0 < n_chunks,
+            n_segs == n_chunks * RADIX,
+            j <= n_segs,
+            offsets@.len() == n_segs + 1,
+            offsets@[n_segs as int] == n,
+            forall |i: usize| j <= i < n_segs ==>
+                #[trigger] segs.contains_key(i)
+                && region_array::wf(*dst, segs[i])
+                && segs[i].lo() == offsets@[i as int]
+                && segs[i].hi() == offsets@[i + 1],
+            region_array::wf(*dst, *dst_perms),
+            dst_perms.lo() == 0,
+            dst_perms.hi() == offsets@[j as int],
+    {
+        proof {
+            let tracked seg = segs.tracked_remove(j);
+            region_array::merge(&*dst, dst_perms, seg);
+        }
+        j += 1;
+    }
+    Ok(())
+}
+
+fn histogram(
+    arr: &Array<i32>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&Region<i32>>,
+    shift: u32,
+) -> (hist: Vec<usize>)
+    requires
+        region_array::wf(*arr, *perms),
+        perms.lo() <= lo <= hi <= perms.hi(),
+        shift < 32,
+    ensures
+        hist.len() == RADIX,
+{
+    let mut hist: Vec<usize> = Vec::new();
+    while hist.len() < RADIX
+        invariant
+            hist.len() <= RADIX,
+            forall |d: int| 0 <= d < hist.len() ==> hist[d] == 0,
+    {
+        hist.push(0);
+    }
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() <= lo <= i <= hi <= perms.hi(),
+            hist.len() == RADIX,
+            forall |d: int| 0 <= d < RADIX ==> hist[d] <= i - lo,
+            shift < 32,
+    {
+        let d = digit(*region_array::read(arr, i, Tracked(perms)), shift);
+        let c = hist[d] + 1;
+        hist.set(d, c);
+        i += 1;
+    }
+    hist
+}
+
+/// Counts the digits of chunks `t_lo..t_hi`, one chunk per thread.
+fn _histograms_parallel(
+    arr: Arc<Array<i32>>,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    chunks: Arc<Vec<usize>>,
+    t_lo: usize, t_hi: usize,
+    shift: u32,
+) -> (ret: Result<Vec<Vec<usize>>, ()>)
+    requires
+        t_lo < t_hi < chunks@.len(),
+        forall |a: int, b: int| 0 <= a < b < chunks@.len() ==> chunks@[a] < chunks@[b],
+        old(perms).lo() <= chunks@[t_lo as int],
+        chunks@[t_hi as int] <= old(perms).hi() <= arr.len(),
+        region_array::wf(*arr, (*old(perms))),
+        shift < 32,
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, (*perms)),
+        ret.is_ok() ==> old(perms).lo() == perms.lo() && old(perms).hi() == perms.hi(),
+        ret.is_ok() ==> ret.unwrap().len() == t_hi - t_lo,
+        ret.is_ok() ==> forall |t: int| 0 <= t < t_hi - t_lo ==> #[trigger] ret.unwrap()@[t].len() == RADIX,
+{
+    if t_hi - t_lo == 1 {
+        let mut hists = Vec::new();
+        hists.push(histogram(&*arr, chunks[t_lo], chunks[t_hi], Tracked(&*perms), shift));
+        return Ok(hists);
+    }
+    let t_mid = t_lo + (t_hi - t_lo) / 2;
+    let (lo, mid) = (chunks[t_lo], chunks[t_mid]);
+
+    let tracked right_perms = region_array::split_off(&*arr, mid, perms);
+    let tracked left_perms = region_array::split_off(&*arr, lo, perms);
+
+    let arr_r1 = Arc::clone(&arr);
+    let arr_r2 = Arc::clone(&arr);
+
+    let chunks_r1 = Arc::clone(&chunks);
+    let chunks_r2 = Arc::clone(&chunks);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<i32>>, Vec<Vec<usize>>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().0@) && ret.unwrap().0@.lo() == lo && ret.unwrap().0@.hi() == mid,
+            ret.is_ok() ==> ret.unwrap().1.len() == t_mid - t_lo,
+            ret.is_ok() ==> forall |t: int| 0 <= t < t_mid - t_lo ==> #[trigger] ret.unwrap().1@[t].len() == RADIX,
+        {
+            let tracked mut left_perms = left_perms;
+            match _histograms_parallel(arr_r1, Tracked(&mut left_perms), chunks_r1, t_lo, t_mid, shift) {
+                Ok(hists) => Ok((Tracked(left_perms), hists)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    let mut right_hists = match _histograms_parallel(arr_r2, Tracked(&mut right_perms), chunks_r2, t_mid, t_hi, shift) {
+        Ok(hists) => hists,
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut left_perms), mut hists) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::merge(&*arr, &mut left_perms, right_perms);
+        region_array::merge(&*arr, perms, left_perms);
+    }
+    hists.append(&mut right_hists);
+    Ok(hists)
+}
+
+/// Moves chunk `t`, i.e. `src[lo..hi]`, into its segments of `dst`.
+/// Returns `false` if a segment turns out to be larger than counted.
+fn scatter_chunk(
+    src: &Array<i32>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&Region<i32>>,
+    dst: &Array<i32>,
+    Tracked(segs): Tracked<&mut Map<usize, Region<i32>>>,
+    offsets: &Vec<usize>,
+    n_chunks: usize,
+    t: usize,
+    shift: u32,
+) -> (ok: bool)
+    requires
+        region_array::wf(*src, *perms),
+        perms.lo() <= lo <= hi <= perms.hi(),
+        t < n_chunks <= MAX_CHUNKS,
+        offsets@.len() == n_chunks * RADIX + 1,
+        owns_segments(*dst, *old(segs), offsets@, n_chunks, t, (t + 1) as usize),
+        shift < 32,
+    ensures
+        owns_segments(*dst, *segs, offsets@, n_chunks, t, (t + 1) as usize),
+{
+    let mut cursors: Vec<usize> = Vec::new();
+    while cursors.len() < RADIX
+        invariant
+            cursors.len() <= RADIX,
+            t < n_chunks <= MAX_CHUNKS,
+            offsets@.len() == n_chunks * RADIX + 1,
+            forall |d: int| 0 <= d < cursors.len() ==> cursors@[d] == offsets@[d * n_chunks + t],
+    {
+        let d = cursors.len();
+        proof {
+            lemma_segment(d, t, n_chunks);
+        }
+        cursors.push(offsets[d * n_chunks + t]);
+    }
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*src, *perms),
+            perms.lo() <= lo <= i <= hi <= perms.hi(),
+            t < n_chunks <= MAX_CHUNKS,
+            offsets@.len() == n_chunks * RADIX + 1,
+            cursors.len() == RADIX,
+            forall |d: int| 0 <= d < RADIX ==> cursors@[d] >= offsets@[d * n_chunks + t],
+            owns_segments(*dst, *segs, offsets@, n_chunks, t, (t + 1) as usize),
+            shift < 32,
+    {
+        let x = *region_array::read(src, i, Tracked(perms));
+        let d = digit(x, shift);
+        proof {
+            lemma_segment(d, t, n_chunks);
+        }
+        let j = d * n_chunks + t;
+        let pos = cursors[d];
+        if pos >= offsets[j + 1] {
+            return false;
+        }
+        let tracked mut seg = segs.tracked_remove(j);
+        region_array::replace(dst, pos, x, Tracked(&mut seg));
+        proof {
+            segs.tracked_insert(j, seg);
+        }
+        cursors.set(d, pos + 1);
+        i += 1;
+    }
+    true
+}
+
+/// Scatters chunks `t_lo..t_hi` of `src` into `dst`, one chunk per thread.
+/// The thread of chunk `t` owns the chunk in `src` and its `RADIX` segments in `dst`.
+fn _scatter_parallel(
+    src: Arc<Array<i32>>,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    dst: Arc<Array<i32>>,
+    Tracked(segs): Tracked<Map<usize, Region<i32>>>,
+    chunks: Arc<Vec<usize>>,
+    offsets: Arc<Vec<usize>>,
+    t_lo: usize, t_hi: usize,
+    shift: u32,
+) -> (ret: Result<Tracked<Map<usize, Region<i32>>>, ()>)
+    requires
+        t_lo < t_hi < chunks@.len() <= MAX_CHUNKS + 1,
+        forall |a: int, b: int| 0 <= a < b < chunks@.len() ==> chunks@[a] < chunks@[b],
+        old(perms).lo() <= chunks@[t_lo as int],
+        chunks@[t_hi as int] <= old(perms).hi() <= src.len(),
+        region_array::wf(*src, (*old(perms))),
+        offsets@.len() == (chunks@.len() - 1) * RADIX + 1,
+        owns_segments(*dst, segs, offsets@, (chunks@.len() - 1) as usize, t_lo, t_hi),
+        shift < 32,
+    ensures
+        ret.is_ok() ==> region_array::wf(*src, (*perms)),
+        ret.is_ok() ==> old(perms).lo() == perms.lo() && old(perms).hi() == perms.hi(),
+        ret.is_ok() ==> owns_segments(*dst, ret.unwrap()@, offsets@, (chunks@.len() - 1) as usize, t_lo, t_hi),
+{
+    let n_chunks = chunks.len() - 1;
+    let tracked mut segs = segs;
+    if t_hi - t_lo == 1 {
+        if !scatter_chunk(&*src, chunks[t_lo], chunks[t_hi], Tracked(&*perms), &*dst, Tracked(&mut segs), &*offsets, n_chunks, t_lo, shift) {
+            return Err(());
+        }
+        return Ok(Tracked(segs));
+    }
+    let t_mid = t_lo + (t_hi - t_lo) / 2;
+    let (lo, mid) = (chunks[t_lo], chunks[t_mid]);
+
+    let tracked right_perms = region_array::split_off(&*src, mid, perms);
+    let tracked left_perms = region_array::split_off(&*src, lo, perms);
+
+    let ghost left_keys = Set::<usize>::new(|j: usize| j < n_chunks * RADIX && t_lo <= j % n_chunks < t_mid);
+    proof {
+        assert(left_keys.subset_of(segs.dom()));
+    }
+    let tracked left_segs = segs.tracked_remove_keys(left_keys);
+
+    let src_r1 = Arc::clone(&src);
+    let src_r2 = Arc::clone(&src);
+
+    let dst_r1 = Arc::clone(&dst);
+    let dst_r2 = Arc::clone(&dst);
+
+    let chunks_r1 = Arc::clone(&chunks);
+    let chunks_r2 = Arc::clone(&chunks);
+
+    let offsets_r1 = Arc::clone(&offsets);
+    let offsets_r2 = Arc::clone(&offsets);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<i32>>, Tracked<Map<usize, Region<i32>>>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*src, ret.unwrap().0@) && ret.unwrap().0@.lo() == lo && ret.unwrap().0@.hi() == mid,
+            ret.is_ok() ==> owns_segments(*dst, ret.unwrap().1@, offsets@, n_chunks, t_lo, t_mid),
+        {
+            let tracked mut left_perms = left_perms;
+            match _scatter_parallel(src_r1, Tracked(&mut left_perms), dst_r1, Tracked(left_segs), chunks_r1, offsets_r1, t_lo, t_mid, shift) {
+                Ok(left_segs) => Ok((Tracked(left_perms), left_segs)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    let Tracked(mut segs) = match _scatter_parallel(src_r2, Tracked(&mut right_perms), dst_r2, Tracked(segs), chunks_r2, offsets_r2, t_mid, t_hi, shift) {
+        Ok(right_segs) => right_segs,
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut left_perms), Tracked(left_segs)) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::merge(&*src, &mut left_perms, right_perms);
+        region_array::merge(&*src, perms, left_perms);
+        segs.tracked_union_prefer_right(left_segs);
+    }
+    Ok(Tracked(segs))
+}
+
+#[test]
+fn test_radix_sort_parallel() {
+    let mut data: Vec<i32> = (0..5000).map(|i: i32| i.wrapping_mul(0x9E37_79B1u32 as i32)).collect();
+    data.extend([i32::MIN, i32::MAX, 0, -1, i32::MIN, 1]);
+    let mut expected = data.clone();
+    expected.sort();
+    let mut arr = ArrayForSorting::new(data);
+    let mut out_arr = ArrayForSorting::new(vec![0; expected.len()]);
+    radix_sort_parallel(&mut arr, &mut out_arr, 300).unwrap();
+    assert_eq!(arr.clone_to_vec(), expected);
+}
+
+}
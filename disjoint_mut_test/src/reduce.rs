@@ -0,0 +1,234 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+};
+
+/// The sequential left fold of `s`, starting from `identity`.
+pub open spec fn folded<T>(s: Seq<T>, identity: T, f: spec_fn(T, T) -> T) -> T
+    decreases s.len(),
+{
+    if s.len() == 0 {
+        identity
+    } else {
+        f(folded(s.drop_last(), identity, f), s.last())
+    }
+}
+
+/// `f` is associative and `identity` is its neutral element.
+pub open spec fn is_monoid<T>(identity: T, f: spec_fn(T, T) -> T) -> bool {
+    &&& forall |a: T, b: T, c: T| #[trigger] f(f(a, b), c) == f(a, f(b, c))
+    &&& forall |a: T| #[trigger] f(identity, a) == a
+    &&& forall |a: T| #[trigger] f(a, identity) == a
+}
+
+/// `op` computes `f` and can be called on anything.
+pub open spec fn computes<T, F: Fn(T, T) -> T>(op: F, f: spec_fn(T, T) -> T) -> bool {
+    &&& forall |a: T, b: T| #[trigger] op.requires((a, b))
+    &&& forall |a: T, b: T, r: T| #[trigger] op.ensures((a, b), r) ==> r == f(a, b)
+}
+
+proof fn lemma_folded_push<T>(s: Seq<T>, a: int, k: int, identity: T, f: spec_fn(T, T) -> T)
+    requires
+        0 <= a <= k < s.len(),
+    ensures
+        folded(s.subrange(a, k + 1), identity, f) == f(folded(s.subrange(a, k), identity, f), s[k]),
+{
+    assert(s.subrange(a, k + 1).drop_last() =~= s.subrange(a, k));
+}
+
+/// Folding two parts and combining the results is folding their concatenation.
+proof fn lemma_folded_append<T>(l: Seq<T>, r: Seq<T>, identity: T, f: spec_fn(T, T) -> T)
+    requires
+        is_monoid(identity, f),
+    ensures
+        folded(l + r, identity, f) == f(folded(l, identity, f), folded(r, identity, f)),
+    decreases r.len(),
+{
+    if r.len() == 0 {
+        assert(l + r =~= l);
+        assert(f(folded(l, identity, f), identity) == folded(l, identity, f));
+    } else {
+        lemma_folded_append(l, r.drop_last(), identity, f);
+        assert((l + r).drop_last() =~= l + r.drop_last());
+        assert((l + r).last() == r.last());
+        let x = folded(l, identity, f);
+        let y = folded(r.drop_last(), identity, f);
+        assert(f(f(x, y), r.last()) == f(x, f(y, r.last())));
+    }
+}
+
+/// Folds the cells `lo..hi` of `perms` with `op`, from left to right.
+pub fn fold<T: Copy, F: Fn(T, T) -> T>(
+    arr: &Array<T>,
+    Tracked(perms): Tracked<&Region<T>>,
+    lo: usize, hi: usize,
+    identity: T,
+    op: F,
+    Ghost(f): Ghost<spec_fn(T, T) -> T>,
+) -> (res: T)
+    requires
+        region_array::wf(*arr, *perms),
+        perms.lo() <= lo <= hi <= perms.hi(),
+        computes(op, f),
+    ensures
+        res == folded(perms.values().subrange(lo - perms.lo(), hi - perms.lo()), identity, f),
+{
+    let ghost v = perms.values();
+    let ghost base = perms.lo() as int;
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+    }
+    let mut acc = identity;
+    let mut i = lo;
+    assert(v.subrange(lo - base, lo - base) =~= Seq::<T>::empty());
+    while i < hi
+        invariant
+            region_array::wf(*arr, *perms),
+            v == perms.values(),
+            base == perms.lo(),
+            v.len() == perms.hi() - base,
+            perms.lo() <= lo <= i <= hi <= perms.hi(),
+            computes(op, f),
+            acc == folded(v.subrange(lo - base, i - base), identity, f),
+    {
+        let x = *region_array::read(arr, i, Tracked(perms));
+        proof {
+            lemma_folded_push(v, lo - base, i - base, identity, f);
+        }
+        acc = op(acc, x);
+        i += 1;
+    }
+    acc
+}
+
+/// Folds the region `lo..hi` with `op`, splitting it in halves that are folded in separate
+/// threads while they are longer than `threshold`. The threads only read the array, so they
+/// share its region through an `Arc`. Since `op` is associative the result is the sequential fold.
+pub fn par_reduce<T: Copy + Send + Sync + 'static, F: Fn(T, T) -> T + Copy + Send + 'static>(
+    arr: Arc<Array<T>>,
+    Tracked(perms): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize,
+    identity: T,
+    op: F,
+    Ghost(f): Ghost<spec_fn(T, T) -> T>,
+    threshold: usize,
+) -> (ret: Result<T, ()>)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        computes(op, f),
+        is_monoid(identity, f),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, *perms),
+        ret.is_ok() ==> perms.lo() == lo && perms.hi() == hi,
+        ret.is_ok() ==> perms.values() == old(perms).values(),
+        ret.is_ok() ==> ret.unwrap() == folded(old(perms).values(), identity, f),
+{
+    // leave an empty region in `perms` while the threads share it
+    let shared = region_array::share_region(&*arr, Tracked(perms), lo);
+
+    let ret = _reduce_parallel(Arc::clone(&arr), Arc::clone(&shared), lo, hi, identity, op, Ghost(f), threshold);
+    // `perms` is given back its region before any error is returned
+    if region_array::unshare_region(Tracked(perms), shared).is_err() {
+        return Err(());
+    }
+    ret
+}
+
+fn _reduce_parallel<T: Copy + Send + Sync + 'static, F: Fn(T, T) -> T + Copy + Send + 'static>(
+    arr: Arc<Array<T>>,
+    shared: Arc<Tracked<Region<T>>>,
+    lo: usize, hi: usize,
+    identity: T,
+    op: F,
+    Ghost(f): Ghost<spec_fn(T, T) -> T>,
+    threshold: usize,
+) -> (ret: Result<T, ()>)
+    requires
+        region_array::wf(*arr, (*shared)@),
+        (*shared)@.lo() <= lo <= hi <= (*shared)@.hi(),
+        computes(op, f),
+        is_monoid(identity, f),
+    ensures
+        ret.is_ok() ==> ret.unwrap() == folded(
+            (*shared)@.values().subrange(lo - (*shared)@.lo(), hi - (*shared)@.lo()), identity, f),
+{
+    let ghost v = (*shared)@.values();
+    let ghost base = (*shared)@.lo() as int;
+    let region: &Tracked<Region<T>> = &*shared;
+    if hi - lo <= threshold || hi - lo < 2 {
+        return Ok(fold(&*arr, Tracked(region.borrow()), lo, hi, identity, op, Ghost(f)));
+    }
+
+    let mid = lo + (hi - lo) / 2;
+
+    let arr_r1 = Arc::clone(&arr);
+    let shared_r1 = Arc::clone(&shared);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<T, ()>)
+        ensures
+            ret.is_ok() ==> ret.unwrap() == folded(v.subrange(lo - base, mid - base), identity, f),
+        {
+            _reduce_parallel(arr_r1, shared_r1, lo, mid, identity, op, Ghost(f), threshold)
+        }
+    );
+
+    let right = match _reduce_parallel(Arc::clone(&arr), Arc::clone(&shared), mid, hi, identity, op, Ghost(f), threshold) {
+        Ok(right) => right,
+        Err(_) => {return Err(());},
+    };
+
+    let left = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::lemma_values_len(&*arr, (*shared)@);
+        assert(v.subrange(lo - base, hi - base) =~= v.subrange(lo - base, mid - base) + v.subrange(mid - base, hi - base));
+        lemma_folded_append(v.subrange(lo - base, mid - base), v.subrange(mid - base, hi - base), identity, f);
+    }
+    Ok(op(left, right))
+}
+
+#[test]
+fn test_par_reduce() {
+    use crate::test_data::{scattered, thresholds};
+
+    let data: Vec<i32> = scattered(2000, 401, 0);
+    let max = *data.iter().max().unwrap();
+    let last = data[data.len() - 1];
+
+    for threshold in thresholds(data.len()) {
+        let mut arr = crate::mergesort::ArrayForSorting::new(data.clone());
+        let n = data.len();
+        let res = par_reduce(
+            Arc::clone(&arr.array), Tracked(arr.perms.borrow_mut()), 0, n,
+            i32::MIN, |a: i32, b: i32| if a < b { b } else { a }, Ghost(|a: i32, b: i32| if a < b { b } else { a }),
+            threshold,
+        );
+        assert_eq!(res, Ok(max));
+
+        // not commutative: the last value other than -1 wins
+        let res = par_reduce(
+            Arc::clone(&arr.array), Tracked(arr.perms.borrow_mut()), 0, n,
+            -1, |a: i32, b: i32| if b == -1 { a } else { b }, Ghost(|a: i32, b: i32| if b == -1 { a } else { b }),
+            threshold,
+        );
+        assert_eq!(res, Ok(last));
+        assert_eq!(arr.clone_to_vec(), data);
+    }
+}
+
+}
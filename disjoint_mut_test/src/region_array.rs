@@ -1,10 +1,13 @@
 use vstd::prelude::*;
 
+use std::sync::Arc;
+
 use crate::permissions_array::SpecPerms;
 
 verus! {
 
 use super::permissions_array::Array;
+use vstd::seq_lib::lemma_multiset_commutative;
 
 pub tracked struct Region<T> {
     tracked lo: usize,
@@ -20,6 +23,20 @@ impl<T> Region<T> {
     pub closed spec fn hi(&self) -> usize {
         self.hi
     }
+
+    /// The values of the cells `lo..hi`, starting from `lo`.
+    pub closed spec fn values(&self) -> Seq<T> {
+        Seq::new((self.hi - self.lo) as nat, |k: int| self.perms[(self.lo + k) as usize]@.value.unwrap())
+    }
+}
+
+pub proof fn lemma_values_len<T>(aself: &Array<T>, region: Region<T>)
+    requires
+        wf(*aself, region),
+    ensures
+        region.lo() <= region.hi(),
+        region.values().len() == region.hi() - region.lo(),
+{
 }
 
 
@@ -38,8 +55,11 @@ where
         region.hi() == m,
         wf(*aself,res),
         res.lo() == m,
-        res.hi() == old(region).hi()
+        res.hi() == old(region).hi(),
+        region.values() == old(region).values().subrange(0, m - old(region).lo()),
+        res.values() == old(region).values().subrange(m - old(region).lo(), old(region).hi() - old(region).lo()),
 {
+    let ghost old_region = *region;
     let ghost old_perms = region.perms;
     let ghost right_keys = Set::<usize>::new(|i: usize| m <= i < region.hi());
     assert(forall |i: usize| region.lo() <= i < region.hi() ==> aself.available(i, region.perms) ==> region.perms.contains_key(i));
@@ -57,9 +77,50 @@ where
     assert(aself.wf(right_perms)) by {
         aself.submap_wf(old_perms, right_perms);
     }
+    assert(region.values() =~= old_region.values().subrange(0, m - old_region.lo));
+    assert(right.values() =~= old_region.values().subrange(m - old_region.lo, old_region.hi - old_region.lo));
     right
 }
 
+/// Like `split_off`, but keeps the right part in `region` and allows either part to be empty.
+pub proof fn split_front<T>(aself: &Array<T>, tracked m: usize, tracked region: &mut Region<T>) -> (tracked res: Region<T>)
+where
+    requires
+        old(region).lo() <= m@ <= old(region).hi(),
+        wf(*aself,*old(region)),
+    ensures
+        wf(*aself,*region),
+        region.lo() == m,
+        region.hi() == old(region).hi(),
+        wf(*aself,res),
+        res.lo() == old(region).lo(),
+        res.hi() == m,
+        res.values() == old(region).values().subrange(0, m - old(region).lo()),
+        region.values() == old(region).values().subrange(m - old(region).lo(), old(region).hi() - old(region).lo()),
+{
+    let ghost old_region = *region;
+    let ghost old_perms = region.perms;
+    let ghost left_keys = Set::<usize>::new(|i: usize| region.lo() <= i < m);
+    assert(forall |i: usize| region.lo() <= i < region.hi() ==> aself.available(i, region.perms) ==> region.perms.contains_key(i));
+
+    let tracked left_perms = region.perms.tracked_remove_keys(left_keys);
+    let tracked left = Region {
+        lo: region.lo,
+        hi: m,
+        perms: left_perms,
+    };
+    region.lo = m;
+    assert(aself.wf(region.perms)) by {
+        aself.submap_wf(old_perms, region.perms);
+    }
+    assert(aself.wf(left_perms)) by {
+        aself.submap_wf(old_perms, left_perms);
+    }
+    assert(left.values() =~= old_region.values().subrange(0, m - old_region.lo));
+    assert(region.values() =~= old_region.values().subrange(m - old_region.lo, old_region.hi - old_region.lo));
+    left
+}
+
 pub proof fn merge<T>(aself: &Array<T>, tracked left: &mut Region<T>, tracked right: Region<T>)
     where
     requires
@@ -69,16 +130,331 @@ pub proof fn merge<T>(aself: &Array<T>, tracked left: &mut Region<T>, tracked ri
     ensures
         wf(*aself,*left),
         left.lo() == old(left).lo(),
-        left.hi() == right.hi()
+        left.hi() == right.hi(),
+        left.values() == old(left).values() + right.values(),
 {
     assert(forall |i: usize| left.lo <= i < left.hi ==> aself.available(i, left.perms) ==> left.perms.contains_key(i));
     assert(forall |i: usize| right.lo <= i < right.hi ==> aself.available(i, right.perms) ==> right.perms.contains_key(i));
-    let tracked right_perms = right.perms;
+    // keys of `right` outside of its range must not shadow the cells of `left`
+    let ghost right_keys = Set::<usize>::new(|i: usize| right.lo <= i < right.hi);
+    let tracked mut all_right_perms = right.perms;
+    let tracked right_perms = all_right_perms.tracked_remove_keys(right_keys);
     left.perms.tracked_union_prefer_right(right_perms);
     left.hi = right.hi;
     assert(aself.wf(left.perms)) by {
-        aself.union_wf(old(left).perms, right.perms);
+        aself.submap_wf(right.perms, right_perms);
+        aself.union_wf(old(left).perms, right_perms);
     }
+    assert(left.values() =~= old(left).values() + right.values());
+}
+
+/// Gives up the region for the permissions of its cells, which can then be split
+/// by arbitrary sets of cells instead of ranges.
+pub proof fn into_perms<T>(aself: &Array<T>, tracked region: Region<T>) -> (tracked res: SpecPerms<T>)
+    requires
+        wf(*aself, region),
+    ensures
+        aself.wf(res),
+        res.dom() == Set::new(|i: usize| region.lo() <= i < region.hi()),
+        forall |i: usize| region.lo() <= i < region.hi() ==>
+            #[trigger] res[i]@.value.unwrap() == region.values()[i - region.lo()],
+{
+    let ghost keys = Set::new(|i: usize| region.lo <= i < region.hi);
+    assert(forall |i: usize| region.lo <= i < region.hi ==> aself.available(i, region.perms) ==> region.perms.contains_key(i));
+    let tracked mut all_perms = region.perms;
+    let tracked res = all_perms.tracked_remove_keys(keys);
+    assert(aself.wf(res)) by {
+        aself.submap_wf(region.perms, res);
+    }
+    assert(res.dom() =~= keys);
+    res
+}
+
+/// Turns the permissions of the cells `lo..hi` back into a region.
+pub proof fn from_perms<T>(aself: &Array<T>, tracked perms: SpecPerms<T>, tracked lo: usize, tracked hi: usize) -> (tracked res: Region<T>)
+    requires
+        lo <= hi <= aself.len(),
+        aself.wf(perms),
+        forall |i: usize| lo <= i < hi ==> #[trigger] perms.contains_key(i),
+    ensures
+        wf(*aself, res),
+        res.lo() == lo,
+        res.hi() == hi,
+        res.values().len() == hi - lo,
+        forall |i: usize| lo <= i < hi ==> #[trigger] perms[i]@.value.unwrap() == res.values()[i - lo],
+{
+    Region {
+        lo: lo,
+        hi: hi,
+        perms: perms,
+    }
+}
+
+/// The regions cover the same cells `lo..hi` of two arrays that are kept in lockstep,
+/// such as the keys and the payloads of a struct-of-arrays.
+pub open spec fn wf_pair<K, V>(keys: Array<K>, payloads: Array<V>, key_region: Region<K>, payload_region: Region<V>) -> bool {
+    &&& wf(keys, key_region)
+    &&& wf(payloads, payload_region)
+    &&& key_region.lo() == payload_region.lo()
+    &&& key_region.hi() == payload_region.hi()
+}
+
+/// `split_off` of both regions of a pair at the same index.
+pub proof fn split_off_pair<K, V>(
+    keys: &Array<K>,
+    payloads: &Array<V>,
+    tracked m: usize,
+    tracked key_region: &mut Region<K>,
+    tracked payload_region: &mut Region<V>,
+) -> (tracked res: (Region<K>, Region<V>))
+    requires
+        wf_pair(*keys, *payloads, *old(key_region), *old(payload_region)),
+        old(key_region).lo() <= m@ < old(key_region).hi(),
+    ensures
+        wf_pair(*keys, *payloads, *key_region, *payload_region),
+        key_region.lo() == old(key_region).lo(),
+        key_region.hi() == m,
+        wf_pair(*keys, *payloads, res.0, res.1),
+        res.0.lo() == m,
+        res.0.hi() == old(key_region).hi(),
+        key_region.values() == old(key_region).values().subrange(0, m - old(key_region).lo()),
+        payload_region.values() == old(payload_region).values().subrange(0, m - old(key_region).lo()),
+        res.0.values() == old(key_region).values().subrange(m - old(key_region).lo(), old(key_region).hi() - old(key_region).lo()),
+        res.1.values() == old(payload_region).values().subrange(m - old(key_region).lo(), old(key_region).hi() - old(key_region).lo()),
+{
+    let tracked right_keys = split_off(keys, m, key_region);
+    let tracked right_payloads = split_off(payloads, m, payload_region);
+    (right_keys, right_payloads)
+}
+
+/// `merge` of both regions of a pair.
+pub proof fn merge_pair<K, V>(
+    keys: &Array<K>,
+    payloads: &Array<V>,
+    tracked left_keys: &mut Region<K>,
+    tracked left_payloads: &mut Region<V>,
+    tracked right_keys: Region<K>,
+    tracked right_payloads: Region<V>,
+)
+    requires
+        wf_pair(*keys, *payloads, *old(left_keys), *old(left_payloads)),
+        wf_pair(*keys, *payloads, right_keys, right_payloads),
+        old(left_keys).hi() == right_keys.lo(),
+    ensures
+        wf_pair(*keys, *payloads, *left_keys, *left_payloads),
+        left_keys.lo() == old(left_keys).lo(),
+        left_keys.hi() == right_keys.hi(),
+        left_keys.values() == old(left_keys).values() + right_keys.values(),
+        left_payloads.values() == old(left_payloads).values() + right_payloads.values(),
+{
+    merge(keys, left_keys, right_keys);
+    merge(payloads, left_payloads, right_payloads);
+}
+
+/// The cells `i` of `lo..hi` whose residue `i % stride` is in `rem_lo..rem_hi`.
+/// Unlike a `Region`, they are interleaved with the cells of the other residues,
+/// so threads can own every `stride`-th cell of the same range.
+pub tracked struct Strided<T> {
+    tracked lo: usize,
+    tracked hi: usize,
+    tracked stride: usize,
+    tracked rem_lo: usize,
+    tracked rem_hi: usize,
+    perms: SpecPerms<T>,
+}
+
+impl<T> Strided<T> {
+    pub closed spec fn lo(&self) -> usize {
+        self.lo
+    }
+
+    pub closed spec fn hi(&self) -> usize {
+        self.hi
+    }
+
+    pub closed spec fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub closed spec fn rem_lo(&self) -> usize {
+        self.rem_lo
+    }
+
+    pub closed spec fn rem_hi(&self) -> usize {
+        self.rem_hi
+    }
+
+    /// `i` is one of the cells of the region.
+    pub open spec fn contains(&self, i: int) -> bool {
+        &&& self.lo() <= i < self.hi()
+        &&& self.rem_lo() <= i % (self.stride() as int) < self.rem_hi()
+    }
+
+    /// The value of the cell `i`.
+    pub closed spec fn value(&self, i: int) -> T {
+        self.perms[i as usize]@.value.unwrap()
+    }
+}
+
+pub closed spec fn strided_wf<T>(aself: Array<T>, s: Strided<T>) -> bool {
+    &&& s.lo <= s.hi <= aself.len()
+    &&& 0 < s.stride
+    &&& s.rem_lo <= s.rem_hi <= s.stride
+    &&& aself.wf(s.perms)
+    &&& forall |i: usize| s.contains(i as int) ==> aself.available(i, s.perms)
+}
+
+/// Gives up `region` for its cells as a strided region with all the residues of `stride`.
+pub proof fn into_strided<T>(aself: &Array<T>, tracked region: Region<T>, tracked stride: usize) -> (tracked res: Strided<T>)
+    requires
+        wf(*aself, region),
+        0 < stride,
+    ensures
+        strided_wf(*aself, res),
+        res.lo() == region.lo(),
+        res.hi() == region.hi(),
+        res.stride() == stride,
+        res.rem_lo() == 0,
+        res.rem_hi() == stride,
+        forall |i: int| res.contains(i) ==> #[trigger] res.value(i) == region.values()[i - region.lo()],
+{
+    Strided {
+        lo: region.lo,
+        hi: region.hi,
+        stride: stride,
+        rem_lo: 0,
+        rem_hi: stride,
+        perms: region.perms,
+    }
+}
+
+/// Turns a strided region with all the residues of its stride back into a `Region`.
+pub proof fn from_strided<T>(aself: &Array<T>, tracked s: Strided<T>) -> (tracked res: Region<T>)
+    requires
+        strided_wf(*aself, s),
+        s.rem_lo() == 0,
+        s.rem_hi() == s.stride(),
+    ensures
+        wf(*aself, res),
+        res.lo() == s.lo(),
+        res.hi() == s.hi(),
+        forall |i: int| s.lo() <= i < s.hi() ==> #[trigger] s.value(i) == res.values()[i - s.lo()],
+{
+    assert forall |i: usize| s.lo <= i < s.hi implies aself.available(i, s.perms) by {
+        vstd::arithmetic::div_mod::lemma_mod_pos_bound(i as int, s.stride as int);
+        assert(s.contains(i as int));
+    }
+    Region {
+        lo: s.lo,
+        hi: s.hi,
+        perms: s.perms,
+    }
+}
+
+/// Splits off the residues `m..rem_hi` of `s`, keeping `rem_lo..m` in `s`.
+pub proof fn split_strided<T>(aself: &Array<T>, tracked m: usize, tracked s: &mut Strided<T>) -> (tracked res: Strided<T>)
+    requires
+        strided_wf(*aself, *old(s)),
+        old(s).rem_lo() <= m <= old(s).rem_hi(),
+    ensures
+        strided_wf(*aself, *s),
+        s.lo() == old(s).lo(),
+        s.hi() == old(s).hi(),
+        s.stride() == old(s).stride(),
+        s.rem_lo() == old(s).rem_lo(),
+        s.rem_hi() == m,
+        strided_wf(*aself, res),
+        res.lo() == old(s).lo(),
+        res.hi() == old(s).hi(),
+        res.stride() == old(s).stride(),
+        res.rem_lo() == m,
+        res.rem_hi() == old(s).rem_hi(),
+        forall |i: int| s.contains(i) ==> #[trigger] s.value(i) == old(s).value(i),
+        forall |i: int| res.contains(i) ==> #[trigger] res.value(i) == old(s).value(i),
+{
+    let ghost old_perms = s.perms;
+    let ghost keys = Set::<usize>::new(|i: usize| s.lo <= i < s.hi && m <= i % s.stride < s.rem_hi);
+    assert(forall |i: usize| s.contains(i as int) ==> aself.available(i, s.perms) ==> s.perms.contains_key(i));
+
+    let tracked res_perms = s.perms.tracked_remove_keys(keys);
+    let tracked res = Strided {
+        lo: s.lo,
+        hi: s.hi,
+        stride: s.stride,
+        rem_lo: m,
+        rem_hi: s.rem_hi,
+        perms: res_perms,
+    };
+    s.rem_hi = m;
+    assert(aself.wf(s.perms)) by {
+        aself.submap_wf(old_perms, s.perms);
+    }
+    assert(aself.wf(res_perms)) by {
+        aself.submap_wf(old_perms, res_perms);
+    }
+    res
+}
+
+/// Puts the residues of `right` back into `left`, where they follow the residues of `left`.
+pub proof fn merge_strided<T>(aself: &Array<T>, tracked left: &mut Strided<T>, tracked right: Strided<T>)
+    requires
+        strided_wf(*aself, *old(left)),
+        strided_wf(*aself, right),
+        old(left).lo() == right.lo(),
+        old(left).hi() == right.hi(),
+        old(left).stride() == right.stride(),
+        old(left).rem_hi() == right.rem_lo(),
+    ensures
+        strided_wf(*aself, *left),
+        left.lo() == old(left).lo(),
+        left.hi() == old(left).hi(),
+        left.stride() == old(left).stride(),
+        left.rem_lo() == old(left).rem_lo(),
+        left.rem_hi() == right.rem_hi(),
+        forall |i: int| old(left).contains(i) ==> #[trigger] left.value(i) == old(left).value(i),
+        forall |i: int| right.contains(i) ==> #[trigger] left.value(i) == right.value(i),
+{
+    // keys of `right` outside of its residues must not shadow the cells of `left`
+    let ghost right_keys = Set::<usize>::new(|i: usize| right.contains(i as int));
+    assert(forall |i: usize| right.contains(i as int) ==> aself.available(i, right.perms) ==> right.perms.contains_key(i));
+    let tracked mut all_right_perms = right.perms;
+    let tracked right_perms = all_right_perms.tracked_remove_keys(right_keys);
+    left.perms.tracked_union_prefer_right(right_perms);
+    left.rem_hi = right.rem_hi;
+    assert(aself.wf(left.perms)) by {
+        aself.submap_wf(right.perms, right_perms);
+        aself.union_wf(old(left).perms, right_perms);
+    }
+}
+
+#[inline]
+pub fn read_strided<'a, T>(aself: &'a Array<T>, i: usize, Tracked(s): Tracked<&'a Strided<T>>) -> (res: &'a T)
+    requires
+        strided_wf(*aself, *s),
+        s.contains(i as int),
+    ensures
+        *res == s.value(i as int),
+{
+    <Array<T>>::read(aself, i, Tracked(&s.perms))
+}
+
+#[inline]
+pub fn replace_strided<T>(aself: &Array<T>, i: usize, x: T, Tracked(s): Tracked<&mut Strided<T>>) -> (res: T)
+    requires
+        strided_wf(*aself, *old(s)),
+        old(s).contains(i as int),
+    ensures
+        strided_wf(*aself, *s),
+        s.lo() == old(s).lo(),
+        s.hi() == old(s).hi(),
+        s.stride() == old(s).stride(),
+        s.rem_lo() == old(s).rem_lo(),
+        s.rem_hi() == old(s).rem_hi(),
+        s.value(i as int) == x,
+        res == old(s).value(i as int),
+        forall |j: int| s.contains(j) && j != i ==> #[trigger] s.value(j) == old(s).value(j),
+{
+    <Array<T>>::replace(aself, i, x, Tracked(&mut s.perms))
 }
 
 pub open spec fn len<T>(aself: &Array<T>) -> usize {
@@ -113,9 +489,33 @@ where
     ensures
         wf(*aself,*perms),
         perms.lo() == old(perms).lo(),
-        perms.hi() == old(perms).hi()
+        perms.hi() == old(perms).hi(),
+        perms.values() == old(perms).values().update(i - old(perms).lo(), x),
+        res == old(perms).values()[i - old(perms).lo()],
 {
-    <Array<T>>::replace(aself, i, x, Tracked(&mut perms.perms))
+    let res = <Array<T>>::replace(aself, i, x, Tracked(&mut perms.perms));
+    assert(perms.values() =~= old(perms).values().update(i - old(perms).lo(), x));
+    res
+}
+
+#[inline]
+pub fn swap<T: Copy>(aself: &Array<T>, i: usize, j: usize, Tracked(perms): Tracked<&mut Region<T>>)
+where
+    requires
+        wf(*aself,(*old(perms))),
+        old(perms).lo() <= i < old(perms).hi(),
+        old(perms).lo() <= j < old(perms).hi(),
+    ensures
+        wf(*aself,*perms),
+        perms.lo() == old(perms).lo(),
+        perms.hi() == old(perms).hi(),
+        perms.values() == old(perms).values()
+            .update(j - old(perms).lo(), old(perms).values()[i - old(perms).lo()])
+            .update(i - old(perms).lo(), old(perms).values()[j - old(perms).lo()]),
+{
+    let a = *read(aself, i, Tracked(perms));
+    let b = replace(aself, j, a, Tracked(perms));
+    replace(aself, i, b, Tracked(perms));
 }
 
 #[inline]
@@ -124,6 +524,8 @@ where
     requires
         wf(*aself,*perms),
         perms.lo() <= i < perms.hi()
+    ensures
+        *res == perms.values()[i - perms.lo()],
 {
     <Array<T>>::read(aself, i, Tracked(&perms.perms))
 }
@@ -139,4 +541,814 @@ pub fn clone_to_vec<T>(aself: &Array<T>, Tracked(perms): Tracked<&Region<T>>) ->
     <Array<T>>::clone_to_vec(aself, Tracked(&perms.perms))
 }
 
+
+/// Gives back the value of `shared` if it is the last reference to it.
+#[verifier::external_body]
+pub fn take_shared<T>(shared: Arc<T>) -> (res: Option<T>)
+    ensures
+        res.is_some() ==> res.unwrap() == *shared,
+{
+    Arc::try_unwrap(shared).ok()
+}
+
+/// Moves `perms` into an `Arc` that threads can share, leaving an empty region in `perms`.
+pub fn share_region<T>(aself: &Array<T>, Tracked(perms): Tracked<&mut Region<T>>, lo: usize) -> (res: Arc<Tracked<Region<T>>>)
+    requires
+        wf(*aself, *old(perms)),
+        old(perms).lo() == lo,
+    ensures
+        wf(*aself, (*res)@),
+        (*res)@.lo() == old(perms).lo(),
+        (*res)@.hi() == old(perms).hi(),
+        (*res)@.values() == old(perms).values(),
+{
+    proof {
+        lemma_values_len(aself, *perms);
+    }
+    let tracked mut region = split_front(aself, lo, perms);
+    proof {
+        vstd::modes::tracked_swap(perms, &mut region);
+        assert(region.values() =~= old(perms).values());
+    }
+    Arc::new(Tracked(region))
+}
+
+/// Puts back the region that `share_region` moved out of `perms`. This fails only while a thread
+/// still holds a reference to it, so it is called on every path, including after errors.
+pub fn unshare_region<T>(Tracked(perms): Tracked<&mut Region<T>>, shared: Arc<Tracked<Region<T>>>) -> (ret: Result<(), ()>)
+    ensures
+        ret.is_ok() ==> *perms == (*shared)@,
+{
+    let Tracked(mut region) = match take_shared(shared) {
+        Some(region) => region,
+        None => {return Err(());},
+    };
+    proof {
+        vstd::modes::tracked_swap(perms, &mut region);
+    }
+    Ok(())
+}
+
+/// Copies the cells `src_lo..src_lo + len` of `src` to `lo..lo + len`, leaving the rest of `perms` as it was.
+pub fn copy_range<T: Copy>(
+    src_arr: &Array<T>,
+    Tracked(src): Tracked<&Region<T>>,
+    src_lo: usize,
+    aself: &Array<T>,
+    Tracked(perms): Tracked<&mut Region<T>>,
+    lo: usize,
+    len: usize,
+)
+    requires
+        wf(*src_arr, *src),
+        src.lo() <= src_lo,
+        src_lo + len <= src.hi(),
+        wf(*aself, *old(perms)),
+        old(perms).lo() <= lo,
+        lo + len <= old(perms).hi(),
+    ensures
+        wf(*aself, *perms),
+        perms.lo() == old(perms).lo(),
+        perms.hi() == old(perms).hi(),
+        perms.values() == old(perms).values().subrange(0, lo - old(perms).lo())
+            + src.values().subrange(src_lo - src.lo(), src_lo - src.lo() + len)
+            + old(perms).values().subrange(lo + len - old(perms).lo(), old(perms).hi() - old(perms).lo()),
+{
+    let ghost old_perms = *perms;
+    <Array<T>>::copy_from(aself, lo, src_arr, src_lo, len, Tracked(&src.perms), Tracked(&mut perms.perms));
+    proof {
+        let a = lo - old_perms.lo;
+        let s = src_lo - src.lo;
+        let n = old_perms.hi - old_perms.lo;
+        let expected = old_perms.values().subrange(0, a)
+            + src.values().subrange(s, s + len)
+            + old_perms.values().subrange(a + len, n);
+        assert forall |k: int| 0 <= k < n implies #[trigger] perms.values()[k] == expected[k] by {
+            let i = (old_perms.lo + k) as usize;
+            if a <= k < a + len {
+                assert(src.values()[s + (k - a)] == src.perms[(src_lo + (i - lo)) as usize]@.value.unwrap());
+            } else {
+                assert(perms.perms[i] == old_perms.perms[i]);
+            }
+        }
+        assert(perms.values() =~= expected);
+    }
+}
+
+/// Like `copy_range`, but one cell at a time in verified code instead of the trusted `Array::copy_from`.
+pub fn copy_range_cells<T: Copy>(
+    src_arr: &Array<T>,
+    Tracked(src): Tracked<&Region<T>>,
+    src_lo: usize,
+    aself: &Array<T>,
+    Tracked(perms): Tracked<&mut Region<T>>,
+    lo: usize,
+    len: usize,
+)
+    requires
+        wf(*src_arr, *src),
+        src.lo() <= src_lo,
+        src_lo + len <= src.hi(),
+        wf(*aself, *old(perms)),
+        old(perms).lo() <= lo,
+        lo + len <= old(perms).hi(),
+    ensures
+        wf(*aself, *perms),
+        perms.lo() == old(perms).lo(),
+        perms.hi() == old(perms).hi(),
+        perms.values() == old(perms).values().subrange(0, lo - old(perms).lo())
+            + src.values().subrange(src_lo - src.lo(), src_lo - src.lo() + len)
+            + old(perms).values().subrange(lo + len - old(perms).lo(), old(perms).hi() - old(perms).lo()),
+{
+    let ghost old_vals = perms.values();
+    let ghost a = lo - perms.lo();
+    let ghost s = src_lo - src.lo();
+    proof {
+        lemma_values_len(aself, *perms);
+        lemma_values_len(src_arr, *src);
+    }
+    let mut k = 0;
+    while k < len
+        invariant
+            wf(*src_arr, *src),
+            src.lo() <= src_lo,
+            src_lo + len <= src.hi(),
+            s == src_lo - src.lo(),
+            src.values().len() == src.hi() - src.lo(),
+            wf(*aself, *perms),
+            perms.lo() == old(perms).lo(),
+            perms.hi() == old(perms).hi(),
+            perms.lo() <= lo,
+            lo + len <= perms.hi(),
+            a == lo - perms.lo(),
+            old_vals == old(perms).values(),
+            old_vals.len() == perms.hi() - perms.lo(),
+            perms.values().len() == old_vals.len(),
+            k <= len,
+            forall |j: int| 0 <= j < old_vals.len() && !(a <= j < a + k) ==> #[trigger] perms.values()[j] == old_vals[j],
+            forall |j: int| a <= j < a + k ==> #[trigger] perms.values()[j] == src.values()[s + (j - a)],
+    {
+        let x = *read(src_arr, src_lo + k, Tracked(src));
+        replace(aself, lo + k, x, Tracked(perms));
+        k += 1;
+    }
+    proof {
+        let n = old_vals.len() as int;
+        let expected = old_vals.subrange(0, a) + src.values().subrange(s, s + len) + old_vals.subrange(a + len, n);
+        assert forall |j: int| 0 <= j < n implies #[trigger] perms.values()[j] == expected[j] by {
+            if a <= j < a + len {
+                assert(perms.values()[j] == src.values()[s + (j - a)]);
+            } else {
+                assert(perms.values()[j] == old_vals[j]);
+            }
+        }
+        assert(perms.values() =~= expected);
+    }
+}
+
+/// Copies the whole region `src_perms` into `dst_perms`, which covers the same cells of `dst_arr`.
+pub fn copy_region<T: Copy>(
+    src_arr: &Array<T>,
+    Tracked(src_perms): Tracked<&Region<T>>,
+    dst_arr: &Array<T>,
+    Tracked(dst_perms): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize,
+)
+    requires
+        wf(*src_arr, *src_perms),
+        src_perms.lo() == lo,
+        src_perms.hi() == hi,
+        wf(*dst_arr, *old(dst_perms)),
+        old(dst_perms).lo() == lo,
+        old(dst_perms).hi() == hi,
+    ensures
+        wf(*dst_arr, *dst_perms),
+        dst_perms.lo() == lo,
+        dst_perms.hi() == hi,
+        dst_perms.values() == src_perms.values(),
+{
+    proof {
+        lemma_values_len(src_arr, *src_perms);
+        lemma_values_len(dst_arr, *dst_perms);
+    }
+    let ghost old_vals = dst_perms.values();
+    copy_range(src_arr, Tracked(src_perms), lo, dst_arr, Tracked(dst_perms), lo, hi - lo);
+    proof {
+        assert(old_vals.subrange(0, 0) =~= Seq::<T>::empty());
+        assert(old_vals.subrange(hi - lo, hi - lo) =~= Seq::<T>::empty());
+        assert(src_perms.values().subrange(0, hi - lo) =~= src_perms.values());
+        assert(dst_perms.values() =~= src_perms.values());
+    }
+}
+
+/// Like `copy_region`, but the regions are split in halves that are copied in separate threads
+/// while they are longer than `threshold`.
+pub fn copy_region_parallel<T: Copy + Send + 'static>(
+    src_arr: Arc<Array<T>>,
+    Tracked(src_perms): Tracked<&mut Region<T>>,
+    dst_arr: Arc<Array<T>>,
+    Tracked(dst_perms): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        wf(*src_arr, *old(src_perms)),
+        old(src_perms).lo() == lo,
+        old(src_perms).hi() == hi,
+        wf(*dst_arr, *old(dst_perms)),
+        old(dst_perms).lo() == lo,
+        old(dst_perms).hi() == hi,
+    ensures
+        ret.is_ok() ==> wf(*src_arr, *src_perms) && src_perms.lo() == lo && src_perms.hi() == hi,
+        ret.is_ok() ==> src_perms.values() == old(src_perms).values(),
+        ret.is_ok() ==> wf(*dst_arr, *dst_perms) && dst_perms.lo() == lo && dst_perms.hi() == hi,
+        ret.is_ok() ==> dst_perms.values() == old(src_perms).values(),
+{
+    if hi - lo <= threshold || hi - lo < 2 {
+        copy_region(&*src_arr, Tracked(src_perms), &*dst_arr, Tracked(dst_perms), lo, hi);
+        return Ok(());
+    }
+
+    let mid = lo + (hi - lo) / 2;
+    let ghost old_vals = src_perms.values();
+    proof {
+        lemma_values_len(&*src_arr, *src_perms);
+    }
+    let tracked left_src = split_front(&*src_arr, mid, src_perms);
+    let tracked mut right_src = split_front(&*src_arr, hi, src_perms);
+    let tracked left_dst = split_front(&*dst_arr, mid, dst_perms);
+    let tracked mut right_dst = split_front(&*dst_arr, hi, dst_perms);
+    let ghost left_vals = left_src.values();
+    let ghost right_vals = right_src.values();
+
+    let src_r1 = Arc::clone(&src_arr);
+    let dst_r1 = Arc::clone(&dst_arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<T>>, Tracked<Region<T>>), ()>)
+        ensures
+            ret.is_ok() ==> wf(*src_arr, ret.unwrap().0@) && ret.unwrap().0@.lo() == lo && ret.unwrap().0@.hi() == mid,
+            ret.is_ok() ==> wf(*dst_arr, ret.unwrap().1@) && ret.unwrap().1@.lo() == lo && ret.unwrap().1@.hi() == mid,
+            ret.is_ok() ==> ret.unwrap().0@.values() == left_vals && ret.unwrap().1@.values() == left_vals,
+        {
+            let tracked mut left_src = left_src;
+            let tracked mut left_dst = left_dst;
+            match copy_region_parallel(src_r1, Tracked(&mut left_src), dst_r1, Tracked(&mut left_dst), lo, mid, threshold) {
+                Ok(()) => Ok((Tracked(left_src), Tracked(left_dst))),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match copy_region_parallel(Arc::clone(&src_arr), Tracked(&mut right_src), Arc::clone(&dst_arr), Tracked(&mut right_dst), mid, hi, threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut left_src), Tracked(mut left_dst)) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        assert(old_vals =~= left_vals + right_vals);
+        merge(&*src_arr, &mut left_src, right_src);
+        merge(&*dst_arr, &mut left_dst, right_dst);
+        vstd::modes::tracked_swap(src_perms, &mut left_src);
+        vstd::modes::tracked_swap(dst_perms, &mut left_dst);
+    }
+    Ok(())
+}
+
+/// Sets every cell of `lo..hi` to `x`.
+pub fn fill<T: Copy>(aself: &Array<T>, Tracked(perms): Tracked<&mut Region<T>>, lo: usize, hi: usize, x: T)
+    requires
+        wf(*aself, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+    ensures
+        wf(*aself, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        perms.values() == Seq::new((hi - lo) as nat, |k: int| x),
+{
+    let mut i = lo;
+    while i < hi
+        invariant
+            wf(*aself, *perms),
+            perms.lo() == lo,
+            perms.hi() == hi,
+            lo <= i <= hi,
+            forall |k: int| 0 <= k < i - lo ==> #[trigger] perms.values()[k] == x,
+    {
+        replace(aself, i, x, Tracked(perms));
+        i += 1;
+    }
+    assert(perms.values() =~= Seq::new((hi - lo) as nat, |k: int| x));
+}
+
+/// Like `fill`, but the region is split in halves that are filled in separate threads
+/// while they are longer than `threshold`.
+pub fn fill_parallel<T: Copy + Send + 'static>(
+    arr: Arc<Array<T>>,
+    Tracked(perms): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize,
+    x: T,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+    ensures
+        ret.is_ok() ==> wf(*arr, *perms) && perms.lo() == lo && perms.hi() == hi,
+        ret.is_ok() ==> perms.values() == Seq::new((hi - lo) as nat, |k: int| x),
+{
+    if hi - lo <= threshold || hi - lo < 2 {
+        fill(&*arr, Tracked(perms), lo, hi, x);
+        return Ok(());
+    }
+
+    let mid = lo + (hi - lo) / 2;
+    let tracked left_perms = split_front(&*arr, mid, perms);
+    let tracked mut right_perms = split_front(&*arr, hi, perms);
+
+    let arr_r1 = Arc::clone(&arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<T>>, ()>)
+        ensures
+            ret.is_ok() ==> wf(*arr, ret.unwrap()@) && ret.unwrap()@.lo() == lo && ret.unwrap()@.hi() == mid,
+            ret.is_ok() ==> ret.unwrap()@.values() == Seq::new((mid - lo) as nat, |k: int| x),
+        {
+            let tracked mut left_perms = left_perms;
+            match fill_parallel(arr_r1, Tracked(&mut left_perms), lo, mid, x, threshold) {
+                Ok(()) => Ok(Tracked(left_perms)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match fill_parallel(Arc::clone(&arr), Tracked(&mut right_perms), mid, hi, x, threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut left_perms) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        merge(&*arr, &mut left_perms, right_perms);
+        vstd::modes::tracked_swap(perms, &mut left_perms);
+        assert(perms.values() =~= Seq::new((hi - lo) as nat, |k: int| x));
+    }
+    Ok(())
+}
+
+/// `b` is `a` read from the back.
+pub open spec fn reversed<T>(a: Seq<T>, b: Seq<T>) -> bool {
+    &&& a.len() == b.len()
+    &&& forall |k: int| 0 <= k < b.len() ==> #[trigger] b[k] == a[b.len() - 1 - k]
+}
+
+pub proof fn lemma_reversed_symmetric<T>(a: Seq<T>, b: Seq<T>)
+    requires
+        reversed(a, b),
+    ensures
+        reversed(b, a),
+{
+    assert forall |k: int| 0 <= k < a.len() implies #[trigger] a[k] == b[a.len() - 1 - k] by {
+        assert(b[b.len() - 1 - k] == a[b.len() - 1 - (b.len() - 1 - k)]);
+    }
+}
+
+/// Reversing a concatenation concatenates the reversed parts in the opposite order.
+pub proof fn lemma_reversed_concat<T>(x: Seq<T>, y: Seq<T>, rx: Seq<T>, ry: Seq<T>)
+    requires
+        reversed(x, rx),
+        reversed(y, ry),
+    ensures
+        reversed(x + y, ry + rx),
+{
+    let n = x.len() + y.len();
+    assert forall |k: int| 0 <= k < n implies #[trigger] (ry + rx)[k] == (x + y)[n - 1 - k] by {
+        if k < ry.len() {
+            assert((ry + rx)[k] == ry[k]);
+        } else {
+            assert((ry + rx)[k] == rx[k - ry.len()]);
+        }
+    }
+}
+
+/// Reversing both parts of `l + r` and then the whole sequence gives `r + l`.
+proof fn lemma_reversed_rotate<T>(l: Seq<T>, r: Seq<T>, rl: Seq<T>, rr: Seq<T>, res: Seq<T>)
+    requires
+        reversed(l, rl),
+        reversed(r, rr),
+        reversed(rl + rr, res),
+    ensures
+        res == r + l,
+{
+    lemma_reversed_symmetric(l, rl);
+    lemma_reversed_symmetric(r, rr);
+    lemma_reversed_concat(rl, rr, l, r);
+    assert(res =~= r + l);
+}
+
+/// Reverses the cells of `lo..hi` in place.
+pub fn reverse<T: Copy>(aself: &Array<T>, Tracked(perms): Tracked<&mut Region<T>>, lo: usize, hi: usize)
+    requires
+        wf(*aself, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+    ensures
+        wf(*aself, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        reversed(old(perms).values(), perms.values()),
+{
+    let ghost old_vals = perms.values();
+    let ghost n = (hi - lo) as int;
+    let mut i = lo;
+    let mut j = hi;
+    while j - i > 1
+        invariant
+            wf(*aself, *perms),
+            perms.lo() == lo,
+            perms.hi() == hi,
+            old_vals.len() == n,
+            perms.values().len() == n,
+            lo <= i <= j <= hi,
+            i - lo == hi - j,
+            forall |k: int| 0 <= k < i - lo ==> #[trigger] perms.values()[k] == old_vals[n - 1 - k],
+            forall |k: int| j - lo <= k < n ==> #[trigger] perms.values()[k] == old_vals[n - 1 - k],
+            forall |k: int| i - lo <= k < j - lo ==> #[trigger] perms.values()[k] == old_vals[k],
+    {
+        j -= 1;
+        swap(aself, i, j, Tracked(perms));
+        i += 1;
+    }
+    proof {
+        assert forall |k: int| 0 <= k < n implies #[trigger] perms.values()[k] == old_vals[n - 1 - k] by {
+            if i - lo <= k < j - lo {
+                assert(k == n - 1 - k);
+            }
+        }
+    }
+}
+
+/// Swaps the regions `a` and `b` of length `len`, reversing both: `a` gets the values of `b`
+/// from the back and `b` gets the values of `a` from the back.
+pub fn swap_reversed<T: Copy>(
+    aself: &Array<T>,
+    Tracked(a): Tracked<&mut Region<T>>, a_lo: usize,
+    Tracked(b): Tracked<&mut Region<T>>, b_lo: usize,
+    len: usize,
+)
+    requires
+        wf(*aself, *old(a)),
+        old(a).lo() == a_lo,
+        old(a).hi() == a_lo + len,
+        wf(*aself, *old(b)),
+        old(b).lo() == b_lo,
+        old(b).hi() == b_lo + len,
+    ensures
+        wf(*aself, *a),
+        a.lo() == a_lo,
+        a.hi() == a_lo + len,
+        wf(*aself, *b),
+        b.lo() == b_lo,
+        b.hi() == b_lo + len,
+        reversed(old(b).values(), a.values()),
+        reversed(old(a).values(), b.values()),
+{
+    let ghost a_vals = a.values();
+    let ghost b_vals = b.values();
+    let mut t: usize = 0;
+    while t < len
+        invariant
+            wf(*aself, *a),
+            a.lo() == a_lo,
+            a.hi() == a_lo + len,
+            wf(*aself, *b),
+            b.lo() == b_lo,
+            b.hi() == b_lo + len,
+            a_vals.len() == len,
+            b_vals.len() == len,
+            a.values().len() == len,
+            b.values().len() == len,
+            t <= len,
+            forall |k: int| 0 <= k < t ==> #[trigger] a.values()[k] == b_vals[len - 1 - k],
+            forall |k: int| t <= k < len ==> #[trigger] a.values()[k] == a_vals[k],
+            forall |k: int| len - t <= k < len ==> #[trigger] b.values()[k] == a_vals[len - 1 - k],
+            forall |k: int| 0 <= k < len - t ==> #[trigger] b.values()[k] == b_vals[k],
+    {
+        let x = *read(aself, a_lo + t, Tracked(a));
+        let y = replace(aself, b_lo + len - 1 - t, x, Tracked(b));
+        replace(aself, a_lo + t, y, Tracked(a));
+        t += 1;
+    }
+}
+
+fn _swap_reversed_parallel<T: Copy + Send + 'static>(
+    arr: Arc<Array<T>>,
+    Tracked(a): Tracked<&mut Region<T>>, a_lo: usize,
+    Tracked(b): Tracked<&mut Region<T>>, b_lo: usize,
+    len: usize,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        wf(*arr, *old(a)),
+        old(a).lo() == a_lo,
+        old(a).hi() == a_lo + len,
+        wf(*arr, *old(b)),
+        old(b).lo() == b_lo,
+        old(b).hi() == b_lo + len,
+    ensures
+        ret.is_ok() ==> wf(*arr, *a) && a.lo() == a_lo && a.hi() == a_lo + len,
+        ret.is_ok() ==> wf(*arr, *b) && b.lo() == b_lo && b.hi() == b_lo + len,
+        ret.is_ok() ==> reversed(old(b).values(), a.values()),
+        ret.is_ok() ==> reversed(old(a).values(), b.values()),
+{
+    if len <= threshold || len < 2 {
+        swap_reversed(&*arr, Tracked(a), a_lo, Tracked(b), b_lo, len);
+        return Ok(());
+    }
+
+    let h = len / 2;
+    let ghost a_vals = a.values();
+    let ghost b_vals = b.values();
+    proof {
+        lemma_values_len(&*arr, *a);
+        lemma_values_len(&*arr, *b);
+    }
+    // the front of `a` goes with the back of `b` and the back of `a` with the front of `b`
+    let tracked a1 = split_front(&*arr, a_lo + h, a);
+    let tracked mut a2 = split_front(&*arr, a_lo + len, a);
+    let tracked mut b1 = split_front(&*arr, b_lo + len - h, b);
+    let tracked b2 = split_front(&*arr, b_lo + len, b);
+    let ghost a1_vals = a1.values();
+    let ghost a2_vals = a2.values();
+    let ghost b1_vals = b1.values();
+    let ghost b2_vals = b2.values();
+
+    let arr_r1 = Arc::clone(&arr);
+
+    let outer = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<T>>, Tracked<Region<T>>), ()>)
+        ensures
+            ret.is_ok() ==> wf(*arr, ret.unwrap().0@) && ret.unwrap().0@.lo() == a_lo && ret.unwrap().0@.hi() == a_lo + h,
+            ret.is_ok() ==> wf(*arr, ret.unwrap().1@) && ret.unwrap().1@.lo() == b_lo + len - h && ret.unwrap().1@.hi() == b_lo + len,
+            ret.is_ok() ==> reversed(b2_vals, ret.unwrap().0@.values()) && reversed(a1_vals, ret.unwrap().1@.values()),
+        {
+            let tracked mut a1 = a1;
+            let tracked mut b2 = b2;
+            match _swap_reversed_parallel(arr_r1, Tracked(&mut a1), a_lo, Tracked(&mut b2), b_lo + len - h, h, threshold) {
+                Ok(()) => Ok((Tracked(a1), Tracked(b2))),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match _swap_reversed_parallel(Arc::clone(&arr), Tracked(&mut a2), a_lo + h, Tracked(&mut b1), b_lo, len - h, threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut a1), Tracked(b2)) = match outer.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        assert(a_vals =~= a1_vals + a2_vals);
+        assert(b_vals =~= b1_vals + b2_vals);
+        lemma_reversed_concat(b1_vals, b2_vals, a2.values(), a1.values());
+        lemma_reversed_concat(a1_vals, a2_vals, b2.values(), b1.values());
+        merge(&*arr, &mut a1, a2);
+        merge(&*arr, &mut b1, b2);
+        vstd::modes::tracked_swap(a, &mut a1);
+        vstd::modes::tracked_swap(b, &mut b1);
+    }
+    Ok(())
+}
+
+/// Like `reverse`, but the two ends of the region are swapped by `swap_reversed` in separate
+/// threads while they are longer than `threshold`.
+pub fn reverse_parallel<T: Copy + Send + 'static>(
+    arr: Arc<Array<T>>,
+    Tracked(perms): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+    ensures
+        ret.is_ok() ==> wf(*arr, *perms) && perms.lo() == lo && perms.hi() == hi,
+        ret.is_ok() ==> reversed(old(perms).values(), perms.values()),
+{
+    if hi - lo <= threshold {
+        reverse(&*arr, Tracked(perms), lo, hi);
+        return Ok(());
+    }
+
+    let h = (hi - lo) / 2;
+    let ghost old_vals = perms.values();
+    proof {
+        lemma_values_len(&*arr, *perms);
+    }
+    // at most one cell is left in the middle, and it stays in place
+    let tracked mut front = split_front(&*arr, lo + h, perms);
+    let tracked middle = split_front(&*arr, hi - h, perms);
+    let tracked mut back = split_front(&*arr, hi, perms);
+    let ghost front_vals = front.values();
+    let ghost middle_vals = middle.values();
+    let ghost back_vals = back.values();
+
+    match _swap_reversed_parallel(Arc::clone(&arr), Tracked(&mut front), lo, Tracked(&mut back), hi - h, h, threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    proof {
+        lemma_values_len(&*arr, middle);
+        assert(old_vals =~= front_vals + middle_vals + back_vals);
+        assert(reversed(middle_vals, middle_vals));
+        lemma_reversed_concat(front_vals, middle_vals, back.values(), middle_vals);
+        lemma_reversed_concat(front_vals + middle_vals, back_vals, middle_vals + back.values(), front.values());
+        assert(front.values() + middle_vals + back.values() =~= front.values() + (middle_vals + back.values()));
+        merge(&*arr, &mut front, middle);
+        merge(&*arr, &mut front, back);
+        vstd::modes::tracked_swap(perms, &mut front);
+    }
+    Ok(())
+}
+
+/// Rotates the cells of `lo..hi` to the left, so that the cell `mid` becomes the first one.
+/// Both parts are reversed and then the whole region is reversed.
+pub fn rotate_left<T: Copy>(aself: &Array<T>, Tracked(perms): Tracked<&mut Region<T>>, lo: usize, hi: usize, mid: usize)
+    requires
+        wf(*aself, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        lo <= mid <= hi,
+    ensures
+        wf(*aself, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        perms.values() == old(perms).values().subrange(mid - lo, hi - lo) + old(perms).values().subrange(0, mid - lo),
+{
+    proof {
+        lemma_values_len(aself, *perms);
+    }
+    let tracked mut left = split_front(aself, mid, perms);
+    let ghost left_vals = left.values();
+    let ghost right_vals = perms.values();
+    reverse(aself, Tracked(&mut left), lo, mid);
+    reverse(aself, Tracked(perms), mid, hi);
+    let ghost reversed_left = left.values();
+    let ghost reversed_right = perms.values();
+    proof {
+        vstd::modes::tracked_swap(perms, &mut left);
+        merge(aself, perms, left);
+    }
+    reverse(aself, Tracked(perms), lo, hi);
+    proof {
+        lemma_reversed_rotate(left_vals, right_vals, reversed_left, reversed_right, perms.values());
+    }
+}
+
+/// Like `rotate_left`, but reverses with `reverse_parallel`.
+pub fn rotate_left_parallel<T: Copy + Send + 'static>(
+    arr: Arc<Array<T>>,
+    Tracked(perms): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize,
+    mid: usize,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        lo <= mid <= hi,
+    ensures
+        ret.is_ok() ==> wf(*arr, *perms) && perms.lo() == lo && perms.hi() == hi,
+        ret.is_ok() ==> perms.values() == old(perms).values().subrange(mid - lo, hi - lo) + old(perms).values().subrange(0, mid - lo),
+{
+    proof {
+        lemma_values_len(&*arr, *perms);
+    }
+    let tracked mut left = split_front(&*arr, mid, perms);
+    let ghost left_vals = left.values();
+    let ghost right_vals = perms.values();
+    match reverse_parallel(Arc::clone(&arr), Tracked(&mut left), lo, mid, threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+    match reverse_parallel(Arc::clone(&arr), Tracked(perms), mid, hi, threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+    let ghost reversed_left = left.values();
+    let ghost reversed_right = perms.values();
+    proof {
+        vstd::modes::tracked_swap(perms, &mut left);
+        merge(&*arr, perms, left);
+    }
+    match reverse_parallel(Arc::clone(&arr), Tracked(perms), lo, hi, threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+    proof {
+        lemma_reversed_rotate(left_vals, right_vals, reversed_left, reversed_right, perms.values());
+    }
+    Ok(())
+}
+
+/// Swapping two elements keeps the multiset of a sequence.
+pub proof fn lemma_swap_multiset<T>(s: Seq<T>, i: int, j: int)
+    requires
+        0 <= i < s.len(),
+        0 <= j < s.len(),
+    ensures
+        s.update(j, s[i]).update(i, s[j]).to_multiset() == s.to_multiset(),
+{
+    let t = s.update(j, s[i]).update(i, s[j]);
+    if i == j {
+        assert(t =~= s);
+        return;
+    }
+    let (a, b) = if i < j { (i, j) } else { (j, i) };
+    let p = s.subrange(0, a);
+    let m = s.subrange(a + 1, b);
+    let q = s.subrange(b + 1, s.len() as int);
+    let x = seq![s[a]];
+    let y = seq![s[b]];
+    assert(s =~= p + x + m + y + q);
+    assert(t =~= p + y + m + x + q);
+    lemma_multiset_commutative(p + x + m + y, q);
+    lemma_multiset_commutative(p + x + m, y);
+    lemma_multiset_commutative(p + x, m);
+    lemma_multiset_commutative(p, x);
+    lemma_multiset_commutative(p + y + m + x, q);
+    lemma_multiset_commutative(p + y + m, x);
+    lemma_multiset_commutative(p + y, m);
+    lemma_multiset_commutative(p, y);
+    assert(t.to_multiset() =~= s.to_multiset());
+}
+
+#[test]
+fn test_bulk_ops() {
+    use crate::test_data::{scattered, thresholds};
+
+    let data: Vec<i32> = scattered(1000, 401, 0);
+    let n = data.len();
+
+    for threshold in thresholds(n) {
+        let (arr, Tracked(mut perms)) = new(data.clone());
+        let arr = Arc::new(arr);
+        let mut expected = data.clone();
+
+        reverse_parallel(Arc::clone(&arr), Tracked(&mut perms), 0, n, threshold).unwrap();
+        expected.reverse();
+        assert_eq!(clone_to_vec(&arr, Tracked(&perms)), expected);
+
+        rotate_left_parallel(Arc::clone(&arr), Tracked(&mut perms), 0, n, 123, threshold).unwrap();
+        expected.rotate_left(123);
+        assert_eq!(clone_to_vec(&arr, Tracked(&perms)), expected);
+
+        let (out_arr, Tracked(mut out_perms)) = new(vec![0; n]);
+        let out_arr = Arc::new(out_arr);
+        copy_region_parallel(Arc::clone(&arr), Tracked(&mut perms), Arc::clone(&out_arr), Tracked(&mut out_perms), 0, n, threshold).unwrap();
+        assert_eq!(clone_to_vec(&out_arr, Tracked(&out_perms)), expected);
+
+        fill_parallel(Arc::clone(&arr), Tracked(&mut perms), 0, n, -1, threshold).unwrap();
+        assert_eq!(clone_to_vec(&arr, Tracked(&perms)), vec![-1; n]);
+        assert_eq!(clone_to_vec(&out_arr, Tracked(&out_perms)), expected);
+    }
+
+    let (src, Tracked(src_perms)) = new(data.clone());
+    let (fast, Tracked(mut fast_perms)) = new(vec![0; n]);
+    let (cells, Tracked(mut cells_perms)) = new(vec![0; n]);
+    copy_range(&src, Tracked(&src_perms), 100, &fast, Tracked(&mut fast_perms), 300, 500);
+    copy_range_cells(&src, Tracked(&src_perms), 100, &cells, Tracked(&mut cells_perms), 300, 500);
+    let mut expected = vec![0; n];
+    expected[300..800].copy_from_slice(&data[100..600]);
+    assert_eq!(clone_to_vec(&fast, Tracked(&fast_perms)), expected);
+    assert_eq!(clone_to_vec(&cells, Tracked(&cells_perms)), expected);
+}
+
 }
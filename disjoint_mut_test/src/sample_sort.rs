@@ -0,0 +1,400 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::{self, ArrayForSorting, MergeKernel},
+    chunks::offsets_wf,
+};
+
+/// Number of samples taken per bucket when choosing splitters.
+pub const OVERSAMPLE: usize = 16;
+
+/// Sorts `arr` by distributing its elements into about `len / threshold` buckets
+/// of `out_arr` in a single pass, and then sorting every bucket in its own thread.
+pub fn sample_sort_parallel(
+    arr: &mut ArrayForSorting<i32>,
+    out_arr: &mut ArrayForSorting<i32>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        old(out_arr).perms@.lo() == 0,
+        old(out_arr).perms@.hi() == old(out_arr).array.len(),
+        region_array::wf(*old(out_arr).array, (old(out_arr).perms@)),
+        old(arr).array.len() == old(out_arr).array.len(),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo(),
+        ret.is_ok() ==> arr.perms@.hi() == old(arr).perms@.hi(),
+{
+    let n = (&*arr.array).length();
+    if n <= threshold || n < 2 {
+        mergesort::_merge_sort(&arr.array, 0, n, Tracked(arr.perms.borrow_mut()), &out_arr.array, 0, Tracked(out_arr.perms.borrow_mut()), MergeKernel::Branching);
+        return Ok(());
+    }
+    let bucket_size = if threshold == 0 { 1 } else { threshold };
+    let n_splitters = (n - 1) / bucket_size;
+
+    let splitters = choose_splitters(&arr.array, n, n_splitters, Tracked(arr.perms.borrow()));
+    let counts = count_buckets(&arr.array, n, &splitters, Tracked(arr.perms.borrow()));
+    let offsets = match bucket_offsets(&counts, n) {
+        Some(offsets) => offsets,
+        None => { return Err(()); },
+    };
+    if !scatter(&arr.array, n, &splitters, &offsets, Tracked(arr.perms.borrow()), &out_arr.array, Tracked(out_arr.perms.borrow_mut())) {
+        return Err(());
+    }
+
+    let n_offsets = offsets.len();
+    _sort_buckets_parallel(
+        Arc::clone(&arr.array),
+        Tracked(arr.perms.borrow_mut()),
+        Arc::clone(&out_arr.array),
+        Tracked(out_arr.perms.borrow_mut()),
+        Arc::new(offsets),
+        0,
+        n_offsets - 1,
+    )
+}
+
+/// Picks `n_splitters` evenly spaced elements of a sorted sample of `arr[0..n]`.
+/// At most `OVERSAMPLE` elements are sampled per bucket.
+fn choose_splitters(
+    arr: &Array<i32>,
+    n: usize,
+    n_splitters: usize,
+    Tracked(perms): Tracked<&Region<i32>>,
+) -> (splitters: Vec<i32>)
+    requires
+        region_array::wf(*arr, *perms),
+        perms.lo() == 0,
+        perms.hi() == n,
+        n_splitters < n,
+    ensures
+        splitters.len() <= n_splitters,
+{
+    let wanted = if n_splitters >= n / OVERSAMPLE {
+        n
+    } else {
+        assert((n_splitters + 1) * OVERSAMPLE <= n) by (nonlinear_arith)
+            requires n_splitters < n / OVERSAMPLE, OVERSAMPLE == 16;
+        (n_splitters + 1) * OVERSAMPLE
+    };
+    let stride = n / wanted;
+    assert(stride >= 1) by (nonlinear_arith)
+        requires 0 < wanted <= n, stride == n / wanted;
+    let mut samples: Vec<i32> = Vec::new();
+    let mut buf: Vec<i32> = Vec::new();
+    let mut pos: usize = 0;
+    while samples.len() < wanted && pos < n
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() == 0,
+            perms.hi() == n,
+            stride >= 1,
+            buf.len() == samples.len() <= wanted,
+    {
+        samples.push(*region_array::read(arr, pos, Tracked(perms)));
+        buf.push(0);
+        if n - pos <= stride {
+            break;
+        }
+        pos += stride;
+    }
+    let n_samples = samples.len();
+    let mut samples = ArrayForSorting::new(samples);
+    let mut buf = ArrayForSorting::new(buf);
+    mergesort::merge_sort(&mut samples, &mut buf);
+
+    let step = n_samples / (n_splitters + 1);
+    let mut splitters: Vec<i32> = Vec::new();
+    if step == 0 {
+        return splitters;
+    }
+    let mut i = step;
+    while splitters.len() < n_splitters && i < n_samples
+        invariant
+            region_array::wf(*samples.array, samples.perms@),
+            samples.perms@.lo() == 0,
+            samples.perms@.hi() == n_samples,
+            splitters.len() <= n_splitters,
+            step > 0,
+    {
+        splitters.push(*region_array::read(&samples.array, i, Tracked(samples.perms.borrow())));
+        if n_samples - i <= step {
+            break;
+        }
+        i += step;
+    }
+    splitters
+}
+
+/// Index of the bucket for `x`: the number of splitters `<= x`.
+fn bucket_of(splitters: &Vec<i32>, x: i32) -> (b: usize)
+    ensures
+        b <= splitters.len(),
+{
+    let mut lo: usize = 0;
+    let mut hi = splitters.len();
+    while lo < hi
+        invariant
+            lo <= hi <= splitters.len(),
+    {
+        let mid = lo + (hi - lo) / 2;
+        if splitters[mid] <= x {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+fn count_buckets(
+    arr: &Array<i32>,
+    n: usize,
+    splitters: &Vec<i32>,
+    Tracked(perms): Tracked<&Region<i32>>,
+) -> (counts: Vec<usize>)
+    requires
+        region_array::wf(*arr, *perms),
+        perms.lo() == 0,
+        perms.hi() == n,
+    ensures
+        counts.len() == splitters.len() + 1,
+{
+    let mut counts: Vec<usize> = Vec::new();
+    while counts.len() <= splitters.len()
+        invariant
+            counts.len() <= splitters.len() + 1,
+            forall |b: int| 0 <= b < counts.len() ==> counts[b] == 0,
+    {
+        counts.push(0);
+    }
+    let mut i: usize = 0;
+    while i < n
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() == 0,
+            perms.hi() == n,
+            i <= n,
+            counts.len() == splitters.len() + 1,
+            forall |b: int| 0 <= b < counts.len() ==> counts[b] <= i,
+    {
+        let b = bucket_of(splitters, *region_array::read(arr, i, Tracked(perms)));
+        let c = counts[b] + 1;
+        counts.set(b, c);
+        i += 1;
+    }
+    counts
+}
+
+/// Prefix sums of `counts`: bucket `b` is `offsets[b]..offsets[b + 1]`.
+/// Returns `None` if the counts do not add up to `n`.
+fn bucket_offsets(counts: &Vec<usize>, n: usize) -> (offsets: Option<Vec<usize>>)
+    ensures
+        offsets.is_some() ==> offsets.unwrap().len() == counts.len() + 1,
+        offsets.is_some() ==> offsets_wf(offsets.unwrap()@, n as int),
+{
+    let mut offsets: Vec<usize> = Vec::new();
+    offsets.push(0);
+    let mut b: usize = 0;
+    while b < counts.len()
+        invariant
+            b <= counts.len(),
+            offsets.len() == b + 1,
+            offsets@.last() <= n,
+            offsets_wf(offsets@, offsets@.last() as int),
+    {
+        let last = offsets[b];
+        if counts[b] > n - last {
+            return None;
+        }
+        offsets.push(last + counts[b]);
+        b += 1;
+    }
+    if offsets[counts.len()] != n {
+        return None;
+    }
+    Some(offsets)
+}
+
+/// Moves every element of `arr` into its bucket in `out_arr`.
+/// Returns `false` if a bucket turns out to be larger than counted.
+fn scatter(
+    arr: &Array<i32>,
+    n: usize,
+    splitters: &Vec<i32>,
+    offsets: &Vec<usize>,
+    Tracked(perms): Tracked<&Region<i32>>,
+    out_arr: &Array<i32>,
+    Tracked(out_perms): Tracked<&mut Region<i32>>,
+) -> (ok: bool)
+    requires
+        region_array::wf(*arr, *perms),
+        perms.lo() == 0,
+        perms.hi() == n,
+        region_array::wf(*out_arr, *old(out_perms)),
+        old(out_perms).lo() == 0,
+        old(out_perms).hi() == n,
+        offsets.len() == splitters.len() + 2,
+        offsets_wf(offsets@, n as int),
+    ensures
+        region_array::wf(*out_arr, *out_perms),
+        out_perms.lo() == old(out_perms).lo(),
+        out_perms.hi() == old(out_perms).hi(),
+{
+    let mut cursors: Vec<usize> = Vec::new();
+    while cursors.len() <= splitters.len()
+        invariant
+            cursors.len() <= splitters.len() + 1,
+            offsets.len() == splitters.len() + 2,
+    {
+        let b = cursors.len();
+        cursors.push(offsets[b]);
+    }
+    let mut i: usize = 0;
+    while i < n
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() == 0,
+            perms.hi() == n,
+            region_array::wf(*out_arr, *out_perms),
+            out_perms.lo() == 0,
+            out_perms.hi() == n,
+            cursors.len() == splitters.len() + 1,
+            offsets.len() == splitters.len() + 2,
+            offsets_wf(offsets@, n as int),
+    {
+        let x = *region_array::read(arr, i, Tracked(perms));
+        let b = bucket_of(splitters, x);
+        let pos = cursors[b];
+        if pos >= offsets[b + 1] {
+            return false;
+        }
+        region_array::replace(out_arr, pos, x, Tracked(out_perms));
+        cursors.set(b, pos + 1);
+        i += 1;
+    }
+    true
+}
+
+/// Sorts buckets `b_lo..b_hi`, which have been scattered into `out_arr`.
+/// Every bucket is handled by its own thread, which owns the bucket's `Region`
+/// in both arrays: it copies the bucket back into `arr` and sorts it there.
+fn _sort_buckets_parallel(
+    arr: Arc<Array<i32>>,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    out_arr: Arc<Array<i32>>,
+    Tracked(out_perms): Tracked<&mut Region<i32>>,
+    offsets: Arc<Vec<usize>>,
+    b_lo: usize, b_hi: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        b_lo < b_hi < offsets@.len(),
+        offsets_wf(offsets@, arr.len() as int),
+        old(perms).lo() <= offsets@[b_lo as int] <= offsets@[b_hi as int] <= old(perms).hi() <= arr.len(),
+        region_array::wf(*arr, (*old(perms))),
+        old(out_perms).lo() <= offsets@[b_lo as int] <= offsets@[b_hi as int] <= old(out_perms).hi() <= out_arr.len(),
+        region_array::wf(*out_arr, *old(out_perms)),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, (*perms)),
+        ret.is_ok() ==> old(perms).lo() == perms.lo() && old(perms).hi() == perms.hi(),
+        ret.is_ok() ==> region_array::wf(*out_arr, *out_perms),
+        ret.is_ok() ==> old(out_perms).lo() == out_perms.lo() && old(out_perms).hi() == out_perms.hi(),
+{
+    let (lo, hi) = (offsets[b_lo], offsets[b_hi]);
+    if b_hi - b_lo == 1 {
+        region_array::copy_range(&*out_arr, Tracked(out_perms), lo, &*arr, Tracked(perms), lo, hi - lo);
+        mergesort::_merge_sort(&*arr, lo, hi, Tracked(perms), &*out_arr, lo, Tracked(out_perms), MergeKernel::Branching);
+        return Ok(());
+    }
+    let b_mid = b_lo + (b_hi - b_lo) / 2;
+    let mid = offsets[b_mid];
+    // empty buckets do not need a thread
+    if mid == lo {
+        return _sort_buckets_parallel(arr, Tracked(perms), out_arr, Tracked(out_perms), offsets, b_mid, b_hi);
+    }
+    if mid == hi {
+        return _sort_buckets_parallel(arr, Tracked(perms), out_arr, Tracked(out_perms), offsets, b_lo, b_mid);
+    }
+
+    let tracked right_perms = region_array::split_off(&*arr, mid, perms);
+    let tracked left_perms = region_array::split_off(&*arr, lo, perms);
+
+    let tracked out_right_perms = region_array::split_off(&*out_arr, mid, out_perms);
+    let tracked out_left_perms = region_array::split_off(&*out_arr, lo, out_perms);
+
+    let arr_r1 = Arc::clone(&arr);
+    let arr_r2 = Arc::clone(&arr);
+
+    let out_arr_r1 = Arc::clone(&out_arr);
+    let out_arr_r2 = Arc::clone(&out_arr);
+
+    let offsets_r1 = Arc::clone(&offsets);
+    let offsets_r2 = Arc::clone(&offsets);
+
+    let left_perms = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<i32>>, Tracked<Region<i32>>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().0@) && ret.unwrap().0@.lo() == lo && ret.unwrap().0@.hi() == mid,
+            ret.is_ok() ==> region_array::wf(*out_arr, ret.unwrap().1@) && ret.unwrap().1@.lo() == lo && ret.unwrap().1@.hi() == mid,
+        {
+            let tracked mut left_perms = left_perms;
+            let tracked mut out_left_perms = out_left_perms;
+            let t = _sort_buckets_parallel(arr_r1, Tracked(&mut left_perms), out_arr_r1, Tracked(&mut out_left_perms), offsets_r1, b_lo, b_mid);
+            if t.is_err() {
+                Err(())
+            } else {
+                Ok((Tracked(left_perms), Tracked(out_left_perms)))
+            }
+        }
+    );
+
+    match _sort_buckets_parallel(arr_r2, Tracked(&mut right_perms), out_arr_r2, Tracked(&mut out_right_perms), offsets_r2, b_mid, b_hi) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut left_perms), Tracked(mut out_left_perms)) = match left_perms.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::merge(&*arr, &mut left_perms, right_perms);
+        region_array::merge(&*arr, perms, left_perms);
+        region_array::merge(&*out_arr, &mut out_left_perms, out_right_perms);
+        region_array::merge(&*out_arr, out_perms, out_left_perms);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sample_sort_parallel() {
+    use crate::test_data::{scattered, thresholds};
+
+    // the second input has only five distinct values, so most buckets get nothing
+    for data in [scattered::<i32>(1000, 1000, -500), scattered(1000, 5, 0)] {
+        let mut expected = data.clone();
+        expected.sort();
+        for threshold in thresholds(data.len()) {
+            let mut arr = ArrayForSorting::new(data.clone());
+            let mut out_arr = ArrayForSorting::new(vec![0; data.len()]);
+            sample_sort_parallel(&mut arr, &mut out_arr, threshold).unwrap();
+            assert_eq!(arr.clone_to_vec(), expected);
+        }
+    }
+}
+
+}
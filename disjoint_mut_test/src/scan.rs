@@ -0,0 +1,681 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::ArrayForSorting,
+    chunks::{chunks_wf, chunk_bounds},
+};
+
+pub open spec fn sum(s: Seq<u64>) -> int
+    decreases s.len(),
+{
+    if s.len() == 0 {
+        0
+    } else {
+        sum(s.drop_last()) + s.last()
+    }
+}
+
+/// `w`, which starts at the cell `base`, holds the prefix sums of `v` from there on.
+/// An inclusive scan counts the cell itself, an exclusive one does not.
+pub open spec fn scanned_from(v: Seq<u64>, w: Seq<u64>, base: int, inclusive: bool) -> bool {
+    forall |i: int| 0 <= i < w.len() ==>
+        #[trigger] w[i] == sum(v.subrange(0, base + i + if inclusive { 1int } else { 0int }))
+}
+
+pub open spec fn scanned(v: Seq<u64>, w: Seq<u64>, inclusive: bool) -> bool {
+    &&& w.len() == v.len()
+    &&& scanned_from(v, w, 0, inclusive)
+}
+
+spec fn chunk(v: Seq<u64>, bounds: Seq<usize>, c: int) -> Seq<u64> {
+    v.subrange(bounds[c] as int, bounds[c + 1] as int)
+}
+
+/// Every chunk of `w`, which holds the chunks `c_lo..c_hi`, is the scan of the same chunk of `v` on its own.
+spec fn chunks_scanned(v: Seq<u64>, w: Seq<u64>, bounds: Seq<usize>, c_lo: int, c_hi: int, inclusive: bool) -> bool {
+    forall |c: int| c_lo <= c < c_hi ==> scanned(
+        #[trigger] chunk(v, bounds, c),
+        w.subrange(bounds[c] - bounds[c_lo], bounds[c + 1] - bounds[c_lo]),
+        inclusive,
+    )
+}
+
+proof fn lemma_sum_nonneg(s: Seq<u64>)
+    ensures
+        sum(s) >= 0,
+    decreases s.len(),
+{
+    if s.len() > 0 {
+        lemma_sum_nonneg(s.drop_last());
+    }
+}
+
+proof fn lemma_sum_append(a: Seq<u64>, b: Seq<u64>)
+    ensures
+        sum(a + b) == sum(a) + sum(b),
+    decreases b.len(),
+{
+    if b.len() == 0 {
+        assert(a + b =~= a);
+    } else {
+        lemma_sum_append(a, b.drop_last());
+        assert((a + b).drop_last() =~= a + b.drop_last());
+    }
+}
+
+/// Splits the prefix `s[0..b]` at `a`, and bounds its parts by the whole sum.
+proof fn lemma_sum_subrange(s: Seq<u64>, a: int, b: int)
+    requires
+        0 <= a <= b <= s.len(),
+    ensures
+        sum(s.subrange(0, b)) == sum(s.subrange(0, a)) + sum(s.subrange(a, b)),
+        sum(s.subrange(0, b)) <= sum(s),
+        sum(s.subrange(a, b)) <= sum(s),
+{
+    assert(s.subrange(0, b) =~= s.subrange(0, a) + s.subrange(a, b));
+    lemma_sum_append(s.subrange(0, a), s.subrange(a, b));
+    assert(s =~= s.subrange(0, b) + s.subrange(b, s.len() as int));
+    lemma_sum_append(s.subrange(0, b), s.subrange(b, s.len() as int));
+    lemma_sum_nonneg(s.subrange(0, a));
+    lemma_sum_nonneg(s.subrange(b, s.len() as int));
+}
+
+proof fn lemma_sum_push(s: Seq<u64>, k: int)
+    requires
+        0 <= k < s.len(),
+    ensures
+        sum(s.subrange(0, k + 1)) == sum(s.subrange(0, k)) + s[k],
+{
+    assert(s.subrange(0, k + 1).drop_last() =~= s.subrange(0, k));
+}
+
+/// The chunks of `l` followed by the chunks of `r` are the chunks of `l + r`.
+proof fn lemma_chunks_append(
+    v: Seq<u64>, l: Seq<u64>, r: Seq<u64>, bounds: Seq<usize>,
+    c_lo: int, c_mid: int, c_hi: int, inclusive: bool,
+)
+    requires
+        forall |a: int, b: int| 0 <= a < b < bounds.len() ==> bounds[a] < bounds[b],
+        0 <= c_lo <= c_mid <= c_hi < bounds.len(),
+        l.len() == bounds[c_mid] - bounds[c_lo],
+        r.len() == bounds[c_hi] - bounds[c_mid],
+        chunks_scanned(v, l, bounds, c_lo, c_mid, inclusive),
+        chunks_scanned(v, r, bounds, c_mid, c_hi, inclusive),
+    ensures
+        chunks_scanned(v, l + r, bounds, c_lo, c_hi, inclusive),
+{
+    let base = bounds[c_lo] as int;
+    assert forall |c: int| c_lo <= c < c_hi implies scanned(
+        #[trigger] chunk(v, bounds, c),
+        (l + r).subrange(bounds[c] - base, bounds[c + 1] - base),
+        inclusive,
+    ) by {
+        if c < c_mid {
+            if c_lo < c {
+                assert(bounds[c_lo] < bounds[c]);
+            }
+            if c + 1 < c_mid {
+                assert(bounds[c + 1] < bounds[c_mid]);
+            }
+            assert((l + r).subrange(bounds[c] - base, bounds[c + 1] - base) =~= l.subrange(bounds[c] - base, bounds[c + 1] - base));
+        } else {
+            if c_mid < c {
+                assert(bounds[c_mid] < bounds[c]);
+            }
+            if c + 1 < c_hi {
+                assert(bounds[c + 1] < bounds[c_hi]);
+            }
+            assert((l + r).subrange(bounds[c] - base, bounds[c + 1] - base)
+                =~= r.subrange(bounds[c] - bounds[c_mid], bounds[c + 1] - bounds[c_mid]));
+        }
+    }
+}
+
+/// Replaces the values of `arr` by their prefix sums. The sums must fit in `u64`.
+pub fn scan(arr: &mut ArrayForSorting<u64>, inclusive: bool)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        sum(old(arr).perms@.values()) <= u64::MAX,
+    ensures
+        region_array::wf(*arr.array, (arr.perms@)),
+        arr.perms@.lo() == old(arr).perms@.lo(),
+        arr.perms@.hi() == old(arr).perms@.hi(),
+        scanned(old(arr).perms@.values(), arr.perms@.values(), inclusive),
+{
+    let n = (&*arr.array).length();
+    scan_chunk(&*arr.array, Tracked(arr.perms.borrow_mut()), 0, n, inclusive);
+}
+
+/// Like `scan`, but work-efficient in parallel. The array is cut into at most `MAX_CHUNKS`
+/// chunks of `threshold` elements. Every chunk is first scanned on its own in a thread of its
+/// own, which gives the sums of the chunks. The prefix sums of those are then added to the
+/// chunks, again in a thread per chunk.
+pub fn scan_parallel(arr: &mut ArrayForSorting<u64>, inclusive: bool, threshold: usize) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        sum(old(arr).perms@.values()) <= u64::MAX,
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo(),
+        ret.is_ok() ==> arr.perms@.hi() == old(arr).perms@.hi(),
+        ret.is_ok() ==> scanned(old(arr).perms@.values(), arr.perms@.values(), inclusive),
+{
+    let n = (&*arr.array).length();
+    let ghost v = arr.perms@.values();
+    proof {
+        region_array::lemma_values_len(&*arr.array, arr.perms@);
+    }
+    if n == 0 {
+        return Ok(());
+    }
+    let bounds = Arc::new(chunk_bounds(n, threshold, 1));
+    let c_hi = (*bounds).len() - 1;
+    assert(v.subrange(0, n as int) =~= v);
+
+    let sums = match _scan_chunks_parallel(
+        Arc::clone(&arr.array), Tracked(arr.perms.borrow_mut()), Arc::clone(&bounds), 0, c_hi, inclusive, Ghost(v),
+    ) {
+        Ok(sums) => sums,
+        Err(_) => {return Err(());},
+    };
+    let offsets = Arc::new(block_offsets(&sums, &*bounds, Ghost(v)));
+    match _add_offsets_parallel(
+        Arc::clone(&arr.array), Tracked(arr.perms.borrow_mut()), bounds, offsets, 0, c_hi, inclusive, Ghost(v),
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+    proof {
+        region_array::lemma_values_len(&*arr.array, arr.perms@);
+    }
+    Ok(())
+}
+
+/// Scans `lo..hi` on its own and returns the sum of its values.
+fn scan_chunk(
+    arr: &Array<u64>,
+    Tracked(perms): Tracked<&mut Region<u64>>,
+    lo: usize, hi: usize,
+    inclusive: bool,
+) -> (res: u64)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        sum(old(perms).values()) <= u64::MAX,
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        scanned(old(perms).values(), perms.values(), inclusive),
+        res == sum(old(perms).values()),
+{
+    let ghost v = perms.values();
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+    }
+    let mut acc: u64 = 0;
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() == lo,
+            perms.hi() == hi,
+            perms.values().len() == v.len(),
+            v.len() == hi - lo,
+            sum(v) <= u64::MAX,
+            lo <= i <= hi,
+            acc == sum(v.subrange(0, i - lo)),
+            forall |k: int| 0 <= k < i - lo ==>
+                #[trigger] perms.values()[k] == sum(v.subrange(0, k + if inclusive { 1int } else { 0int })),
+            forall |k: int| i - lo <= k < v.len() ==> #[trigger] perms.values()[k] == v[k],
+    {
+        let x = *region_array::read(arr, i, Tracked(perms));
+        proof {
+            lemma_sum_push(v, i - lo);
+            lemma_sum_subrange(v, 0, i - lo + 1);
+        }
+        if inclusive {
+            acc = acc + x;
+            region_array::replace(arr, i, acc, Tracked(perms));
+        } else {
+            region_array::replace(arr, i, acc, Tracked(perms));
+            acc = acc + x;
+        }
+        i += 1;
+    }
+    proof {
+        assert(v.subrange(0, hi - lo) =~= v);
+    }
+    acc
+}
+
+/// Scans every chunk of `c_lo..c_hi` on its own and returns their sums. The chunks are split
+/// in halves that are scanned in separate threads.
+fn _scan_chunks_parallel(
+    arr: Arc<Array<u64>>,
+    Tracked(perms): Tracked<&mut Region<u64>>,
+    bounds: Arc<Vec<usize>>,
+    c_lo: usize, c_hi: usize,
+    inclusive: bool,
+    Ghost(v): Ghost<Seq<u64>>,
+) -> (ret: Result<Vec<u64>, ()>)
+    requires
+        chunks_wf((*bounds)@, v.len() as int),
+        sum(v) <= u64::MAX,
+        c_lo < c_hi < (*bounds).len(),
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == (*bounds)@[c_lo as int],
+        old(perms).hi() == (*bounds)@[c_hi as int],
+        old(perms).values() == v.subrange((*bounds)@[c_lo as int] as int, (*bounds)@[c_hi as int] as int),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, *perms),
+        ret.is_ok() ==> perms.lo() == old(perms).lo() && perms.hi() == old(perms).hi(),
+        ret.is_ok() ==> chunks_scanned(v, perms.values(), (*bounds)@, c_lo as int, c_hi as int, inclusive),
+        ret.is_ok() ==> ret.unwrap().len() == c_hi - c_lo,
+        ret.is_ok() ==> forall |k: int| 0 <= k < c_hi - c_lo ==>
+            #[trigger] ret.unwrap()@[k] == sum(chunk(v, (*bounds)@, c_lo + k)),
+{
+    let lo = (*bounds)[c_lo];
+    let hi = (*bounds)[c_hi];
+    proof {
+        if c_hi < (*bounds).len() - 1 {
+            assert((*bounds)@[c_hi as int] < (*bounds)@[(*bounds).len() - 1]);
+        }
+        region_array::lemma_values_len(&*arr, *perms);
+        lemma_sum_subrange(v, lo as int, hi as int);
+    }
+    if c_hi - c_lo == 1 {
+        let ghost old_vals = perms.values();
+        let s = scan_chunk(&*arr, Tracked(perms), lo, hi, inclusive);
+        proof {
+            assert(chunk(v, (*bounds)@, c_lo as int) == old_vals);
+            assert(perms.values().subrange(0, hi - lo) =~= perms.values());
+        }
+        let mut res: Vec<u64> = Vec::new();
+        res.push(s);
+        return Ok(res);
+    }
+
+    let c_mid = c_lo + (c_hi - c_lo) / 2;
+    let mid = (*bounds)[c_mid];
+    let tracked left_perms = region_array::split_front(&*arr, mid, perms);
+    let tracked mut right_perms = region_array::split_front(&*arr, hi, perms);
+    proof {
+        assert(left_perms.values() =~= v.subrange(lo as int, mid as int));
+        assert(right_perms.values() =~= v.subrange(mid as int, hi as int));
+    }
+
+    let arr_r1 = Arc::clone(&arr);
+    let bounds_r1 = Arc::clone(&bounds);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<u64>>, Vec<u64>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().0@) && ret.unwrap().0@.lo() == lo && ret.unwrap().0@.hi() == mid,
+            ret.is_ok() ==> chunks_scanned(v, ret.unwrap().0@.values(), (*bounds)@, c_lo as int, c_mid as int, inclusive),
+            ret.is_ok() ==> ret.unwrap().1.len() == c_mid - c_lo,
+            ret.is_ok() ==> forall |k: int| 0 <= k < c_mid - c_lo ==>
+                #[trigger] ret.unwrap().1@[k] == sum(chunk(v, (*bounds)@, c_lo + k)),
+        {
+            let tracked mut left_perms = left_perms;
+            match _scan_chunks_parallel(arr_r1, Tracked(&mut left_perms), bounds_r1, c_lo, c_mid, inclusive, Ghost(v)) {
+                Ok(sums) => Ok((Tracked(left_perms), sums)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    let mut right = match _scan_chunks_parallel(
+        Arc::clone(&arr), Tracked(&mut right_perms), Arc::clone(&bounds), c_mid, c_hi, inclusive, Ghost(v),
+    ) {
+        Ok(right) => right,
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut left_perms), mut res) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+    let ghost left_res = res@;
+    let ghost right_res = right@;
+    res.append(&mut right);
+    assert forall |k: int| 0 <= k < c_hi - c_lo implies
+        #[trigger] res@[k] == sum(chunk(v, (*bounds)@, c_lo + k)) by {
+        if k >= c_mid - c_lo {
+            assert(res@[k] == right_res[k - (c_mid - c_lo)]);
+        } else {
+            assert(res@[k] == left_res[k]);
+        }
+    }
+
+    proof {
+        region_array::lemma_values_len(&*arr, left_perms);
+        region_array::lemma_values_len(&*arr, right_perms);
+        lemma_chunks_append(v, left_perms.values(), right_perms.values(), (*bounds)@, c_lo as int, c_mid as int, c_hi as int, inclusive);
+        region_array::merge(&*arr, &mut left_perms, right_perms);
+        vstd::modes::tracked_swap(perms, &mut left_perms);
+    }
+    Ok(res)
+}
+
+/// Exclusive prefix sums of the chunk sums: `res[c]` is the sum of everything before chunk `c`.
+fn block_offsets(sums: &Vec<u64>, bounds: &Vec<usize>, Ghost(v): Ghost<Seq<u64>>) -> (res: Vec<u64>)
+    requires
+        chunks_wf(bounds@, v.len() as int),
+        sums.len() + 1 == bounds.len(),
+        sum(v) <= u64::MAX,
+        forall |c: int| 0 <= c < sums.len() ==> #[trigger] sums@[c] == sum(chunk(v, bounds@, c)),
+    ensures
+        res.len() == sums.len(),
+        forall |c: int| 0 <= c < res.len() ==> #[trigger] res@[c] == sum(v.subrange(0, bounds@[c] as int)),
+{
+    let mut res: Vec<u64> = Vec::new();
+    let mut acc: u64 = 0;
+    let mut c = 0;
+    assert(v.subrange(0, 0) =~= Seq::<u64>::empty());
+    while c < sums.len()
+        invariant
+            chunks_wf(bounds@, v.len() as int),
+            sums.len() + 1 == bounds.len(),
+            sum(v) <= u64::MAX,
+            forall |c: int| 0 <= c < sums.len() ==> #[trigger] sums@[c] == sum(chunk(v, bounds@, c)),
+            0 <= c <= sums.len(),
+            res.len() == c,
+            acc == sum(v.subrange(0, bounds@[c as int] as int)),
+            forall |j: int| 0 <= j < res.len() ==> #[trigger] res@[j] == sum(v.subrange(0, bounds@[j] as int)),
+    {
+        res.push(acc);
+        proof {
+            assert(bounds@[c as int] < bounds@[c + 1]);
+            if c + 1 < bounds.len() - 1 {
+                assert(bounds@[c + 1] < bounds@[bounds.len() - 1]);
+            }
+            assert(sums@[c as int] == sum(chunk(v, bounds@, c as int)));
+            lemma_sum_subrange(v, bounds@[c as int] as int, bounds@[c + 1] as int);
+        }
+        acc = acc + sums[c];
+        c += 1;
+    }
+    res
+}
+
+/// Exclusive prefix sums of `counts`, the sizes of the outputs of the chunks of `bounds`, none
+/// larger than its chunk: the output of chunk `c` goes to `res[c]..res[c + 1]`.
+pub fn chunk_offsets(counts: &Vec<usize>, bounds: &Vec<usize>) -> (res: Vec<usize>)
+    requires
+        counts.len() + 1 == bounds.len(),
+        bounds@[0] == 0,
+        forall |a: int, b: int| 0 <= a < b < bounds.len() ==> bounds@[a] < bounds@[b],
+        forall |k: int| 0 <= k < counts.len() ==> #[trigger] counts@[k] <= bounds@[k + 1] - bounds@[k],
+    ensures
+        res.len() == bounds.len(),
+        res@[0] == 0,
+        forall |a: int, b: int| 0 <= a <= b < res.len() ==> res@[a] <= res@[b],
+        forall |k: int| 0 <= k < res.len() ==> #[trigger] res@[k] <= bounds@[k],
+        forall |k: int| 0 <= k < counts.len() ==> #[trigger] res@[k + 1] == res@[k] + counts@[k],
+{
+    let mut res: Vec<usize> = Vec::new();
+    res.push(0);
+    let mut k = 0;
+    while k < counts.len()
+        invariant
+            counts.len() + 1 == bounds.len(),
+            forall |a: int, b: int| 0 <= a < b < bounds.len() ==> bounds@[a] < bounds@[b],
+            forall |k: int| 0 <= k < counts.len() ==> #[trigger] counts@[k] <= bounds@[k + 1] - bounds@[k],
+            0 <= k <= counts.len(),
+            res.len() == k + 1,
+            res@[0] == 0,
+            forall |a: int, b: int| 0 <= a <= b < res.len() ==> res@[a] <= res@[b],
+            forall |j: int| 0 <= j < res.len() ==> #[trigger] res@[j] <= bounds@[j],
+            forall |j: int| 0 <= j < k ==> #[trigger] res@[j + 1] == res@[j] + counts@[j],
+    {
+        assert(counts@[k as int] <= bounds@[k + 1] - bounds@[k as int]);
+        let next = res[k] + counts[k];
+        res.push(next);
+        assert forall |a: int, b: int| 0 <= a <= b < res.len() implies res@[a] <= res@[b] by {
+            if b == k + 1 && a < b {
+                assert(res@[a] <= res@[k as int]);
+            }
+        }
+        k += 1;
+    }
+    res
+}
+
+/// Adds `d`, the sum of everything before `lo`, to the scanned chunk `lo..hi`.
+fn add_offset(
+    arr: &Array<u64>,
+    Tracked(perms): Tracked<&mut Region<u64>>,
+    lo: usize, hi: usize,
+    d: u64,
+    inclusive: bool,
+    Ghost(v): Ghost<Seq<u64>>,
+)
+    requires
+        lo < hi <= v.len(),
+        sum(v) <= u64::MAX,
+        d == sum(v.subrange(0, lo as int)),
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        scanned(v.subrange(lo as int, hi as int), old(perms).values(), inclusive),
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        scanned_from(v, perms.values(), lo as int, inclusive),
+{
+    let ghost local = perms.values();
+    let ghost vs = v.subrange(lo as int, hi as int);
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+    }
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() == lo,
+            perms.hi() == hi,
+            lo < hi <= v.len(),
+            sum(v) <= u64::MAX,
+            d == sum(v.subrange(0, lo as int)),
+            vs == v.subrange(lo as int, hi as int),
+            scanned(vs, local, inclusive),
+            perms.values().len() == hi - lo,
+            lo <= i <= hi,
+            forall |k: int| 0 <= k < i - lo ==>
+                #[trigger] perms.values()[k] == sum(v.subrange(0, lo + k + if inclusive { 1int } else { 0int })),
+            forall |k: int| i - lo <= k < hi - lo ==> #[trigger] perms.values()[k] == local[k],
+    {
+        let x = *region_array::read(arr, i, Tracked(perms));
+        proof {
+            let k = i - lo;
+            let e = if inclusive { 1int } else { 0int };
+            assert(x == local[k]);
+            assert(local[k] == sum(vs.subrange(0, k + e)));
+            assert(vs.subrange(0, k + e) =~= v.subrange(lo as int, lo + k + e));
+            lemma_sum_subrange(v, lo as int, lo + k + e);
+        }
+        region_array::replace(arr, i, x + d, Tracked(perms));
+        i += 1;
+    }
+}
+
+/// Turns the chunk scans of `c_lo..c_hi` into the scan of the whole array. The chunks are
+/// split in halves that are done in separate threads.
+fn _add_offsets_parallel(
+    arr: Arc<Array<u64>>,
+    Tracked(perms): Tracked<&mut Region<u64>>,
+    bounds: Arc<Vec<usize>>,
+    offsets: Arc<Vec<u64>>,
+    c_lo: usize, c_hi: usize,
+    inclusive: bool,
+    Ghost(v): Ghost<Seq<u64>>,
+) -> (ret: Result<(), ()>)
+    requires
+        chunks_wf((*bounds)@, v.len() as int),
+        sum(v) <= u64::MAX,
+        c_lo < c_hi < (*bounds).len(),
+        (*offsets).len() + 1 == (*bounds).len(),
+        forall |c: int| 0 <= c < (*offsets).len() ==> #[trigger] (*offsets)@[c] == sum(v.subrange(0, (*bounds)@[c] as int)),
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == (*bounds)@[c_lo as int],
+        old(perms).hi() == (*bounds)@[c_hi as int],
+        chunks_scanned(v, old(perms).values(), (*bounds)@, c_lo as int, c_hi as int, inclusive),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, *perms),
+        ret.is_ok() ==> perms.lo() == old(perms).lo() && perms.hi() == old(perms).hi(),
+        ret.is_ok() ==> scanned_from(v, perms.values(), (*bounds)@[c_lo as int] as int, inclusive),
+{
+    let lo = (*bounds)[c_lo];
+    let hi = (*bounds)[c_hi];
+    proof {
+        if c_hi < (*bounds).len() - 1 {
+            assert((*bounds)@[c_hi as int] < (*bounds)@[(*bounds).len() - 1]);
+        }
+        region_array::lemma_values_len(&*arr, *perms);
+    }
+    if c_hi - c_lo == 1 {
+        proof {
+            assert(chunk(v, (*bounds)@, c_lo as int) == v.subrange(lo as int, hi as int));
+            assert(perms.values().subrange(0, hi - lo) =~= perms.values());
+        }
+        add_offset(&*arr, Tracked(perms), lo, hi, (*offsets)[c_lo], inclusive, Ghost(v));
+        return Ok(());
+    }
+
+    let c_mid = c_lo + (c_hi - c_lo) / 2;
+    let mid = (*bounds)[c_mid];
+    let ghost vals = perms.values();
+    let tracked left_perms = region_array::split_front(&*arr, mid, perms);
+    let tracked mut right_perms = region_array::split_front(&*arr, hi, perms);
+    proof {
+        assert((*bounds)@[c_lo as int] < (*bounds)@[c_mid as int] < (*bounds)@[c_hi as int]);
+        assert(vals =~= left_perms.values() + right_perms.values());
+        assert forall |c: int| c_lo <= c < c_mid implies scanned(
+            #[trigger] chunk(v, (*bounds)@, c),
+            left_perms.values().subrange((*bounds)@[c] - lo, (*bounds)@[c + 1] - lo),
+            inclusive,
+        ) by {
+            if c_lo < c {
+                assert((*bounds)@[c_lo as int] < (*bounds)@[c]);
+            }
+            if c + 1 < c_mid {
+                assert((*bounds)@[c + 1] < (*bounds)@[c_mid as int]);
+            }
+            assert(left_perms.values().subrange((*bounds)@[c] - lo, (*bounds)@[c + 1] - lo)
+                =~= vals.subrange((*bounds)@[c] - lo, (*bounds)@[c + 1] - lo));
+        }
+        assert forall |c: int| c_mid <= c < c_hi implies scanned(
+            #[trigger] chunk(v, (*bounds)@, c),
+            right_perms.values().subrange((*bounds)@[c] - mid, (*bounds)@[c + 1] - mid),
+            inclusive,
+        ) by {
+            if c_mid < c {
+                assert((*bounds)@[c_mid as int] < (*bounds)@[c]);
+            }
+            if c + 1 < c_hi {
+                assert((*bounds)@[c + 1] < (*bounds)@[c_hi as int]);
+            }
+            assert(right_perms.values().subrange((*bounds)@[c] - mid, (*bounds)@[c + 1] - mid)
+                =~= vals.subrange((*bounds)@[c] - lo, (*bounds)@[c + 1] - lo));
+        }
+    }
+
+    let arr_r1 = Arc::clone(&arr);
+    let bounds_r1 = Arc::clone(&bounds);
+    let offsets_r1 = Arc::clone(&offsets);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<u64>>, ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap()@) && ret.unwrap()@.lo() == lo && ret.unwrap()@.hi() == mid,
+            ret.is_ok() ==> scanned_from(v, ret.unwrap()@.values(), lo as int, inclusive),
+        {
+            let tracked mut left_perms = left_perms;
+            match _add_offsets_parallel(arr_r1, Tracked(&mut left_perms), bounds_r1, offsets_r1, c_lo, c_mid, inclusive, Ghost(v)) {
+                Ok(()) => Ok(Tracked(left_perms)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match _add_offsets_parallel(
+        Arc::clone(&arr), Tracked(&mut right_perms), Arc::clone(&bounds), Arc::clone(&offsets), c_mid, c_hi, inclusive, Ghost(v),
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut left_perms) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::lemma_values_len(&*arr, left_perms);
+        let l = left_perms.values();
+        let r = right_perms.values();
+        assert forall |i: int| 0 <= i < (l + r).len() implies
+            #[trigger] (l + r)[i] == sum(v.subrange(0, lo + i + if inclusive { 1int } else { 0int })) by {
+            if i >= l.len() {
+                assert((l + r)[i] == r[i - l.len()]);
+            } else {
+                assert((l + r)[i] == l[i]);
+            }
+        }
+        region_array::merge(&*arr, &mut left_perms, right_perms);
+        vstd::modes::tracked_swap(perms, &mut left_perms);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_scan() {
+    use crate::test_data::{scattered, thresholds};
+
+    let data: Vec<u64> = scattered(2000, 211, 0);
+    let mut inclusive = Vec::new();
+    let mut exclusive = Vec::new();
+    let mut acc = 0;
+    for x in &data {
+        exclusive.push(acc);
+        acc += x;
+        inclusive.push(acc);
+    }
+
+    let mut arr = ArrayForSorting::new(data.clone());
+    scan(&mut arr, true);
+    assert_eq!(arr.clone_to_vec(), inclusive);
+    let mut arr = ArrayForSorting::new(data.clone());
+    scan(&mut arr, false);
+    assert_eq!(arr.clone_to_vec(), exclusive);
+
+    for threshold in thresholds(data.len()) {
+        let mut arr = ArrayForSorting::new(data.clone());
+        scan_parallel(&mut arr, true, threshold).unwrap();
+        assert_eq!(arr.clone_to_vec(), inclusive);
+        let mut arr = ArrayForSorting::new(data.clone());
+        scan_parallel(&mut arr, false, threshold).unwrap();
+        assert_eq!(arr.clone_to_vec(), exclusive);
+    }
+}
+
+}
@@ -0,0 +1,641 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::{Array, SpecPerms},
+    region_array::{self, Region},
+    mergesort::{self, ArrayForSorting},
+};
+
+/// `dst[i]` is `src[idx[i]]` for every `i`.
+pub open spec fn gathered<T>(src: Seq<T>, idx: Seq<usize>, dst: Seq<T>) -> bool {
+    &&& dst.len() == idx.len()
+    &&& forall |i: int| 0 <= i < dst.len() ==> #[trigger] dst[i] == src[idx[i] as int]
+}
+
+/// No index appears twice in `idx`.
+pub open spec fn injective(idx: Seq<usize>) -> bool {
+    forall |i: int, j: int| 0 <= i < idx.len() && 0 <= j < idx.len() && i != j ==> idx[i] != idx[j]
+}
+
+/// `dst` is `old_dst` with `src[i]` written to the cell `idx[i]` for every `i`.
+pub open spec fn scattered<T>(src: Seq<T>, idx: Seq<usize>, old_dst: Seq<T>, dst: Seq<T>) -> bool {
+    &&& dst.len() == old_dst.len()
+    &&& forall |i: int| 0 <= i < idx.len() ==> dst[#[trigger] idx[i] as int] == src[i]
+    &&& forall |j: int| 0 <= j < dst.len() && !idx.contains(j as usize) ==> #[trigger] dst[j] == old_dst[j]
+}
+
+/// The cells that `idx[lo..hi]` points to.
+spec fn targets(idx: Seq<usize>, lo: int, hi: int) -> Set<usize> {
+    Set::new(|j: usize| exists |i: int| lo <= i < hi && idx[i] == j)
+}
+
+/// The targets of `lo..hi` are split by `mid` into two disjoint sets.
+proof fn lemma_targets_split(idx: Seq<usize>, lo: int, mid: int, hi: int)
+    requires
+        0 <= lo <= mid <= hi <= idx.len(),
+        injective(idx),
+    ensures
+        targets(idx, lo, mid).subset_of(targets(idx, lo, hi)),
+        targets(idx, lo, hi).difference(targets(idx, lo, mid)) == targets(idx, mid, hi),
+        targets(idx, lo, mid).disjoint(targets(idx, mid, hi)),
+        targets(idx, lo, mid).union(targets(idx, mid, hi)) == targets(idx, lo, hi),
+{
+    assert forall |j: usize| targets(idx, lo, mid).contains(j) && targets(idx, mid, hi).contains(j) implies false by {
+        let a = choose |i: int| lo <= i < mid && idx[i] == j;
+        let b = choose |i: int| mid <= i < hi && idx[i] == j;
+        assert(idx[a] != idx[b]);
+    }
+    assert forall |j: usize| #[trigger] targets(idx, lo, hi).contains(j) implies
+        targets(idx, lo, mid).contains(j) || targets(idx, mid, hi).contains(j) by {
+        let i = choose |i: int| lo <= i < hi && idx[i] == j;
+        if i < mid {
+            assert(targets(idx, lo, mid).contains(j));
+        } else {
+            assert(targets(idx, mid, hi).contains(j));
+        }
+    }
+    assert forall |j: usize| #[trigger] targets(idx, lo, mid).contains(j) implies targets(idx, lo, hi).contains(j) by {
+        let i = choose |i: int| lo <= i < mid && idx[i] == j;
+        assert(lo <= i < hi && idx[i] == j);
+    }
+    assert forall |j: usize| #[trigger] targets(idx, mid, hi).contains(j) implies targets(idx, lo, hi).contains(j) by {
+        let i = choose |i: int| mid <= i < hi && idx[i] == j;
+        assert(lo <= i < hi && idx[i] == j);
+    }
+    assert(targets(idx, lo, hi).difference(targets(idx, lo, mid)) =~= targets(idx, mid, hi));
+    assert(targets(idx, lo, mid).union(targets(idx, mid, hi)) =~= targets(idx, lo, hi));
+}
+
+/// Writes `src[idx[i]]` to `dst[i]` for every `i`. The output is split in halves that are
+/// gathered in separate threads while they are longer than `threshold`. The threads only read
+/// the source and the indices, so they share their regions through an `Arc`.
+pub fn par_gather<T: Copy + Send + Sync + 'static>(
+    src: &mut ArrayForSorting<T>,
+    idx: &mut ArrayForSorting<usize>,
+    dst: &mut ArrayForSorting<T>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(src).perms@.lo() == 0,
+        old(src).perms@.hi() == old(src).array.len(),
+        region_array::wf(*old(src).array, (old(src).perms@)),
+        old(idx).perms@.lo() == 0,
+        old(idx).perms@.hi() == old(idx).array.len(),
+        region_array::wf(*old(idx).array, (old(idx).perms@)),
+        old(dst).perms@.lo() == 0,
+        old(dst).perms@.hi() == old(dst).array.len(),
+        region_array::wf(*old(dst).array, (old(dst).perms@)),
+        old(dst).array.len() == old(idx).array.len(),
+        forall |i: int| 0 <= i < old(idx).array.len() ==> #[trigger] old(idx).perms@.values()[i] < old(src).array.len(),
+    ensures
+        ret.is_ok() ==> region_array::wf(*src.array, (src.perms@)),
+        ret.is_ok() ==> src.perms@.lo() == old(src).perms@.lo() && src.perms@.hi() == old(src).perms@.hi(),
+        ret.is_ok() ==> src.perms@.values() == old(src).perms@.values(),
+        ret.is_ok() ==> region_array::wf(*idx.array, (idx.perms@)),
+        ret.is_ok() ==> idx.perms@.lo() == old(idx).perms@.lo() && idx.perms@.hi() == old(idx).perms@.hi(),
+        ret.is_ok() ==> idx.perms@.values() == old(idx).perms@.values(),
+        ret.is_ok() ==> region_array::wf(*dst.array, (dst.perms@)),
+        ret.is_ok() ==> dst.perms@.lo() == old(dst).perms@.lo() && dst.perms@.hi() == old(dst).perms@.hi(),
+        ret.is_ok() ==> gathered(old(src).perms@.values(), old(idx).perms@.values(), dst.perms@.values()),
+{
+    let n = (&*dst.array).length();
+    proof {
+        region_array::lemma_values_len(&*src.array, src.perms@);
+        region_array::lemma_values_len(&*idx.array, idx.perms@);
+    }
+
+    // leave empty regions in `src` and `idx` while the threads share them
+    let src_shared = mergesort::share(src);
+    let idx_shared = mergesort::share(idx);
+
+    let ret = _gather_parallel(
+        Arc::clone(&src.array),
+        Arc::clone(&src_shared),
+        Arc::clone(&idx.array),
+        Arc::clone(&idx_shared),
+        Arc::clone(&dst.array),
+        Tracked(dst.perms.borrow_mut()),
+        0,
+        n,
+        threshold,
+    );
+    // `src` and `idx` are given back their regions before any error is returned
+    let src_ret = mergesort::unshare(src, src_shared);
+    let idx_ret = mergesort::unshare(idx, idx_shared);
+    if ret.is_err() || src_ret.is_err() || idx_ret.is_err() {
+        return Err(());
+    }
+    proof {
+        region_array::lemma_values_len(&*dst.array, dst.perms@);
+    }
+    Ok(())
+}
+
+/// Writes `src[idx[i]]` to the cell `i` of `dst` for every `i` of `lo..hi`.
+fn gather_range<T: Copy>(
+    src_arr: &Array<T>,
+    Tracked(src): Tracked<&Region<T>>,
+    idx_arr: &Array<usize>,
+    Tracked(idx): Tracked<&Region<usize>>,
+    dst_arr: &Array<T>,
+    Tracked(dst): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize,
+)
+    requires
+        region_array::wf(*src_arr, *src),
+        src.lo() == 0,
+        region_array::wf(*idx_arr, *idx),
+        idx.lo() == 0,
+        hi <= idx.hi(),
+        forall |i: int| 0 <= i < idx.hi() ==> #[trigger] idx.values()[i] < src.hi(),
+        region_array::wf(*dst_arr, *old(dst)),
+        old(dst).lo() == lo,
+        old(dst).hi() == hi,
+    ensures
+        region_array::wf(*dst_arr, *dst),
+        dst.lo() == lo,
+        dst.hi() == hi,
+        forall |k: int| 0 <= k < hi - lo ==> #[trigger] dst.values()[k] == src.values()[idx.values()[lo + k] as int],
+{
+    proof {
+        region_array::lemma_values_len(dst_arr, *dst);
+    }
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*src_arr, *src),
+            src.lo() == 0,
+            region_array::wf(*idx_arr, *idx),
+            idx.lo() == 0,
+            hi <= idx.hi(),
+            forall |i: int| 0 <= i < idx.hi() ==> #[trigger] idx.values()[i] < src.hi(),
+            region_array::wf(*dst_arr, *dst),
+            dst.lo() == lo,
+            dst.hi() == hi,
+            dst.values().len() == hi - lo,
+            lo <= i <= hi,
+            forall |k: int| 0 <= k < i - lo ==> #[trigger] dst.values()[k] == src.values()[idx.values()[lo + k] as int],
+    {
+        let j = *region_array::read(idx_arr, i, Tracked(idx));
+        assert(j == idx.values()[i as int]);
+        let x = *region_array::read(src_arr, j, Tracked(src));
+        region_array::replace(dst_arr, i, x, Tracked(dst));
+        assert(lo + (i - lo) == i);
+        i += 1;
+    }
+}
+
+fn _gather_parallel<T: Copy + Send + Sync + 'static>(
+    src_arr: Arc<Array<T>>,
+    src_shared: Arc<Tracked<Region<T>>>,
+    idx_arr: Arc<Array<usize>>,
+    idx_shared: Arc<Tracked<Region<usize>>>,
+    dst_arr: Arc<Array<T>>,
+    Tracked(dst): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        region_array::wf(*src_arr, (*src_shared)@),
+        (*src_shared)@.lo() == 0,
+        region_array::wf(*idx_arr, (*idx_shared)@),
+        (*idx_shared)@.lo() == 0,
+        hi <= (*idx_shared)@.hi(),
+        forall |i: int| 0 <= i < (*idx_shared)@.hi() ==> #[trigger] (*idx_shared)@.values()[i] < (*src_shared)@.hi(),
+        region_array::wf(*dst_arr, *old(dst)),
+        old(dst).lo() == lo,
+        old(dst).hi() == hi,
+    ensures
+        ret.is_ok() ==> region_array::wf(*dst_arr, *dst) && dst.lo() == lo && dst.hi() == hi,
+        ret.is_ok() ==> forall |k: int| 0 <= k < hi - lo ==>
+            #[trigger] dst.values()[k] == (*src_shared)@.values()[(*idx_shared)@.values()[lo + k] as int],
+{
+    let ghost s = (*src_shared)@.values();
+    let ghost v = (*idx_shared)@.values();
+    if hi - lo <= threshold || hi - lo < 2 {
+        let src_region: &Tracked<Region<T>> = &*src_shared;
+        let idx_region: &Tracked<Region<usize>> = &*idx_shared;
+        gather_range(
+            &*src_arr, Tracked(src_region.borrow()), &*idx_arr, Tracked(idx_region.borrow()),
+            &*dst_arr, Tracked(dst), lo, hi,
+        );
+        return Ok(());
+    }
+
+    let mid = lo + (hi - lo) / 2;
+    let tracked left_dst = region_array::split_front(&*dst_arr, mid, dst);
+    let tracked mut right_dst = region_array::split_front(&*dst_arr, hi, dst);
+
+    let src_arr_r1 = Arc::clone(&src_arr);
+    let src_shared_r1 = Arc::clone(&src_shared);
+    let idx_arr_r1 = Arc::clone(&idx_arr);
+    let idx_shared_r1 = Arc::clone(&idx_shared);
+    let dst_arr_r1 = Arc::clone(&dst_arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<T>>, ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*dst_arr, ret.unwrap()@) && ret.unwrap()@.lo() == lo && ret.unwrap()@.hi() == mid,
+            ret.is_ok() ==> forall |k: int| 0 <= k < mid - lo ==>
+                #[trigger] ret.unwrap()@.values()[k] == s[v[lo + k] as int],
+        {
+            let tracked mut left_dst = left_dst;
+            match _gather_parallel(
+                src_arr_r1, src_shared_r1, idx_arr_r1, idx_shared_r1, dst_arr_r1, Tracked(&mut left_dst),
+                lo, mid, threshold,
+            ) {
+                Ok(()) => Ok(Tracked(left_dst)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match _gather_parallel(
+        Arc::clone(&src_arr), Arc::clone(&src_shared), Arc::clone(&idx_arr), Arc::clone(&idx_shared),
+        Arc::clone(&dst_arr), Tracked(&mut right_dst), mid, hi, threshold,
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut left_dst) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::lemma_values_len(&*dst_arr, left_dst);
+        region_array::lemma_values_len(&*dst_arr, right_dst);
+        let l = left_dst.values();
+        let r = right_dst.values();
+        assert forall |k: int| 0 <= k < hi - lo implies #[trigger] (l + r)[k] == s[v[lo + k] as int] by {
+            if k >= l.len() {
+                assert((l + r)[k] == r[k - l.len()]);
+                assert(mid + (k - l.len()) == lo + k);
+            }
+        }
+        region_array::merge(&*dst_arr, &mut left_dst, right_dst);
+        vstd::modes::tracked_swap(dst, &mut left_dst);
+    }
+    Ok(())
+}
+
+/// Writes `src[i]` to the cell `idx[i]` of `dst` for every `i`, leaving the cells that `idx`
+/// does not point to as they were. Since `idx` is injective, every half of the input can be
+/// given the permissions of exactly the cells it writes to, whatever their order. The halves
+/// are scattered in separate threads while they are longer than `threshold`.
+pub fn par_scatter<T: Copy + Send + Sync + 'static>(
+    src: &mut ArrayForSorting<T>,
+    idx: &mut ArrayForSorting<usize>,
+    dst: &mut ArrayForSorting<T>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(src).perms@.lo() == 0,
+        old(src).perms@.hi() == old(src).array.len(),
+        region_array::wf(*old(src).array, (old(src).perms@)),
+        old(idx).perms@.lo() == 0,
+        old(idx).perms@.hi() == old(idx).array.len(),
+        region_array::wf(*old(idx).array, (old(idx).perms@)),
+        old(dst).perms@.lo() == 0,
+        old(dst).perms@.hi() == old(dst).array.len(),
+        region_array::wf(*old(dst).array, (old(dst).perms@)),
+        old(src).array.len() == old(idx).array.len(),
+        forall |i: int| 0 <= i < old(idx).array.len() ==> #[trigger] old(idx).perms@.values()[i] < old(dst).array.len(),
+        injective(old(idx).perms@.values()),
+    ensures
+        ret.is_ok() ==> region_array::wf(*src.array, (src.perms@)),
+        ret.is_ok() ==> src.perms@.lo() == old(src).perms@.lo() && src.perms@.hi() == old(src).perms@.hi(),
+        ret.is_ok() ==> src.perms@.values() == old(src).perms@.values(),
+        ret.is_ok() ==> region_array::wf(*idx.array, (idx.perms@)),
+        ret.is_ok() ==> idx.perms@.lo() == old(idx).perms@.lo() && idx.perms@.hi() == old(idx).perms@.hi(),
+        ret.is_ok() ==> idx.perms@.values() == old(idx).perms@.values(),
+        ret.is_ok() ==> region_array::wf(*dst.array, (dst.perms@)),
+        ret.is_ok() ==> dst.perms@.lo() == old(dst).perms@.lo() && dst.perms@.hi() == old(dst).perms@.hi(),
+        ret.is_ok() ==> scattered(old(src).perms@.values(), old(idx).perms@.values(), old(dst).perms@.values(), dst.perms@.values()),
+{
+    let n = (&*idx.array).length();
+    let m = (&*dst.array).length();
+    proof {
+        region_array::lemma_values_len(&*src.array, src.perms@);
+        region_array::lemma_values_len(&*idx.array, idx.perms@);
+        region_array::lemma_values_len(&*dst.array, dst.perms@);
+    }
+
+    // leave empty regions in `src` and `idx` while the threads share them
+    let src_shared = mergesort::share(src);
+    let idx_shared = mergesort::share(idx);
+    let ghost s = (*src_shared)@.values();
+    let ghost v = (*idx_shared)@.values();
+    let ghost old_dst = dst.perms@.values();
+
+    // take the cells that are written to out of the region of `dst`
+    let tracked mut dst_region = region_array::split_front(&*dst.array, 0, dst.perms.borrow_mut());
+    proof {
+        vstd::modes::tracked_swap(dst.perms.borrow_mut(), &mut dst_region);
+        assert(dst_region.values() =~= old_dst);
+    }
+    let tracked mut rest = region_array::into_perms(&*dst.array, dst_region);
+    let ghost all = rest;
+    proof {
+        assert forall |j: usize| #[trigger] targets(v, 0, n as int).contains(j) implies rest.dom().contains(j) by {
+            let i = choose |i: int| 0 <= i < n && v[i] == j;
+            assert(v[i] < m);
+        }
+    }
+    let tracked mut cells = rest.tracked_remove_keys(targets(v, 0, n as int));
+    proof {
+        (*dst.array).submap_wf(all, cells);
+        (*dst.array).submap_wf(all, rest);
+    }
+
+    let ret = _scatter_parallel(
+        Arc::clone(&src.array),
+        Arc::clone(&src_shared),
+        Arc::clone(&idx.array),
+        Arc::clone(&idx_shared),
+        Arc::clone(&dst.array),
+        Tracked(&mut cells),
+        0,
+        n,
+        threshold,
+    );
+    // `src` and `idx` are given back their regions before any error is returned
+    let src_ret = mergesort::unshare(src, src_shared);
+    let idx_ret = mergesort::unshare(idx, idx_shared);
+    if ret.is_err() || src_ret.is_err() || idx_ret.is_err() {
+        return Err(());
+    }
+
+    let ghost kept = rest;
+    let ghost written = cells;
+    proof {
+        rest.tracked_union_prefer_right(cells);
+        (*dst.array).union_wf(kept, written);
+        assert forall |j: usize| 0 <= j < m implies #[trigger] rest.contains_key(j) by {
+            if !written.dom().contains(j) {
+                assert(kept.dom().contains(j));
+            }
+        }
+    }
+    let ghost merged = rest;
+    let tracked mut region = region_array::from_perms(&*dst.array, rest, 0, m);
+    proof {
+        vstd::modes::tracked_swap(dst.perms.borrow_mut(), &mut region);
+        let w = dst.perms@.values();
+        assert forall |i: int| 0 <= i < v.len() implies w[#[trigger] v[i] as int] == s[i] by {
+            let j = v[i];
+            assert(targets(v, 0, n as int).contains(j));
+            assert(merged[j]@.value.unwrap() == w[j as int]);
+        }
+        assert forall |j: int| 0 <= j < w.len() && !v.contains(j as usize) implies #[trigger] w[j] == old_dst[j] by {
+            let k = j as usize;
+            if targets(v, 0, n as int).contains(k) {
+                let i = choose |i: int| 0 <= i < n && v[i] == k;
+                assert(v[i] == k);
+            }
+            assert(merged[k]@.value.unwrap() == w[j]);
+            assert(all[k]@.value.unwrap() == old_dst[j]);
+        }
+    }
+    Ok(())
+}
+
+/// Writes `src[i]` to the cell `idx[i]` for every `i` of `lo..hi`, holding the
+/// permissions of exactly those cells.
+fn scatter_range<T: Copy>(
+    src_arr: &Array<T>,
+    Tracked(src): Tracked<&Region<T>>,
+    idx_arr: &Array<usize>,
+    Tracked(idx): Tracked<&Region<usize>>,
+    dst_arr: &Array<T>,
+    Tracked(cells): Tracked<&mut SpecPerms<T>>,
+    lo: usize, hi: usize,
+)
+    requires
+        region_array::wf(*src_arr, *src),
+        src.lo() == 0,
+        hi <= src.hi(),
+        region_array::wf(*idx_arr, *idx),
+        idx.lo() == 0,
+        lo <= hi <= idx.hi(),
+        forall |i: int| 0 <= i < idx.hi() ==> #[trigger] idx.values()[i] < dst_arr.len(),
+        injective(idx.values()),
+        dst_arr.wf(*old(cells)),
+        old(cells).dom() == targets(idx.values(), lo as int, hi as int),
+    ensures
+        dst_arr.wf(*cells),
+        cells.dom() == targets(idx.values(), lo as int, hi as int),
+        forall |i: int| lo <= i < hi ==> #[trigger] cells[idx.values()[i]]@.value.unwrap() == src.values()[i],
+{
+    let ghost v = idx.values();
+    proof {
+        region_array::lemma_values_len(idx_arr, *idx);
+    }
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*src_arr, *src),
+            src.lo() == 0,
+            hi <= src.hi(),
+            region_array::wf(*idx_arr, *idx),
+            idx.lo() == 0,
+            v == idx.values(),
+            v.len() == idx.hi(),
+            lo <= i <= hi <= idx.hi(),
+            forall |i: int| 0 <= i < idx.hi() ==> #[trigger] v[i] < dst_arr.len(),
+            injective(v),
+            dst_arr.wf(*cells),
+            cells.dom() == targets(v, lo as int, hi as int),
+            forall |k: int| lo <= k < i ==> #[trigger] cells[v[k]]@.value.unwrap() == src.values()[k],
+    {
+        let j = *region_array::read(idx_arr, i, Tracked(idx));
+        let x = *region_array::read(src_arr, i, Tracked(src));
+        proof {
+            assert(v[i as int] == j);
+            assert(targets(v, lo as int, hi as int).contains(j));
+        }
+        dst_arr.replace(j, x, Tracked(cells));
+        proof {
+            assert(cells.dom() =~= targets(v, lo as int, hi as int));
+            assert forall |k: int| lo <= k < i + 1 implies #[trigger] cells[v[k]]@.value.unwrap() == src.values()[k] by {
+                if k < i {
+                    assert(v[k] != v[i as int]);
+                }
+            }
+        }
+        i += 1;
+    }
+}
+
+fn _scatter_parallel<T: Copy + Send + Sync + 'static>(
+    src_arr: Arc<Array<T>>,
+    src_shared: Arc<Tracked<Region<T>>>,
+    idx_arr: Arc<Array<usize>>,
+    idx_shared: Arc<Tracked<Region<usize>>>,
+    dst_arr: Arc<Array<T>>,
+    Tracked(cells): Tracked<&mut SpecPerms<T>>,
+    lo: usize, hi: usize,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        region_array::wf(*src_arr, (*src_shared)@),
+        (*src_shared)@.lo() == 0,
+        hi <= (*src_shared)@.hi(),
+        region_array::wf(*idx_arr, (*idx_shared)@),
+        (*idx_shared)@.lo() == 0,
+        lo <= hi <= (*idx_shared)@.hi(),
+        forall |i: int| 0 <= i < (*idx_shared)@.hi() ==> #[trigger] (*idx_shared)@.values()[i] < dst_arr.len(),
+        injective((*idx_shared)@.values()),
+        dst_arr.wf(*old(cells)),
+        old(cells).dom() == targets((*idx_shared)@.values(), lo as int, hi as int),
+    ensures
+        ret.is_ok() ==> dst_arr.wf(*cells),
+        ret.is_ok() ==> cells.dom() == targets((*idx_shared)@.values(), lo as int, hi as int),
+        ret.is_ok() ==> forall |i: int| lo <= i < hi ==>
+            #[trigger] cells[(*idx_shared)@.values()[i]]@.value.unwrap() == (*src_shared)@.values()[i],
+{
+    let ghost s = (*src_shared)@.values();
+    let ghost v = (*idx_shared)@.values();
+    if hi - lo <= threshold || hi - lo < 2 {
+        let src_region: &Tracked<Region<T>> = &*src_shared;
+        let idx_region: &Tracked<Region<usize>> = &*idx_shared;
+        scatter_range(
+            &*src_arr, Tracked(src_region.borrow()), &*idx_arr, Tracked(idx_region.borrow()),
+            &*dst_arr, Tracked(cells), lo, hi,
+        );
+        return Ok(());
+    }
+
+    let mid = lo + (hi - lo) / 2;
+    proof {
+        region_array::lemma_values_len(&*idx_arr, (*idx_shared)@);
+        lemma_targets_split(v, lo as int, mid as int, hi as int);
+    }
+    // the cells of the two halves are disjoint since `idx` is injective
+    let ghost all = *cells;
+    let tracked left_cells = cells.tracked_remove_keys(targets(v, lo as int, mid as int));
+    proof {
+        (*dst_arr).submap_wf(all, left_cells);
+        (*dst_arr).submap_wf(all, *cells);
+    }
+
+    let src_arr_r1 = Arc::clone(&src_arr);
+    let src_shared_r1 = Arc::clone(&src_shared);
+    let idx_arr_r1 = Arc::clone(&idx_arr);
+    let idx_shared_r1 = Arc::clone(&idx_shared);
+    let dst_arr_r1 = Arc::clone(&dst_arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Tracked<SpecPerms<T>>, ()>)
+        ensures
+            ret.is_ok() ==> (*dst_arr).wf(ret.unwrap()@),
+            ret.is_ok() ==> ret.unwrap()@.dom() == targets(v, lo as int, mid as int),
+            ret.is_ok() ==> forall |i: int| lo <= i < mid ==> #[trigger] ret.unwrap()@[v[i]]@.value.unwrap() == s[i],
+        {
+            let tracked mut left_cells = left_cells;
+            match _scatter_parallel(
+                src_arr_r1, src_shared_r1, idx_arr_r1, idx_shared_r1, dst_arr_r1, Tracked(&mut left_cells),
+                lo, mid, threshold,
+            ) {
+                Ok(()) => Ok(Tracked(left_cells)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match _scatter_parallel(
+        Arc::clone(&src_arr), Arc::clone(&src_shared), Arc::clone(&idx_arr), Arc::clone(&idx_shared),
+        Arc::clone(&dst_arr), Tracked(cells), mid, hi, threshold,
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(left_cells) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    let ghost right_cells = *cells;
+    proof {
+        cells.tracked_union_prefer_right(left_cells);
+        (*dst_arr).union_wf(right_cells, left_cells);
+        assert(cells.dom() =~= targets(v, lo as int, hi as int));
+        assert forall |i: int| lo <= i < hi implies #[trigger] cells[v[i]]@.value.unwrap() == s[i] by {
+            if i < mid {
+                assert(targets(v, lo as int, mid as int).contains(v[i]));
+            } else {
+                assert(targets(v, mid as int, hi as int).contains(v[i]));
+                assert(!left_cells.dom().contains(v[i]));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_par_gather_scatter() {
+    use crate::test_data::{scattered, thresholds};
+
+    let data: Vec<i32> = scattered(1000, 401, -200);
+    let n = data.len();
+    let perm: Vec<usize> = (0..n).map(|i| (i * 7) % n).collect();
+    let gathered_expected: Vec<i32> = perm.iter().map(|&j| data[j]).collect();
+    let mut scattered_expected = vec![0; n];
+    for i in 0..n {
+        scattered_expected[perm[i]] = data[i];
+    }
+
+    for threshold in thresholds(n) {
+        let mut src = ArrayForSorting::new(data.clone());
+        let mut idx = ArrayForSorting::new(perm.clone());
+        let mut dst = ArrayForSorting::new(vec![0; n]);
+        par_gather(&mut src, &mut idx, &mut dst, threshold).unwrap();
+        assert_eq!(dst.clone_to_vec(), gathered_expected);
+        par_scatter(&mut src, &mut idx, &mut dst, threshold).unwrap();
+        assert_eq!(dst.clone_to_vec(), scattered_expected);
+        assert_eq!(src.clone_to_vec(), data);
+    }
+
+    // the cells that no index points to are left as they were
+    let mut src = ArrayForSorting::new(vec![1, 2, 3]);
+    let mut idx = ArrayForSorting::new(vec![4, 0, 2]);
+    let mut dst = ArrayForSorting::new(vec![9; 6]);
+    par_scatter(&mut src, &mut idx, &mut dst, 1).unwrap();
+    assert_eq!(dst.clone_to_vec(), vec![2, 9, 3, 9, 1, 9]);
+
+    // an index that is injective but no permutation, one element per thread
+    let spread: Vec<usize> = perm.iter().map(|&j| 2 * j + 1).collect();
+    let mut spread_expected = vec![0; 2 * n + 1];
+    for i in 0..n {
+        spread_expected[spread[i]] = data[i];
+    }
+    let mut src = ArrayForSorting::new(data.clone());
+    let mut idx = ArrayForSorting::new(spread.clone());
+    let mut dst = ArrayForSorting::new(vec![0; 2 * n + 1]);
+    par_scatter(&mut src, &mut idx, &mut dst, 1).unwrap();
+    assert_eq!(dst.clone_to_vec(), spread_expected);
+    let mut back = ArrayForSorting::new(vec![0; n]);
+    par_gather(&mut dst, &mut idx, &mut back, 1).unwrap();
+    assert_eq!(back.clone_to_vec(), data);
+
+    let mut src = ArrayForSorting::new(Vec::<i32>::new());
+    let mut idx = ArrayForSorting::new(Vec::<usize>::new());
+    let mut dst = ArrayForSorting::new(Vec::<i32>::new());
+    par_gather(&mut src, &mut idx, &mut dst, 1).unwrap();
+    par_scatter(&mut src, &mut idx, &mut dst, 1).unwrap();
+    assert!(dst.clone_to_vec().is_empty());
+}
+
+}
@@ -0,0 +1,460 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::{self, ArrayForSorting, MergeKernel},
+    merge_sorted::sorted,
+    natural_mergesort,
+    chunks::offsets_wf,
+};
+
+/// Segments up to this length are sorted by insertion, longer ones by mergesort.
+pub const SMALL_SEGMENT: usize = 32;
+
+/// Segment `s` of `vals`, which start at the cell `base`.
+pub open spec fn segment(vals: Seq<i32>, offsets: Seq<usize>, base: int, s: int) -> Seq<i32> {
+    vals.subrange(offsets[s] - base, offsets[s + 1] - base)
+}
+
+pub open spec fn segments_sorted(vals: Seq<i32>, offsets: Seq<usize>, base: int, s_lo: int, s_hi: int) -> bool {
+    forall |s: int| s_lo <= s < s_hi ==> sorted(#[trigger] segment(vals, offsets, base, s))
+}
+
+/// Every segment of `after` holds the elements of the same segment of `before`.
+pub open spec fn segments_permuted(before: Seq<i32>, after: Seq<i32>, offsets: Seq<usize>, base: int, s_lo: int, s_hi: int) -> bool {
+    forall |s: int| #![trigger segment(after, offsets, base, s)] s_lo <= s < s_hi ==>
+        segment(after, offsets, base, s).to_multiset() == segment(before, offsets, base, s).to_multiset()
+}
+
+/// The segments of `l` followed by the segments of `r` are the segments of `l + r`.
+proof fn lemma_segments_append(l: Seq<i32>, r: Seq<i32>, offsets: Seq<usize>, s_lo: int, s_mid: int, s_hi: int)
+    requires
+        forall |a: int, b: int| 0 <= a <= b < offsets.len() ==> offsets[a] <= offsets[b],
+        0 <= s_lo <= s_mid <= s_hi < offsets.len(),
+        l.len() == offsets[s_mid] - offsets[s_lo],
+        r.len() == offsets[s_hi] - offsets[s_mid],
+        segments_sorted(l, offsets, offsets[s_lo] as int, s_lo, s_mid),
+        segments_sorted(r, offsets, offsets[s_mid] as int, s_mid, s_hi),
+    ensures
+        segments_sorted(l + r, offsets, offsets[s_lo] as int, s_lo, s_hi),
+{
+    let base = offsets[s_lo] as int;
+    assert forall |s: int| s_lo <= s < s_hi implies sorted(#[trigger] segment(l + r, offsets, base, s)) by {
+        assert(offsets[s_lo] <= offsets[s] <= offsets[s + 1] <= offsets[s_hi]);
+        if s < s_mid {
+            assert(offsets[s + 1] <= offsets[s_mid]);
+            assert(segment(l + r, offsets, base, s) =~= segment(l, offsets, base, s));
+        } else {
+            assert(offsets[s_mid] <= offsets[s]);
+            assert(segment(l + r, offsets, base, s) =~= segment(r, offsets, offsets[s_mid] as int, s));
+        }
+    }
+}
+
+/// Like `lemma_segments_append`, for the elements of the segments.
+proof fn lemma_segments_permuted_append(
+    l0: Seq<i32>, r0: Seq<i32>,
+    l: Seq<i32>, r: Seq<i32>,
+    offsets: Seq<usize>, s_lo: int, s_mid: int, s_hi: int,
+)
+    requires
+        forall |a: int, b: int| 0 <= a <= b < offsets.len() ==> offsets[a] <= offsets[b],
+        0 <= s_lo <= s_mid <= s_hi < offsets.len(),
+        l0.len() == offsets[s_mid] - offsets[s_lo],
+        l.len() == offsets[s_mid] - offsets[s_lo],
+        r0.len() == offsets[s_hi] - offsets[s_mid],
+        r.len() == offsets[s_hi] - offsets[s_mid],
+        segments_permuted(l0, l, offsets, offsets[s_lo] as int, s_lo, s_mid),
+        segments_permuted(r0, r, offsets, offsets[s_mid] as int, s_mid, s_hi),
+    ensures
+        segments_permuted(l0 + r0, l + r, offsets, offsets[s_lo] as int, s_lo, s_hi),
+{
+    let base = offsets[s_lo] as int;
+    assert forall |s: int| s_lo <= s < s_hi implies
+        #[trigger] segment(l + r, offsets, base, s).to_multiset() == segment(l0 + r0, offsets, base, s).to_multiset() by {
+        assert(offsets[s_lo] <= offsets[s] <= offsets[s + 1] <= offsets[s_hi]);
+        if s < s_mid {
+            assert(offsets[s + 1] <= offsets[s_mid]);
+            assert(segment(l + r, offsets, base, s) =~= segment(l, offsets, base, s));
+            assert(segment(l0 + r0, offsets, base, s) =~= segment(l0, offsets, base, s));
+        } else {
+            assert(offsets[s_mid] <= offsets[s]);
+            assert(segment(l + r, offsets, base, s) =~= segment(r, offsets, offsets[s_mid] as int, s));
+            assert(segment(l0 + r0, offsets, base, s) =~= segment(r0, offsets, offsets[s_mid] as int, s));
+        }
+    }
+}
+
+/// Sorts every segment `offsets[s]..offsets[s + 1]` of `arr`, using `buf`, which must be as long
+/// as `arr`, as scratch space. Runs of consecutive segments with about the same number of elements
+/// are handed to separate threads while they hold more than `threshold` elements.
+/// Returns `Err` if `offsets` do not describe segments of `arr`.
+pub fn segmented_sort(
+    arr: &mut ArrayForSorting<i32>,
+    buf: &mut ArrayForSorting<i32>,
+    offsets: Vec<usize>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        old(buf).perms@.lo() == 0,
+        old(buf).perms@.hi() == old(buf).array.len(),
+        region_array::wf(*old(buf).array, (old(buf).perms@)),
+        old(arr).array.len() == old(buf).array.len(),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo(),
+        ret.is_ok() ==> arr.perms@.hi() == old(arr).perms@.hi(),
+        ret.is_ok() ==> region_array::wf(*buf.array, (buf.perms@)),
+        ret.is_ok() ==> buf.perms@.lo() == old(buf).perms@.lo(),
+        ret.is_ok() ==> buf.perms@.hi() == old(buf).perms@.hi(),
+        ret.is_ok() ==> offsets_wf(offsets@, old(arr).array.len() as int),
+        ret.is_ok() ==> segments_sorted(arr.perms@.values(), offsets@, 0, 0, offsets.len() - 1),
+        ret.is_ok() ==> segments_permuted(old(arr).perms@.values(), arr.perms@.values(), offsets@, 0, 0, offsets.len() - 1),
+{
+    let n = (&*arr.array).length();
+    if !check_offsets(&offsets, n) {
+        return Err(());
+    }
+    let s_hi = offsets.len() - 1;
+    _segmented_sort_parallel(
+        Arc::clone(&arr.array),
+        Tracked(arr.perms.borrow_mut()),
+        Arc::clone(&buf.array),
+        Tracked(buf.perms.borrow_mut()),
+        Arc::new(offsets),
+        0,
+        s_hi,
+        threshold,
+    )
+}
+
+fn check_offsets(offsets: &Vec<usize>, n: usize) -> (res: bool)
+    ensures
+        res ==> offsets_wf(offsets@, n as int),
+{
+    if offsets.len() == 0 || offsets[0] != 0 || offsets[offsets.len() - 1] != n {
+        return false;
+    }
+    let mut i = 1;
+    while i < offsets.len()
+        invariant
+            1 <= i <= offsets.len(),
+            forall |a: int, b: int| 0 <= a <= b < i ==> offsets@[a] <= offsets@[b],
+    {
+        if offsets[i - 1] > offsets[i] {
+            return false;
+        }
+        assert forall |a: int, b: int| 0 <= a <= b < i + 1 implies offsets@[a] <= offsets@[b] by {
+            if b == i && a < b {
+                assert(offsets@[a] <= offsets@[i - 1]);
+            }
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Returns the first segment `s_lo < s < s_hi` that starts at or after the middle element
+/// of `offsets[s_lo]..offsets[s_hi]`, or `s_hi - 1` if there is none.
+fn middle_segment(offsets: &Vec<usize>, s_lo: usize, s_hi: usize) -> (res: usize)
+    requires
+        s_lo + 2 <= s_hi < offsets.len(),
+        forall |a: int, b: int| 0 <= a <= b < offsets.len() ==> offsets@[a] <= offsets@[b],
+    ensures
+        s_lo < res < s_hi,
+{
+    let target = offsets[s_lo] + (offsets[s_hi] - offsets[s_lo]) / 2;
+    let mut l = s_lo + 1;
+    let mut h = s_hi - 1;
+    while l < h
+        invariant
+            s_lo < l <= h < s_hi,
+    {
+        let m = l + (h - l) / 2;
+        if offsets[m] < target {
+            l = m + 1;
+        } else {
+            h = m;
+        }
+    }
+    l
+}
+
+fn _segmented_sort_parallel(
+    arr: Arc<Array<i32>>,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    buf_arr: Arc<Array<i32>>,
+    Tracked(buf_perms): Tracked<&mut Region<i32>>,
+    offsets: Arc<Vec<usize>>,
+    s_lo: usize, s_hi: usize,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        s_lo <= s_hi < (*offsets).len(),
+        forall |a: int, b: int| 0 <= a <= b < (*offsets).len() ==> (*offsets)@[a] <= (*offsets)@[b],
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == (*offsets)@[s_lo as int],
+        old(perms).hi() == (*offsets)@[s_hi as int],
+        region_array::wf(*buf_arr, *old(buf_perms)),
+        old(buf_perms).lo() == (*offsets)@[s_lo as int],
+        old(buf_perms).hi() == (*offsets)@[s_hi as int],
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, *perms),
+        ret.is_ok() ==> perms.lo() == old(perms).lo() && perms.hi() == old(perms).hi(),
+        ret.is_ok() ==> region_array::wf(*buf_arr, *buf_perms),
+        ret.is_ok() ==> buf_perms.lo() == old(buf_perms).lo() && buf_perms.hi() == old(buf_perms).hi(),
+        ret.is_ok() ==> segments_sorted(perms.values(), (*offsets)@, (*offsets)@[s_lo as int] as int, s_lo as int, s_hi as int),
+        ret.is_ok() ==> segments_permuted(old(perms).values(), perms.values(), (*offsets)@, (*offsets)@[s_lo as int] as int, s_lo as int, s_hi as int),
+{
+    let lo = (*offsets)[s_lo];
+    let hi = (*offsets)[s_hi];
+    if hi - lo <= threshold || s_hi - s_lo < 2 {
+        sort_segments(&*arr, Tracked(perms), &*buf_arr, Tracked(buf_perms), &*offsets, s_lo, s_hi);
+        return Ok(());
+    }
+
+    let ghost vals = perms.values();
+    let s_mid = middle_segment(&*offsets, s_lo, s_hi);
+    let mid = (*offsets)[s_mid];
+    let tracked left_perms = region_array::split_front(&*arr, mid, perms);
+    let tracked mut right_perms = region_array::split_front(&*arr, hi, perms);
+    let tracked left_buf = region_array::split_front(&*buf_arr, mid, buf_perms);
+    let tracked mut right_buf = region_array::split_front(&*buf_arr, hi, buf_perms);
+    let ghost left_vals = left_perms.values();
+    let ghost right_vals = right_perms.values();
+    proof {
+        region_array::lemma_values_len(&*arr, left_perms);
+        region_array::lemma_values_len(&*arr, right_perms);
+    }
+
+    let arr_r1 = Arc::clone(&arr);
+    let buf_arr_r1 = Arc::clone(&buf_arr);
+    let offsets_r1 = Arc::clone(&offsets);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<i32>>, Tracked<Region<i32>>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().0@) && ret.unwrap().0@.lo() == lo && ret.unwrap().0@.hi() == mid,
+            ret.is_ok() ==> region_array::wf(*buf_arr, ret.unwrap().1@) && ret.unwrap().1@.lo() == lo && ret.unwrap().1@.hi() == mid,
+            ret.is_ok() ==> segments_sorted(ret.unwrap().0@.values(), (*offsets)@, lo as int, s_lo as int, s_mid as int),
+            ret.is_ok() ==> segments_permuted(left_vals, ret.unwrap().0@.values(), (*offsets)@, lo as int, s_lo as int, s_mid as int),
+        {
+            let tracked mut left_perms = left_perms;
+            let tracked mut left_buf = left_buf;
+            match _segmented_sort_parallel(arr_r1, Tracked(&mut left_perms), buf_arr_r1, Tracked(&mut left_buf), offsets_r1, s_lo, s_mid, threshold) {
+                Ok(()) => Ok((Tracked(left_perms), Tracked(left_buf))),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match _segmented_sort_parallel(
+        Arc::clone(&arr), Tracked(&mut right_perms),
+        Arc::clone(&buf_arr), Tracked(&mut right_buf),
+        Arc::clone(&offsets), s_mid, s_hi, threshold,
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut left_perms), Tracked(mut left_buf)) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::lemma_values_len(&*arr, left_perms);
+        region_array::lemma_values_len(&*arr, right_perms);
+        lemma_segments_append(left_perms.values(), right_perms.values(), (*offsets)@, s_lo as int, s_mid as int, s_hi as int);
+        assert(left_vals + right_vals =~= vals);
+        lemma_segments_permuted_append(
+            left_vals, right_vals, left_perms.values(), right_perms.values(),
+            (*offsets)@, s_lo as int, s_mid as int, s_hi as int,
+        );
+        region_array::merge(&*arr, &mut left_perms, right_perms);
+        vstd::modes::tracked_swap(perms, &mut left_perms);
+        region_array::merge(&*buf_arr, &mut left_buf, right_buf);
+        vstd::modes::tracked_swap(buf_perms, &mut left_buf);
+    }
+    Ok(())
+}
+
+/// Sorts the segments `s_lo..s_hi` one after another, each in a region of its own.
+fn sort_segments(
+    arr: &Array<i32>,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    buf_arr: &Array<i32>,
+    Tracked(buf_perms): Tracked<&mut Region<i32>>,
+    offsets: &Vec<usize>,
+    s_lo: usize, s_hi: usize,
+)
+    requires
+        s_lo <= s_hi < offsets.len(),
+        forall |a: int, b: int| 0 <= a <= b < offsets.len() ==> offsets@[a] <= offsets@[b],
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == offsets@[s_lo as int],
+        old(perms).hi() == offsets@[s_hi as int],
+        region_array::wf(*buf_arr, *old(buf_perms)),
+        old(buf_perms).lo() == offsets@[s_lo as int],
+        old(buf_perms).hi() == offsets@[s_hi as int],
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == old(perms).lo() && perms.hi() == old(perms).hi(),
+        region_array::wf(*buf_arr, *buf_perms),
+        buf_perms.lo() == old(buf_perms).lo() && buf_perms.hi() == old(buf_perms).hi(),
+        segments_sorted(perms.values(), offsets@, offsets@[s_lo as int] as int, s_lo as int, s_hi as int),
+        segments_permuted(old(perms).values(), perms.values(), offsets@, offsets@[s_lo as int] as int, s_lo as int, s_hi as int),
+{
+    let lo = offsets[s_lo];
+    let hi = offsets[s_hi];
+    let ghost vals = perms.values();
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+    }
+    // `done` holds the sorted segments `s_lo..s`, `perms` the rest, and the buffer is split alike
+    let tracked mut done = region_array::split_front(arr, lo, perms);
+    let tracked mut buf_done = region_array::split_front(buf_arr, lo, buf_perms);
+    proof {
+        region_array::lemma_values_len(arr, done);
+    }
+
+    let mut s = s_lo;
+    while s < s_hi
+        invariant
+            s_lo <= s <= s_hi < offsets.len(),
+            forall |a: int, b: int| 0 <= a <= b < offsets.len() ==> offsets@[a] <= offsets@[b],
+            lo == offsets@[s_lo as int],
+            hi == offsets@[s_hi as int],
+            vals.len() == hi - lo,
+            region_array::wf(*arr, done),
+            done.lo() == lo,
+            done.hi() == offsets@[s as int],
+            region_array::wf(*arr, *perms),
+            perms.lo() == offsets@[s as int],
+            perms.hi() == hi,
+            perms.values() == vals.subrange(offsets@[s as int] - lo, hi - lo),
+            region_array::wf(*buf_arr, buf_done),
+            buf_done.lo() == lo,
+            buf_done.hi() == offsets@[s as int],
+            region_array::wf(*buf_arr, *buf_perms),
+            buf_perms.lo() == offsets@[s as int],
+            buf_perms.hi() == hi,
+            segments_sorted(done.values(), offsets@, lo as int, s_lo as int, s as int),
+            segments_permuted(vals.subrange(0, offsets@[s as int] - lo), done.values(), offsets@, lo as int, s_lo as int, s as int),
+    {
+        let seg_lo = offsets[s];
+        let seg_hi = offsets[s + 1];
+        assert(seg_lo <= seg_hi <= hi);
+        let tracked mut seg = region_array::split_front(arr, seg_hi, perms);
+        let tracked mut buf_seg = region_array::split_front(buf_arr, seg_hi, buf_perms);
+        let ghost seg_vals = seg.values();
+        sort_segment(arr, Tracked(&mut seg), buf_arr, Tracked(&mut buf_seg), seg_lo, seg_hi);
+        proof {
+            region_array::lemma_values_len(arr, done);
+            region_array::lemma_values_len(arr, seg);
+            assert(segment(seg.values(), offsets@, seg_lo as int, s as int) =~= seg.values());
+            assert(seg_vals =~= vals.subrange(seg_lo - lo, seg_hi - lo));
+            assert(segment(seg_vals, offsets@, seg_lo as int, s as int) =~= seg_vals);
+            lemma_segments_append(done.values(), seg.values(), offsets@, s_lo as int, s as int, s + 1);
+            assert(vals.subrange(0, seg_lo - lo) + seg_vals =~= vals.subrange(0, seg_hi - lo));
+            lemma_segments_permuted_append(
+                vals.subrange(0, seg_lo - lo), seg_vals, done.values(), seg.values(),
+                offsets@, s_lo as int, s as int, s + 1,
+            );
+            region_array::merge(arr, &mut done, seg);
+            region_array::merge(buf_arr, &mut buf_done, buf_seg);
+            assert(perms.values() =~= vals.subrange(seg_hi - lo, hi - lo));
+        }
+        s += 1;
+    }
+
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+        vstd::modes::tracked_swap(perms, &mut done);
+        assert(perms.values() + done.values() =~= perms.values());
+        region_array::merge(arr, perms, done);
+        vstd::modes::tracked_swap(buf_perms, &mut buf_done);
+        region_array::merge(buf_arr, buf_perms, buf_done);
+        assert(vals.subrange(0, hi - lo) =~= vals);
+    }
+}
+
+/// Sorts the region `lo..hi`, using the same cells of `buf_arr` as scratch space.
+fn sort_segment(
+    arr: &Array<i32>,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    buf_arr: &Array<i32>,
+    Tracked(buf_perms): Tracked<&mut Region<i32>>,
+    lo: usize, hi: usize,
+)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        region_array::wf(*buf_arr, *old(buf_perms)),
+        old(buf_perms).lo() == lo,
+        old(buf_perms).hi() == hi,
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        region_array::wf(*buf_arr, *buf_perms),
+        buf_perms.lo() == lo,
+        buf_perms.hi() == hi,
+        sorted(perms.values()),
+        perms.values().to_multiset() == old(perms).values().to_multiset(),
+{
+    if hi - lo <= SMALL_SEGMENT {
+        natural_mergesort::insertion_sort(arr, Tracked(perms), lo, hi);
+        return;
+    }
+    proof {
+        region_array::lemma_values_len(arr, *perms);
+    }
+    mergesort::_merge_sort(arr, lo, hi, Tracked(perms), buf_arr, lo, Tracked(buf_perms), MergeKernel::Branching);
+    proof {
+        mergesort::lemma_sorts_range_whole(old(perms).values(), perms.values());
+    }
+}
+
+#[test]
+fn test_segmented_sort() {
+    use crate::test_data::{scattered, thresholds};
+
+    let data: Vec<i32> = scattered(2000, 211, -100);
+    let mut offsets = vec![0];
+    let mut len = 0;
+    while offsets[offsets.len() - 1] < data.len() {
+        let next = std::cmp::min(offsets[offsets.len() - 1] + len, data.len());
+        offsets.push(next);
+        len = (len * 7 + 3) % 100;
+    }
+    let mut expected = data.clone();
+    for s in 0..offsets.len() - 1 {
+        expected[offsets[s]..offsets[s + 1]].sort();
+    }
+
+    for threshold in thresholds(data.len()) {
+        let mut arr = ArrayForSorting::new(data.clone());
+        let mut buf = ArrayForSorting::new(vec![0; data.len()]);
+        segmented_sort(&mut arr, &mut buf, offsets.clone(), threshold).unwrap();
+        assert_eq!(arr.clone_to_vec(), expected);
+    }
+
+    let mut arr = ArrayForSorting::new(data.clone());
+    let mut buf = ArrayForSorting::new(vec![0; data.len()]);
+    assert!(segmented_sort(&mut arr, &mut buf, vec![0, 10, 5, data.len()], 16).is_err());
+}
+
+}
@@ -0,0 +1,773 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use vstd::seq_lib::lemma_multiset_commutative;
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::ArrayForSorting,
+};
+
+/// Nothing before `k` is greater than `vals[k]` and nothing after it is smaller,
+/// i.e. `vals[k]` is where it would be if `vals` were sorted.
+pub open spec fn is_nth(vals: Seq<i32>, k: int) -> bool {
+    &&& 0 <= k < vals.len()
+    &&& forall |i: int| 0 <= i < k ==> vals[i] <= vals[k]
+    &&& forall |i: int| k < i < vals.len() ==> vals[k] <= vals[i]
+}
+
+pub open spec fn bounded(vals: Seq<i32>, low: int, high: int) -> bool {
+    forall |i: int| 0 <= i < vals.len() ==> low <= vals[i] <= high
+}
+
+pub open spec fn goes_left(x: i32, pivot: i32, inclusive: bool) -> bool {
+    if inclusive { x <= pivot } else { x < pivot }
+}
+
+pub open spec fn all_go(vals: Seq<i32>, pivot: i32, inclusive: bool, left: bool) -> bool {
+    forall |i: int| 0 <= i < vals.len() ==> goes_left(vals[i], pivot, inclusive) == left
+}
+
+/// `vals[..p]` goes left of `pivot` and `vals[p..]` does not.
+pub open spec fn partitioned(vals: Seq<i32>, p: int, pivot: i32, inclusive: bool) -> bool {
+    &&& 0 <= p <= vals.len()
+    &&& forall |i: int| 0 <= i < p ==> goes_left(vals[i], pivot, inclusive)
+    &&& forall |i: int| p <= i < vals.len() ==> !goes_left(vals[i], pivot, inclusive)
+}
+
+proof fn lemma_bounded_add(a: Seq<i32>, b: Seq<i32>, low: int, high: int)
+    requires
+        bounded(a, low, high),
+        bounded(b, low, high),
+    ensures
+        bounded(a + b, low, high),
+{
+    assert forall |i: int| 0 <= i < (a + b).len() implies low <= #[trigger] (a + b)[i] <= high by {
+        if i >= a.len() {
+            assert((a + b)[i] == b[i - a.len()]);
+        }
+    }
+}
+
+proof fn lemma_all_go_add(a: Seq<i32>, b: Seq<i32>, pivot: i32, inclusive: bool, left: bool)
+    requires
+        all_go(a, pivot, inclusive, left),
+        all_go(b, pivot, inclusive, left),
+    ensures
+        all_go(a + b, pivot, inclusive, left),
+{
+    assert forall |i: int| 0 <= i < (a + b).len() implies goes_left(#[trigger] (a + b)[i], pivot, inclusive) == left by {
+        if i >= a.len() {
+            assert((a + b)[i] == b[i - a.len()]);
+        }
+    }
+}
+
+proof fn lemma_partitioned_add(a: Seq<i32>, b: Seq<i32>, pivot: i32, inclusive: bool)
+    requires
+        all_go(a, pivot, inclusive, true),
+        all_go(b, pivot, inclusive, false),
+    ensures
+        partitioned(a + b, a.len() as int, pivot, inclusive),
+{
+    assert forall |i: int| a.len() <= i < (a + b).len() implies !goes_left(#[trigger] (a + b)[i], pivot, inclusive) by {
+        assert((a + b)[i] == b[i - a.len()]);
+    }
+}
+
+/// The pieces left behind by the block exchange of `_partition_parallel`:
+/// of the two pieces in the middle at most one is not empty.
+proof fn lemma_partitioned_pieces(
+    s0: Seq<i32>, s1: Seq<i32>, s2: Seq<i32>, s3: Seq<i32>, s4: Seq<i32>, s5: Seq<i32>,
+    pivot: i32, inclusive: bool,
+)
+    requires
+        all_go(s0, pivot, inclusive, true),
+        all_go(s1, pivot, inclusive, true),
+        all_go(s2, pivot, inclusive, false),
+        all_go(s3, pivot, inclusive, true),
+        all_go(s4, pivot, inclusive, false),
+        all_go(s5, pivot, inclusive, false),
+        s2.len() == 0 || s3.len() == 0,
+    ensures
+        partitioned(s0 + s1 + s2 + s3 + s4 + s5, s0.len() + s1.len() + s3.len(), pivot, inclusive),
+{
+    let t = s0 + s1 + s2 + s3 + s4 + s5;
+    lemma_all_go_add(s0, s1, pivot, inclusive, true);
+    if s2.len() == 0 {
+        lemma_all_go_add(s0 + s1, s3, pivot, inclusive, true);
+        lemma_all_go_add(s4, s5, pivot, inclusive, false);
+        lemma_partitioned_add(s0 + s1 + s3, s4 + s5, pivot, inclusive);
+        assert(t =~= (s0 + s1 + s3) + (s4 + s5));
+    } else {
+        lemma_all_go_add(s2, s4, pivot, inclusive, false);
+        lemma_all_go_add(s2 + s4, s5, pivot, inclusive, false);
+        lemma_partitioned_add(s0 + s1, s2 + s4 + s5, pivot, inclusive);
+        assert(t =~= (s0 + s1) + (s2 + s4 + s5));
+    }
+}
+
+/// Exchanging the pieces `s1` and `s4` of `s0 + s1 + s2 + s3 + s4 + s5` keeps its elements.
+proof fn lemma_multiset_exchange(
+    s0: Seq<i32>, s1: Seq<i32>, s2: Seq<i32>, s3: Seq<i32>, s4: Seq<i32>, s5: Seq<i32>,
+)
+    ensures
+        (s0 + s4 + s2 + s3 + s1 + s5).to_multiset() == (s0 + s1 + s2).to_multiset().add((s3 + s4 + s5).to_multiset()),
+{
+    lemma_multiset_commutative(s0, s1);
+    lemma_multiset_commutative(s0 + s1, s2);
+    lemma_multiset_commutative(s3, s4);
+    lemma_multiset_commutative(s3 + s4, s5);
+    lemma_multiset_commutative(s0, s4);
+    lemma_multiset_commutative(s0 + s4, s2);
+    lemma_multiset_commutative(s0 + s4 + s2, s3);
+    lemma_multiset_commutative(s0 + s4 + s2 + s3, s1);
+    lemma_multiset_commutative(s0 + s4 + s2 + s3 + s1, s5);
+    assert((s0 + s4 + s2 + s3 + s1 + s5).to_multiset() =~= (s0 + s1 + s2).to_multiset().add((s3 + s4 + s5).to_multiset()));
+}
+
+/// Putting the pieces of `s` back together after rearranging each of them keeps the elements of `s`.
+proof fn lemma_multiset_pieces(s: Seq<i32>, l: Seq<i32>, m: Seq<i32>, r: Seq<i32>, l2: Seq<i32>, m2: Seq<i32>, r2: Seq<i32>)
+    requires
+        s.to_multiset() == l.to_multiset().add((m + r).to_multiset()),
+        l2.to_multiset() == l.to_multiset(),
+        m2.to_multiset() == m.to_multiset(),
+        r2.to_multiset() == r.to_multiset(),
+    ensures
+        (l2 + m2 + r2).to_multiset() == s.to_multiset(),
+{
+    lemma_multiset_commutative(m, r);
+    lemma_multiset_commutative(l2, m2);
+    lemma_multiset_commutative(l2 + m2, r2);
+    assert((l2 + m2 + r2).to_multiset() =~= s.to_multiset());
+}
+
+/// `l` holds what is `< pivot`, `m` what is `== pivot` and `r` what is `> pivot`.
+proof fn lemma_nth_pieces(l: Seq<i32>, m: Seq<i32>, r: Seq<i32>, pivot: i32, k: int)
+    requires
+        forall |i: int| 0 <= i < l.len() ==> l[i] < pivot,
+        forall |i: int| 0 <= i < m.len() ==> m[i] == pivot,
+        forall |i: int| 0 <= i < r.len() ==> r[i] > pivot,
+        0 <= k < l.len() + m.len() + r.len(),
+        k < l.len() ==> is_nth(l, k),
+        l.len() + m.len() <= k ==> is_nth(r, k - l.len() - m.len()),
+    ensures
+        is_nth(l + m + r, k),
+{
+    let t = l + m + r;
+    let lm = l.len() + m.len();
+    assert forall |i: int| 0 <= i < t.len() implies
+        #[trigger] t[i] == if i < l.len() { l[i] } else if i < lm { m[i - l.len()] } else { r[i - lm] } by {}
+    assert forall |i: int| 0 <= i < k implies t[i] <= t[k] by {
+        assert(t[i] == if i < l.len() { l[i] } else if i < lm { m[i - l.len()] } else { r[i - lm] });
+        assert(t[k] == if k < l.len() { l[k] } else if k < lm { m[k - l.len()] } else { r[k - lm] });
+    }
+    assert forall |i: int| k < i < t.len() implies t[k] <= t[i] by {
+        assert(t[i] == if i < l.len() { l[i] } else if i < lm { m[i - l.len()] } else { r[i - lm] });
+        assert(t[k] == if k < l.len() { l[k] } else if k < lm { m[k - l.len()] } else { r[k - lm] });
+    }
+}
+
+/// Moves the element that belongs at position `k` there, everything not greater before it
+/// and everything not smaller after it.
+pub fn select_nth(arr: &mut ArrayForSorting<i32>, k: usize)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        k < old(arr).array.len(),
+    ensures
+        region_array::wf(*arr.array, (arr.perms@)),
+        arr.perms@.lo() == old(arr).perms@.lo(),
+        arr.perms@.hi() == old(arr).perms@.hi(),
+        is_nth(arr.perms@.values(), k as int),
+        arr.perms@.values().to_multiset() == old(arr).perms@.values().to_multiset(),
+{
+    _select(&arr.array, 0, (&*arr.array).length(), Tracked(arr.perms.borrow_mut()), k, Ghost(i32::MIN as int), Ghost(i32::MAX as int))
+}
+
+pub fn select_nth_parallel(
+    arr: &mut ArrayForSorting<i32>,
+    k: usize,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        k < old(arr).array.len(),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo(),
+        ret.is_ok() ==> arr.perms@.hi() == old(arr).perms@.hi(),
+        ret.is_ok() ==> is_nth(arr.perms@.values(), k as int),
+        ret.is_ok() ==> arr.perms@.values().to_multiset() == old(arr).perms@.values().to_multiset(),
+{
+    _select_parallel(
+        Arc::clone(&arr.array),
+        0,
+        (&*arr.array).length(),
+        Tracked(arr.perms.borrow_mut()),
+        k,
+        Ghost(i32::MIN as int),
+        Ghost(i32::MAX as int),
+        threshold,
+    )
+}
+
+/// Moves the `k` largest elements, in no particular order, to the end of `arr`.
+pub fn top_k(arr: &mut ArrayForSorting<i32>, k: usize, threshold: usize) -> (ret: Result<(), ()>)
+    requires
+        old(arr).perms@.lo() == 0,
+        old(arr).perms@.hi() == old(arr).array.len(),
+        region_array::wf(*old(arr).array, (old(arr).perms@)),
+        k <= old(arr).array.len(),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr.array, (arr.perms@)),
+        ret.is_ok() ==> arr.perms@.lo() == old(arr).perms@.lo(),
+        ret.is_ok() ==> arr.perms@.hi() == old(arr).perms@.hi(),
+        ret.is_ok() ==> forall |i: int, j: int|
+            0 <= i < old(arr).array.len() - k <= j < old(arr).array.len() ==> arr.perms@.values()[i] <= arr.perms@.values()[j],
+        ret.is_ok() ==> arr.perms@.values().to_multiset() == old(arr).perms@.values().to_multiset(),
+{
+    let n = (&*arr.array).length();
+    if k == 0 || k == n {
+        return Ok(());
+    }
+    let ret = select_nth_parallel(arr, n - k, threshold);
+    assert(ret.is_ok() ==> forall |i: int, j: int| 0 <= i < n - k <= j < n ==>
+        arr.perms@.values()[i] <= arr.perms@.values()[n - k] <= arr.perms@.values()[j]);
+    ret
+}
+
+/// Partitions `lo..hi` into what goes left of `pivot` and what does not,
+/// returning where the second part starts.
+pub(crate) fn partition(
+    arr: &Array<i32>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    pivot: i32,
+    inclusive: bool,
+    Ghost(low): Ghost<int>,
+    Ghost(high): Ghost<int>,
+) -> (p: usize)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        bounded(old(perms).values(), low, high),
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        lo <= p <= hi,
+        bounded(perms.values(), low, high),
+        partitioned(perms.values(), p - lo, pivot, inclusive),
+        perms.values().to_multiset() == old(perms).values().to_multiset(),
+{
+    proof { region_array::lemma_values_len(arr, *perms); }
+    let mut store = lo;
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*arr, *perms),
+            perms.lo() == lo,
+            perms.hi() == hi,
+            perms.values().len() == hi - lo,
+            lo <= store <= i <= hi,
+            bounded(perms.values(), low, high),
+            perms.values().to_multiset() == old(perms).values().to_multiset(),
+            forall |t: int| 0 <= t < store - lo ==> goes_left(perms.values()[t], pivot, inclusive),
+            forall |t: int| store - lo <= t < i - lo ==> !goes_left(perms.values()[t], pivot, inclusive),
+    {
+        let x = *region_array::read(arr, i, Tracked(perms));
+        let left = if inclusive { x <= pivot } else { x < pivot };
+        if left {
+            let ghost vals = perms.values();
+            region_array::swap(arr, i, store, Tracked(perms));
+            proof {
+                region_array::lemma_swap_multiset(vals, i - lo, store - lo);
+            }
+            assert(goes_left(perms.values()[store - lo], pivot, inclusive));
+            assert(store < i ==> perms.values()[i - lo] == vals[store - lo]);
+            store += 1;
+        }
+        i += 1;
+    }
+    store
+}
+
+/// Exchanges the contents of two regions of the same length.
+fn _swap_ranges_parallel(
+    arr: Arc<Array<i32>>,
+    a_lo: usize, b_lo: usize, len: usize,
+    Tracked(a_perms): Tracked<&mut Region<i32>>,
+    Tracked(b_perms): Tracked<&mut Region<i32>>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        region_array::wf(*arr, *old(a_perms)),
+        old(a_perms).lo() == a_lo,
+        old(a_perms).hi() == a_lo + len,
+        region_array::wf(*arr, *old(b_perms)),
+        old(b_perms).lo() == b_lo,
+        old(b_perms).hi() == b_lo + len,
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, *a_perms) && a_perms.lo() == a_lo && a_perms.hi() == a_lo + len,
+        ret.is_ok() ==> region_array::wf(*arr, *b_perms) && b_perms.lo() == b_lo && b_perms.hi() == b_lo + len,
+        ret.is_ok() ==> a_perms.values() == old(b_perms).values(),
+        ret.is_ok() ==> b_perms.values() == old(a_perms).values(),
+{
+    let ghost a_vals = a_perms.values();
+    let ghost b_vals = b_perms.values();
+    proof {
+        region_array::lemma_values_len(&*arr, *a_perms);
+        region_array::lemma_values_len(&*arr, *b_perms);
+    }
+
+    if len <= threshold || len < 2 {
+        let mut t = 0;
+        while t < len
+            invariant
+                region_array::wf(*arr, *a_perms),
+                a_perms.lo() == a_lo,
+                a_perms.hi() == a_lo + len,
+                region_array::wf(*arr, *b_perms),
+                b_perms.lo() == b_lo,
+                b_perms.hi() == b_lo + len,
+                a_perms.values().len() == len,
+                b_perms.values().len() == len,
+                a_vals.len() == len,
+                b_vals.len() == len,
+                0 <= t <= len,
+                forall |u: int| 0 <= u < t ==> a_perms.values()[u] == b_vals[u] && b_perms.values()[u] == a_vals[u],
+                forall |u: int| t <= u < len ==> a_perms.values()[u] == a_vals[u] && b_perms.values()[u] == b_vals[u],
+        {
+            let x = *region_array::read(&*arr, a_lo + t, Tracked(a_perms));
+            let y = region_array::replace(&*arr, b_lo + t, x, Tracked(b_perms));
+            region_array::replace(&*arr, a_lo + t, y, Tracked(a_perms));
+            t += 1;
+        }
+        assert(a_perms.values() =~= b_vals);
+        assert(b_perms.values() =~= a_vals);
+        return Ok(());
+    }
+
+    let half = len / 2;
+    let tracked a_left = region_array::split_front(&*arr, a_lo + half, a_perms);
+    let tracked mut a_right = region_array::split_front(&*arr, a_lo + len, a_perms);
+    let tracked b_left = region_array::split_front(&*arr, b_lo + half, b_perms);
+    let tracked mut b_right = region_array::split_front(&*arr, b_lo + len, b_perms);
+
+    let ghost a_left_vals = a_left.values();
+    let ghost b_left_vals = b_left.values();
+    proof {
+        assert(a_right.values() =~= a_vals.subrange(half as int, len as int));
+        assert(b_right.values() =~= b_vals.subrange(half as int, len as int));
+    }
+
+    let arr_r1 = Arc::clone(&arr);
+    let arr_r2 = Arc::clone(&arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<i32>>, Tracked<Region<i32>>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().0@) && ret.unwrap().0@.lo() == a_lo && ret.unwrap().0@.hi() == a_lo + half,
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().1@) && ret.unwrap().1@.lo() == b_lo && ret.unwrap().1@.hi() == b_lo + half,
+            ret.is_ok() ==> ret.unwrap().0@.values() == b_left_vals,
+            ret.is_ok() ==> ret.unwrap().1@.values() == a_left_vals,
+        {
+            let tracked mut a_left = a_left;
+            let tracked mut b_left = b_left;
+            let t = _swap_ranges_parallel(arr_r1, a_lo, b_lo, half, Tracked(&mut a_left), Tracked(&mut b_left), threshold);
+            if t.is_err() {
+                Err(())
+            } else {
+                Ok((Tracked(a_left), Tracked(b_left)))
+            }
+        }
+    );
+
+    match _swap_ranges_parallel(arr_r2, a_lo + half, b_lo + half, len - half, Tracked(&mut a_right), Tracked(&mut b_right), threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut a_left), Tracked(mut b_left)) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::merge(&*arr, &mut a_left, a_right);
+        vstd::modes::tracked_swap(a_perms, &mut a_left);
+        region_array::merge(&*arr, &mut b_left, b_right);
+        vstd::modes::tracked_swap(b_perms, &mut b_left);
+        assert(a_perms.values() =~= b_vals);
+        assert(b_perms.values() =~= a_vals);
+    }
+    Ok(())
+}
+
+/// Same as `partition`, but partitions both halves in parallel and then exchanges
+/// the shorter of the two misplaced blocks with the far end of the other one.
+fn _partition_parallel(
+    arr: Arc<Array<i32>>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    pivot: i32,
+    inclusive: bool,
+    Ghost(low): Ghost<int>,
+    Ghost(high): Ghost<int>,
+    threshold: usize,
+) -> (ret: Result<usize, ()>)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        bounded(old(perms).values(), low, high),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, *perms) && perms.lo() == lo && perms.hi() == hi,
+        ret.is_ok() ==> lo <= ret.unwrap() <= hi,
+        ret.is_ok() ==> bounded(perms.values(), low, high),
+        ret.is_ok() ==> partitioned(perms.values(), ret.unwrap() - lo, pivot, inclusive),
+        ret.is_ok() ==> perms.values().to_multiset() == old(perms).values().to_multiset(),
+{
+    let ghost vals = perms.values();
+    proof { region_array::lemma_values_len(&*arr, *perms); }
+
+    if hi - lo <= threshold || hi - lo < 2 {
+        return Ok(partition(&*arr, lo, hi, Tracked(perms), pivot, inclusive, Ghost(low), Ghost(high)));
+    }
+
+    let mid = lo + (hi - lo) / 2;
+    let tracked left_perms = region_array::split_front(&*arr, mid, perms);
+    let tracked mut right_perms = region_array::split_front(&*arr, hi, perms);
+    let ghost left_orig = left_perms.values();
+    let ghost right_orig = right_perms.values();
+    proof {
+        assert(right_perms.values() =~= vals.subrange(mid - lo, hi - lo));
+        assert(left_orig + right_orig =~= vals);
+        lemma_multiset_commutative(left_orig, right_orig);
+        assert(bounded(left_perms.values(), low, high));
+        assert(bounded(right_perms.values(), low, high));
+    }
+
+    let arr_r1 = Arc::clone(&arr);
+    let arr_r2 = Arc::clone(&arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<(usize, Tracked<Region<i32>>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*arr, ret.unwrap().1@) && ret.unwrap().1@.lo() == lo && ret.unwrap().1@.hi() == mid,
+            ret.is_ok() ==> lo <= ret.unwrap().0 <= mid,
+            ret.is_ok() ==> bounded(ret.unwrap().1@.values(), low, high),
+            ret.is_ok() ==> partitioned(ret.unwrap().1@.values(), ret.unwrap().0 - lo, pivot, inclusive),
+            ret.is_ok() ==> ret.unwrap().1@.values().to_multiset() == left_orig.to_multiset(),
+        {
+            let tracked mut left_perms = left_perms;
+            match _partition_parallel(arr_r1, lo, mid, Tracked(&mut left_perms), pivot, inclusive, Ghost(low), Ghost(high), threshold) {
+                Ok(pl) => Ok((pl, Tracked(left_perms))),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    let pr = match _partition_parallel(arr_r2, mid, hi, Tracked(&mut right_perms), pivot, inclusive, Ghost(low), Ghost(high), threshold) {
+        Ok(pr) => pr,
+        Err(_) => {return Err(());},
+    };
+
+    let (pl, Tracked(mut left_perms)) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    // `pl..mid` does not go left and `mid..pr` does
+    let m = if mid - pl < pr - mid { mid - pl } else { pr - mid };
+
+    let ghost l_vals = left_perms.values();
+    let ghost r_vals = right_perms.values();
+    proof {
+        region_array::lemma_values_len(&*arr, left_perms);
+        region_array::lemma_values_len(&*arr, right_perms);
+    }
+    let tracked mut l0 = region_array::split_front(&*arr, pl, &mut left_perms);
+    let tracked mut ra = region_array::split_front(&*arr, pl + m, &mut left_perms);
+    let tracked r0 = region_array::split_front(&*arr, pr - m, &mut right_perms);
+    let tracked mut rb = region_array::split_front(&*arr, pr, &mut right_perms);
+
+    let ghost l0_vals = l0.values();
+    let ghost ra_vals = ra.values();
+    let ghost l2_vals = left_perms.values();
+    let ghost r0_vals = r0.values();
+    let ghost rb_vals = rb.values();
+    let ghost r3_vals = right_perms.values();
+    proof {
+        assert(l0_vals =~= l_vals.subrange(0, pl - lo));
+        assert(ra_vals =~= l_vals.subrange(pl - lo, pl + m - lo));
+        assert(l2_vals =~= l_vals.subrange(pl + m - lo, mid - lo));
+        assert(r0_vals =~= r_vals.subrange(0, pr - m - mid));
+        assert(rb_vals =~= r_vals.subrange(pr - m - mid, pr - mid));
+        assert(r3_vals =~= r_vals.subrange(pr - mid, hi - mid));
+        assert(l0_vals + ra_vals + l2_vals =~= l_vals);
+        assert(r0_vals + rb_vals + r3_vals =~= r_vals);
+    }
+
+    match _swap_ranges_parallel(Arc::clone(&arr), pl, pr - m, m, Tracked(&mut ra), Tracked(&mut rb), threshold) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    proof {
+        assert(all_go(l0_vals, pivot, inclusive, true));
+        assert(all_go(rb_vals, pivot, inclusive, true));
+        assert(all_go(l2_vals, pivot, inclusive, false));
+        assert(all_go(r0_vals, pivot, inclusive, true));
+        assert(all_go(ra_vals, pivot, inclusive, false));
+        assert(all_go(r3_vals, pivot, inclusive, false));
+        lemma_partitioned_pieces(l0_vals, rb_vals, l2_vals, r0_vals, ra_vals, r3_vals, pivot, inclusive);
+        lemma_multiset_exchange(l0_vals, ra_vals, l2_vals, r0_vals, rb_vals, r3_vals);
+
+        assert(bounded(l0_vals, low, high));
+        assert(bounded(rb_vals, low, high));
+        assert(bounded(l2_vals, low, high));
+        assert(bounded(r0_vals, low, high));
+        assert(bounded(ra_vals, low, high));
+        assert(bounded(r3_vals, low, high));
+        lemma_bounded_add(l0_vals, rb_vals, low, high);
+        lemma_bounded_add(l0_vals + rb_vals, l2_vals, low, high);
+        lemma_bounded_add(l0_vals + rb_vals + l2_vals, r0_vals, low, high);
+        lemma_bounded_add(l0_vals + rb_vals + l2_vals + r0_vals, ra_vals, low, high);
+        lemma_bounded_add(l0_vals + rb_vals + l2_vals + r0_vals + ra_vals, r3_vals, low, high);
+
+        region_array::merge(&*arr, &mut l0, ra);
+        region_array::merge(&*arr, &mut l0, left_perms);
+        region_array::merge(&*arr, &mut l0, r0);
+        region_array::merge(&*arr, &mut l0, rb);
+        region_array::merge(&*arr, &mut l0, right_perms);
+        vstd::modes::tracked_swap(perms, &mut l0);
+        assert(perms.values() =~= l0_vals + rb_vals + l2_vals + r0_vals + ra_vals + r3_vals);
+    }
+    Ok(pl + (pr - mid))
+}
+
+fn _select(
+    arr: &Array<i32>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    k: usize,
+    Ghost(low): Ghost<int>,
+    Ghost(high): Ghost<int>,
+)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        lo <= k < hi,
+        bounded(old(perms).values(), low, high),
+    ensures
+        region_array::wf(*arr, *perms),
+        perms.lo() == lo,
+        perms.hi() == hi,
+        bounded(perms.values(), low, high),
+        is_nth(perms.values(), k - lo),
+        perms.values().to_multiset() == old(perms).values().to_multiset(),
+{
+    proof { region_array::lemma_values_len(arr, *perms); }
+    if hi - lo == 1 {
+        return;
+    }
+
+    let pivot = *region_array::read(arr, lo + (hi - lo) / 2, Tracked(perms));
+    assert(low <= pivot <= high);
+    let p = partition(arr, lo, hi, Tracked(perms), pivot, false, Ghost(low), Ghost(high));
+    let ghost vals = perms.values();
+    let tracked mut left = region_array::split_front(arr, p, perms);
+    let ghost rest_before = perms.values();
+    proof {
+        assert(bounded(perms.values(), pivot as int, high));
+    }
+    let q = partition(arr, p, hi, Tracked(perms), pivot, true, Ghost(pivot as int), Ghost(high));
+    let ghost rest_after = perms.values();
+    let tracked middle = region_array::split_front(arr, q, perms);
+    let ghost l_before = left.values();
+    let ghost m_before = middle.values();
+    let ghost r_before = perms.values();
+    proof {
+        // the pieces hold the elements of the partitioned region
+        assert(l_before + rest_before =~= vals);
+        lemma_multiset_commutative(l_before, rest_before);
+        assert(m_before + r_before =~= rest_after);
+        region_array::lemma_values_len(arr, left);
+        region_array::lemma_values_len(arr, *perms);
+        assert(bounded(left.values(), low, pivot - 1));
+        assert(bounded(perms.values(), pivot + 1, high));
+        assert(forall |i: int| 0 <= i < middle.values().len() ==> middle.values()[i] == pivot);
+    }
+
+    if k < p {
+        _select(arr, lo, p, Tracked(&mut left), k, Ghost(low), Ghost(pivot - 1));
+    } else if k >= q {
+        _select(arr, q, hi, Tracked(perms), k, Ghost(pivot + 1), Ghost(high));
+    }
+
+    proof {
+        let l_vals = left.values();
+        let m_vals = middle.values();
+        let r_vals = perms.values();
+        region_array::lemma_values_len(arr, left);
+        region_array::lemma_values_len(arr, middle);
+        region_array::lemma_values_len(arr, *perms);
+        assert(bounded(l_vals, low, pivot - 1) && bounded(l_vals, low, high));
+        assert(bounded(r_vals, pivot + 1, high) && bounded(r_vals, low, high));
+        assert(bounded(m_vals, low, high));
+        lemma_nth_pieces(l_vals, m_vals, r_vals, pivot, k - lo);
+        lemma_multiset_pieces(old(perms).values(), l_before, m_before, r_before, l_vals, m_vals, r_vals);
+        lemma_bounded_add(l_vals, m_vals, low, high);
+        lemma_bounded_add(l_vals + m_vals, r_vals, low, high);
+
+        region_array::merge(arr, &mut left, middle);
+        vstd::modes::tracked_swap(perms, &mut left);
+        region_array::merge(arr, perms, left);
+    }
+}
+
+/// Same as `_select`, but each partition step runs in parallel.
+fn _select_parallel(
+    arr: Arc<Array<i32>>,
+    lo: usize, hi: usize,
+    Tracked(perms): Tracked<&mut Region<i32>>,
+    k: usize,
+    Ghost(low): Ghost<int>,
+    Ghost(high): Ghost<int>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        region_array::wf(*arr, *old(perms)),
+        old(perms).lo() == lo,
+        old(perms).hi() == hi,
+        lo <= k < hi,
+        bounded(old(perms).values(), low, high),
+    ensures
+        ret.is_ok() ==> region_array::wf(*arr, *perms) && perms.lo() == lo && perms.hi() == hi,
+        ret.is_ok() ==> bounded(perms.values(), low, high),
+        ret.is_ok() ==> is_nth(perms.values(), k - lo),
+        ret.is_ok() ==> perms.values().to_multiset() == old(perms).values().to_multiset(),
+{
+    proof { region_array::lemma_values_len(&*arr, *perms); }
+    if hi - lo <= threshold || hi - lo < 2 {
+        _select(&*arr, lo, hi, Tracked(perms), k, Ghost(low), Ghost(high));
+        return Ok(());
+    }
+
+    let pivot = *region_array::read(&*arr, lo + (hi - lo) / 2, Tracked(perms));
+    assert(low <= pivot <= high);
+    let p = match _partition_parallel(Arc::clone(&arr), lo, hi, Tracked(perms), pivot, false, Ghost(low), Ghost(high), threshold) {
+        Ok(p) => p,
+        Err(_) => {return Err(());},
+    };
+    let ghost vals = perms.values();
+    let tracked mut left = region_array::split_front(&*arr, p, perms);
+    let ghost rest_before = perms.values();
+    proof {
+        assert(bounded(perms.values(), pivot as int, high));
+    }
+    let q = match _partition_parallel(Arc::clone(&arr), p, hi, Tracked(perms), pivot, true, Ghost(pivot as int), Ghost(high), threshold) {
+        Ok(q) => q,
+        Err(_) => {return Err(());},
+    };
+    let ghost rest_after = perms.values();
+    let tracked middle = region_array::split_front(&*arr, q, perms);
+    let ghost l_before = left.values();
+    let ghost m_before = middle.values();
+    let ghost r_before = perms.values();
+    proof {
+        // the pieces hold the elements of the partitioned region
+        assert(l_before + rest_before =~= vals);
+        lemma_multiset_commutative(l_before, rest_before);
+        assert(m_before + r_before =~= rest_after);
+        region_array::lemma_values_len(&*arr, left);
+        region_array::lemma_values_len(&*arr, *perms);
+        assert(bounded(left.values(), low, pivot - 1));
+        assert(bounded(perms.values(), pivot + 1, high));
+        assert(forall |i: int| 0 <= i < middle.values().len() ==> middle.values()[i] == pivot);
+    }
+
+    if k < p {
+        match _select_parallel(Arc::clone(&arr), lo, p, Tracked(&mut left), k, Ghost(low), Ghost(pivot - 1), threshold) {
+            Ok(()) => {},
+            Err(_) => {return Err(());},
+        };
+    } else if k >= q {
+        match _select_parallel(Arc::clone(&arr), q, hi, Tracked(perms), k, Ghost(pivot + 1), Ghost(high), threshold) {
+            Ok(()) => {},
+            Err(_) => {return Err(());},
+        };
+    }
+
+    proof {
+        let l_vals = left.values();
+        let m_vals = middle.values();
+        let r_vals = perms.values();
+        region_array::lemma_values_len(&*arr, left);
+        region_array::lemma_values_len(&*arr, middle);
+        region_array::lemma_values_len(&*arr, *perms);
+        assert(bounded(l_vals, low, pivot - 1) && bounded(l_vals, low, high));
+        assert(bounded(r_vals, pivot + 1, high) && bounded(r_vals, low, high));
+        assert(bounded(m_vals, low, high));
+        lemma_nth_pieces(l_vals, m_vals, r_vals, pivot, k - lo);
+        lemma_multiset_pieces(old(perms).values(), l_before, m_before, r_before, l_vals, m_vals, r_vals);
+        lemma_bounded_add(l_vals, m_vals, low, high);
+        lemma_bounded_add(l_vals + m_vals, r_vals, low, high);
+
+        region_array::merge(&*arr, &mut left, middle);
+        vstd::modes::tracked_swap(perms, &mut left);
+        region_array::merge(&*arr, perms, left);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_select() {
+    use crate::test_data::{scattered, thresholds};
+
+    let input: Vec<i32> = scattered(1000, 211, -100);
+    let mut expected = input.clone();
+    expected.sort();
+
+    for k in [0, 1, 499, 500, 998, 999] {
+        let mut arr = ArrayForSorting::new(input.clone());
+        select_nth(&mut arr, k);
+        assert_eq!(arr.clone_to_vec()[k], expected[k]);
+
+        for threshold in thresholds(input.len()) {
+            let mut arr = ArrayForSorting::new(input.clone());
+            select_nth_parallel(&mut arr, k, threshold).unwrap();
+            let out = arr.clone_to_vec();
+            assert_eq!(out[k], expected[k]);
+            assert!(out[..k].iter().all(|x| *x <= out[k]));
+            assert!(out[k + 1..].iter().all(|x| *x >= out[k]));
+        }
+    }
+
+    for k in [0, 10, 1000] {
+        let mut arr = ArrayForSorting::new(input.clone());
+        top_k(&mut arr, k, 16).unwrap();
+        let mut top = arr.clone_to_vec()[1000 - k..].to_vec();
+        top.sort();
+        assert_eq!(top, expected[1000 - k..]);
+    }
+}
+
+}
@@ -0,0 +1,567 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use vstd::seq_lib::lemma_multiset_commutative;
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::ArrayForSorting,
+    merge_sorted::{self, MergeOrder},
+};
+
+/// The records of a struct-of-arrays with the columns `keys` and `payloads`.
+pub open spec fn zip<V>(keys: Seq<i32>, payloads: Seq<V>) -> Seq<(i32, V)> {
+    Seq::new(keys.len(), |i: int| (keys[i], payloads[i]))
+}
+
+/// Orders records by their key.
+pub struct ByKey;
+
+impl<V> MergeOrder<(i32, V)> for ByKey {
+    open spec fn inv(&self) -> bool {
+        true
+    }
+
+    open spec fn valid(&self, x: (i32, V)) -> bool {
+        true
+    }
+
+    open spec fn key(&self, x: (i32, V)) -> int {
+        x.0 as int
+    }
+
+    fn le(&self, x: (i32, V), y: (i32, V)) -> (res: bool) {
+        x.0 <= y.0
+    }
+}
+
+pub open spec fn sorted_by_key<V>(s: Seq<(i32, V)>) -> bool {
+    merge_sorted::sorted_by(ByKey, s)
+}
+
+/// Sorts `keys` and moves `payloads` along, so that every payload stays with its key.
+/// The buffers must be as long as the arrays.
+pub fn sort_by_key<V: Copy>(
+    keys: &mut ArrayForSorting<i32>,
+    payloads: &mut ArrayForSorting<V>,
+    key_buf: &mut ArrayForSorting<i32>,
+    payload_buf: &mut ArrayForSorting<V>,
+)
+    requires
+        old(keys).perms@.lo() == 0,
+        old(keys).perms@.hi() == old(keys).array.len(),
+        region_array::wf(*old(keys).array, (old(keys).perms@)),
+        old(payloads).perms@.lo() == 0,
+        old(payloads).perms@.hi() == old(payloads).array.len(),
+        region_array::wf(*old(payloads).array, (old(payloads).perms@)),
+        old(key_buf).perms@.lo() == 0,
+        old(key_buf).perms@.hi() == old(key_buf).array.len(),
+        region_array::wf(*old(key_buf).array, (old(key_buf).perms@)),
+        old(payload_buf).perms@.lo() == 0,
+        old(payload_buf).perms@.hi() == old(payload_buf).array.len(),
+        region_array::wf(*old(payload_buf).array, (old(payload_buf).perms@)),
+        old(keys).array.len() == old(payloads).array.len(),
+        old(keys).array.len() == old(key_buf).array.len(),
+        old(keys).array.len() == old(payload_buf).array.len(),
+    ensures
+        region_array::wf(*keys.array, (keys.perms@)),
+        keys.perms@.lo() == old(keys).perms@.lo() && keys.perms@.hi() == old(keys).perms@.hi(),
+        region_array::wf(*payloads.array, (payloads.perms@)),
+        payloads.perms@.lo() == old(payloads).perms@.lo() && payloads.perms@.hi() == old(payloads).perms@.hi(),
+        region_array::wf(*key_buf.array, (key_buf.perms@)),
+        key_buf.perms@.lo() == old(key_buf).perms@.lo() && key_buf.perms@.hi() == old(key_buf).perms@.hi(),
+        region_array::wf(*payload_buf.array, (payload_buf.perms@)),
+        payload_buf.perms@.lo() == old(payload_buf).perms@.lo() && payload_buf.perms@.hi() == old(payload_buf).perms@.hi(),
+        sorted_by_key(zip(keys.perms@.values(), payloads.perms@.values())),
+        zip(keys.perms@.values(), payloads.perms@.values()).to_multiset()
+            == zip(old(keys).perms@.values(), old(payloads).perms@.values()).to_multiset(),
+{
+    _sort_by_key(
+        &keys.array,
+        &payloads.array,
+        0,
+        (&*keys.array).length(),
+        Tracked(keys.perms.borrow_mut()),
+        Tracked(payloads.perms.borrow_mut()),
+        &key_buf.array,
+        &payload_buf.array,
+        Tracked(key_buf.perms.borrow_mut()),
+        Tracked(payload_buf.perms.borrow_mut()),
+    )
+}
+
+/// Like `sort_by_key`, but the halves are sorted in separate threads while they are longer
+/// than `threshold`. Each thread owns the matching regions of both columns and of both buffers.
+pub fn sort_by_key_parallel<V: Copy + Send + 'static>(
+    keys: &mut ArrayForSorting<i32>,
+    payloads: &mut ArrayForSorting<V>,
+    key_buf: &mut ArrayForSorting<i32>,
+    payload_buf: &mut ArrayForSorting<V>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(keys).perms@.lo() == 0,
+        old(keys).perms@.hi() == old(keys).array.len(),
+        region_array::wf(*old(keys).array, (old(keys).perms@)),
+        old(payloads).perms@.lo() == 0,
+        old(payloads).perms@.hi() == old(payloads).array.len(),
+        region_array::wf(*old(payloads).array, (old(payloads).perms@)),
+        old(key_buf).perms@.lo() == 0,
+        old(key_buf).perms@.hi() == old(key_buf).array.len(),
+        region_array::wf(*old(key_buf).array, (old(key_buf).perms@)),
+        old(payload_buf).perms@.lo() == 0,
+        old(payload_buf).perms@.hi() == old(payload_buf).array.len(),
+        region_array::wf(*old(payload_buf).array, (old(payload_buf).perms@)),
+        old(keys).array.len() == old(payloads).array.len(),
+        old(keys).array.len() == old(key_buf).array.len(),
+        old(keys).array.len() == old(payload_buf).array.len(),
+    ensures
+        ret.is_ok() ==> region_array::wf(*keys.array, (keys.perms@)),
+        ret.is_ok() ==> keys.perms@.lo() == old(keys).perms@.lo() && keys.perms@.hi() == old(keys).perms@.hi(),
+        ret.is_ok() ==> region_array::wf(*payloads.array, (payloads.perms@)),
+        ret.is_ok() ==> payloads.perms@.lo() == old(payloads).perms@.lo() && payloads.perms@.hi() == old(payloads).perms@.hi(),
+        ret.is_ok() ==> region_array::wf(*key_buf.array, (key_buf.perms@)),
+        ret.is_ok() ==> key_buf.perms@.lo() == old(key_buf).perms@.lo() && key_buf.perms@.hi() == old(key_buf).perms@.hi(),
+        ret.is_ok() ==> region_array::wf(*payload_buf.array, (payload_buf.perms@)),
+        ret.is_ok() ==> payload_buf.perms@.lo() == old(payload_buf).perms@.lo() && payload_buf.perms@.hi() == old(payload_buf).perms@.hi(),
+        ret.is_ok() ==> sorted_by_key(zip(keys.perms@.values(), payloads.perms@.values())),
+        ret.is_ok() ==> zip(keys.perms@.values(), payloads.perms@.values()).to_multiset()
+            == zip(old(keys).perms@.values(), old(payloads).perms@.values()).to_multiset(),
+{
+    _sort_by_key_parallel(
+        Arc::clone(&keys.array),
+        Arc::clone(&payloads.array),
+        0,
+        (&*keys.array).length(),
+        Tracked(keys.perms.borrow_mut()),
+        Tracked(payloads.perms.borrow_mut()),
+        Arc::clone(&key_buf.array),
+        Arc::clone(&payload_buf.array),
+        Tracked(key_buf.perms.borrow_mut()),
+        Tracked(payload_buf.perms.borrow_mut()),
+        threshold,
+    )
+}
+
+/// Merges the whole pairs of regions `a` and `b` of `keys` and `payloads` into the pair `out`
+/// by key, moving every payload along with its key. On ties `a` goes first.
+fn merge_pairs<V: Copy>(
+    keys: &Array<i32>,
+    payloads: &Array<V>,
+    Tracked(a_keys): Tracked<&Region<i32>>,
+    Tracked(a_payloads): Tracked<&Region<V>>,
+    a_lo: usize, a_hi: usize,
+    Tracked(b_keys): Tracked<&Region<i32>>,
+    Tracked(b_payloads): Tracked<&Region<V>>,
+    b_lo: usize, b_hi: usize,
+    key_out: &Array<i32>,
+    payload_out: &Array<V>,
+    Tracked(out_keys): Tracked<&mut Region<i32>>,
+    Tracked(out_payloads): Tracked<&mut Region<V>>,
+    out_lo: usize,
+)
+    requires
+        region_array::wf_pair(*keys, *payloads, *a_keys, *a_payloads),
+        a_keys.lo() == a_lo,
+        a_keys.hi() == a_hi,
+        region_array::wf_pair(*keys, *payloads, *b_keys, *b_payloads),
+        b_keys.lo() == b_lo,
+        b_keys.hi() == b_hi,
+        region_array::wf_pair(*key_out, *payload_out, *old(out_keys), *old(out_payloads)),
+        old(out_keys).lo() == out_lo,
+        old(out_keys).hi() - out_lo == (a_hi - a_lo) + (b_hi - b_lo),
+        sorted_by_key(zip(a_keys.values(), a_payloads.values())),
+        sorted_by_key(zip(b_keys.values(), b_payloads.values())),
+    ensures
+        region_array::wf_pair(*key_out, *payload_out, *out_keys, *out_payloads),
+        out_keys.lo() == old(out_keys).lo(),
+        out_keys.hi() == old(out_keys).hi(),
+        merge_sorted::merged_by(
+            ByKey,
+            zip(a_keys.values(), a_payloads.values()),
+            zip(b_keys.values(), b_payloads.values()),
+            zip(out_keys.values(), out_payloads.values()),
+        ),
+{
+    let ghost a_vals = zip(a_keys.values(), a_payloads.values());
+    let ghost b_vals = zip(b_keys.values(), b_payloads.values());
+    let ghost out_hi = out_keys.hi();
+    proof {
+        region_array::lemma_values_len(keys, *a_keys);
+        region_array::lemma_values_len(payloads, *a_payloads);
+        region_array::lemma_values_len(keys, *b_keys);
+        region_array::lemma_values_len(payloads, *b_payloads);
+        region_array::lemma_values_len(key_out, *out_keys);
+        region_array::lemma_values_len(payload_out, *out_payloads);
+        merge_sorted::lemma_merged_by_empty::<(i32, V), ByKey>(ByKey);
+        assert(a_vals.subrange(0, 0) =~= Seq::empty());
+        assert(b_vals.subrange(0, 0) =~= Seq::empty());
+        assert(zip(out_keys.values(), out_payloads.values()).subrange(0, 0) =~= Seq::empty());
+    }
+
+    let mut i = a_lo;
+    let mut j = b_lo;
+    let mut o = out_lo;
+    while i < a_hi || j < b_hi
+        invariant
+            region_array::wf_pair(*keys, *payloads, *a_keys, *a_payloads),
+            a_keys.lo() == a_lo,
+            a_keys.hi() == a_hi,
+            a_keys.values().len() == a_hi - a_lo,
+            a_payloads.values().len() == a_hi - a_lo,
+            a_vals == zip(a_keys.values(), a_payloads.values()),
+            sorted_by_key(a_vals),
+            region_array::wf_pair(*keys, *payloads, *b_keys, *b_payloads),
+            b_keys.lo() == b_lo,
+            b_keys.hi() == b_hi,
+            b_keys.values().len() == b_hi - b_lo,
+            b_payloads.values().len() == b_hi - b_lo,
+            b_vals == zip(b_keys.values(), b_payloads.values()),
+            sorted_by_key(b_vals),
+            region_array::wf_pair(*key_out, *payload_out, *out_keys, *out_payloads),
+            out_keys.lo() == out_lo,
+            out_keys.hi() == out_hi,
+            out_keys.values().len() == out_hi - out_lo,
+            out_payloads.values().len() == out_hi - out_lo,
+            out_hi - out_lo == (a_hi - a_lo) + (b_hi - b_lo),
+            a_lo <= i <= a_hi,
+            b_lo <= j <= b_hi,
+            o - out_lo == (i - a_lo) + (j - b_lo),
+            merge_sorted::merged_by(
+                ByKey,
+                a_vals.subrange(0, i - a_lo),
+                b_vals.subrange(0, j - b_lo),
+                zip(out_keys.values(), out_payloads.values()).subrange(0, o - out_lo),
+            ),
+            o > out_lo && i < a_hi ==> out_keys.values()[o - out_lo - 1] <= a_vals[i - a_lo].0,
+            o > out_lo && j < b_hi ==> out_keys.values()[o - out_lo - 1] <= b_vals[j - b_lo].0,
+    {
+        let ghost done = zip(out_keys.values(), out_payloads.values()).subrange(0, o - out_lo);
+        let ghost a_done = a_vals.subrange(0, i - a_lo);
+        let ghost b_done = b_vals.subrange(0, j - b_lo);
+
+        // on ties `a` goes first
+        let take_a = if i == a_hi {
+            false
+        } else if j == b_hi {
+            true
+        } else {
+            *region_array::read(keys, i, Tracked(a_keys)) <= *region_array::read(keys, j, Tracked(b_keys))
+        };
+        let k;
+        let p;
+        if take_a {
+            k = *region_array::read(keys, i, Tracked(a_keys));
+            p = *region_array::read(payloads, i, Tracked(a_payloads));
+            i += 1;
+        } else {
+            k = *region_array::read(keys, j, Tracked(b_keys));
+            p = *region_array::read(payloads, j, Tracked(b_payloads));
+            j += 1;
+        }
+        region_array::replace(key_out, o, k, Tracked(out_keys));
+        region_array::replace(payload_out, o, p, Tracked(out_payloads));
+        o += 1;
+
+        proof {
+            merge_sorted::lemma_merged_by_push(ByKey, a_done, b_done, done, (k, p), take_a);
+            assert(zip(out_keys.values(), out_payloads.values()).subrange(0, o - out_lo) =~= done.push((k, p)));
+            if take_a {
+                assert(a_vals.subrange(0, i - a_lo) =~= a_done.push((k, p)));
+            } else {
+                assert(b_vals.subrange(0, j - b_lo) =~= b_done.push((k, p)));
+            }
+        }
+    }
+
+    proof {
+        assert(a_vals.subrange(0, i - a_lo) =~= a_vals);
+        assert(b_vals.subrange(0, j - b_lo) =~= b_vals);
+        assert(zip(out_keys.values(), out_payloads.values()).subrange(0, o - out_lo)
+            =~= zip(out_keys.values(), out_payloads.values()));
+    }
+}
+
+/// The records of the two halves add up to the records of the whole.
+proof fn lemma_zip_halves<V>(
+    keys: Seq<i32>, payloads: Seq<V>,
+    left_keys: Seq<i32>, left_payloads: Seq<V>,
+    right_keys: Seq<i32>, right_payloads: Seq<V>,
+)
+    requires
+        keys.len() == payloads.len(),
+        left_keys.len() == left_payloads.len(),
+        left_keys + right_keys == keys,
+        left_payloads + right_payloads == payloads,
+    ensures
+        zip(keys, payloads).to_multiset()
+            == zip(left_keys, left_payloads).to_multiset().add(zip(right_keys, right_payloads).to_multiset()),
+{
+    assert(zip(keys, payloads) =~= zip(left_keys, left_payloads) + zip(right_keys, right_payloads));
+    lemma_multiset_commutative(zip(left_keys, left_payloads), zip(right_keys, right_payloads));
+}
+
+fn _sort_by_key<V: Copy>(
+    keys: &Array<i32>,
+    payloads: &Array<V>,
+    lo: usize, hi: usize,
+    Tracked(key_perms): Tracked<&mut Region<i32>>,
+    Tracked(payload_perms): Tracked<&mut Region<V>>,
+    key_buf: &Array<i32>,
+    payload_buf: &Array<V>,
+    Tracked(key_buf_perms): Tracked<&mut Region<i32>>,
+    Tracked(payload_buf_perms): Tracked<&mut Region<V>>,
+)
+    requires
+        region_array::wf_pair(*keys, *payloads, *old(key_perms), *old(payload_perms)),
+        old(key_perms).lo() == lo,
+        old(key_perms).hi() == hi,
+        region_array::wf_pair(*key_buf, *payload_buf, *old(key_buf_perms), *old(payload_buf_perms)),
+        old(key_buf_perms).lo() == lo,
+        old(key_buf_perms).hi() == hi,
+    ensures
+        region_array::wf_pair(*keys, *payloads, *key_perms, *payload_perms),
+        key_perms.lo() == lo,
+        key_perms.hi() == hi,
+        region_array::wf_pair(*key_buf, *payload_buf, *key_buf_perms, *payload_buf_perms),
+        key_buf_perms.lo() == lo,
+        key_buf_perms.hi() == hi,
+        sorted_by_key(zip(key_perms.values(), payload_perms.values())),
+        zip(key_perms.values(), payload_perms.values()).to_multiset()
+            == zip(old(key_perms).values(), old(payload_perms).values()).to_multiset(),
+{
+    let ghost old_keys = key_perms.values();
+    let ghost old_payloads = payload_perms.values();
+    proof {
+        region_array::lemma_values_len(keys, *key_perms);
+        region_array::lemma_values_len(payloads, *payload_perms);
+    }
+    let mid = lo + (hi - lo) / 2;
+    if mid == lo {
+        return;
+    }
+
+    let tracked right = region_array::split_off_pair(keys, payloads, mid, key_perms, payload_perms);
+    let tracked mut right_keys = right.0;
+    let tracked mut right_payloads = right.1;
+    let tracked left = region_array::split_off_pair(keys, payloads, lo, key_perms, payload_perms);
+    let tracked mut left_keys = left.0;
+    let tracked mut left_payloads = left.1;
+    let tracked right_buf = region_array::split_off_pair(key_buf, payload_buf, mid, key_buf_perms, payload_buf_perms);
+    let tracked mut right_key_buf = right_buf.0;
+    let tracked mut right_payload_buf = right_buf.1;
+    let tracked left_buf = region_array::split_off_pair(key_buf, payload_buf, lo, key_buf_perms, payload_buf_perms);
+    let tracked mut left_key_buf = left_buf.0;
+    let tracked mut left_payload_buf = left_buf.1;
+    proof {
+        assert(left_keys.values() + right_keys.values() =~= old_keys);
+        assert(left_payloads.values() + right_payloads.values() =~= old_payloads);
+        lemma_zip_halves(old_keys, old_payloads, left_keys.values(), left_payloads.values(), right_keys.values(), right_payloads.values());
+    }
+
+    _sort_by_key(
+        keys, payloads, lo, mid, Tracked(&mut left_keys), Tracked(&mut left_payloads),
+        key_buf, payload_buf, Tracked(&mut left_key_buf), Tracked(&mut left_payload_buf),
+    );
+    _sort_by_key(
+        keys, payloads, mid, hi, Tracked(&mut right_keys), Tracked(&mut right_payloads),
+        key_buf, payload_buf, Tracked(&mut right_key_buf), Tracked(&mut right_payload_buf),
+    );
+
+    proof {
+        region_array::merge_pair(key_buf, payload_buf, &mut left_key_buf, &mut left_payload_buf, right_key_buf, right_payload_buf);
+    }
+    merge_pairs(
+        keys, payloads,
+        Tracked(&left_keys), Tracked(&left_payloads), lo, mid,
+        Tracked(&right_keys), Tracked(&right_payloads), mid, hi,
+        key_buf, payload_buf,
+        Tracked(&mut left_key_buf), Tracked(&mut left_payload_buf), lo,
+    );
+    proof {
+        region_array::merge_pair(keys, payloads, &mut left_keys, &mut left_payloads, right_keys, right_payloads);
+    }
+    region_array::copy_region(key_buf, Tracked(&left_key_buf), keys, Tracked(&mut left_keys), lo, hi);
+    region_array::copy_region(payload_buf, Tracked(&left_payload_buf), payloads, Tracked(&mut left_payloads), lo, hi);
+    proof {
+        region_array::merge_pair(keys, payloads, key_perms, payload_perms, left_keys, left_payloads);
+        region_array::merge_pair(key_buf, payload_buf, key_buf_perms, payload_buf_perms, left_key_buf, left_payload_buf);
+        assert(key_perms.values() =~= left_keys.values());
+        assert(payload_perms.values() =~= left_payloads.values());
+    }
+}
+
+fn _sort_by_key_parallel<V: Copy + Send + 'static>(
+    keys: Arc<Array<i32>>,
+    payloads: Arc<Array<V>>,
+    lo: usize, hi: usize,
+    Tracked(key_perms): Tracked<&mut Region<i32>>,
+    Tracked(payload_perms): Tracked<&mut Region<V>>,
+    key_buf: Arc<Array<i32>>,
+    payload_buf: Arc<Array<V>>,
+    Tracked(key_buf_perms): Tracked<&mut Region<i32>>,
+    Tracked(payload_buf_perms): Tracked<&mut Region<V>>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        region_array::wf_pair(*keys, *payloads, *old(key_perms), *old(payload_perms)),
+        old(key_perms).lo() == lo,
+        old(key_perms).hi() == hi,
+        region_array::wf_pair(*key_buf, *payload_buf, *old(key_buf_perms), *old(payload_buf_perms)),
+        old(key_buf_perms).lo() == lo,
+        old(key_buf_perms).hi() == hi,
+    ensures
+        ret.is_ok() ==> region_array::wf_pair(*keys, *payloads, *key_perms, *payload_perms),
+        ret.is_ok() ==> key_perms.lo() == lo && key_perms.hi() == hi,
+        ret.is_ok() ==> region_array::wf_pair(*key_buf, *payload_buf, *key_buf_perms, *payload_buf_perms),
+        ret.is_ok() ==> key_buf_perms.lo() == lo && key_buf_perms.hi() == hi,
+        ret.is_ok() ==> sorted_by_key(zip(key_perms.values(), payload_perms.values())),
+        ret.is_ok() ==> zip(key_perms.values(), payload_perms.values()).to_multiset()
+            == zip(old(key_perms).values(), old(payload_perms).values()).to_multiset(),
+{
+    let ghost old_keys = key_perms.values();
+    let ghost old_payloads = payload_perms.values();
+    proof {
+        region_array::lemma_values_len(&*keys, *key_perms);
+        region_array::lemma_values_len(&*payloads, *payload_perms);
+    }
+    let mid = lo + (hi - lo) / 2;
+    if mid == lo {
+        return Ok(());
+    }
+
+    if hi - lo <= threshold {
+        _sort_by_key(
+            &*keys, &*payloads, lo, hi, Tracked(key_perms), Tracked(payload_perms),
+            &*key_buf, &*payload_buf, Tracked(key_buf_perms), Tracked(payload_buf_perms),
+        );
+        return Ok(());
+    }
+
+    let tracked right = region_array::split_off_pair(&*keys, &*payloads, mid, key_perms, payload_perms);
+    let tracked mut right_keys = right.0;
+    let tracked mut right_payloads = right.1;
+    let tracked left = region_array::split_off_pair(&*keys, &*payloads, lo, key_perms, payload_perms);
+    let tracked left_keys = left.0;
+    let tracked left_payloads = left.1;
+    let tracked right_buf = region_array::split_off_pair(&*key_buf, &*payload_buf, mid, key_buf_perms, payload_buf_perms);
+    let tracked mut right_key_buf = right_buf.0;
+    let tracked mut right_payload_buf = right_buf.1;
+    let tracked left_buf = region_array::split_off_pair(&*key_buf, &*payload_buf, lo, key_buf_perms, payload_buf_perms);
+    let tracked left_key_buf = left_buf.0;
+    let tracked left_payload_buf = left_buf.1;
+    proof {
+        assert(left_keys.values() + right_keys.values() =~= old_keys);
+        assert(left_payloads.values() + right_payloads.values() =~= old_payloads);
+        lemma_zip_halves(old_keys, old_payloads, left_keys.values(), left_payloads.values(), right_keys.values(), right_payloads.values());
+    }
+    let ghost left_zip = zip(left_keys.values(), left_payloads.values());
+
+    let keys_r1 = Arc::clone(&keys);
+    let payloads_r1 = Arc::clone(&payloads);
+    let key_buf_r1 = Arc::clone(&key_buf);
+    let payload_buf_r1 = Arc::clone(&payload_buf);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<(Tracked<Region<i32>>, Tracked<Region<V>>, Tracked<Region<i32>>, Tracked<Region<V>>), ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf_pair(*keys, *payloads, ret.unwrap().0@, ret.unwrap().1@),
+            ret.is_ok() ==> ret.unwrap().0@.lo() == lo && ret.unwrap().0@.hi() == mid,
+            ret.is_ok() ==> region_array::wf_pair(*key_buf, *payload_buf, ret.unwrap().2@, ret.unwrap().3@),
+            ret.is_ok() ==> ret.unwrap().2@.lo() == lo && ret.unwrap().2@.hi() == mid,
+            ret.is_ok() ==> sorted_by_key(zip(ret.unwrap().0@.values(), ret.unwrap().1@.values())),
+            ret.is_ok() ==> zip(ret.unwrap().0@.values(), ret.unwrap().1@.values()).to_multiset() == left_zip.to_multiset(),
+        {
+            let tracked mut left_keys = left_keys;
+            let tracked mut left_payloads = left_payloads;
+            let tracked mut left_key_buf = left_key_buf;
+            let tracked mut left_payload_buf = left_payload_buf;
+            let t = _sort_by_key_parallel(
+                keys_r1, payloads_r1, lo, mid, Tracked(&mut left_keys), Tracked(&mut left_payloads),
+                key_buf_r1, payload_buf_r1, Tracked(&mut left_key_buf), Tracked(&mut left_payload_buf),
+                threshold,
+            );
+            if t.is_err() {
+                Err(())
+            } else {
+                Ok((Tracked(left_keys), Tracked(left_payloads), Tracked(left_key_buf), Tracked(left_payload_buf)))
+            }
+        }
+    );
+
+    match _sort_by_key_parallel(
+        Arc::clone(&keys), Arc::clone(&payloads), mid, hi, Tracked(&mut right_keys), Tracked(&mut right_payloads),
+        Arc::clone(&key_buf), Arc::clone(&payload_buf), Tracked(&mut right_key_buf), Tracked(&mut right_payload_buf),
+        threshold,
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let (Tracked(mut left_keys), Tracked(mut left_payloads), Tracked(mut left_key_buf), Tracked(mut left_payload_buf)) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::merge_pair(&*key_buf, &*payload_buf, &mut left_key_buf, &mut left_payload_buf, right_key_buf, right_payload_buf);
+    }
+    merge_pairs(
+        &*keys, &*payloads,
+        Tracked(&left_keys), Tracked(&left_payloads), lo, mid,
+        Tracked(&right_keys), Tracked(&right_payloads), mid, hi,
+        &*key_buf, &*payload_buf,
+        Tracked(&mut left_key_buf), Tracked(&mut left_payload_buf), lo,
+    );
+    proof {
+        region_array::merge_pair(&*keys, &*payloads, &mut left_keys, &mut left_payloads, right_keys, right_payloads);
+    }
+    region_array::copy_region(&*key_buf, Tracked(&left_key_buf), &*keys, Tracked(&mut left_keys), lo, hi);
+    region_array::copy_region(&*payload_buf, Tracked(&left_payload_buf), &*payloads, Tracked(&mut left_payloads), lo, hi);
+    proof {
+        region_array::merge_pair(&*keys, &*payloads, key_perms, payload_perms, left_keys, left_payloads);
+        region_array::merge_pair(&*key_buf, &*payload_buf, key_buf_perms, payload_buf_perms, left_key_buf, left_payload_buf);
+        assert(key_perms.values() =~= left_keys.values());
+        assert(payload_perms.values() =~= left_payloads.values());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sort_by_key() {
+    use crate::test_data::{scattered, thresholds};
+
+    let keys: Vec<i32> = scattered(1000, 211, -100);
+    let payloads: Vec<u64> = (0..1000).map(|i| i as u64 * 3).collect();
+    let mut expected: Vec<(i32, u64)> = keys.iter().cloned().zip(payloads.iter().cloned()).collect();
+    expected.sort_by_key(|r| r.0);
+    let expected_keys: Vec<i32> = expected.iter().map(|r| r.0).collect();
+    let expected_payloads: Vec<u64> = expected.iter().map(|r| r.1).collect();
+
+    let mut k = ArrayForSorting::new(keys.clone());
+    let mut p = ArrayForSorting::new(payloads.clone());
+    let mut k_buf = ArrayForSorting::new(vec![0; keys.len()]);
+    let mut p_buf = ArrayForSorting::new(vec![0; keys.len()]);
+    sort_by_key(&mut k, &mut p, &mut k_buf, &mut p_buf);
+    assert_eq!(k.clone_to_vec(), expected_keys);
+    assert_eq!(p.clone_to_vec(), expected_payloads);
+
+    for threshold in thresholds(keys.len()) {
+        let mut k = ArrayForSorting::new(keys.clone());
+        let mut p = ArrayForSorting::new(payloads.clone());
+        let mut k_buf = ArrayForSorting::new(vec![0; keys.len()]);
+        let mut p_buf = ArrayForSorting::new(vec![0; keys.len()]);
+        sort_by_key_parallel(&mut k, &mut p, &mut k_buf, &mut p_buf, threshold).unwrap();
+        assert_eq!(k.clone_to_vec(), expected_keys);
+        assert_eq!(p.clone_to_vec(), expected_payloads);
+    }
+}
+
+}
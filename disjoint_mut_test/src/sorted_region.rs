@@ -0,0 +1,361 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::{self, ArrayForSorting, MergeKernel},
+    merge_sorted::{self, merged, sorted_by, lemma_sorted_by_subrange, lemma_sorted_by_value, ByValue, MergeOrder},
+    binary_search::{self, is_lower_bound, is_upper_bound},
+};
+
+/// A region whose values are in ascending order of `order`. It is built only from regions that
+/// are known to be sorted, and it is written to only in ways that keep the order. Everything else
+/// goes through `into_region`. `ByValue` gives the order of `i32`, which is what `sorted` is about.
+pub tracked struct SortedRegion<T, O> {
+    tracked region: Region<T>,
+    ghost order: O,
+}
+
+impl<T, O: MergeOrder<T>> SortedRegion<T, O> {
+    pub closed spec fn lo(&self) -> usize {
+        self.region.lo()
+    }
+
+    pub closed spec fn hi(&self) -> usize {
+        self.region.hi()
+    }
+
+    pub closed spec fn values(&self) -> Seq<T> {
+        self.region.values()
+    }
+
+    pub closed spec fn order(&self) -> O {
+        self.order
+    }
+}
+
+pub closed spec fn wf<T, O: MergeOrder<T>>(aself: Array<T>, sorted_region: SortedRegion<T, O>) -> bool {
+    region_array::wf(aself, sorted_region.region) && sorted_by(sorted_region.order, sorted_region.region.values())
+}
+
+pub proof fn lemma_sorted<T, O: MergeOrder<T>>(aself: &Array<T>, tracked sorted_region: &SortedRegion<T, O>)
+    requires
+        wf(*aself, *sorted_region),
+    ensures
+        sorted_by(sorted_region.order(), sorted_region.values()),
+        sorted_region.lo() <= sorted_region.hi(),
+        sorted_region.values().len() == sorted_region.hi() - sorted_region.lo(),
+{
+    region_array::lemma_values_len(aself, sorted_region.region);
+}
+
+pub proof fn from_region<T, O: MergeOrder<T>>(aself: &Array<T>, tracked region: Region<T>, order: O) -> (tracked res: SortedRegion<T, O>)
+    requires
+        region_array::wf(*aself, region),
+        sorted_by(order, region.values()),
+    ensures
+        wf(*aself, res),
+        res.lo() == region.lo(),
+        res.hi() == region.hi(),
+        res.values() == region.values(),
+        res.order() == order,
+{
+    SortedRegion { region, order }
+}
+
+/// Gives up the order, e.g. to write to the region freely.
+pub proof fn into_region<T, O: MergeOrder<T>>(aself: &Array<T>, tracked sorted_region: SortedRegion<T, O>) -> (tracked res: Region<T>)
+    requires
+        wf(*aself, sorted_region),
+    ensures
+        region_array::wf(*aself, res),
+        res.lo() == sorted_region.lo(),
+        res.hi() == sorted_region.hi(),
+        res.values() == sorted_region.values(),
+        sorted_by(sorted_region.order(), res.values()),
+{
+    sorted_region.region
+}
+
+/// Read access to the underlying region.
+pub proof fn as_region<'a, T, O: MergeOrder<T>>(aself: &Array<T>, tracked sorted_region: &'a SortedRegion<T, O>) -> (tracked res: &'a Region<T>)
+    requires
+        wf(*aself, *sorted_region),
+    ensures
+        region_array::wf(*aself, *res),
+        res.lo() == sorted_region.lo(),
+        res.hi() == sorted_region.hi(),
+        res.values() == sorted_region.values(),
+        sorted_by(sorted_region.order(), res.values()),
+{
+    &sorted_region.region
+}
+
+/// Like `region_array::split_front`. Both parts stay sorted.
+pub proof fn split_front<T, O: MergeOrder<T>>(aself: &Array<T>, tracked m: usize, tracked sorted_region: &mut SortedRegion<T, O>) -> (tracked res: SortedRegion<T, O>)
+    requires
+        old(sorted_region).lo() <= m <= old(sorted_region).hi(),
+        wf(*aself, *old(sorted_region)),
+    ensures
+        wf(*aself, *sorted_region),
+        sorted_region.lo() == m,
+        sorted_region.hi() == old(sorted_region).hi(),
+        sorted_region.values() == old(sorted_region).values().subrange(m - old(sorted_region).lo(), old(sorted_region).hi() - old(sorted_region).lo()),
+        sorted_region.order() == old(sorted_region).order(),
+        wf(*aself, res),
+        res.lo() == old(sorted_region).lo(),
+        res.hi() == m,
+        res.values() == old(sorted_region).values().subrange(0, m - old(sorted_region).lo()),
+        res.order() == old(sorted_region).order(),
+{
+    let ghost vals = sorted_region.region.values();
+    let ghost lo = sorted_region.region.lo();
+    let ghost hi = sorted_region.region.hi();
+    region_array::lemma_values_len(aself, sorted_region.region);
+    let tracked left = region_array::split_front(aself, m, &mut sorted_region.region);
+    lemma_sorted_by_subrange(sorted_region.order, vals, 0, m - lo);
+    lemma_sorted_by_subrange(sorted_region.order, vals, m - lo, hi - lo);
+    SortedRegion { region: left, order: sorted_region.order }
+}
+
+/// Writes `x` at `i`, which must fit between the neighbours of `i`.
+pub fn replace<T, O: MergeOrder<T>>(aself: &Array<T>, i: usize, x: T, Tracked(sorted_region): Tracked<&mut SortedRegion<T, O>>) -> (res: T)
+    requires
+        wf(*aself, *old(sorted_region)),
+        old(sorted_region).lo() <= i < old(sorted_region).hi(),
+        i > old(sorted_region).lo() ==> old(sorted_region).order().key(old(sorted_region).values()[i - 1 - old(sorted_region).lo()]) <= old(sorted_region).order().key(x),
+        i + 1 < old(sorted_region).hi() ==> old(sorted_region).order().key(x) <= old(sorted_region).order().key(old(sorted_region).values()[i + 1 - old(sorted_region).lo()]),
+    ensures
+        wf(*aself, *sorted_region),
+        sorted_region.lo() == old(sorted_region).lo(),
+        sorted_region.hi() == old(sorted_region).hi(),
+        sorted_region.values() == old(sorted_region).values().update(i - old(sorted_region).lo(), x),
+        sorted_region.order() == old(sorted_region).order(),
+{
+    let ghost vals = sorted_region.region.values();
+    let ghost k = i - sorted_region.region.lo();
+    let ghost order = sorted_region.order;
+    proof {
+        region_array::lemma_values_len(aself, sorted_region.region);
+    }
+    let res = region_array::replace(aself, i, x, Tracked(&mut sorted_region.region));
+    let ghost new_vals = sorted_region.region.values();
+    assert forall |p: int, q: int| 0 <= p <= q < new_vals.len() implies order.key(new_vals[p]) <= order.key(new_vals[q]) by {
+        if p < k && q == k {
+            assert(order.key(vals[p]) <= order.key(vals[k - 1]));
+        } else if p == k && k < q {
+            assert(order.key(vals[k + 1]) <= order.key(vals[q]));
+        } else if p != k && q != k {
+            assert(order.key(vals[p]) <= order.key(vals[q]));
+        }
+    }
+    res
+}
+
+/// An `ArrayForSorting` whose whole region is sorted by value.
+pub struct SortedArray {
+    pub array: Arc<Array<i32>>,
+    pub perms: Tracked<SortedRegion<i32, ByValue>>,
+}
+
+impl SortedArray {
+    pub open spec fn wf(&self) -> bool {
+        &&& wf(*self.array, self.perms@)
+        &&& self.perms@.lo() == 0
+        &&& self.perms@.hi() == self.array.len()
+        &&& self.perms@.order() == ByValue
+    }
+
+    /// Sorts `arr` with `merge_sort_parallel`. If that fails, `arr` is given back.
+    pub fn sort_parallel(
+        arr: ArrayForSorting<i32>,
+        out_arr: &mut ArrayForSorting<i32>,
+        threshold: usize,
+    ) -> (ret: Result<Self, ArrayForSorting<i32>>)
+        requires
+            arr.perms@.lo() == 0,
+            arr.perms@.hi() == arr.array.len(),
+            region_array::wf(*arr.array, (arr.perms@)),
+            old(out_arr).perms@.lo() == 0,
+            old(out_arr).perms@.hi() == old(out_arr).array.len(),
+            region_array::wf(*old(out_arr).array, (old(out_arr).perms@)),
+            arr.array.len() == old(out_arr).array.len(),
+        ensures
+            ret.is_ok() ==> ret.unwrap().wf(),
+            ret.is_ok() ==> ret.unwrap().array == arr.array,
+            ret.is_ok() ==> ret.unwrap().perms@.values().to_multiset() == arr.perms@.values().to_multiset(),
+            ret is Err ==> ret->Err_0.array == arr.array,
+    {
+        let n = (&*arr.array).length();
+        let ArrayForSorting { array, perms } = arr;
+        let tracked mut region = perms.get();
+        proof {
+            region_array::lemma_values_len(&*array, region);
+        }
+        let ghost vals = region.values();
+        match mergesort::_merge_sort_parallel(
+            Arc::clone(&array),
+            0,
+            n,
+            Tracked(&mut region),
+            Arc::clone(&out_arr.array),
+            0,
+            Tracked(out_arr.perms.borrow_mut()),
+            threshold,
+            MergeKernel::Branching,
+        ) {
+            Ok(()) => {},
+            Err(_) => {return Err(ArrayForSorting { array, perms: Tracked(region) });},
+        };
+        proof {
+            mergesort::lemma_sorts_range_whole(vals, region.values());
+            lemma_sorted_by_value(region.values());
+        }
+        let tracked sorted_region = from_region(&*array, region, ByValue);
+        Ok(SortedArray { array, perms: Tracked(sorted_region) })
+    }
+
+    /// Gives up the order.
+    pub fn into_array(self) -> (res: ArrayForSorting<i32>)
+        requires
+            self.wf(),
+        ensures
+            res.array == self.array,
+            region_array::wf(*res.array, res.perms@),
+            res.perms@.lo() == 0,
+            res.perms@.hi() == res.array.len(),
+            res.perms@.values() == self.perms@.values(),
+    {
+        let SortedArray { array, perms } = self;
+        let tracked region = into_region(&*array, perms.get());
+        ArrayForSorting { array, perms: Tracked(region) }
+    }
+
+    pub fn len(&self) -> (res: usize)
+        ensures
+            res == self.array.len(),
+    {
+        (&*self.array).length()
+    }
+
+    pub fn lower_bound(&self, x: i32) -> (res: usize)
+        requires
+            self.wf(),
+        ensures
+            is_lower_bound(self.perms@.values(), x, res as int),
+    {
+        let tracked region = as_region(&*self.array, self.perms.borrow());
+        proof {
+            lemma_sorted_by_value(region.values());
+        }
+        binary_search::lower_bound(&self.array, Tracked(region), 0, self.len(), x)
+    }
+
+    pub fn upper_bound(&self, x: i32) -> (res: usize)
+        requires
+            self.wf(),
+        ensures
+            is_upper_bound(self.perms@.values(), x, res as int),
+    {
+        let tracked region = as_region(&*self.array, self.perms.borrow());
+        proof {
+            lemma_sorted_by_value(region.values());
+        }
+        binary_search::upper_bound(&self.array, Tracked(region), 0, self.len(), x)
+    }
+
+    pub fn equal_range(&self, x: i32) -> (res: (usize, usize))
+        requires
+            self.wf(),
+        ensures
+            res.0 <= res.1,
+            is_lower_bound(self.perms@.values(), x, res.0 as int),
+            is_upper_bound(self.perms@.values(), x, res.1 as int),
+    {
+        let tracked region = as_region(&*self.array, self.perms.borrow());
+        proof {
+            lemma_sorted_by_value(region.values());
+        }
+        binary_search::equal_range(&self.array, Tracked(region), 0, self.len(), x)
+    }
+
+    /// Merges `a` and `b` into `out`.
+    pub fn merge(a: &SortedArray, b: &SortedArray, out: ArrayForSorting<i32>) -> (res: SortedArray)
+        requires
+            a.wf(),
+            b.wf(),
+            out.perms@.lo() == 0,
+            out.perms@.hi() == out.array.len(),
+            region_array::wf(*out.array, (out.perms@)),
+            a.array.len() + b.array.len() == out.array.len(),
+        ensures
+            res.wf(),
+            res.array == out.array,
+            merged(a.perms@.values(), b.perms@.values(), res.perms@.values()),
+    {
+        let tracked a_region = as_region(&*a.array, a.perms.borrow());
+        let tracked b_region = as_region(&*b.array, b.perms.borrow());
+        let ArrayForSorting { array, perms } = out;
+        let tracked mut region = perms.get();
+        proof {
+            lemma_sorted_by_value(a_region.values());
+            lemma_sorted_by_value(b_region.values());
+            merge_sorted::lemma_whole_subrange(&*a.array, *a_region);
+            merge_sorted::lemma_whole_subrange(&*b.array, *b_region);
+        }
+        merge_sorted::merge_into(
+            &a.array,
+            Tracked(a_region),
+            0,
+            a.len(),
+            &b.array,
+            Tracked(b_region),
+            0,
+            b.len(),
+            &array,
+            Tracked(&mut region),
+            0,
+        );
+        proof {
+            lemma_sorted_by_value(region.values());
+        }
+        let tracked sorted_region = from_region(&*array, region, ByValue);
+        SortedArray { array, perms: Tracked(sorted_region) }
+    }
+}
+
+#[test]
+fn test_sorted_region() {
+    use crate::test_data::scattered;
+
+    let left: Vec<i32> = scattered(700, 401, -200);
+    let right: Vec<i32> = (0..300).map(|i| (i * 104729) % 211 - 100).collect();
+    let mut expected_left = left.clone();
+    expected_left.sort();
+    let mut expected = left.clone();
+    expected.extend_from_slice(&right);
+    expected.sort();
+
+    let mut buf = ArrayForSorting::new(vec![0; left.len()]);
+    let a = SortedArray::sort_parallel(ArrayForSorting::new(left.clone()), &mut buf, 64).ok().unwrap();
+    let mut buf = ArrayForSorting::new(vec![0; right.len()]);
+    let b = SortedArray::sort_parallel(ArrayForSorting::new(right.clone()), &mut buf, 64).ok().unwrap();
+
+    for x in -250..250 {
+        let lower = expected_left.partition_point(|&y| y < x);
+        let upper = expected_left.partition_point(|&y| y <= x);
+        assert_eq!(a.lower_bound(x), lower);
+        assert_eq!(a.upper_bound(x), upper);
+        assert_eq!(a.equal_range(x), (lower, upper));
+    }
+
+    let merged = SortedArray::merge(&a, &b, ArrayForSorting::new(vec![0; expected.len()]));
+    assert_eq!(merged.into_array().clone_to_vec(), expected);
+    assert_eq!(a.into_array().clone_to_vec(), expected_left);
+}
+
+}
@@ -0,0 +1,613 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::{ArrayForSorting, is_full, share, unshare},
+    chunks::offsets_wf,
+};
+
+/// `row_ptr` and `col_idx` describe a matrix with `cols` columns in compressed sparse row format:
+/// the entries of row `i` are `row_ptr[i]..row_ptr[i + 1]`, and `col_idx` holds their columns.
+pub open spec fn csr_wf(row_ptr: Seq<usize>, col_idx: Seq<usize>, cols: int) -> bool {
+    &&& offsets_wf(row_ptr, col_idx.len() as int)
+    &&& forall |k: int| 0 <= k < col_idx.len() ==> #[trigger] col_idx[k] < cols
+}
+
+/// The sum of `vals[k] * x[col_idx[k]]` over the entries `lo..hi`.
+pub open spec fn row_sum(col_idx: Seq<usize>, vals: Seq<u64>, x: Seq<u64>, lo: int, hi: int) -> int
+    decreases hi - lo,
+{
+    if hi <= lo {
+        0
+    } else {
+        row_sum(col_idx, vals, x, lo, hi - 1) + vals[hi - 1] * x[col_idx[hi - 1] as int]
+    }
+}
+
+/// The entry `i` of the product of the matrix and `x`.
+pub open spec fn row_value(row_ptr: Seq<usize>, col_idx: Seq<usize>, vals: Seq<u64>, x: Seq<u64>, i: int) -> int {
+    row_sum(col_idx, vals, x, row_ptr[i] as int, row_ptr[i + 1] as int)
+}
+
+/// Every entry of the product fits in `u64`.
+pub open spec fn fits(row_ptr: Seq<usize>, col_idx: Seq<usize>, vals: Seq<u64>, x: Seq<u64>) -> bool {
+    forall |i: int| 0 <= i < row_ptr.len() - 1 ==> #[trigger] row_value(row_ptr, col_idx, vals, x, i) <= u64::MAX
+}
+
+/// `y` is the product of the matrix and `x`.
+pub open spec fn multiplied(row_ptr: Seq<usize>, col_idx: Seq<usize>, vals: Seq<u64>, x: Seq<u64>, y: Seq<u64>) -> bool {
+    &&& y.len() + 1 == row_ptr.len()
+    &&& forall |i: int| 0 <= i < y.len() ==> #[trigger] y[i] == row_value(row_ptr, col_idx, vals, x, i)
+}
+
+proof fn lemma_row_sum_monotonic(col_idx: Seq<usize>, vals: Seq<u64>, x: Seq<u64>, lo: int, m1: int, m2: int)
+    requires
+        lo <= m1 <= m2,
+    ensures
+        0 <= row_sum(col_idx, vals, x, lo, m1) <= row_sum(col_idx, vals, x, lo, m2),
+    decreases m2 - lo,
+{
+    if m2 > lo {
+        if m1 < m2 {
+            lemma_row_sum_monotonic(col_idx, vals, x, lo, m1, m2 - 1);
+        } else {
+            lemma_row_sum_monotonic(col_idx, vals, x, lo, m1 - 1, m2 - 1);
+        }
+        let a = vals[m2 - 1] as int;
+        let b = x[col_idx[m2 - 1] as int] as int;
+        assert(a * b >= 0) by (nonlinear_arith)
+            requires a >= 0, b >= 0;
+    }
+}
+
+/// A sparse matrix with `cols` columns in compressed sparse row format.
+pub struct Csr {
+    pub cols: usize,
+    pub row_ptr: ArrayForSorting<usize>,
+    pub col_idx: ArrayForSorting<usize>,
+    pub vals: ArrayForSorting<u64>,
+}
+
+impl Csr {
+    pub open spec fn wf(&self) -> bool {
+        &&& is_full(self.row_ptr)
+        &&& is_full(self.col_idx)
+        &&& is_full(self.vals)
+        &&& self.vals.array.len() == self.col_idx.array.len()
+        &&& csr_wf(self.row_ptr.perms@.values(), self.col_idx.perms@.values(), self.cols as int)
+    }
+
+    /// Builds the matrix from its arrays, or returns `None` if they do not describe
+    /// a matrix with `cols` columns.
+    pub fn new(row_ptr: Vec<usize>, col_idx: Vec<usize>, vals: Vec<u64>, cols: usize) -> (res: Option<Self>)
+        ensures
+            res.is_some() ==> res.unwrap().wf() && res.unwrap().cols == cols,
+    {
+        let row_ptr = ArrayForSorting::new(row_ptr);
+        let col_idx = ArrayForSorting::new(col_idx);
+        let vals = ArrayForSorting::new(vals);
+        let nnz = (&*col_idx.array).length();
+        if (&*vals.array).length() != nnz {
+            return None;
+        }
+        proof {
+            region_array::lemma_values_len(&*col_idx.array, col_idx.perms@);
+        }
+        if !check_row_ptr(&row_ptr, nnz) || !check_col_idx(&col_idx, cols) {
+            return None;
+        }
+        Some(Csr { cols, row_ptr, col_idx, vals })
+    }
+}
+
+/// Checks that `row_ptr` starts at 0, does not decrease and ends at `nnz`.
+fn check_row_ptr(row_ptr: &ArrayForSorting<usize>, nnz: usize) -> (res: bool)
+    requires
+        is_full(*row_ptr),
+    ensures
+        res ==> {
+            let v = row_ptr.perms@.values();
+            &&& v.len() >= 1
+            &&& v[0] == 0
+            &&& v.last() == nnz
+            &&& forall |a: int, b: int| 0 <= a <= b < v.len() ==> v[a] <= v[b]
+        },
+{
+    let ghost v = row_ptr.perms@.values();
+    let n = (&*row_ptr.array).length();
+    proof {
+        region_array::lemma_values_len(&*row_ptr.array, row_ptr.perms@);
+    }
+    if n == 0 || *region_array::read(&*row_ptr.array, 0, Tracked(row_ptr.perms.borrow())) != 0 {
+        return false;
+    }
+    let mut i: usize = 1;
+    while i < n
+        invariant
+            is_full(*row_ptr),
+            v == row_ptr.perms@.values(),
+            v.len() == n,
+            1 <= i <= n,
+            v[0] == 0,
+            forall |a: int, b: int| 0 <= a <= b < i ==> v[a] <= v[b],
+    {
+        let prev = *region_array::read(&*row_ptr.array, i - 1, Tracked(row_ptr.perms.borrow()));
+        let cur = *region_array::read(&*row_ptr.array, i, Tracked(row_ptr.perms.borrow()));
+        if prev > cur {
+            return false;
+        }
+        assert forall |a: int, b: int| 0 <= a <= b < i + 1 implies v[a] <= v[b] by {
+            if b == i && a < i {
+                assert(v[a] <= v[i - 1]);
+            }
+        }
+        i += 1;
+    }
+    *region_array::read(&*row_ptr.array, n - 1, Tracked(row_ptr.perms.borrow())) == nnz
+}
+
+/// Checks that every column of `col_idx` is less than `cols`.
+fn check_col_idx(col_idx: &ArrayForSorting<usize>, cols: usize) -> (res: bool)
+    requires
+        is_full(*col_idx),
+    ensures
+        res ==> forall |k: int| 0 <= k < col_idx.perms@.values().len() ==> #[trigger] col_idx.perms@.values()[k] < cols,
+{
+    let ghost v = col_idx.perms@.values();
+    let n = (&*col_idx.array).length();
+    proof {
+        region_array::lemma_values_len(&*col_idx.array, col_idx.perms@);
+    }
+    let mut k: usize = 0;
+    while k < n
+        invariant
+            is_full(*col_idx),
+            v == col_idx.perms@.values(),
+            v.len() == n,
+            k <= n,
+            forall |j: int| 0 <= j < k ==> #[trigger] v[j] < cols,
+    {
+        if *region_array::read(&*col_idx.array, k, Tracked(col_idx.perms.borrow())) >= cols {
+            return false;
+        }
+        k += 1;
+    }
+    true
+}
+
+/// Computes the sum of `vals[k] * x[col_idx[k]]` over the entries `start..end`.
+fn row_entry(
+    ci_arr: &Array<usize>,
+    Tracked(ci): Tracked<&Region<usize>>,
+    v_arr: &Array<u64>,
+    Tracked(v): Tracked<&Region<u64>>,
+    x_arr: &Array<u64>,
+    Tracked(x): Tracked<&Region<u64>>,
+    start: usize, end: usize,
+) -> (res: u64)
+    requires
+        region_array::wf(*ci_arr, *ci),
+        ci.lo() == 0,
+        region_array::wf(*v_arr, *v),
+        v.lo() == 0,
+        v.hi() == ci.hi(),
+        region_array::wf(*x_arr, *x),
+        x.lo() == 0,
+        start <= end <= ci.hi(),
+        forall |k: int| 0 <= k < ci.hi() ==> #[trigger] ci.values()[k] < x.hi(),
+        row_sum(ci.values(), v.values(), x.values(), start as int, end as int) <= u64::MAX,
+    ensures
+        res == row_sum(ci.values(), v.values(), x.values(), start as int, end as int),
+{
+    let ghost civ = ci.values();
+    let ghost vv = v.values();
+    let ghost xv = x.values();
+    let mut acc: u64 = 0;
+    let mut k = start;
+    while k < end
+        invariant
+            region_array::wf(*ci_arr, *ci),
+            ci.lo() == 0,
+            region_array::wf(*v_arr, *v),
+            v.lo() == 0,
+            v.hi() == ci.hi(),
+            region_array::wf(*x_arr, *x),
+            x.lo() == 0,
+            civ == ci.values(),
+            vv == v.values(),
+            xv == x.values(),
+            start <= k <= end <= ci.hi(),
+            forall |j: int| 0 <= j < ci.hi() ==> #[trigger] ci.values()[j] < x.hi(),
+            row_sum(civ, vv, xv, start as int, end as int) <= u64::MAX,
+            acc == row_sum(civ, vv, xv, start as int, k as int),
+    {
+        let c = *region_array::read(ci_arr, k, Tracked(ci));
+        assert(c < x.hi());
+        let a = *region_array::read(v_arr, k, Tracked(v));
+        let b = *region_array::read(x_arr, c, Tracked(x));
+        proof {
+            assert(row_sum(civ, vv, xv, start as int, k + 1) == acc + a * b);
+            lemma_row_sum_monotonic(civ, vv, xv, start as int, k + 1, end as int);
+        }
+        acc = acc + a * b;
+        k += 1;
+    }
+    acc
+}
+
+/// Writes the entries `lo..hi` of the product of the matrix and `x` to `y`.
+fn spmv_rows(
+    rp_arr: &Array<usize>,
+    Tracked(rp): Tracked<&Region<usize>>,
+    ci_arr: &Array<usize>,
+    Tracked(ci): Tracked<&Region<usize>>,
+    v_arr: &Array<u64>,
+    Tracked(v): Tracked<&Region<u64>>,
+    x_arr: &Array<u64>,
+    Tracked(x): Tracked<&Region<u64>>,
+    y_arr: &Array<u64>,
+    Tracked(y): Tracked<&mut Region<u64>>,
+    lo: usize, hi: usize, cols: usize,
+)
+    requires
+        region_array::wf(*rp_arr, *rp),
+        rp.lo() == 0,
+        region_array::wf(*ci_arr, *ci),
+        ci.lo() == 0,
+        region_array::wf(*v_arr, *v),
+        v.lo() == 0,
+        v.hi() == ci.hi(),
+        region_array::wf(*x_arr, *x),
+        x.lo() == 0,
+        x.hi() == cols,
+        csr_wf(rp.values(), ci.values(), cols as int),
+        lo <= hi < rp.hi(),
+        fits(rp.values(), ci.values(), v.values(), x.values()),
+        region_array::wf(*y_arr, *old(y)),
+        old(y).lo() == lo,
+        old(y).hi() == hi,
+    ensures
+        region_array::wf(*y_arr, *y),
+        y.lo() == lo,
+        y.hi() == hi,
+        forall |k: int| 0 <= k < hi - lo ==>
+            #[trigger] y.values()[k] == row_value(rp.values(), ci.values(), v.values(), x.values(), lo + k),
+{
+    let ghost rpv = rp.values();
+    let ghost civ = ci.values();
+    let ghost vv = v.values();
+    let ghost xv = x.values();
+    proof {
+        region_array::lemma_values_len(rp_arr, *rp);
+        region_array::lemma_values_len(ci_arr, *ci);
+        region_array::lemma_values_len(y_arr, *y);
+    }
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*rp_arr, *rp),
+            rp.lo() == 0,
+            region_array::wf(*ci_arr, *ci),
+            ci.lo() == 0,
+            region_array::wf(*v_arr, *v),
+            v.lo() == 0,
+            v.hi() == ci.hi(),
+            region_array::wf(*x_arr, *x),
+            x.lo() == 0,
+            x.hi() == cols,
+            rpv == rp.values(),
+            civ == ci.values(),
+            vv == v.values(),
+            xv == x.values(),
+            rpv.len() == rp.hi(),
+            civ.len() == ci.hi(),
+            csr_wf(rpv, civ, cols as int),
+            lo <= i <= hi < rp.hi(),
+            fits(rpv, civ, vv, xv),
+            region_array::wf(*y_arr, *y),
+            y.lo() == lo,
+            y.hi() == hi,
+            y.values().len() == hi - lo,
+            forall |k: int| 0 <= k < i - lo ==> #[trigger] y.values()[k] == row_value(rpv, civ, vv, xv, lo + k),
+    {
+        let start = *region_array::read(rp_arr, i, Tracked(rp));
+        let end = *region_array::read(rp_arr, i + 1, Tracked(rp));
+        proof {
+            assert(rpv[i as int] <= rpv[i + 1]);
+            assert(rpv[i + 1] <= rpv[rpv.len() - 1]);
+            assert(row_value(rpv, civ, vv, xv, i as int) <= u64::MAX);
+        }
+        let s = row_entry(ci_arr, Tracked(ci), v_arr, Tracked(v), x_arr, Tracked(x), start, end);
+        region_array::replace(y_arr, i, s, Tracked(y));
+        assert(lo + (i - lo) == i);
+        i += 1;
+    }
+}
+
+/// Returns a row `mid` of `lo + 1..hi` such that the rows `lo..mid` hold about half of the
+/// entries of the rows `lo..hi`. The split only balances the work of the two halves.
+fn split_by_nnz(rp_arr: &Array<usize>, Tracked(rp): Tracked<&Region<usize>>, lo: usize, hi: usize) -> (mid: usize)
+    requires
+        region_array::wf(*rp_arr, *rp),
+        rp.lo() == 0,
+        lo + 2 <= hi < rp.hi(),
+    ensures
+        lo < mid < hi,
+{
+    let start = *region_array::read(rp_arr, lo, Tracked(rp));
+    let end = *region_array::read(rp_arr, hi, Tracked(rp));
+    let target = if start < end { start + (end - start) / 2 } else { start };
+    let (mut l, mut h) = (lo + 1, hi - 1);
+    while l < h
+        invariant
+            region_array::wf(*rp_arr, *rp),
+            rp.lo() == 0,
+            hi < rp.hi(),
+            lo < l <= h < hi,
+    {
+        let m = l + (h - l) / 2;
+        if *region_array::read(rp_arr, m, Tracked(rp)) < target {
+            l = m + 1;
+        } else {
+            h = m;
+        }
+    }
+    l
+}
+
+fn _spmv_parallel(
+    rp_arr: Arc<Array<usize>>,
+    rp_shared: Arc<Tracked<Region<usize>>>,
+    ci_arr: Arc<Array<usize>>,
+    ci_shared: Arc<Tracked<Region<usize>>>,
+    v_arr: Arc<Array<u64>>,
+    v_shared: Arc<Tracked<Region<u64>>>,
+    x_arr: Arc<Array<u64>>,
+    x_shared: Arc<Tracked<Region<u64>>>,
+    y_arr: Arc<Array<u64>>,
+    Tracked(y): Tracked<&mut Region<u64>>,
+    lo: usize, hi: usize, cols: usize,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        region_array::wf(*rp_arr, (*rp_shared)@),
+        (*rp_shared)@.lo() == 0,
+        region_array::wf(*ci_arr, (*ci_shared)@),
+        (*ci_shared)@.lo() == 0,
+        region_array::wf(*v_arr, (*v_shared)@),
+        (*v_shared)@.lo() == 0,
+        (*v_shared)@.hi() == (*ci_shared)@.hi(),
+        region_array::wf(*x_arr, (*x_shared)@),
+        (*x_shared)@.lo() == 0,
+        (*x_shared)@.hi() == cols,
+        csr_wf((*rp_shared)@.values(), (*ci_shared)@.values(), cols as int),
+        lo <= hi < (*rp_shared)@.hi(),
+        fits((*rp_shared)@.values(), (*ci_shared)@.values(), (*v_shared)@.values(), (*x_shared)@.values()),
+        region_array::wf(*y_arr, *old(y)),
+        old(y).lo() == lo,
+        old(y).hi() == hi,
+    ensures
+        ret.is_ok() ==> region_array::wf(*y_arr, *y) && y.lo() == lo && y.hi() == hi,
+        ret.is_ok() ==> forall |k: int| 0 <= k < hi - lo ==> #[trigger] y.values()[k] == row_value(
+            (*rp_shared)@.values(), (*ci_shared)@.values(), (*v_shared)@.values(), (*x_shared)@.values(), lo + k),
+{
+    let ghost rpv = (*rp_shared)@.values();
+    let ghost civ = (*ci_shared)@.values();
+    let ghost vv = (*v_shared)@.values();
+    let ghost xv = (*x_shared)@.values();
+    let rp_region: &Tracked<Region<usize>> = &*rp_shared;
+    if hi - lo < 2 {
+        let ci_region: &Tracked<Region<usize>> = &*ci_shared;
+        let v_region: &Tracked<Region<u64>> = &*v_shared;
+        let x_region: &Tracked<Region<u64>> = &*x_shared;
+        spmv_rows(
+            &*rp_arr, Tracked(rp_region.borrow()), &*ci_arr, Tracked(ci_region.borrow()),
+            &*v_arr, Tracked(v_region.borrow()), &*x_arr, Tracked(x_region.borrow()),
+            &*y_arr, Tracked(y), lo, hi, cols,
+        );
+        return Ok(());
+    }
+    // the rows are split by their number of entries, which is their amount of work
+    let start = *region_array::read(&*rp_arr, lo, Tracked(rp_region.borrow()));
+    let end = *region_array::read(&*rp_arr, hi, Tracked(rp_region.borrow()));
+    proof {
+        assert(rpv[lo as int] <= rpv[hi as int]);
+    }
+    if end - start <= threshold {
+        let ci_region: &Tracked<Region<usize>> = &*ci_shared;
+        let v_region: &Tracked<Region<u64>> = &*v_shared;
+        let x_region: &Tracked<Region<u64>> = &*x_shared;
+        spmv_rows(
+            &*rp_arr, Tracked(rp_region.borrow()), &*ci_arr, Tracked(ci_region.borrow()),
+            &*v_arr, Tracked(v_region.borrow()), &*x_arr, Tracked(x_region.borrow()),
+            &*y_arr, Tracked(y), lo, hi, cols,
+        );
+        return Ok(());
+    }
+
+    let mid = split_by_nnz(&*rp_arr, Tracked(rp_region.borrow()), lo, hi);
+    let tracked left_y = region_array::split_front(&*y_arr, mid, y);
+    let tracked mut right_y = region_array::split_front(&*y_arr, hi, y);
+
+    let rp_arr_r1 = Arc::clone(&rp_arr);
+    let rp_shared_r1 = Arc::clone(&rp_shared);
+    let ci_arr_r1 = Arc::clone(&ci_arr);
+    let ci_shared_r1 = Arc::clone(&ci_shared);
+    let v_arr_r1 = Arc::clone(&v_arr);
+    let v_shared_r1 = Arc::clone(&v_shared);
+    let x_arr_r1 = Arc::clone(&x_arr);
+    let x_shared_r1 = Arc::clone(&x_shared);
+    let y_arr_r1 = Arc::clone(&y_arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<u64>>, ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*y_arr, ret.unwrap()@) && ret.unwrap()@.lo() == lo && ret.unwrap()@.hi() == mid,
+            ret.is_ok() ==> forall |k: int| 0 <= k < mid - lo ==>
+                #[trigger] ret.unwrap()@.values()[k] == row_value(rpv, civ, vv, xv, lo + k),
+        {
+            let tracked mut left_y = left_y;
+            match _spmv_parallel(
+                rp_arr_r1, rp_shared_r1, ci_arr_r1, ci_shared_r1, v_arr_r1, v_shared_r1, x_arr_r1, x_shared_r1,
+                y_arr_r1, Tracked(&mut left_y), lo, mid, cols, threshold,
+            ) {
+                Ok(()) => Ok(Tracked(left_y)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match _spmv_parallel(
+        Arc::clone(&rp_arr), Arc::clone(&rp_shared), Arc::clone(&ci_arr), Arc::clone(&ci_shared),
+        Arc::clone(&v_arr), Arc::clone(&v_shared), Arc::clone(&x_arr), Arc::clone(&x_shared),
+        Arc::clone(&y_arr), Tracked(&mut right_y), mid, hi, cols, threshold,
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut left_y) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::lemma_values_len(&*y_arr, left_y);
+        region_array::lemma_values_len(&*y_arr, right_y);
+        let l = left_y.values();
+        let r = right_y.values();
+        assert forall |k: int| 0 <= k < hi - lo implies #[trigger] (l + r)[k] == row_value(rpv, civ, vv, xv, lo + k) by {
+            if k >= l.len() {
+                assert((l + r)[k] == r[k - l.len()]);
+                assert(mid + (k - l.len()) == lo + k);
+            }
+        }
+        region_array::merge(&*y_arr, &mut left_y, right_y);
+        vstd::modes::tracked_swap(y, &mut left_y);
+    }
+    Ok(())
+}
+
+/// Writes the product of `a` and `x` to `y`. The rows are split in two parts holding about
+/// the same number of entries, which are multiplied in separate threads while they hold more than
+/// `threshold` entries. Every thread owns its rows of `y` and shares `a` and `x` through `Arc`s.
+/// The entries of the product must fit in `u64`.
+pub fn par_spmv(
+    a: &mut Csr,
+    x: &mut ArrayForSorting<u64>,
+    y: &mut ArrayForSorting<u64>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(a).wf(),
+        is_full(*old(x)),
+        is_full(*old(y)),
+        old(x).array.len() == old(a).cols,
+        old(y).array.len() + 1 == old(a).row_ptr.array.len(),
+        fits(old(a).row_ptr.perms@.values(), old(a).col_idx.perms@.values(), old(a).vals.perms@.values(), old(x).perms@.values()),
+    ensures
+        ret.is_ok() ==> a.wf() && a.cols == old(a).cols,
+        ret.is_ok() ==> a.row_ptr.perms@.values() == old(a).row_ptr.perms@.values(),
+        ret.is_ok() ==> a.col_idx.perms@.values() == old(a).col_idx.perms@.values(),
+        ret.is_ok() ==> a.vals.perms@.values() == old(a).vals.perms@.values(),
+        ret.is_ok() ==> is_full(*x) && x.perms@.values() == old(x).perms@.values(),
+        ret.is_ok() ==> is_full(*y),
+        ret.is_ok() ==> multiplied(
+            old(a).row_ptr.perms@.values(), old(a).col_idx.perms@.values(), old(a).vals.perms@.values(),
+            old(x).perms@.values(), y.perms@.values(),
+        ),
+{
+    let rows = (&*y.array).length();
+    let cols = a.cols;
+    proof {
+        region_array::lemma_values_len(&*a.row_ptr.array, a.row_ptr.perms@);
+        region_array::lemma_values_len(&*a.col_idx.array, a.col_idx.perms@);
+    }
+    let rp_shared = share(&mut a.row_ptr);
+    let ci_shared = share(&mut a.col_idx);
+    let v_shared = share(&mut a.vals);
+    let x_shared = share(x);
+    let ghost rpv = (*rp_shared)@.values();
+    let ghost civ = (*ci_shared)@.values();
+    let ghost vv = (*v_shared)@.values();
+    let ghost xv = (*x_shared)@.values();
+
+    let ret = _spmv_parallel(
+        Arc::clone(&a.row_ptr.array),
+        Arc::clone(&rp_shared),
+        Arc::clone(&a.col_idx.array),
+        Arc::clone(&ci_shared),
+        Arc::clone(&a.vals.array),
+        Arc::clone(&v_shared),
+        Arc::clone(&x.array),
+        Arc::clone(&x_shared),
+        Arc::clone(&y.array),
+        Tracked(y.perms.borrow_mut()),
+        0,
+        rows,
+        cols,
+        threshold,
+    );
+    // the inputs are given back their regions before any error is returned
+    let rp_ret = unshare(&mut a.row_ptr, rp_shared);
+    let ci_ret = unshare(&mut a.col_idx, ci_shared);
+    let v_ret = unshare(&mut a.vals, v_shared);
+    let x_ret = unshare(x, x_shared);
+    if ret.is_err() || rp_ret.is_err() || ci_ret.is_err() || v_ret.is_err() || x_ret.is_err() {
+        return Err(());
+    }
+    proof {
+        region_array::lemma_values_len(&*y.array, y.perms@);
+        assert forall |i: int| 0 <= i < rows implies #[trigger] y.perms@.values()[i] == row_value(rpv, civ, vv, xv, i) by {
+            assert(y.perms@.values()[i] == row_value(rpv, civ, vv, xv, 0 + i));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_par_spmv() {
+    use crate::test_data::thresholds;
+
+    let (rows, cols) = (300, 200);
+    let mut row_ptr = vec![0];
+    let mut col_idx = Vec::new();
+    let mut vals = Vec::new();
+    for i in 0..rows {
+        // some rows are empty and one is much heavier than the others
+        let len = if i == 150 { cols } else { (i * 7) % 13 };
+        for t in 0..len {
+            col_idx.push((i * 31 + t * 17) % cols);
+            vals.push(((i + t) % 10) as u64);
+        }
+        row_ptr.push(col_idx.len());
+    }
+    let x_data: Vec<u64> = (0..cols).map(|j| (j % 7) as u64).collect();
+    let expected: Vec<u64> = (0..rows)
+        .map(|i| (row_ptr[i]..row_ptr[i + 1]).map(|k| vals[k] * x_data[col_idx[k]]).sum())
+        .collect();
+
+    for threshold in thresholds(rows).into_iter().chain([0]) {
+        let mut a = Csr::new(row_ptr.clone(), col_idx.clone(), vals.clone(), cols).unwrap();
+        let mut x = ArrayForSorting::new(x_data.clone());
+        let mut y = ArrayForSorting::new(vec![0; rows]);
+        par_spmv(&mut a, &mut x, &mut y, threshold).unwrap();
+        assert_eq!(y.clone_to_vec(), expected);
+        assert_eq!(x.clone_to_vec(), x_data);
+    }
+
+    assert!(Csr::new(vec![0, 2, 1], vec![0, 1], vec![1, 1], 2).is_none());
+    assert!(Csr::new(vec![0, 1, 2], vec![0, 2], vec![1, 1], 2).is_none());
+    assert!(Csr::new(vec![0, 1, 3], vec![0, 1], vec![1, 1], 2).is_none());
+}
+
+}
@@ -0,0 +1,708 @@
+use vstd::prelude::*;
+
+use std::sync::Arc;
+
+verus! {
+
+use crate::{
+    permissions_array::Array,
+    region_array::{self, Region},
+    mergesort::{self, ArrayForSorting},
+    matrix::{self, Matrix, Tile},
+};
+
+/// `op` computes the new value `f(left, cell, right)` of a cell and can be called on anything.
+pub open spec fn computes<T, F: Fn(T, T, T) -> T>(op: F, f: spec_fn(T, T, T) -> T) -> bool {
+    &&& forall |a: T, b: T, c: T| #[trigger] op.requires((a, b, c))
+    &&& forall |a: T, b: T, c: T, r: T| #[trigger] op.ensures((a, b, c), r) ==> r == f(a, b, c)
+}
+
+/// One Jacobi step: every inner cell becomes `f` of its neighbourhood in `v`,
+/// the first and the last cell keep their values.
+pub open spec fn jacobi_step<T>(v: Seq<T>, f: spec_fn(T, T, T) -> T) -> Seq<T> {
+    Seq::new(v.len(), |i: int| if 0 < i < v.len() - 1 { f(v[i - 1], v[i], v[i + 1]) } else { v[i] })
+}
+
+pub open spec fn iterated<T>(v: Seq<T>, f: spec_fn(T, T, T) -> T, steps: nat) -> Seq<T>
+    decreases steps,
+{
+    if steps == 0 {
+        v
+    } else {
+        jacobi_step(iterated(v, f, (steps - 1) as nat), f)
+    }
+}
+
+/// `op` computes the new value `f(up, left, cell, right, down)` of a cell and can be called on anything.
+pub open spec fn computes_2d<T, F: Fn(T, T, T, T, T) -> T>(op: F, f: spec_fn(T, T, T, T, T) -> T) -> bool {
+    &&& forall |a: T, b: T, c: T, d: T, e: T| #[trigger] op.requires((a, b, c, d, e))
+    &&& forall |a: T, b: T, c: T, d: T, e: T, r: T| #[trigger] op.ensures((a, b, c, d, e), r) ==> r == f(a, b, c, d, e)
+}
+
+/// Like `jacobi_step` with the five-point neighbourhood of every cell off the border of `v`.
+pub open spec fn jacobi_step_2d<T>(v: Seq<Seq<T>>, f: spec_fn(T, T, T, T, T) -> T) -> Seq<Seq<T>> {
+    Seq::new(v.len(), |i: int| Seq::new(v[i].len(), |j: int|
+        if 0 < i < v.len() - 1 && 0 < j < v[i].len() - 1 {
+            f(v[i - 1][j], v[i][j - 1], v[i][j], v[i][j + 1], v[i + 1][j])
+        } else {
+            v[i][j]
+        }))
+}
+
+pub open spec fn iterated_2d<T>(v: Seq<Seq<T>>, f: spec_fn(T, T, T, T, T) -> T, steps: nat) -> Seq<Seq<T>>
+    decreases steps,
+{
+    if steps == 0 {
+        v
+    } else {
+        jacobi_step_2d(iterated_2d(v, f, (steps - 1) as nat), f)
+    }
+}
+
+/// Writes the cells `lo..hi` of one step of the stencil of `src`, which has `n` cells.
+/// `src` is read as a whole, so the neighbours of the first and the last cell of the range
+/// are read like any other cell, even though other threads write them in `dst`.
+fn stencil_range<T: Copy, F: Fn(T, T, T) -> T>(
+    src_arr: &Array<T>,
+    Tracked(src): Tracked<&Region<T>>,
+    dst_arr: &Array<T>,
+    Tracked(dst): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize, n: usize,
+    op: F,
+    Ghost(f): Ghost<spec_fn(T, T, T) -> T>,
+)
+    requires
+        region_array::wf(*src_arr, *src),
+        src.lo() == 0,
+        src.hi() == n,
+        region_array::wf(*dst_arr, *old(dst)),
+        old(dst).lo() == lo,
+        old(dst).hi() == hi,
+        hi <= n,
+        computes(op, f),
+    ensures
+        region_array::wf(*dst_arr, *dst),
+        dst.lo() == lo,
+        dst.hi() == hi,
+        forall |k: int| 0 <= k < hi - lo ==> #[trigger] dst.values()[k] == jacobi_step(src.values(), f)[lo + k],
+{
+    let ghost s = src.values();
+    proof {
+        region_array::lemma_values_len(src_arr, *src);
+        region_array::lemma_values_len(dst_arr, *dst);
+    }
+    let mut i = lo;
+    while i < hi
+        invariant
+            region_array::wf(*src_arr, *src),
+            src.lo() == 0,
+            src.hi() == n,
+            s == src.values(),
+            s.len() == n,
+            region_array::wf(*dst_arr, *dst),
+            dst.lo() == lo,
+            dst.hi() == hi,
+            dst.values().len() == hi - lo,
+            hi <= n,
+            lo <= i <= hi,
+            computes(op, f),
+            forall |k: int| 0 <= k < i - lo ==> #[trigger] dst.values()[k] == jacobi_step(s, f)[lo + k],
+    {
+        let y = if 0 < i && i + 1 < n {
+            let l = *region_array::read(src_arr, i - 1, Tracked(src));
+            let c = *region_array::read(src_arr, i, Tracked(src));
+            let r = *region_array::read(src_arr, i + 1, Tracked(src));
+            op(l, c, r)
+        } else {
+            *region_array::read(src_arr, i, Tracked(src))
+        };
+        proof {
+            assert(y == jacobi_step(s, f)[i as int]);
+        }
+        region_array::replace(dst_arr, i, y, Tracked(dst));
+        assert(lo + (i - lo) == i);
+        i += 1;
+    }
+}
+
+fn _stencil_parallel<T: Copy + Send + Sync + 'static, F: Fn(T, T, T) -> T + Copy + Send + 'static>(
+    src_arr: Arc<Array<T>>,
+    src_shared: Arc<Tracked<Region<T>>>,
+    dst_arr: Arc<Array<T>>,
+    Tracked(dst): Tracked<&mut Region<T>>,
+    lo: usize, hi: usize, n: usize,
+    op: F,
+    Ghost(f): Ghost<spec_fn(T, T, T) -> T>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        region_array::wf(*src_arr, (*src_shared)@),
+        (*src_shared)@.lo() == 0,
+        (*src_shared)@.hi() == n,
+        region_array::wf(*dst_arr, *old(dst)),
+        old(dst).lo() == lo,
+        old(dst).hi() == hi,
+        hi <= n,
+        computes(op, f),
+    ensures
+        ret.is_ok() ==> region_array::wf(*dst_arr, *dst) && dst.lo() == lo && dst.hi() == hi,
+        ret.is_ok() ==> forall |k: int| 0 <= k < hi - lo ==>
+            #[trigger] dst.values()[k] == jacobi_step((*src_shared)@.values(), f)[lo + k],
+{
+    let ghost w = jacobi_step((*src_shared)@.values(), f);
+    if hi - lo <= threshold || hi - lo < 2 {
+        let src_region: &Tracked<Region<T>> = &*src_shared;
+        stencil_range(&*src_arr, Tracked(src_region.borrow()), &*dst_arr, Tracked(dst), lo, hi, n, op, Ghost(f));
+        return Ok(());
+    }
+
+    let mid = lo + (hi - lo) / 2;
+    let tracked left_dst = region_array::split_front(&*dst_arr, mid, dst);
+    let tracked mut right_dst = region_array::split_front(&*dst_arr, hi, dst);
+
+    let src_arr_r1 = Arc::clone(&src_arr);
+    let src_shared_r1 = Arc::clone(&src_shared);
+    let dst_arr_r1 = Arc::clone(&dst_arr);
+
+    let left = vstd::thread::spawn(move || -> (ret: Result<Tracked<Region<T>>, ()>)
+        ensures
+            ret.is_ok() ==> region_array::wf(*dst_arr, ret.unwrap()@) && ret.unwrap()@.lo() == lo && ret.unwrap()@.hi() == mid,
+            ret.is_ok() ==> forall |k: int| 0 <= k < mid - lo ==> #[trigger] ret.unwrap()@.values()[k] == w[lo + k],
+        {
+            let tracked mut left_dst = left_dst;
+            match _stencil_parallel(
+                src_arr_r1, src_shared_r1, dst_arr_r1, Tracked(&mut left_dst), lo, mid, n, op, Ghost(f), threshold,
+            ) {
+                Ok(()) => Ok(Tracked(left_dst)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match _stencil_parallel(
+        Arc::clone(&src_arr), Arc::clone(&src_shared), Arc::clone(&dst_arr), Tracked(&mut right_dst),
+        mid, hi, n, op, Ghost(f), threshold,
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut left_dst) = match left.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        region_array::lemma_values_len(&*dst_arr, left_dst);
+        region_array::lemma_values_len(&*dst_arr, right_dst);
+        let l = left_dst.values();
+        let r = right_dst.values();
+        assert forall |k: int| 0 <= k < hi - lo implies #[trigger] (l + r)[k] == w[lo + k] by {
+            if k >= l.len() {
+                assert((l + r)[k] == r[k - l.len()]);
+                assert(mid + (k - l.len()) == lo + k);
+            }
+        }
+        region_array::merge(&*dst_arr, &mut left_dst, right_dst);
+        vstd::modes::tracked_swap(dst, &mut left_dst);
+    }
+    Ok(())
+}
+
+/// Writes one step of the stencil of `src` to `dst`. The cells of `dst` are split in halves,
+/// which are written by separate threads while they are longer than `threshold`;
+/// all of them share the whole of `src` through an `Arc`.
+pub fn stencil_step<T: Copy + Send + Sync + 'static, F: Fn(T, T, T) -> T + Copy + Send + 'static>(
+    src: &mut ArrayForSorting<T>,
+    dst: &mut ArrayForSorting<T>,
+    op: F,
+    Ghost(f): Ghost<spec_fn(T, T, T) -> T>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(src).perms@.lo() == 0,
+        old(src).perms@.hi() == old(src).array.len(),
+        region_array::wf(*old(src).array, (old(src).perms@)),
+        old(dst).perms@.lo() == 0,
+        old(dst).perms@.hi() == old(dst).array.len(),
+        region_array::wf(*old(dst).array, (old(dst).perms@)),
+        old(src).array.len() == old(dst).array.len(),
+        computes(op, f),
+    ensures
+        src.array == old(src).array,
+        dst.array == old(dst).array,
+        ret.is_ok() ==> region_array::wf(*src.array, (src.perms@)),
+        ret.is_ok() ==> src.perms@.lo() == 0 && src.perms@.hi() == src.array.len(),
+        ret.is_ok() ==> src.perms@.values() == old(src).perms@.values(),
+        ret.is_ok() ==> region_array::wf(*dst.array, (dst.perms@)),
+        ret.is_ok() ==> dst.perms@.lo() == 0 && dst.perms@.hi() == dst.array.len(),
+        ret.is_ok() ==> dst.perms@.values() == jacobi_step(old(src).perms@.values(), f),
+{
+    let n = (&*src.array).length();
+    proof {
+        region_array::lemma_values_len(&*src.array, src.perms@);
+    }
+
+    // leave an empty region in `src` while the threads share it
+    let src_shared = mergesort::share(src);
+    let ghost s = (*src_shared)@.values();
+
+    let ret = _stencil_parallel(
+        Arc::clone(&src.array),
+        Arc::clone(&src_shared),
+        Arc::clone(&dst.array),
+        Tracked(dst.perms.borrow_mut()),
+        0,
+        n,
+        n,
+        op,
+        Ghost(f),
+        threshold,
+    );
+    // `src` is given back its region before any error is returned
+    if mergesort::unshare(src, src_shared).is_err() || ret.is_err() {
+        return Err(());
+    }
+    proof {
+        region_array::lemma_values_len(&*dst.array, dst.perms@);
+        assert(dst.perms@.values() =~= jacobi_step(s, f));
+    }
+    Ok(())
+}
+
+/// Runs `steps` steps of the stencil on `a`, using `b` as the second buffer: every step reads
+/// one of them and writes the other, so the result ends up in `a` after an even number of steps
+/// and in `b` after an odd one.
+pub fn par_stencil<T: Copy + Send + Sync + 'static, F: Fn(T, T, T) -> T + Copy + Send + 'static>(
+    a: &mut ArrayForSorting<T>,
+    b: &mut ArrayForSorting<T>,
+    steps: usize,
+    op: F,
+    Ghost(f): Ghost<spec_fn(T, T, T) -> T>,
+    threshold: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(a).perms@.lo() == 0,
+        old(a).perms@.hi() == old(a).array.len(),
+        region_array::wf(*old(a).array, (old(a).perms@)),
+        old(b).perms@.lo() == 0,
+        old(b).perms@.hi() == old(b).array.len(),
+        region_array::wf(*old(b).array, (old(b).perms@)),
+        old(a).array.len() == old(b).array.len(),
+        computes(op, f),
+    ensures
+        ret.is_ok() ==> region_array::wf(*a.array, (a.perms@)),
+        ret.is_ok() ==> a.perms@.lo() == 0 && a.perms@.hi() == a.array.len(),
+        ret.is_ok() ==> region_array::wf(*b.array, (b.perms@)),
+        ret.is_ok() ==> b.perms@.lo() == 0 && b.perms@.hi() == b.array.len(),
+        ret.is_ok() && steps % 2 == 0 ==> a.perms@.values() == iterated(old(a).perms@.values(), f, steps as nat),
+        ret.is_ok() && steps % 2 == 1 ==> b.perms@.values() == iterated(old(a).perms@.values(), f, steps as nat),
+{
+    let ghost v = a.perms@.values();
+    let mut t: usize = 0;
+    while t < steps
+        invariant
+            region_array::wf(*a.array, (a.perms@)),
+            a.perms@.lo() == 0,
+            a.perms@.hi() == a.array.len(),
+            region_array::wf(*b.array, (b.perms@)),
+            b.perms@.lo() == 0,
+            b.perms@.hi() == b.array.len(),
+            a.array.len() == b.array.len(),
+            computes(op, f),
+            t <= steps,
+            t % 2 == 0 ==> a.perms@.values() == iterated(v, f, t as nat),
+            t % 2 == 1 ==> b.perms@.values() == iterated(v, f, t as nat),
+    {
+        // the buffers swap their roles at every step
+        if t % 2 == 0 {
+            match stencil_step(a, b, op, Ghost(f), threshold) {
+                Ok(()) => {},
+                Err(_) => {return Err(());},
+            };
+        } else {
+            match stencil_step(b, a, op, Ghost(f), threshold) {
+                Ok(()) => {},
+                Err(_) => {return Err(());},
+            };
+        }
+        proof {
+            assert(((t + 1) - 1) as nat == t as nat);
+        }
+        t += 1;
+    }
+    Ok(())
+}
+
+/// Writes the cells of the tile `dst` of one step of the five-point stencil of `src`.
+fn stencil_tile_2d<T: Copy, F: Fn(T, T, T, T, T) -> T>(
+    src_arr: &Array<T>,
+    Tracked(src): Tracked<&Tile<T>>,
+    dst_arr: &Array<T>,
+    Tracked(dst): Tracked<&mut Tile<T>>,
+    rows: usize, cols: usize,
+    r0: usize, r1: usize, c0: usize, c1: usize,
+    op: F,
+    Ghost(f): Ghost<spec_fn(T, T, T, T, T) -> T>,
+)
+    requires
+        matrix::wf(*src_arr, *src),
+        src.is_full(rows, cols),
+        matrix::wf(*dst_arr, *old(dst)),
+        old(dst).cols() == cols,
+        old(dst).r0() == r0 && old(dst).r1() == r1 && old(dst).c0() == c0 && old(dst).c1() == c1,
+        r1 <= rows,
+        computes_2d(op, f),
+    ensures
+        matrix::wf(*dst_arr, *dst),
+        dst.same_bounds(*old(dst)),
+        forall |r: int, q: int| dst.contains(r, q) ==>
+            #[trigger] dst.get(r, q) == jacobi_step_2d(src.values(), f)[r][q],
+{
+    let ghost sv = src.values();
+    let ghost w = jacobi_step_2d(sv, f);
+    proof {
+        matrix::lemma_bounds(dst_arr, *dst);
+    }
+    let mut i = r0;
+    while i < r1
+        invariant
+            matrix::wf(*src_arr, *src),
+            src.is_full(rows, cols),
+            sv == src.values(),
+            w == jacobi_step_2d(sv, f),
+            matrix::wf(*dst_arr, *dst),
+            dst.same_bounds(*old(dst)),
+            old(dst).cols() == cols,
+            old(dst).r0() == r0 && old(dst).r1() == r1 && old(dst).c0() == c0 && old(dst).c1() == c1,
+            c1 <= cols,
+            r1 <= rows,
+            r0 <= i <= r1,
+            computes_2d(op, f),
+            forall |r: int, q: int| dst.contains(r, q) && r < i ==> #[trigger] dst.get(r, q) == w[r][q],
+    {
+        let mut j = c0;
+        while j < c1
+            invariant
+                matrix::wf(*src_arr, *src),
+                src.is_full(rows, cols),
+                sv == src.values(),
+                w == jacobi_step_2d(sv, f),
+                matrix::wf(*dst_arr, *dst),
+                dst.same_bounds(*old(dst)),
+                old(dst).cols() == cols,
+                old(dst).r0() == r0 && old(dst).r1() == r1 && old(dst).c0() == c0 && old(dst).c1() == c1,
+                c1 <= cols,
+                r1 <= rows,
+                r0 <= i < r1,
+                c0 <= j <= c1,
+                computes_2d(op, f),
+                forall |r: int, q: int| dst.contains(r, q) && (r < i || (r == i && q < j)) ==>
+                    #[trigger] dst.get(r, q) == w[r][q],
+        {
+            let y = if 0 < i && i + 1 < rows && 0 < j && j + 1 < cols {
+                let up = *matrix::read(src_arr, cols, i - 1, j, Tracked(src));
+                let left = *matrix::read(src_arr, cols, i, j - 1, Tracked(src));
+                let cell = *matrix::read(src_arr, cols, i, j, Tracked(src));
+                let right = *matrix::read(src_arr, cols, i, j + 1, Tracked(src));
+                let down = *matrix::read(src_arr, cols, i + 1, j, Tracked(src));
+                op(up, left, cell, right, down)
+            } else {
+                *matrix::read(src_arr, cols, i, j, Tracked(src))
+            };
+            proof {
+                assert(sv.len() == rows);
+                assert(sv[i as int].len() == cols);
+                assert(y == w[i as int][j as int]);
+            }
+            matrix::replace(dst_arr, cols, i, j, y, Tracked(dst));
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+fn _stencil_parallel_2d<T: Copy + Send + Sync + 'static, F: Fn(T, T, T, T, T) -> T + Copy + Send + 'static>(
+    src_arr: Arc<Array<T>>,
+    src_shared: Arc<Tracked<Tile<T>>>,
+    dst_arr: Arc<Array<T>>,
+    Tracked(dst): Tracked<&mut Tile<T>>,
+    rows: usize, cols: usize,
+    r0: usize, r1: usize, c0: usize, c1: usize,
+    op: F,
+    Ghost(f): Ghost<spec_fn(T, T, T, T, T) -> T>,
+    tile_rows: usize, tile_cols: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        matrix::wf(*src_arr, (*src_shared)@),
+        (*src_shared)@.is_full(rows, cols),
+        matrix::wf(*dst_arr, *old(dst)),
+        old(dst).cols() == cols,
+        old(dst).r0() == r0 && old(dst).r1() == r1 && old(dst).c0() == c0 && old(dst).c1() == c1,
+        r1 <= rows,
+        computes_2d(op, f),
+    ensures
+        ret.is_ok() ==> matrix::wf(*dst_arr, *dst) && dst.same_bounds(*old(dst)),
+        ret.is_ok() ==> forall |r: int, q: int| dst.contains(r, q) ==>
+            #[trigger] dst.get(r, q) == jacobi_step_2d((*src_shared)@.values(), f)[r][q],
+{
+    let ghost w = jacobi_step_2d((*src_shared)@.values(), f);
+    proof {
+        matrix::lemma_bounds(&*dst_arr, *dst);
+    }
+    let split_rows_first = r1 - r0 > tile_rows && r1 - r0 >= 2;
+    if !split_rows_first && (c1 - c0 <= tile_cols || c1 - c0 < 2) {
+        let src_tile: &Tracked<Tile<T>> = &*src_shared;
+        stencil_tile_2d(
+            &*src_arr, Tracked(src_tile.borrow()), &*dst_arr, Tracked(dst),
+            rows, cols, r0, r1, c0, c1, op, Ghost(f),
+        );
+        return Ok(());
+    }
+
+    let mid = if split_rows_first { r0 + (r1 - r0) / 2 } else { c0 + (c1 - c0) / 2 };
+    let tracked first_tile = if split_rows_first {
+        matrix::split_rows(&*dst_arr, mid, dst)
+    } else {
+        matrix::split_cols(&*dst_arr, mid, dst)
+    };
+    let tracked mut second_tile = if split_rows_first {
+        matrix::split_rows(&*dst_arr, r1, dst)
+    } else {
+        matrix::split_cols(&*dst_arr, c1, dst)
+    };
+    let ghost first_bounds = first_tile;
+    let (r0_1, r1_1, c0_1, c1_1) = if split_rows_first { (r0, mid, c0, c1) } else { (r0, r1, c0, mid) };
+    let (r0_2, r1_2, c0_2, c1_2) = if split_rows_first { (mid, r1, c0, c1) } else { (r0, r1, mid, c1) };
+
+    let src_arr_r1 = Arc::clone(&src_arr);
+    let src_shared_r1 = Arc::clone(&src_shared);
+    let dst_arr_r1 = Arc::clone(&dst_arr);
+
+    let first = vstd::thread::spawn(move || -> (ret: Result<Tracked<Tile<T>>, ()>)
+        ensures
+            ret.is_ok() ==> matrix::wf(*dst_arr, ret.unwrap()@) && ret.unwrap()@.same_bounds(first_bounds),
+            ret.is_ok() ==> forall |r: int, q: int| ret.unwrap()@.contains(r, q) ==>
+                #[trigger] ret.unwrap()@.get(r, q) == w[r][q],
+        {
+            let tracked mut first_tile = first_tile;
+            match _stencil_parallel_2d(
+                src_arr_r1, src_shared_r1, dst_arr_r1, Tracked(&mut first_tile),
+                rows, cols, r0_1, r1_1, c0_1, c1_1, op, Ghost(f), tile_rows, tile_cols,
+            ) {
+                Ok(()) => Ok(Tracked(first_tile)),
+                Err(_) => Err(()),
+            }
+        }
+    );
+
+    match _stencil_parallel_2d(
+        Arc::clone(&src_arr), Arc::clone(&src_shared), Arc::clone(&dst_arr), Tracked(&mut second_tile),
+        rows, cols, r0_2, r1_2, c0_2, c1_2, op, Ghost(f), tile_rows, tile_cols,
+    ) {
+        Ok(()) => {},
+        Err(_) => {return Err(());},
+    };
+
+    let Tracked(mut first_tile) = match first.join() {
+        Result::Ok(Ok(l)) => {
+            l
+        },
+        _ => {
+            return Result::Err(());
+        }
+    };
+
+    proof {
+        let ghost first_done = first_tile;
+        let ghost second_done = second_tile;
+        if split_rows_first {
+            matrix::merge_rows(&*dst_arr, &mut first_tile, second_tile);
+        } else {
+            matrix::merge_cols(&*dst_arr, &mut first_tile, second_tile);
+        }
+        vstd::modes::tracked_swap(dst, &mut first_tile);
+        assert forall |r: int, q: int| dst.contains(r, q) implies #[trigger] dst.get(r, q) == w[r][q] by {
+            if first_done.contains(r, q) {
+                assert(first_done.get(r, q) == w[r][q]);
+            } else {
+                assert(second_done.contains(r, q));
+                assert(second_done.get(r, q) == w[r][q]);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes one step of the five-point stencil of `src` to `dst`. The tile of `dst` is split
+/// like in `matrix::par_matmul`, and all the threads share the whole of `src` through an `Arc`.
+pub fn stencil_step_2d<T: Copy + Send + Sync + 'static, F: Fn(T, T, T, T, T) -> T + Copy + Send + 'static>(
+    src: &mut Matrix<T>,
+    dst: &mut Matrix<T>,
+    op: F,
+    Ghost(f): Ghost<spec_fn(T, T, T, T, T) -> T>,
+    tile_rows: usize,
+    tile_cols: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(src).full(),
+        old(dst).full(),
+        old(src).rows == old(dst).rows,
+        old(src).cols == old(dst).cols,
+        computes_2d(op, f),
+    ensures
+        src.rows == old(src).rows && src.cols == old(src).cols,
+        dst.rows == old(dst).rows && dst.cols == old(dst).cols,
+        ret.is_ok() ==> src.full() && src.perms@.values() == old(src).perms@.values(),
+        ret.is_ok() ==> dst.full() && dst.perms@.values() == jacobi_step_2d(old(src).perms@.values(), f),
+{
+    let rows = src.rows;
+    let cols = src.cols;
+
+    // leave an empty tile in `src` while the threads share it
+    let src_shared = matrix::share(src);
+    let ghost sv = (*src_shared)@.values();
+
+    let ret = _stencil_parallel_2d(
+        Arc::clone(&src.array),
+        Arc::clone(&src_shared),
+        Arc::clone(&dst.array),
+        Tracked(dst.perms.borrow_mut()),
+        rows, cols,
+        0, rows, 0, cols,
+        op, Ghost(f),
+        tile_rows, tile_cols,
+    );
+    // `src` is given back its tile before any error is returned
+    if matrix::unshare(src, src_shared).is_err() || ret.is_err() {
+        return Err(());
+    }
+    proof {
+        let w = jacobi_step_2d(sv, f);
+        assert forall |i: int| 0 <= i < rows implies #[trigger] dst.perms@.values()[i] =~= w[i] by {
+            assert forall |j: int| 0 <= j < cols implies #[trigger] dst.perms@.values()[i][j] == w[i][j] by {
+                assert(dst.perms@.contains(i, j));
+            }
+        }
+        assert(dst.perms@.values() =~= w);
+    }
+    Ok(())
+}
+
+/// Like `par_stencil` with the five-point stencil of a matrix.
+pub fn par_stencil_2d<T: Copy + Send + Sync + 'static, F: Fn(T, T, T, T, T) -> T + Copy + Send + 'static>(
+    a: &mut Matrix<T>,
+    b: &mut Matrix<T>,
+    steps: usize,
+    op: F,
+    Ghost(f): Ghost<spec_fn(T, T, T, T, T) -> T>,
+    tile_rows: usize,
+    tile_cols: usize,
+) -> (ret: Result<(), ()>)
+    requires
+        old(a).full(),
+        old(b).full(),
+        old(a).rows == old(b).rows,
+        old(a).cols == old(b).cols,
+        computes_2d(op, f),
+    ensures
+        ret.is_ok() ==> a.full() && a.rows == old(a).rows && a.cols == old(a).cols,
+        ret.is_ok() ==> b.full() && b.rows == old(b).rows && b.cols == old(b).cols,
+        ret.is_ok() && steps % 2 == 0 ==> a.perms@.values() == iterated_2d(old(a).perms@.values(), f, steps as nat),
+        ret.is_ok() && steps % 2 == 1 ==> b.perms@.values() == iterated_2d(old(a).perms@.values(), f, steps as nat),
+{
+    let ghost v = a.perms@.values();
+    let mut t: usize = 0;
+    while t < steps
+        invariant
+            a.full(),
+            b.full(),
+            a.rows == old(a).rows && a.cols == old(a).cols,
+            b.rows == old(b).rows && b.cols == old(b).cols,
+            a.rows == b.rows,
+            a.cols == b.cols,
+            computes_2d(op, f),
+            t <= steps,
+            t % 2 == 0 ==> a.perms@.values() == iterated_2d(v, f, t as nat),
+            t % 2 == 1 ==> b.perms@.values() == iterated_2d(v, f, t as nat),
+    {
+        // the buffers swap their roles at every step
+        if t % 2 == 0 {
+            match stencil_step_2d(a, b, op, Ghost(f), tile_rows, tile_cols) {
+                Ok(()) => {},
+                Err(_) => {return Err(());},
+            };
+        } else {
+            match stencil_step_2d(b, a, op, Ghost(f), tile_rows, tile_cols) {
+                Ok(()) => {},
+                Err(_) => {return Err(());},
+            };
+        }
+        proof {
+            assert(((t + 1) - 1) as nat == t as nat);
+        }
+        t += 1;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_par_stencil() {
+    use crate::test_data::{scattered, thresholds};
+
+    let data: Vec<u32> = scattered(1000, 401, 0);
+    let n = data.len();
+    for steps in [0, 1, 4, 7] {
+        let mut expected = data.clone();
+        for _ in 0..steps {
+            let prev = expected.clone();
+            for i in 1..n - 1 {
+                expected[i] = prev[i - 1] ^ prev[i + 1] ^ (prev[i] & 7);
+            }
+        }
+        for threshold in thresholds(n) {
+            let mut a = ArrayForSorting::new(data.clone());
+            let mut b = ArrayForSorting::new(vec![0; n]);
+            par_stencil(
+                &mut a, &mut b, steps,
+                |l: u32, c: u32, r: u32| l ^ r ^ (c & 7), Ghost(|l: u32, c: u32, r: u32| l ^ r ^ (c & 7)),
+                threshold,
+            ).unwrap();
+            let res = if steps % 2 == 0 { a.clone_to_vec() } else { b.clone_to_vec() };
+            assert_eq!(res, expected);
+        }
+    }
+
+    let (rows, cols) = (23, 31);
+    let data: Vec<u32> = scattered(rows * cols, 401, 0);
+    for steps in [1, 4, 7] {
+        let mut expected = data.clone();
+        for _ in 0..steps {
+            let prev = expected.clone();
+            for i in 1..rows - 1 {
+                for j in 1..cols - 1 {
+                    let k = i * cols + j;
+                    expected[k] = prev[k - cols] ^ prev[k - 1] ^ prev[k + 1] ^ prev[k + cols] ^ (prev[k] & 7);
+                }
+            }
+        }
+        for (tile_rows, tile_cols) in [(1, 1), (4, 7), (100, 100)] {
+            let mut a = Matrix::new(data.clone(), rows, cols);
+            let mut b = Matrix::new(vec![0; rows * cols], rows, cols);
+            par_stencil_2d(
+                &mut a, &mut b, steps,
+                |u: u32, l: u32, c: u32, r: u32, d: u32| u ^ l ^ r ^ d ^ (c & 7),
+                Ghost(|u: u32, l: u32, c: u32, r: u32, d: u32| u ^ l ^ r ^ d ^ (c & 7)),
+                tile_rows, tile_cols,
+            ).unwrap();
+            let res = if steps % 2 == 0 { a.clone_to_vec() } else { b.clone_to_vec() };
+            assert_eq!(res, expected);
+        }
+    }
+}
+
+}
@@ -0,0 +1,15 @@
+//! Inputs shared by the tests of the parallel algorithms.
+
+/// `n` values of `lo..lo + m`, spread over it in no particular order.
+pub(crate) fn scattered<T: TryFrom<i64>>(n: usize, m: i64, lo: i64) -> Vec<T>
+where
+    T::Error: std::fmt::Debug,
+{
+    (0..n as i64).map(|i| T::try_from((i * 7919) % m + lo).unwrap()).collect()
+}
+
+/// The thresholds to run a parallel algorithm on `n` elements with: one element per thread,
+/// an odd and an even size in between, and the whole input in a single thread.
+pub(crate) fn thresholds(n: usize) -> [usize; 4] {
+    [1, 7, 64, n + 1]
+}
@@ -106,6 +106,60 @@ fn verus_merge(c: &mut Criterion) {
     }
 }
 
+fn verus_merge_sorted(c: &mut Criterion) {
+    for size in ARRAY_SIZES {
+        let (left, right) = generate_merge_task(size);
+
+        let left = disjoint_mut_test::mergesort::ArrayForSorting::new(left);
+        let right = disjoint_mut_test::mergesort::ArrayForSorting::new(right);
+        let mut out = disjoint_mut_test::mergesort::ArrayForSorting::new(vec![0; size * 2]);
+
+        c.bench_with_input(BenchmarkId::new("verus merge_sorted", size), &size, |b, _| {
+            b.iter(|| {
+                disjoint_mut_test::merge_sorted::merge_sorted(
+                    black_box(&left),
+                    black_box(&right),
+                    black_box(&mut out),
+                );
+            });
+        });
+    }
+}
+
+fn branchless_merge(c: &mut Criterion) {
+    for size in ARRAY_SIZES {
+        let (left, right) = generate_merge_task(size);
+        let mut out = vec![0; size * 2];
+
+        c.bench_with_input(BenchmarkId::new("branchless merge", size), &size, |b, _| {
+            b.iter(|| {
+                mergesort_benchmarks::merge_sorts::merge_kernels::merge_branchless(
+                    black_box(&left),
+                    black_box(&right),
+                    black_box(&mut out),
+                );
+            });
+        });
+    }
+}
+
+fn simd_merge(c: &mut Criterion) {
+    for size in ARRAY_SIZES {
+        let (left, right) = generate_merge_task(size);
+        let mut out = vec![0; size * 2];
+
+        c.bench_with_input(BenchmarkId::new("simd merge", size), &size, |b, _| {
+            b.iter(|| {
+                mergesort_benchmarks::merge_sorts::merge_kernels::merge_simd(
+                    black_box(&left),
+                    black_box(&right),
+                    black_box(&mut out),
+                );
+            });
+        });
+    }
+}
+
 static ARRAY_SIZES: [usize; 8] = [
     100_000,
     1_000_000,
@@ -125,6 +179,6 @@ fn small_config() -> Criterion {
 criterion_group! {
     name = merge;
     config = small_config();
-    targets = standard_merge, verus_merge
+    targets = standard_merge, verus_merge, verus_merge_sorted, branchless_merge, simd_merge
 }
 criterion_main!(merge);
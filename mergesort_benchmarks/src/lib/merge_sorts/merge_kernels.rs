@@ -0,0 +1,145 @@
+//! Merge kernels for `i32` that avoid the data-dependent branch of the
+//! classic two-pointer merge.
+//!
+//! `merge_branchless` selects the next element with conditional moves,
+//! `merge_simd` merges 4 + 4 elements at a time with an SSE4.1 bitonic
+//! network and falls back to `merge_branchless` on other targets.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+#[inline(always)]
+fn copy(from: &[i32], to: &mut [i32]) {
+    to[..from.len()].copy_from_slice(from);
+}
+
+/// Merges sorted `left` and `right` into `out[..left.len() + right.len()]`.
+pub fn merge_branchless(left: &[i32], right: &[i32], out: &mut [i32]) {
+    assert!(out.len() >= left.len() + right.len());
+    let mut left_i = 0;
+    let mut right_i = 0;
+    let mut out_i = 0;
+    while left_i < left.len() && right_i < right.len() {
+        let (l, r) = unsafe { (*left.get_unchecked(left_i), *right.get_unchecked(right_i)) };
+        let take_left = l <= r;
+        unsafe {
+            *out.get_unchecked_mut(out_i) = if take_left { l } else { r };
+        }
+        left_i += take_left as usize;
+        right_i += !take_left as usize;
+        out_i += 1;
+    }
+    copy(&left[left_i..], &mut out[out_i..]);
+    out_i += left.len() - left_i;
+    copy(&right[right_i..], &mut out[out_i..]);
+}
+
+/// Merges sorted `left` and `right` into `out[..left.len() + right.len()]`.
+pub fn merge_simd(left: &[i32], right: &[i32], out: &mut [i32]) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("sse4.1") {
+            unsafe { merge_sse41(left, right, out) };
+            return;
+        }
+    }
+    merge_branchless(left, right, out)
+}
+
+/// Sorts a bitonic sequence of 4 lanes.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.1")]
+#[inline]
+fn bitonic_sort_4(v: __m128i) -> __m128i {
+    // compare lanes (0, 2) and (1, 3)
+    let t = _mm_shuffle_epi32::<0b01_00_11_10>(v);
+    let v = _mm_blend_epi16::<0xF0>(_mm_min_epi32(v, t), _mm_max_epi32(v, t));
+    // compare lanes (0, 1) and (2, 3)
+    let t = _mm_shuffle_epi32::<0b10_11_00_01>(v);
+    _mm_blend_epi16::<0xCC>(_mm_min_epi32(v, t), _mm_max_epi32(v, t))
+}
+
+/// Merges two sorted 4-lane vectors into the 4 smallest and the 4 largest
+/// elements, both sorted.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.1")]
+#[inline]
+fn bitonic_merge_4x4(a: __m128i, b: __m128i) -> (__m128i, __m128i) {
+    let b = _mm_shuffle_epi32::<0b00_01_10_11>(b);
+    let lo = _mm_min_epi32(a, b);
+    let hi = _mm_max_epi32(a, b);
+    (bitonic_sort_4(lo), bitonic_sort_4(hi))
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.1")]
+unsafe fn merge_sse41(left: &[i32], right: &[i32], out: &mut [i32]) {
+    assert!(out.len() >= left.len() + right.len());
+    if left.len() < 4 || right.len() < 4 {
+        return merge_branchless(left, right, out);
+    }
+    let load = |s: &[i32], i: usize| unsafe { _mm_loadu_si128(s.as_ptr().add(i) as *const __m128i) };
+
+    let mut left_i = 4;
+    let mut right_i = 4;
+    let mut out_i = 0;
+    let mut carry = load(right, 0);
+    let mut next = load(left, 0);
+    // Everything stored so far is <= `carry` and the unread parts of both inputs.
+    loop {
+        let (lo, hi) = bitonic_merge_4x4(carry, next);
+        unsafe { _mm_storeu_si128(out.as_mut_ptr().add(out_i) as *mut __m128i, lo) };
+        out_i += 4;
+        carry = hi;
+        if left_i + 4 > left.len() || right_i + 4 > right.len() {
+            break;
+        }
+        if left[left_i] <= right[right_i] {
+            next = load(left, left_i);
+            left_i += 4;
+        } else {
+            next = load(right, right_i);
+            right_i += 4;
+        }
+    }
+
+    // `carry`, the short tail and the long tail are all sorted, merge them.
+    let mut carried = [0; 4];
+    unsafe { _mm_storeu_si128(carried.as_mut_ptr() as *mut __m128i, carry) };
+    let (short, long) = if left.len() - left_i < 4 {
+        (&left[left_i..], &right[right_i..])
+    } else {
+        (&right[right_i..], &left[left_i..])
+    };
+    let mut small = [0; 7];
+    let small_len = carried.len() + short.len();
+    merge_branchless(&carried, short, &mut small);
+    merge_branchless(&small[..small_len], long, &mut out[out_i..]);
+}
+
+#[test]
+fn test_merge_kernels() {
+    use rand::Rng;
+
+    let mut rng = rand::rng();
+    for _ in 0..200 {
+        let mut left = (0..rng.random_range(0..50))
+            .map(|_| rng.random_range(-20..20))
+            .collect::<Vec<i32>>();
+        let mut right = (0..rng.random_range(0..50))
+            .map(|_| rng.random_range(-20..20))
+            .collect::<Vec<i32>>();
+        left.sort();
+        right.sort();
+        let mut expected = [left.clone(), right.clone()].concat();
+        expected.sort();
+
+        let mut out = vec![0; expected.len()];
+        merge_branchless(&left, &right, &mut out);
+        assert_eq!(out, expected);
+
+        let mut out = vec![0; expected.len()];
+        merge_simd(&left, &right, &mut out);
+        assert_eq!(out, expected);
+    }
+}
@@ -258,3 +258,4 @@ pub mod verus_changed5;
 pub mod verus_without_ghost_profiled;
 pub mod parallel_profiled;
 pub mod minimalistic_sorts;
+pub mod merge_kernels;